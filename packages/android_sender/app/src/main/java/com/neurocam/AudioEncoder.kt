@@ -0,0 +1,140 @@
+// --- packages/android_sender/app/src/main/java/com/neurocam/AudioEncoder.kt ---
+package com.neurocam
+
+import android.annotation.SuppressLint
+import android.media.AudioFormat
+import android.media.AudioRecord
+import android.media.MediaCodec
+import android.media.MediaCodecInfo
+import android.media.MediaFormat
+import android.media.MediaRecorder
+import android.util.Log
+import kotlinx.coroutines.CoroutineScope
+import kotlinx.coroutines.Dispatchers
+import kotlinx.coroutines.Job
+import kotlinx.coroutines.isActive
+import kotlinx.coroutines.launch
+
+/**
+ * 从麦克风采集 PCM，用 MediaCodec 编码为 AAC-LC，通过 NativeBridge.sendAudioFrame 发给接收端。
+ * 每一帧的时间戳是它第一个采样的采集时刻（Unix 纪元的墙上时钟），与视频帧的时间戳同一个时钟，接收端据此对齐音画。
+ * 调用 start() 之前必须已经取得 RECORD_AUDIO 权限。
+ */
+class AudioEncoder(
+    private val sampleRate: Int = 48_000,
+    private val channels: Int = 1,
+    private val bitrate: Int = 64_000
+) {
+    companion object {
+        private const val TAG = "NeuroCam/AudioEncoder"
+        // 协议中的音频编解码器编号 (AudioCodec)：1 为 AAC-LC
+        private const val CODEC_AAC = 1
+        private const val BYTES_PER_SAMPLE = 2 // PCM 16 位
+    }
+
+    private var audioRecord: AudioRecord? = null
+    private var mediaCodec: MediaCodec? = null
+    private var encodeJob: Job? = null
+
+    @SuppressLint("MissingPermission")
+    fun start() {
+        if (encodeJob != null) {
+            Log.w(TAG, "Encoder is already running.")
+            return
+        }
+        try {
+            val channelConfig =
+                if (channels == 2) AudioFormat.CHANNEL_IN_STEREO else AudioFormat.CHANNEL_IN_MONO
+            val minBufferSize = AudioRecord.getMinBufferSize(
+                sampleRate, channelConfig, AudioFormat.ENCODING_PCM_16BIT
+            )
+            val record = AudioRecord(
+                MediaRecorder.AudioSource.VOICE_COMMUNICATION,
+                sampleRate,
+                channelConfig,
+                AudioFormat.ENCODING_PCM_16BIT,
+                minBufferSize * 2
+            )
+            val format = MediaFormat.createAudioFormat(
+                MediaFormat.MIMETYPE_AUDIO_AAC, sampleRate, channels
+            ).apply {
+                setInteger(MediaFormat.KEY_AAC_PROFILE, MediaCodecInfo.CodecProfileLevel.AACObjectLC)
+                setInteger(MediaFormat.KEY_BIT_RATE, bitrate)
+            }
+            val codec = MediaCodec.createEncoderByType(MediaFormat.MIMETYPE_AUDIO_AAC)
+            codec.configure(format, null, null, MediaCodec.CONFIGURE_FLAG_ENCODE)
+            codec.start()
+            record.startRecording()
+            audioRecord = record
+            mediaCodec = codec
+            encodeJob = CoroutineScope(Dispatchers.IO).launch {
+                val pcm = ByteArray(minBufferSize)
+                while (isActive) {
+                    val read = record.read(pcm, 0, pcm.size)
+                    if (read <= 0) continue
+                    // 这一块 PCM 的第一个采样是在 read 返回之前这么久采集的
+                    val durationNs = read.toLong() / (BYTES_PER_SAMPLE * channels) * 1_000_000_000L / sampleRate
+                    val captureNs = System.currentTimeMillis() * 1_000_000 - durationNs
+                    encode(codec, pcm, read, captureNs)
+                }
+            }
+            Log.i(TAG, "AudioEncoder started: AAC-LC ${sampleRate}Hz x$channels, $bitrate bps.")
+        } catch (e: Exception) {
+            Log.e(TAG, "Failed to start AudioEncoder", e)
+            stop()
+        }
+    }
+
+    fun stop() {
+        encodeJob?.cancel()
+        encodeJob = null
+        try {
+            audioRecord?.stop()
+            audioRecord?.release()
+            mediaCodec?.stop()
+            mediaCodec?.release()
+        } catch (e: Exception) {
+            Log.e(TAG, "Error stopping AudioEncoder", e)
+        } finally {
+            audioRecord = null
+            mediaCodec = null
+            Log.i(TAG, "AudioEncoder stopped.")
+        }
+    }
+
+    private fun encode(codec: MediaCodec, pcm: ByteArray, size: Int, captureNs: Long) {
+        try {
+            val inputBufferIndex = codec.dequeueInputBuffer(10000)
+            if (inputBufferIndex >= 0) {
+                val inputBuffer = codec.getInputBuffer(inputBufferIndex)!!
+                inputBuffer.clear()
+                val length = minOf(size, inputBuffer.remaining())
+                inputBuffer.put(pcm, 0, length)
+                // 采集时间戳随 PCM 一起交给编码器，输出帧的 presentationTimeUs 就是它第一个采样的采集时刻
+                codec.queueInputBuffer(inputBufferIndex, 0, length, captureNs / 1000, 0)
+            }
+
+            val bufferInfo = MediaCodec.BufferInfo()
+            while (true) {
+                val outputBufferIndex = codec.dequeueOutputBuffer(bufferInfo, 0)
+                if (outputBufferIndex < 0) break
+                val outputBuffer = codec.getOutputBuffer(outputBufferIndex)
+                // 编码配置 (csd-0) 不需要发送：接收端由采样率和声道数推出 AAC-LC 的配置
+                val isConfig = (bufferInfo.flags and MediaCodec.BUFFER_FLAG_CODEC_CONFIG) != 0
+                if (outputBuffer != null && bufferInfo.size > 0 && !isConfig) {
+                    NativeBridge.sendAudioFrame(
+                        outputBuffer,
+                        bufferInfo.size,
+                        CODEC_AAC,
+                        sampleRate,
+                        channels,
+                        bufferInfo.presentationTimeUs * 1000
+                    )
+                }
+                codec.releaseOutputBuffer(outputBufferIndex, false)
+            }
+        } catch (e: Exception) {
+            Log.e(TAG, "Audio encoding error", e)
+        }
+    }
+}
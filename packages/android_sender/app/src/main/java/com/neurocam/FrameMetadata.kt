@@ -0,0 +1,68 @@
+// --- packages/android_sender/app/src/main/java/com/neurocam/FrameMetadata.kt ---
+package com.neurocam
+
+import java.io.ByteArrayOutputStream
+import java.nio.ByteBuffer
+
+/**
+ * 一帧视频的元数据，编码格式与 Rust 端 protocol::metadata 的 TLV 条目一致（大端序）。
+ * 用 NativeBridge.sendFrameMetadata 发送，frameId 取 NativeBridge.sendVideoFrame 的返回值。
+ * 所有时间戳与视频帧的时间戳使用同一个时钟（Unix 纪元的墙上时钟，纳秒）；浮点数必须是有限值。
+ */
+class FrameMetadata {
+    private companion object {
+        const val TAG_INTRINSICS = 1
+        const val TAG_EXPOSURE = 2
+        const val TAG_FOCUS_DISTANCE = 3
+        const val TAG_ORIENTATION = 4
+        const val TAG_IMU = 5
+        const val TAG_GPS = 6
+    }
+
+    private val out = ByteArrayOutputStream()
+
+    val isEmpty: Boolean
+        get() = out.size() == 0
+
+    /** 相机内参（像素），即 CaptureResult.LENS_INTRINSIC_CALIBRATION 的 [fx, fy, cx, cy, s]。 */
+    fun intrinsics(fx: Float, fy: Float, cx: Float, cy: Float, skew: Float) = entry(TAG_INTRINSICS, 20) {
+        it.putFloat(fx).putFloat(fy).putFloat(cx).putFloat(cy).putFloat(skew)
+    }
+
+    fun exposure(exposureTimeNs: Long, iso: Int) = entry(TAG_EXPOSURE, 12) {
+        it.putLong(exposureTimeNs).putInt(iso)
+    }
+
+    /** 对焦距离（屈光度，0 表示无穷远），即 CaptureResult.LENS_FOCUS_DISTANCE。 */
+    fun focusDistance(diopters: Float) = entry(TAG_FOCUS_DISTANCE, 4) {
+        it.putFloat(diopters)
+    }
+
+    /** 设备姿态的单位四元数 (w, x, y, z)。 */
+    fun orientation(w: Float, x: Float, y: Float, z: Float) = entry(TAG_ORIENTATION, 16) {
+        it.putFloat(w).putFloat(x).putFloat(y).putFloat(z)
+    }
+
+    /** 一个 IMU 样本：加速度 (m/s²) 与角速度 (rad/s)，各三个分量。 */
+    fun imu(timestampNs: Long, accel: FloatArray, gyro: FloatArray) = entry(TAG_IMU, 32) {
+        it.putLong(timestampNs)
+        accel.take(3).forEach { value -> it.putFloat(value) }
+        gyro.take(3).forEach { value -> it.putFloat(value) }
+    }
+
+    fun gps(timestampNs: Long, latitude: Double, longitude: Double, altitudeM: Float, accuracyM: Float) =
+        entry(TAG_GPS, 32) {
+            it.putLong(timestampNs).putDouble(latitude).putDouble(longitude)
+                .putFloat(altitudeM).putFloat(accuracyM)
+        }
+
+    fun toByteArray(): ByteArray = out.toByteArray()
+
+    private fun entry(tag: Int, length: Int, write: (ByteBuffer) -> Unit): FrameMetadata {
+        val buffer = ByteBuffer.allocate(3 + length) // ByteBuffer 默认就是大端序
+        buffer.put(tag.toByte()).putShort(length.toShort())
+        write(buffer)
+        out.write(buffer.array())
+        return this
+    }
+}
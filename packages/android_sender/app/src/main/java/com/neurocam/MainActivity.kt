@@ -84,21 +84,30 @@ fun MainScreen(modifier: Modifier = Modifier) {
         )
     }
 
+    // 麦克风权限是可选的：拒绝后只推视频
     val permissionLauncher = rememberLauncherForActivityResult(
-        contract = ActivityResultContracts.RequestPermission(),
-        onResult = { isGranted ->
+        contract = ActivityResultContracts.RequestMultiplePermissions(),
+        onResult = { results ->
+            val isGranted = results[Manifest.permission.CAMERA] == true
             hasPermission = isGranted
             if (isGranted) {
                 Log.i("NeuroCam/MainScreen", "摄像头权限已被用户授予。")
             } else {
                 Log.w("NeuroCam/MainScreen", "摄像头权限被用户拒绝。")
             }
+            if (results[Manifest.permission.RECORD_AUDIO] != true) {
+                Log.w("NeuroCam/MainScreen", "麦克风权限未授予，只推送视频。")
+            }
         }
     )
+    val requiredPermissions = arrayOf(Manifest.permission.CAMERA, Manifest.permission.RECORD_AUDIO)
 
     LaunchedEffect(key1 = Unit) {
-        if (!hasPermission) {
-            permissionLauncher.launch(Manifest.permission.CAMERA)
+        val hasAudioPermission = ContextCompat.checkSelfPermission(
+            context, Manifest.permission.RECORD_AUDIO
+        ) == PackageManager.PERMISSION_GRANTED
+        if (!hasPermission || !hasAudioPermission) {
+            permissionLauncher.launch(requiredPermissions)
         }
     }
 
@@ -109,7 +118,7 @@ fun MainScreen(modifier: Modifier = Modifier) {
             } else {
                 PermissionDeniedScreen(
                     onRequestPermission = {
-                        permissionLauncher.launch(Manifest.permission.CAMERA)
+                        permissionLauncher.launch(requiredPermissions)
                     }
                 )
             }
@@ -128,6 +137,8 @@ fun CameraPreview(modifier: Modifier = Modifier) {
     val cameraExecutor = remember { java.util.concurrent.Executors.newSingleThreadExecutor() }
 
     var videoEncoder: VideoEncoder? by remember { mutableStateOf(null) }
+    var audioEncoder: AudioEncoder? by remember { mutableStateOf(null) }
+    val sensorMetadata = remember { SensorMetadataCollector(context) }
 
     // --- 监听来自 Rust 的 I-Frame 请求 ---
     LaunchedEffect(videoEncoder) {
@@ -169,10 +180,24 @@ fun CameraPreview(modifier: Modifier = Modifier) {
                                 Log.i("NeuroCam/CameraPreview", "First frame received. " +
                                         "Actual resolution: ${actualWidth}x${actualHeight}. Initializing encoder.")
                                 videoEncoder = VideoEncoder(width = actualWidth, height = actualHeight).apply {
+                                    // 每一帧附上两帧之间的 IMU 样本和当前姿态
+                                    onFrameSent = { frameId, timestampNs ->
+                                        val metadata = sensorMetadata.takeFrameMetadata()
+                                        if (!metadata.isEmpty) {
+                                            val entries = metadata.toByteArray()
+                                            NativeBridge.sendFrameMetadata(streamId, frameId, timestampNs, entries, entries.size)
+                                        }
+                                    }
                                     start()
                                     startSpsPpsHeartbeat() // 关键：初始化后立即启动心跳
                                 }
+                                sensorMetadata.start()
                                 NativeBridge.videoEncoder = videoEncoder // 关键：赋值给 NativeBridge
+                                if (ContextCompat.checkSelfPermission(ctx, Manifest.permission.RECORD_AUDIO) ==
+                                    PackageManager.PERMISSION_GRANTED
+                                ) {
+                                    audioEncoder = AudioEncoder().apply { start() }
+                                }
                             }
                             videoEncoder?.encodeFrame(imageProxy)
                             imageProxy.close()
@@ -205,6 +230,8 @@ fun CameraPreview(modifier: Modifier = Modifier) {
             cameraExecutor.shutdown()
             videoEncoder?.stopSpsPpsHeartbeat()
             videoEncoder?.stop()
+            audioEncoder?.stop()
+            sensorMetadata.stop()
         }
     }
 }
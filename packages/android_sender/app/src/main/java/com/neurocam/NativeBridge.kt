@@ -11,15 +11,34 @@ object NativeBridge {
     // --- Rust to Kotlin Communication via SharedFlow ---
     private val _keyFrameRequestFlow = kotlinx.coroutines.flow.MutableSharedFlow<Unit>()
     val keyFrameRequestFlow = _keyFrameRequestFlow.asSharedFlow()
-    var videoEncoder: VideoEncoder? = null
+    /** 按流 ID 登记的编码器，关键帧请求按流分发到对应的编码器。 */
+    val videoEncoders = java.util.concurrent.ConcurrentHashMap<Int, VideoEncoder>()
+
+    /** 第 0 路（主）流的编码器，它的参数通过握手告知接收端，拥塞控制也只调整它的码率。 */
+    var videoEncoder: VideoEncoder?
+        get() = videoEncoders[0]
+        set(value) {
+            if (value == null) videoEncoders.remove(0) else videoEncoders[0] = value
+        }
+
     /**
      * 这个函数由 Rust 层的 JNI 代码调用，作为一个回调。
      * 它向一个 SharedFlow 发射一个事件，通知应用层需要请求一个关键帧。
+     * @param streamId 需要关键帧的流。
      */
     @JvmStatic
-    fun requestKeyFrameFromNative() {
-        Log.i("NativeBridge", "JNI回调: requestKeyFrameFromNative 被调用")
-        onIFrameRequestFromRust()
+    fun requestKeyFrameFromNative(streamId: Int) {
+        Log.i("NativeBridge", "JNI回调: requestKeyFrameFromNative($streamId) 被调用")
+        onIFrameRequestFromRust(streamId)
+    }
+
+    /**
+     * 由 Rust 层的拥塞控制调用：接收端反馈的网络状况变化后，把编码器的码率调整为 bitrateBps。
+     */
+    @JvmStatic
+    fun targetBitrateFromNative(bitrateBps: Int) {
+        Log.i("NativeBridge", "JNI回调: targetBitrateFromNative($bitrateBps)")
+        videoEncoder?.setBitrate(bitrateBps)
     }
     // --- End of Communication Channel ---
 
@@ -36,16 +55,108 @@ object NativeBridge {
      * @param size 缓冲区中有效数据的实际大小。
      * @param isKeyFrame 标记此帧是否为关键帧 (I-frame)。
      * @param timestampNs 帧的捕获时间戳（纳秒）。
+     * @param streamId 帧所属的流（0..255），同一会话可以同时发送多路流，接收端把每路流输出到各自的设备。
+     * @return 分配给这一帧的帧号，用于 sendFrameMetadata；帧被丢弃时返回 -1。
      */
-    external fun sendVideoFrame(frameBuffer: java.nio.ByteBuffer, size: Int, isKeyFrame: Boolean, timestampNs: Long)
+    external fun sendVideoFrame(frameBuffer: java.nio.ByteBuffer, size: Int, isKeyFrame: Boolean, timestampNs: Long, streamId: Int): Long
 
+    /**
+     * 发送一帧视频的元数据（见 FrameMetadata）。接收端把它和解码后的这一帧对应起来，通过旁路输出给下游。
+     * @param streamId 帧所属的流。
+     * @param frameId sendVideoFrame 返回的帧号。
+     * @param timestampNs 这一帧的采集时间戳，与 sendVideoFrame 的相同。
+     */
+    external fun sendFrameMetadata(streamId: Int, frameId: Long, timestampNs: Long, entries: ByteArray, size: Int)
+
+    /**
+     * 发送一个编码后的音频帧，接收端把它与视频按采集时间戳对齐后播放。
+     * @param frameBuffer 一个 Direct ByteBuffer，装着一个完整的音频帧（Opus，或不带 ADTS 头的 AAC-LC）。
+     * @param size 帧的字节数，最大 1400。
+     * @param codec 0 为 Opus，1 为 AAC-LC。
+     * @param sampleRate 采样率 (8000..96000)。
+     * @param channels 声道数，1 或 2。
+     * @param timestampNs 帧中第一个采样的采集时间戳（纳秒），必须与 sendVideoFrame 的时间戳使用同一个时钟。
+     */
+    external fun sendAudioFrame(frameBuffer: ByteBuffer, size: Int, codec: Int, sampleRate: Int, channels: Int, timestampNs: Long)
+
+    /** 仅适用于第 0 路 H.264 流的旧接口，新代码请使用 sendParameterSets。 */
     external fun sendSpsPps(buffer: ByteArray, size: Int)
 
-    fun onIFrameRequestFromRust() {
-        Log.i("NativeBridge", "收到I-Frame请求，videoEncoder=${videoEncoder != null}")
-        videoEncoder?.shouldSendSpsPps = true
-        videoEncoder?.requestKeyFrame()
+    /**
+     * 发送一路流的参数集（Annex-B 格式）：H.264 为 SPS/PPS，H.265 为 VPS/SPS/PPS。
+     * 参数集变化时接收端会重启这路流的解码管线并请求关键帧。
+     * @param streamId 参数集所属的流。
+     * @param codec 这路流的编解码器：0 为 H.264，1 为 H.265，2 为 MJPEG（没有参数集，传空数组）。
+     */
+    external fun sendParameterSets(streamId: Int, codec: Int, buffer: ByteArray, size: Int)
+
+    /**
+     * 告知 Rust 层第 0 路（主）流的编码参数，它们会通过 Hello 握手包发送给接收端。
+     * @param width 编码宽度。
+     * @param height 编码高度。
+     * @param fps 目标帧率。
+     * @param codec 编解码器：0 为 H.264，1 为 H.265，2 为 MJPEG。接收端据此选择解码管线。
+     */
+    external fun configureStream(width: Int, height: Int, fps: Int, codec: Int)
+
+    /**
+     * 配置前向纠错 (FEC)。
+     * @param scheme 负数关闭 FEC，0 为异或，1 为 Reed-Solomon。
+     * @param redundancy 校验分片与数据分片的比例，例如 0.25。
+     * @param interleaveDepth 每帧分片交织成的最少组数。
+     * @param crossFrameInterleave 是否把校验分片推迟到下一帧的数据分片之间发送。
+     */
+    external fun setFecConfig(scheme: Int, redundancy: Float, interleaveDepth: Int, crossFrameInterleave: Boolean)
+
+    /**
+     * 配置与接收端共享的 32 字节密钥，开启 XChaCha20-Poly1305 认证加密。
+     * 传入空数组则关闭加密。接收端需要通过 NEUROCAM_PSK 环境变量配置同一个密钥。
+     */
+    external fun setPreSharedKey(key: ByteArray)
+
+    /**
+     * 配置连接状态机的超时（毫秒）。
+     * @param heartbeatIntervalMs 会话建立后发送心跳的间隔。
+     * @param stallTimeoutMs 接收端沉默多久视为卡顿。
+     * @param lostTimeoutMs 接收端沉默多久视为断开（之后重新握手），必须大于 stallTimeoutMs。
+     */
+    external fun setConnectionTimeouts(heartbeatIntervalMs: Int, stallTimeoutMs: Int, lostTimeoutMs: Int)
+
+    /**
+     * 配置拥塞控制的码率范围（比特/秒）。目标码率回到 startBps，它应当等于编码器当前的码率。
+     * 之后目标码率变化时通过 targetBitrateFromNative 回调通知。
+     */
+    external fun setBitrateLimits(minBps: Int, startBps: Int, maxBps: Int)
+
+    /**
+     * 设置在握手中请求的最大分片负载（字节，512..8800，默认 1400）。
+     * 实际大小取接收端上限与该值的较小者；超过路径 MTU 时应同时开启 setPathMtuDiscovery。
+     */
+    external fun setMaxPayloadSize(maxPayloadSize: Int)
+
+    /**
+     * 开启或关闭路径 MTU 探测：数据报带 DF 标志，分片大小不会超过探测确认能通过的大小。
+     * 需要接收端也支持，否则不会发出探测。
+     */
+    external fun setPathMtuDiscovery(enabled: Boolean)
+
+    /**
+     * 关键帧请求的统计。Rust 层会合并短时间内的重复请求，两个关键帧之间至少间隔 500ms，
+     * 关键帧没有得到接收端确认时先重发参数集再重新请求。
+     * @return 依次为：收到的请求、被合并的请求、向编码器请求的次数、发出的关键帧、应请求发出并得到接收端确认的关键帧、超时升级的次数。
+     */
+    external fun getKeyFrameStats(): LongArray
+
+    fun onIFrameRequestFromRust(streamId: Int) {
+        val encoder = videoEncoders[streamId]
+        Log.i("NativeBridge", "收到流 $streamId 的I-Frame请求，videoEncoder=${encoder != null}")
+        encoder?.shouldSendSpsPps = true
+        encoder?.requestKeyFrame()
     }
     
+    /**
+     * 停止推流：先向接收端发送 Bye 并等待确认（最多约 250ms），接收端据此立即结束会话，
+     * 然后停止后台线程。
+     */
     external fun close()
 }
\ No newline at end of file
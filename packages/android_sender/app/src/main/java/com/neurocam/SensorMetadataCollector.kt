@@ -0,0 +1,71 @@
+// --- packages/android_sender/app/src/main/java/com/neurocam/SensorMetadataCollector.kt ---
+package com.neurocam
+
+import android.content.Context
+import android.hardware.Sensor
+import android.hardware.SensorEvent
+import android.hardware.SensorEventListener
+import android.hardware.SensorManager
+import android.os.SystemClock
+
+/**
+ * 采集手机的 IMU（加速度计 + 陀螺仪）和旋转矢量传感器，每发送一帧视频就把两帧之间的 IMU 样本
+ * 与最新的姿态打包成这一帧的元数据。
+ */
+class SensorMetadataCollector(context: Context) : SensorEventListener {
+    companion object {
+        // 两帧之间最多保留多少个 IMU 样本，超出时丢弃最早的（每个 35 字节，一个 Metadata 包最多 1400 字节）
+        private const val MAX_IMU_SAMPLES_PER_FRAME = 36
+    }
+
+    private val sensorManager = context.getSystemService(Context.SENSOR_SERVICE) as SensorManager
+    private val lock = Any()
+    private val lastAccel = FloatArray(3)
+    private val imuSamples = ArrayDeque<Pair<Long, Pair<FloatArray, FloatArray>>>()
+    private var orientation: FloatArray? = null
+
+    fun start() {
+        listOf(Sensor.TYPE_ACCELEROMETER, Sensor.TYPE_GYROSCOPE, Sensor.TYPE_ROTATION_VECTOR).forEach { type ->
+            sensorManager.getDefaultSensor(type)?.let {
+                sensorManager.registerListener(this, it, SensorManager.SENSOR_DELAY_GAME)
+            }
+        }
+    }
+
+    fun stop() {
+        sensorManager.unregisterListener(this)
+    }
+
+    /** 取出自上一帧以来的元数据。 */
+    fun takeFrameMetadata(): FrameMetadata = synchronized(lock) {
+        val metadata = FrameMetadata()
+        orientation?.let { (w, x, y, z) -> metadata.orientation(w, x, y, z) }
+        imuSamples.forEach { (timestampNs, sample) -> metadata.imu(timestampNs, sample.first, sample.second) }
+        imuSamples.clear()
+        metadata
+    }
+
+    override fun onSensorChanged(event: SensorEvent) {
+        synchronized(lock) {
+            when (event.sensor.type) {
+                Sensor.TYPE_ACCELEROMETER -> event.values.copyInto(lastAccel, endIndex = 3)
+                // 每个陀螺仪样本配上最近的加速度，组成一个 IMU 样本
+                Sensor.TYPE_GYROSCOPE -> {
+                    if (imuSamples.size >= MAX_IMU_SAMPLES_PER_FRAME) imuSamples.removeFirst()
+                    imuSamples.addLast(toWallClockNs(event.timestamp) to (lastAccel.copyOf() to event.values.copyOf(3)))
+                }
+                Sensor.TYPE_ROTATION_VECTOR -> {
+                    val quaternion = FloatArray(4)
+                    SensorManager.getQuaternionFromVector(quaternion, event.values)
+                    orientation = quaternion
+                }
+            }
+        }
+    }
+
+    override fun onAccuracyChanged(sensor: Sensor, accuracy: Int) {}
+
+    // 传感器的时间戳基于开机后的单调时钟，换算到视频帧使用的墙上时钟
+    private fun toWallClockNs(sensorTimestampNs: Long): Long =
+        System.currentTimeMillis() * 1_000_000 - (SystemClock.elapsedRealtimeNanos() - sensorTimestampNs)
+}
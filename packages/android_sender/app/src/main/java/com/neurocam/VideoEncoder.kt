@@ -15,23 +15,33 @@ import kotlinx.coroutines.isActive
 import kotlinx.coroutines.launch
 
 /**
- * 封装了 MediaCodec API，用于将来自 CameraX 的 ImageProxy (YUV_420_888) 编码为 H.264 视频流。
+ * 封装了 MediaCodec API，用于将来自 CameraX 的 ImageProxy (YUV_420_888) 编码为 H.264 或 H.265 视频流。
+ * @param mimeType MediaFormat.MIMETYPE_VIDEO_AVC (H.264) 或 MediaFormat.MIMETYPE_VIDEO_HEVC (H.265)。
+ * @param streamId 这路流在会话中的编号。第 0 路是主流，它的参数通过握手告知接收端；
+ *   其余的流（例如长焦镜头或预览流）由参数集声明编解码器，需要登记到 NativeBridge.videoEncoders。
  */
 class VideoEncoder(
     private val width: Int,
     private val height: Int,
-    private val bitrate: Int = 2_000_000 // 2 Mbps
+    private val bitrate: Int = 2_000_000, // 2 Mbps
+    private val mimeType: String = MediaFormat.MIMETYPE_VIDEO_AVC,
+    val streamId: Int = 0
 ) {
     companion object {
         private const val TAG = "NeuroCam/VideoEncoder"
-        private const val MIME_TYPE = "video/avc" // H.264
         private const val FRAME_RATE = 30
         private const val I_FRAME_INTERVAL = 1 // 1 秒一个 I-帧
+        // 拥塞控制可以调整的码率范围
+        private const val MIN_BITRATE = 300_000
+        private const val MAX_BITRATE = 8_000_000
     }
 
     private var mediaCodec: MediaCodec? = null
     private var isRunning = false
 
+    /** 每发送一帧后调用，参数是帧号和采集时间戳，用于发送这一帧的元数据（见 NativeBridge.sendFrameMetadata）。 */
+    var onFrameSent: ((frameId: Long, timestampNs: Long) -> Unit)? = null
+
     
     /**
      *  请求编码器立即生成一个关键帧 (I-frame)。
@@ -53,6 +63,24 @@ class VideoEncoder(
     }
     
 
+    /**
+     *  运行中调整编码码率，由拥塞控制通过 NativeBridge 回调触发。
+     */
+    fun setBitrate(bitrateBps: Int) {
+        if (!isRunning || mediaCodec == null) {
+            Log.w(TAG, "Cannot set bitrate, encoder is not running.")
+            return
+        }
+        try {
+            Log.i(TAG, "Adjusting bitrate to $bitrateBps bps")
+            val params = Bundle()
+            params.putInt(MediaCodec.PARAMETER_KEY_VIDEO_BITRATE, bitrateBps)
+            mediaCodec?.setParameters(params)
+        } catch (e: Exception) {
+            Log.e(TAG, "Failed to set bitrate", e)
+        }
+    }
+
     fun nv21ToNv12(nv21: ByteArray, width: Int, height: Int): ByteArray {
         val nv12 = ByteArray(nv21.size)
         val frameSize = width * height
@@ -68,29 +96,29 @@ class VideoEncoder(
         return nv12
     }
 
-    // VideoEncoder.kt
-    var cachedSps: ByteArray? = null
-    var cachedPps: ByteArray? = null
+    /** 协议中的编解码器编号 (VideoCodec)：0 为 H.264，1 为 H.265。 */
+    private val codecId: Int
+        get() = if (mimeType == MediaFormat.MIMETYPE_VIDEO_HEVC) 1 else 0
+
+    // 参数集：H.264 的 csd-0 是 SPS、csd-1 是 PPS；H.265 的 VPS/SPS/PPS 全部在 csd-0 中
+    var cachedParameterSets: ByteArray? = null
 
     fun sendSpsPpsHandshake() {
         val format = mediaCodec?.outputFormat
-        val sps = format?.getByteBuffer("csd-0")
-        val pps = format?.getByteBuffer("csd-1")
-        Log.i(TAG, "SPS: $sps, PPS: $pps")
-        if (sps != null && pps != null) {
-            sps.rewind()
-            pps.rewind()
-            cachedSps = ByteArray(sps.remaining())
-            cachedPps = ByteArray(pps.remaining())
-            sps.get(cachedSps)
-            pps.get(cachedPps)
-            val handshake = ByteArray(cachedSps!!.size + cachedPps!!.size)
-            System.arraycopy(cachedSps!!, 0, handshake, 0, cachedSps!!.size)
-            System.arraycopy(cachedPps!!, 0, handshake, cachedSps!!.size, cachedPps!!.size)
-            NativeBridge.sendSpsPps(handshake, handshake.size)
-            Log.i(TAG, "SPS/PPS handshake sent to Rust")
+        val parts = listOf("csd-0", "csd-1").mapNotNull { key ->
+            format?.getByteBuffer(key)?.let { buffer ->
+                buffer.rewind()
+                ByteArray(buffer.remaining()).also { buffer.get(it) }
+            }
+        }
+        Log.i(TAG, "Parameter sets: ${parts.map { it.size }} bytes")
+        if (parts.isNotEmpty()) {
+            val parameterSets = parts.reduce { acc, part -> acc + part }
+            cachedParameterSets = parameterSets
+            NativeBridge.sendParameterSets(streamId, codecId, parameterSets, parameterSets.size)
+            Log.i(TAG, "Parameter sets handshake sent to Rust")
         } else {
-            Log.w(TAG, "sendSpsPpsHandshake: SPS/PPS is null!")
+            Log.w(TAG, "sendSpsPpsHandshake: parameter sets are null!")
         }
     }
 
@@ -101,12 +129,9 @@ class VideoEncoder(
         spsPpsHeartbeatJob?.cancel()
         spsPpsHeartbeatJob = CoroutineScope(Dispatchers.IO).launch {
             while (isActive) {
-                if (cachedSps != null && cachedPps != null) {
-                    val handshake = ByteArray(cachedSps!!.size + cachedPps!!.size)
-                    System.arraycopy(cachedSps!!, 0, handshake, 0, cachedSps!!.size)
-                    System.arraycopy(cachedPps!!, 0, handshake, cachedSps!!.size, cachedPps!!.size)
-                    NativeBridge.sendSpsPps(handshake, handshake.size)
-                    Log.i(TAG, "SPS/PPS heartbeat sent to Rust")
+                cachedParameterSets?.let {
+                    NativeBridge.sendParameterSets(streamId, codecId, it, it.size)
+                    Log.i(TAG, "Parameter sets heartbeat sent to Rust")
                 }
                 delay(2000)
             }
@@ -124,7 +149,7 @@ class VideoEncoder(
             return
         }
         try {
-            val format = MediaFormat.createVideoFormat(MIME_TYPE, width, height).apply {
+            val format = MediaFormat.createVideoFormat(mimeType, width, height).apply {
                 setInteger(
                     MediaFormat.KEY_COLOR_FORMAT,
                     MediaCodecInfo.CodecCapabilities.COLOR_FormatYUV420Flexible
@@ -133,10 +158,14 @@ class VideoEncoder(
                 setInteger(MediaFormat.KEY_FRAME_RATE, FRAME_RATE)
                 setInteger(MediaFormat.KEY_I_FRAME_INTERVAL, I_FRAME_INTERVAL)
             }
-            mediaCodec = MediaCodec.createEncoderByType(MIME_TYPE)
+            mediaCodec = MediaCodec.createEncoderByType(mimeType)
             mediaCodec?.configure(format, null, null, MediaCodec.CONFIGURE_FLAG_ENCODE)
             mediaCodec?.start()
             isRunning = true
+            if (streamId == 0) {
+                NativeBridge.configureStream(width, height, FRAME_RATE, codecId)
+                NativeBridge.setBitrateLimits(MIN_BITRATE, bitrate, MAX_BITRATE)
+            }
             Log.i(TAG, "VideoEncoder started successfully.")
         } catch (e: Exception) {
             Log.e(TAG, "Failed to start VideoEncoder", e)
@@ -191,38 +220,32 @@ class VideoEncoder(
                             val isKeyFrame =
                                 (bufferInfo.flags and MediaCodec.BUFFER_FLAG_KEY_FRAME) != 0
                             // 核心修复：不再使用 bufferInfo 的时间戳，因为它基于单调时钟。
-                            // 我们在即将发送数据时，获取当前的“墙上时钟”时间（基于Unix纪元），
-                            // 这样就能与 Linux 端的时钟进行有意义的比较。
+                            // 我们在即将发送数据时，获取当前的“墙上时钟”时间（基于Unix纪元）。
+                            // Rust 端回答接收端的 ClockPing 时用的也是这个时钟，接收端据此把它换算到本地时钟。
                             val timestampNs = System.currentTimeMillis() * 1_000_000
                             if (shouldSendSpsPps) {
                                 Log.i(
                                     TAG,
-                                    "shouldSendSpsPps触发，cachedSps=${cachedSps != null}, cachedPps=${cachedPps != null}"
+                                    "shouldSendSpsPps触发，cachedParameterSets=${cachedParameterSets != null}"
                                 )
-                                if (cachedSps != null && cachedPps != null) {
-                                    val handshake = ByteArray(cachedSps!!.size + cachedPps!!.size)
-                                    System.arraycopy(cachedSps!!, 0, handshake, 0, cachedSps!!.size)
-                                    System.arraycopy(
-                                        cachedPps!!,
-                                        0,
-                                        handshake,
-                                        cachedSps!!.size,
-                                        cachedPps!!.size
-                                    )
-                                    NativeBridge.sendSpsPps(handshake, handshake.size)
-                                    Log.i(TAG, "SPS/PPS handshake sent to Rust (from cache)")
+                                val parameterSets = cachedParameterSets
+                                if (parameterSets != null) {
+                                    NativeBridge.sendParameterSets(streamId, codecId, parameterSets, parameterSets.size)
+                                    Log.i(TAG, "Parameter sets handshake sent to Rust (from cache)")
                                 } else {
-                                    Log.w(TAG, "shouldSendSpsPps but SPS/PPS cache is null!")
+                                    Log.w(TAG, "shouldSendSpsPps but parameter set cache is null!")
                                 }
                             }
                             shouldSendSpsPps = false
 
-                            NativeBridge.sendVideoFrame(
+                            val frameId = NativeBridge.sendVideoFrame(
                                 outputBuffer,
                                 bufferInfo.size,
                                 isKeyFrame,
-                                timestampNs
+                                timestampNs,
+                                streamId
                             )
+                            if (frameId >= 0) onFrameSent?.invoke(frameId, timestampNs)
                         }
                         codec.releaseOutputBuffer(outputBufferIndex, false)
                     }
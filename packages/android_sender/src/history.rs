@@ -0,0 +1,80 @@
+// --- packages/android_sender/src/history.rs ---
+
+//! 已发送分片的有界历史，用于响应接收端的 NACK 做选择性重传。
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+struct SentPacket {
+    data: Vec<u8>,
+    sent_at: Instant,
+    resend_count: u8,
+}
+
+/// 按 (stream_id, frame_id, packet_id) 索引的发送历史。
+/// 超过容量时淘汰最早的分片；超过 `max_age` 的分片即使还在缓存里也不再重传，
+/// 因为它们到达接收端时早已错过了解码期限。
+pub struct PacketHistory {
+    packets: HashMap<(u8, u32, u16), SentPacket>,
+    order: VecDeque<(u8, u32, u16)>,
+    capacity: usize,
+    max_age: Duration,
+    max_resends: u8,
+}
+
+impl PacketHistory {
+    pub fn new(capacity: usize, max_age: Duration, max_resends: u8) -> Self {
+        PacketHistory {
+            packets: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+            capacity,
+            max_age,
+            max_resends,
+        }
+    }
+
+    pub fn insert(&mut self, stream_id: u8, frame_id: u32, packet_id: u16, data: Vec<u8>) {
+        let key = (stream_id, frame_id, packet_id);
+        let now = Instant::now();
+        // 先淘汰过期的，再按容量淘汰最早的
+        while let Some(oldest) = self.order.front() {
+            let expired = self
+                .packets
+                .get(oldest)
+                .is_none_or(|p| now.duration_since(p.sent_at) > self.max_age);
+            if !expired && self.order.len() < self.capacity {
+                break;
+            }
+            let oldest = self.order.pop_front().unwrap();
+            self.packets.remove(&oldest);
+        }
+        if self
+            .packets
+            .insert(
+                key,
+                SentPacket {
+                    data,
+                    sent_at: now,
+                    resend_count: 0,
+                },
+            )
+            .is_none()
+        {
+            self.order.push_back(key);
+        }
+    }
+
+    /// 取出需要重传的分片数据。分片不存在、已超过期限或重传次数用尽时返回 `None`。
+    pub fn take_for_resend(
+        &mut self,
+        stream_id: u8,
+        frame_id: u32,
+        packet_id: u16,
+    ) -> Option<&[u8]> {
+        let packet = self.packets.get_mut(&(stream_id, frame_id, packet_id))?;
+        if packet.sent_at.elapsed() > self.max_age || packet.resend_count >= self.max_resends {
+            return None;
+        }
+        packet.resend_count += 1;
+        Some(&packet.data)
+    }
+}
@@ -0,0 +1,277 @@
+// --- packages/android_sender/src/keyframe.rs ---
+
+//! 关键帧请求策略：接收端在参考链断开期间会反复发送 IFrameRequest，发送队列丢弃参考帧时也会请求
+//! 关键帧。每个请求都直接让编码器出一个 IDR 会在丢包严重时连续产生大量关键帧，而关键帧的体积
+//! 又让拥塞更加严重。
+//!
+//! 这里按流合并请求：已经有请求在等待、编码器还没交出关键帧、关键帧还在等待 ACK，或者距离上次
+//! 向编码器请求还不到合并窗口时，新的请求都并入已有的那个。两个关键帧之间至少间隔
+//! `MIN_KEY_FRAME_INTERVAL`。应请求发出的关键帧超时没有收到 ACK 时逐级升级：先重发参数集，
+//! 再重新请求关键帧，最多升级 `MAX_ESCALATIONS` 次。
+use crate::logger;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+// 距离上次向编码器请求关键帧不到这个时长的请求并入上一个（例如同一次丢包引发的多个请求）
+const COALESCE_WINDOW: Duration = Duration::from_millis(100);
+const MIN_KEY_FRAME_INTERVAL: Duration = Duration::from_millis(500);
+// 向编码器请求后等待关键帧发出、以及关键帧发出后等待 ACK 的最长时间
+const KEY_FRAME_TIMEOUT: Duration = Duration::from_millis(500);
+const MAX_ESCALATIONS: u32 = 3;
+
+/// 调用方需要执行的动作。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyFrameAction {
+    /// 让编码器为这路流编码一个关键帧
+    RequestKeyFrame(u8),
+    /// 重发这路流最近一次的参数集
+    ResendParameterSets(u8),
+}
+
+/// 关键帧请求的统计（所有流合计）。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyFrameStats {
+    /// 收到的关键帧请求（接收端的 IFrameRequest 与发送队列丢弃参考帧）
+    pub requests_received: u64,
+    /// 并入已有请求、没有单独请求编码器的请求
+    pub requests_coalesced: u64,
+    /// 向编码器请求关键帧的次数
+    pub encoder_requests: u64,
+    pub key_frames_sent: u64,
+    /// 应请求发出、并且得到接收端确认的关键帧（周期性关键帧的 ACK 不计入）
+    pub key_frames_acked: u64,
+    /// 关键帧超时未确认而升级的次数
+    pub escalations: u64,
+}
+
+#[derive(Default)]
+struct StreamPolicy {
+    // 等待发给编码器的请求：要等到与上一个关键帧的间隔足够
+    pending: bool,
+    // 已经请求了编码器、还没有发出关键帧
+    requested_at: Option<Instant>,
+    last_request_at: Option<Instant>,
+    last_key_frame_at: Option<Instant>,
+    // 应请求发出、还没有收到 ACK 的关键帧：(frame_id, 发出时刻)
+    awaiting_ack: Option<(u32, Instant)>,
+    escalations: u32,
+}
+
+impl StreamPolicy {
+    fn in_flight(&self) -> bool {
+        self.pending || self.requested_at.is_some() || self.awaiting_ack.is_some()
+    }
+}
+
+#[derive(Default)]
+pub struct KeyFramePolicy {
+    streams: BTreeMap<u8, StreamPolicy>,
+    stats: KeyFrameStats,
+}
+
+impl KeyFramePolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 登记一个关键帧请求，由 `poll` 决定何时真正请求编码器。
+    pub fn on_request(&mut self, stream_id: u8, now: Instant) {
+        self.stats.requests_received += 1;
+        let stream = self.streams.entry(stream_id).or_default();
+        let recent = stream
+            .last_request_at
+            .is_some_and(|t| now.duration_since(t) < COALESCE_WINDOW);
+        if stream.in_flight() || recent {
+            self.stats.requests_coalesced += 1;
+            return;
+        }
+        stream.pending = true;
+    }
+
+    /// 一个关键帧交给了发送队列。它同时满足这路流上所有未完成的请求。
+    pub fn on_key_frame_sent(&mut self, stream_id: u8, frame_id: u32, now: Instant) {
+        self.stats.key_frames_sent += 1;
+        let stream = self.streams.entry(stream_id).or_default();
+        stream.last_key_frame_at = Some(now);
+        let answered = stream.requested_at.take().is_some() || stream.pending;
+        stream.pending = false;
+        if answered || stream.awaiting_ack.is_some() {
+            stream.awaiting_ack = Some((frame_id, now));
+        }
+    }
+
+    /// 接收端收齐了一个关键帧（接收端只为关键帧发送 ACK）。
+    pub fn on_ack(&mut self, stream_id: u8, frame_id: u32) {
+        let Some(stream) = self.streams.get_mut(&stream_id) else {
+            return;
+        };
+        if stream.awaiting_ack.is_some_and(|(id, _)| id == frame_id) {
+            stream.awaiting_ack = None;
+            stream.escalations = 0;
+            self.stats.key_frames_acked += 1;
+        }
+    }
+
+    /// 处理到期的请求与超时，返回调用方要执行的动作。
+    pub fn poll(&mut self, now: Instant) -> Vec<KeyFrameAction> {
+        let mut actions = Vec::new();
+        for (&stream_id, stream) in self.streams.iter_mut() {
+            let timed_out = match (stream.awaiting_ack, stream.requested_at) {
+                (Some((_, sent_at)), _) => now.duration_since(sent_at) >= KEY_FRAME_TIMEOUT,
+                (None, Some(requested_at)) => now.duration_since(requested_at) >= KEY_FRAME_TIMEOUT,
+                (None, None) => false,
+            };
+            if timed_out {
+                stream.awaiting_ack = None;
+                stream.requested_at = None;
+                if stream.escalations < MAX_ESCALATIONS {
+                    stream.escalations += 1;
+                    self.stats.escalations += 1;
+                    logger::warn(&format!(
+                        "[KEYFRAME] Key frame for stream {} not confirmed, resending parameter sets and requesting again ({}/{}).",
+                        stream_id, stream.escalations, MAX_ESCALATIONS
+                    ));
+                    actions.push(KeyFrameAction::ResendParameterSets(stream_id));
+                    stream.pending = true;
+                } else {
+                    logger::warn(&format!(
+                        "[KEYFRAME] Key frame for stream {} still not confirmed after {} escalations, waiting for the next request.",
+                        stream_id, MAX_ESCALATIONS
+                    ));
+                    stream.escalations = 0;
+                }
+            }
+
+            let spaced = stream
+                .last_key_frame_at
+                .is_none_or(|t| now.duration_since(t) >= MIN_KEY_FRAME_INTERVAL);
+            if stream.pending && spaced {
+                stream.pending = false;
+                stream.requested_at = Some(now);
+                stream.last_request_at = Some(now);
+                self.stats.encoder_requests += 1;
+                actions.push(KeyFrameAction::RequestKeyFrame(stream_id));
+            }
+        }
+        actions
+    }
+
+    pub fn stats(&self) -> &KeyFrameStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STREAM: u8 = 1;
+
+    fn at(t0: Instant, ms: u64) -> Instant {
+        t0 + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_coalesces_requests_until_the_key_frame_is_acked() {
+        let mut policy = KeyFramePolicy::new();
+        let t0 = Instant::now();
+        policy.on_request(STREAM, t0);
+        policy.on_request(STREAM, at(t0, 1));
+        assert_eq!(
+            policy.poll(at(t0, 2)),
+            [KeyFrameAction::RequestKeyFrame(STREAM)]
+        );
+        // 编码器还没交出关键帧、关键帧还在等待 ACK 时都不再请求
+        policy.on_request(STREAM, at(t0, 20));
+        policy.on_key_frame_sent(STREAM, 7, at(t0, 40));
+        policy.on_request(STREAM, at(t0, 60));
+        assert!(policy.poll(at(t0, 80)).is_empty());
+        policy.on_ack(STREAM, 7);
+        // 合并窗口内到达的请求多半是在关键帧到达之前发出的
+        policy.on_request(STREAM, at(t0, 90));
+        assert!(policy.poll(at(t0, 600)).is_empty());
+
+        let stats = *policy.stats();
+        assert_eq!(stats.requests_received, 5);
+        assert_eq!(stats.requests_coalesced, 4);
+        assert_eq!(stats.encoder_requests, 1);
+        assert_eq!(stats.key_frames_sent, 1);
+        assert_eq!(stats.key_frames_acked, 1);
+        assert_eq!(stats.escalations, 0);
+    }
+
+    #[test]
+    fn test_enforces_minimum_key_frame_interval() {
+        let mut policy = KeyFramePolicy::new();
+        let t0 = Instant::now();
+        // 周期性关键帧不需要 ACK，但同样计入间隔
+        policy.on_key_frame_sent(STREAM, 0, t0);
+        policy.on_request(STREAM, at(t0, 200));
+        assert!(policy.poll(at(t0, 499)).is_empty());
+        assert_eq!(
+            policy.poll(at(t0, 500)),
+            [KeyFrameAction::RequestKeyFrame(STREAM)]
+        );
+        // 其他流不受影响
+        policy.on_request(STREAM + 1, at(t0, 501));
+        assert_eq!(
+            policy.poll(at(t0, 501)),
+            [KeyFrameAction::RequestKeyFrame(STREAM + 1)]
+        );
+    }
+
+    #[test]
+    fn test_escalates_unacked_key_frames_up_to_the_limit() {
+        let mut policy = KeyFramePolicy::new();
+        let t0 = Instant::now();
+        policy.on_request(STREAM, t0);
+        assert_eq!(policy.poll(t0), [KeyFrameAction::RequestKeyFrame(STREAM)]);
+        let mut now = t0;
+        for attempt in 0..MAX_ESCALATIONS {
+            now += Duration::from_millis(10);
+            policy.on_key_frame_sent(STREAM, attempt, now);
+            // 不匹配的 ACK 不算确认
+            policy.on_ack(STREAM, attempt + 100);
+            now += KEY_FRAME_TIMEOUT;
+            assert_eq!(
+                policy.poll(now),
+                [
+                    KeyFrameAction::ResendParameterSets(STREAM),
+                    KeyFrameAction::RequestKeyFrame(STREAM)
+                ]
+            );
+        }
+        // 升级次数用完后放弃，等待下一个请求
+        now += Duration::from_millis(10);
+        policy.on_key_frame_sent(STREAM, 99, now);
+        now += KEY_FRAME_TIMEOUT;
+        assert!(policy.poll(now).is_empty());
+        policy.on_request(STREAM, now);
+        assert_eq!(policy.poll(now), [KeyFrameAction::RequestKeyFrame(STREAM)]);
+
+        let stats = *policy.stats();
+        assert_eq!(stats.escalations, MAX_ESCALATIONS as u64);
+        assert_eq!(stats.key_frames_acked, 0);
+    }
+
+    #[test]
+    fn test_encoder_that_never_delivers_is_asked_again() {
+        let mut policy = KeyFramePolicy::new();
+        let t0 = Instant::now();
+        policy.on_request(STREAM, t0);
+        policy.poll(t0);
+        assert!(policy.poll(at(t0, 499)).is_empty());
+        assert_eq!(
+            policy.poll(at(t0, 500)),
+            [
+                KeyFrameAction::ResendParameterSets(STREAM),
+                KeyFrameAction::RequestKeyFrame(STREAM)
+            ]
+        );
+        // 请求之后发出的关键帧得到确认，升级计数清零
+        policy.on_key_frame_sent(STREAM, 3, at(t0, 520));
+        policy.on_ack(STREAM, 3);
+        assert!(policy.poll(at(t0, 2000)).is_empty());
+        assert_eq!(policy.stats().key_frames_acked, 1);
+    }
+}
@@ -1,23 +1,59 @@
 // --- packages/android_sender/src/lib.rs ---
 
-use jni::objects::{GlobalRef, JByteBuffer, JClass};
+use jni::objects::{GlobalRef, JByteArray, JByteBuffer, JClass, JValue};
 use jni::sys::jboolean;
 use jni::{JNIEnv, JavaVM};
 use lazy_static::lazy_static;
-use protocol::{AckPacket, DataHeader, PacketType, DATA_HEADER_SIZE, MAX_PAYLOAD_SIZE};
-use std::collections::{HashMap, HashSet};
+use protocol::congestion::{BitrateLimits, CongestionController};
+use protocol::connection::ConnectionTimeouts;
+use protocol::crypto::{PacketOpener, PacketSealer, KEY_SIZE, SECURE_HEADER_SIZE, TAG_SIZE};
+use protocol::fec::{self, FecConfig, FecHeader, FecInterleaver, FecScheme};
+use protocol::metadata;
+use protocol::nal::{self, NalKind};
+use protocol::pmtu::PathMtuProber;
+use protocol::{
+    check_audio_header, handshake_session_id, payload_size_for_packet, AudioHeader, ByePacket,
+    ByeReason, ClockPongPacket, DataHeader, HelloAckPacket, HelloStatus, MetadataHeader,
+    MtuProbePacket, NackPacket, Packet, PacketType, ProtocolError, VideoCodec, CAP_AUDIO, CAP_BYE,
+    CAP_CLOCK_SYNC, CAP_CONGESTION_CONTROL, CAP_ENCRYPTION, CAP_FEC, CAP_HEARTBEAT, CAP_METADATA,
+    CAP_NACK, CAP_PMTU_PROBE, FRAGMENT_OVERHEAD, MAX_FRAME_SIZE, MAX_PAYLOAD_SIZE,
+    MIN_PAYLOAD_SIZE, PROTOCOL_MAGIC,
+};
+use std::collections::HashMap;
 use std::net::UdpSocket;
-use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, OnceLock};
 use std::thread::{self, JoinHandle};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+mod history;
+mod keyframe;
 mod logger;
+mod pacer;
+mod session;
+
+use history::PacketHistory;
+use keyframe::{KeyFrameAction, KeyFramePolicy};
+use pacer::Pacer;
+use session::{HandshakeState, Session};
 
 const TARGET_ADDR: &str = "192.168.1.3:8080";
-const RETRANSMISSION_TIMEOUT: Duration = Duration::from_millis(500);
-const MAX_RETRIES: u8 = 5;
-const CONTROL_MSG_BUFFER_SIZE: usize = 128;
+// 控制消息（NACK 可能携带数百个 packet_id）的接收缓冲区
+const CONTROL_MSG_BUFFER_SIZE: usize = 1500;
+const CONTROL_POLL_INTERVAL: Duration = Duration::from_millis(2);
+// 发送历史：最多缓存的分片数，以及分片可被重传的最长时间（超过即错过接收端的解码期限）
+const PACKET_HISTORY_CAPACITY: usize = 4096;
+const RETRANSMISSION_DEADLINE: Duration = Duration::from_millis(300);
+const MAX_RESENDS_PER_PACKET: u8 = 3;
+// 关闭时 Bye 的最多发送次数，以及每次发送后等待 ByeAck 的时间
+const BYE_ATTEMPTS: u32 = 5;
+const BYE_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+// 节流速率是目标码率的这么多倍：关键帧可以比平均码率发得快，但不再是瞬间打满线速
+const PACING_FACTOR: f64 = 2.5;
+// 发送队列积压超过这个时长时开始丢弃还没发出的普通帧
+const PACER_LATENCY_BUDGET: Duration = Duration::from_millis(200);
+// 发送线程在队列为空或令牌不足时的最长休眠时间
+const PACER_POLL_INTERVAL: Duration = Duration::from_millis(1);
 
 // --- 全局状态与缓存 ---
 lazy_static! {
@@ -28,23 +64,74 @@ lazy_static! {
             .expect("Failed to set socket to non-blocking");
         Arc::new(socket)
     };
-    static ref ACKED_FRAMES: Arc<Mutex<HashSet<u32>>> = Arc::new(Mutex::new(HashSet::new()));
-    static ref UNACKED_IFRAMES: Arc<Mutex<HashMap<u32, (Vec<Vec<u8>>, Instant, u8)>>> =
-        Arc::new(Mutex::new(HashMap::new()));
+    static ref PACKET_HISTORY: Arc<Mutex<PacketHistory>> =
+        Arc::new(Mutex::new(PacketHistory::new(
+            PACKET_HISTORY_CAPACITY,
+            RETRANSMISSION_DEADLINE,
+            MAX_RESENDS_PER_PACKET
+        )));
+    // FEC 默认关闭，由 Kotlin 层通过 setFecConfig 开启
+    static ref FEC_CONFIG: Mutex<Option<FecConfig>> = Mutex::new(None);
+    static ref FEC_INTERLEAVER: Mutex<FecInterleaver> = Mutex::new(FecInterleaver::default());
+    // 认证加密默认关闭，由 Kotlin 层通过 setPreSharedKey 配置
+    static ref PACKET_SEALER: Mutex<Option<PacketSealer>> = Mutex::new(None);
+    static ref PACKET_OPENER: Mutex<Option<PacketOpener>> = Mutex::new(None);
+    static ref SESSION: Mutex<Session> = Mutex::new(Session::new());
+    // 根据接收端的 Feedback 计算编码器的目标码率
+    static ref CONGESTION: Mutex<CongestionController> =
+        Mutex::new(CongestionController::new(BitrateLimits::default()));
+    // 路径 MTU 探测，仅在 setPathMtuDiscovery 开启后存在
+    static ref PMTU_PROBER: Mutex<Option<PathMtuProber>> = Mutex::new(None);
+    // 每一路视频流各自的帧号和编解码器，按 stream_id 索引
+    static ref STREAMS: Mutex<HashMap<u8, OutgoingStream>> = Mutex::new(HashMap::new());
+    // 合并关键帧请求，并在关键帧没有得到确认时升级
+    static ref KEY_FRAMES: Mutex<KeyFramePolicy> = Mutex::new(KeyFramePolicy::new());
+    static ref PACER: Mutex<Pacer> = Mutex::new(Pacer::new(
+        pacing_rate(BitrateLimits::default().start_bps),
+        PACER_LATENCY_BUDGET,
+    ));
     static ref THREAD_HANDLES: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
 }
 static JAVA_VM: OnceLock<JavaVM> = OnceLock::new();
 static NATIVE_BRIDGE_CLASS: OnceLock<GlobalRef> = OnceLock::new(); // 新增：存储 NativeBridge 类的全局引用
-static FRAME_COUNTER: AtomicU32 = AtomicU32::new(0);
 static SHUTDOWN_FLAG: AtomicBool = AtomicBool::new(false);
+static AUTH_FAILURES: AtomicU64 = AtomicU64::new(0);
+static AUDIO_SEQUENCE: AtomicU32 = AtomicU32::new(0);
+// 控制线程收到当前会话的 ByeAck 时置位
+static BYE_ACKED: AtomicBool = AtomicBool::new(false);
 static ONCE_INIT: std::sync::Once = std::sync::Once::new();
 
-fn call_request_key_frame_from_native() {
+/// 一路视频流的发送状态。帧号在每路流内独立递增，接收端按 stream_id 分别重组。
+struct OutgoingStream {
+    next_frame_id: u32,
+    codec: VideoCodec,
+    // 最近一次发送的参数集数据报，关键帧得不到确认时重发
+    parameter_sets: Option<Vec<u8>>,
+}
+
+impl OutgoingStream {
+    fn new(codec: VideoCodec) -> Self {
+        OutgoingStream {
+            next_frame_id: 0,
+            codec,
+            parameter_sets: None,
+        }
+    }
+}
+
+/// 通知 Kotlin 层为 `stream_id` 这一路流编码一个关键帧。
+fn call_request_key_frame_from_native(stream_id: u8) {
     if let (Some(vm), Some(class_ref)) = (JAVA_VM.get(), NATIVE_BRIDGE_CLASS.get()) {
         match vm.attach_current_thread() {
             Ok(mut env) => {
                 // 核心修复：直接使用全局类引用 (class_ref) 进行调用，而不是字符串
-                match env.call_static_method(class_ref, "requestKeyFrameFromNative", "()V", &[]) {
+                let stream_id = JValue::Int(stream_id as i32);
+                match env.call_static_method(
+                    class_ref,
+                    "requestKeyFrameFromNative",
+                    "(I)V",
+                    &[stream_id],
+                ) {
                     Ok(_) => logger::info("[JNI] Successfully called requestKeyFrameFromNative."),
                     Err(e) => {
                         logger::error(&format!("[JNI] Failed to call static method: {:?}", e))
@@ -56,6 +143,331 @@ fn call_request_key_frame_from_native() {
     }
 }
 
+/// 通知 Kotlin 层把编码器的码率调整为 `bitrate_bps`。
+fn call_target_bitrate_from_native(bitrate_bps: u32) {
+    if let (Some(vm), Some(class_ref)) = (JAVA_VM.get(), NATIVE_BRIDGE_CLASS.get()) {
+        match vm.attach_current_thread() {
+            Ok(mut env) => {
+                let bitrate = JValue::Int(bitrate_bps.min(i32::MAX as u32) as i32);
+                if let Err(e) =
+                    env.call_static_method(class_ref, "targetBitrateFromNative", "(I)V", &[bitrate])
+                {
+                    logger::error(&format!("[JNI] Failed to call static method: {:?}", e))
+                }
+            }
+            Err(e) => logger::error(&format!("[JNI] Failed to attach current thread: {:?}", e)),
+        }
+    }
+}
+
+/// 目标码率对应的节流速率。
+fn pacing_rate(target_bps: u32) -> u32 {
+    (target_bps as f64 * PACING_FACTOR).min(u32::MAX as f64) as u32
+}
+
+/// 帧是否可能被后续帧参考，由第一个条带 NAL 决定（见 `NalKind::Slice`）；MJPEG 没有帧间参考。
+/// 找不到条带（或不是 Annex-B 格式）时保守地认为是参考帧。
+fn is_reference_frame(codec: VideoCodec, frame: &[u8]) -> bool {
+    if codec == VideoCodec::Mjpeg {
+        return false;
+    }
+    nal::annex_b_units(frame)
+        .find_map(|unit| match nal::nal_kind(codec, unit) {
+            Some(NalKind::Slice { reference, .. }) => Some(reference),
+            _ => None,
+        })
+        .unwrap_or(true)
+}
+
+/// 发送线程：按节流速率从发送队列取出分片发往接收端。
+fn run_pacer(socket: &UdpSocket) {
+    while !SHUTDOWN_FLAG.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        let (packet, next_send_time, key_frames_needed) = {
+            let mut pacer = PACER.lock().unwrap();
+            let packet = pacer.poll(now);
+            (
+                packet,
+                pacer.next_send_time(now),
+                pacer.take_key_frame_requests(),
+            )
+        };
+        if !key_frames_needed.is_empty() {
+            // 丢掉了参考帧，这路流后面的帧在下一个关键帧之前都无法解码
+            let mut key_frames = KEY_FRAMES.lock().unwrap();
+            for stream_id in key_frames_needed {
+                key_frames.on_request(stream_id, now);
+            }
+        }
+        let Some(packet) = packet else {
+            let wait = next_send_time.map_or(PACER_POLL_INTERVAL, |t| t - now);
+            thread::sleep(wait.min(PACER_POLL_INTERVAL));
+            continue;
+        };
+        match send_packet(socket, &packet) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                // 套接字缓冲区满了：放回队首，稍后重试，而不是丢掉这一帧剩下的分片
+                PACER.lock().unwrap().requeue(packet);
+                thread::sleep(PACER_POLL_INTERVAL);
+            }
+            Err(e) => {
+                logger::error(&format!("[PACER] Failed to send UDP packet. Error: {}", e));
+            }
+        }
+    }
+    let stats = *PACER.lock().unwrap().stats();
+    logger::info(&format!(
+        "Pacer thread shutting down. Sent {} packets, dropped {} frames ({} packets) over the latency budget.",
+        stats.packets_sent, stats.frames_dropped, stats.packets_dropped
+    ));
+}
+
+/// 执行关键帧请求策略到期的动作：请求编码器出关键帧，或重发参数集。
+fn service_key_frame_requests(socket: &UdpSocket) {
+    let actions = KEY_FRAMES.lock().unwrap().poll(Instant::now());
+    for action in actions {
+        match action {
+            KeyFrameAction::RequestKeyFrame(stream_id) => {
+                call_request_key_frame_from_native(stream_id);
+            }
+            KeyFrameAction::ResendParameterSets(stream_id) => {
+                let packet = STREAMS
+                    .lock()
+                    .unwrap()
+                    .get(&stream_id)
+                    .and_then(|stream| stream.parameter_sets.clone());
+                if let Some(packet) = packet {
+                    if let Err(e) = send_packet(socket, &packet) {
+                        logger::error(&format!(
+                            "[KEYFRAME] Failed to resend parameter sets of stream {}: {}",
+                            stream_id, e
+                        ));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 向接收端发送一个数据报；配置了预共享密钥时先加密。
+fn send_packet(socket: &UdpSocket, packet: &[u8]) -> std::io::Result<usize> {
+    match PACKET_SEALER.lock().unwrap().as_mut() {
+        Some(sealer) => socket.send_to(&sealer.seal(packet), TARGET_ADDR),
+        None => socket.send_to(packet, TARGET_ADDR),
+    }
+}
+
+/// 加密给每个数据报增加的字节数（未配置预共享密钥时为 0）。
+fn sealing_overhead() -> usize {
+    if PACKET_SEALER.lock().unwrap().is_some() {
+        SECURE_HEADER_SIZE + TAG_SIZE
+    } else {
+        0
+    }
+}
+
+/// 分片时使用的负载大小：握手协商的上限，开启了路径 MTU 探测时还不能超过已确认的路径大小。
+fn current_payload_size() -> usize {
+    let negotiated = SESSION.lock().unwrap().payload_size;
+    match PMTU_PROBER.lock().unwrap().as_ref() {
+        Some(prober) => negotiated.min(payload_size_for_packet(
+            prober.confirmed().saturating_sub(sealing_overhead()),
+        )),
+        None => negotiated,
+    }
+}
+
+/// 在套接字上设置 DF 并忽略内核缓存的路径 MTU（IP_PMTUDISC_PROBE），过大的探测会被丢弃而不是分片；
+/// 关闭时恢复内核的默认行为。
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_path_mtu_probe_mode(socket: &UdpSocket, enabled: bool) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let mode: libc::c_int = if enabled {
+        libc::IP_PMTUDISC_PROBE
+    } else {
+        libc::IP_PMTUDISC_WANT
+    };
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            &mode as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn set_path_mtu_probe_mode(_socket: &UdpSocket, _enabled: bool) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "IP_MTU_DISCOVER is only available on Linux/Android",
+    ))
+}
+
+/// 会话建立且接收端支持时，按路径 MTU 探测的节奏发出 MtuProbe。
+fn probe_path_mtu(socket: &UdpSocket) {
+    {
+        let session = SESSION.lock().unwrap();
+        if session.state != HandshakeState::Established
+            || session.accepted_capabilities & CAP_PMTU_PROBE == 0
+        {
+            return;
+        }
+    }
+    let overhead = sealing_overhead();
+    let mut prober = PMTU_PROBER.lock().unwrap();
+    let Some(prober) = prober.as_mut() else {
+        return;
+    };
+    let Some(probe) = prober.poll(Instant::now()) else {
+        return;
+    };
+    // 探测器按线上的数据报大小计算，MtuProbe 声明的是加密前的大小
+    let datagram_size = probe.size as usize;
+    let packet = Packet::MtuProbe(MtuProbePacket {
+        probe_id: probe.probe_id,
+        size: (datagram_size - overhead) as u16,
+    })
+    .encode();
+    if let Err(e) = send_packet(socket, &packet) {
+        if e.raw_os_error() == Some(libc::EMSGSIZE) {
+            // 本机网卡的 MTU 就已经放不下这个大小
+            prober.on_too_big(datagram_size);
+        } else if e.kind() != std::io::ErrorKind::WouldBlock {
+            logger::warn(&format!("[PMTU] Failed to send probe: {}", e));
+        }
+    }
+}
+
+/// 采集时间戳所在的时钟（Kotlin 端的 `System.currentTimeMillis()`，即 Unix 墙上时钟），单位纳秒。
+/// 回答 ClockPing 时必须用同一个时钟，接收端才能据此换算采集时间戳。
+fn wall_clock_ns() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+/// 本端当前启用的能力，在 Hello 中告知接收端。
+fn local_capabilities() -> u32 {
+    let mut capabilities = CAP_NACK
+        | CAP_CLOCK_SYNC
+        | CAP_HEARTBEAT
+        | CAP_BYE
+        | CAP_CONGESTION_CONTROL
+        | CAP_AUDIO
+        | CAP_METADATA;
+    if FEC_CONFIG.lock().unwrap().is_some() {
+        capabilities |= CAP_FEC;
+    }
+    if PACKET_SEALER.lock().unwrap().is_some() {
+        capabilities |= CAP_ENCRYPTION;
+    }
+    if PMTU_PROBER.lock().unwrap().is_some() {
+        capabilities |= CAP_PMTU_PROBE;
+    }
+    capabilities
+}
+
+/// 到期时发送 Hello：握手完成前快速重试，完成后低频保活。
+fn send_hello_if_due(socket: &UdpSocket) {
+    let capabilities = local_capabilities();
+    let hello = {
+        let mut session = SESSION.lock().unwrap();
+        session
+            .poll_hello(Instant::now())
+            .then(|| session.hello_packet(capabilities))
+    };
+    if let Some(hello) = hello {
+        if let Err(e) = send_packet(socket, &hello) {
+            logger::warn(&format!("[HANDSHAKE] Failed to send Hello: {}", e));
+        }
+    }
+}
+
+/// 推进连接状态，并在会话建立后按间隔向接收端发送 Heartbeat。
+fn poll_connection(socket: &UdpSocket) {
+    let now = Instant::now();
+    let heartbeat_due = {
+        let mut session = SESSION.lock().unwrap();
+        session.poll_connection(now);
+        session.accepted_capabilities & CAP_HEARTBEAT != 0 && session.connection.heartbeat_due(now)
+    };
+    if heartbeat_due {
+        if let Err(e) = send_packet(socket, &Packet::Heartbeat.encode()) {
+            logger::warn(&format!("[CONNECTION] Failed to send heartbeat: {}", e));
+        }
+    }
+}
+
+/// 告诉接收端本会话结束，重发直到收到 ByeAck 或次数用完。
+/// 必须在控制线程退出之前调用，ByeAck 由控制线程接收。
+fn say_goodbye(socket: &UdpSocket, reason: ByeReason) {
+    let bye = {
+        let session = SESSION.lock().unwrap();
+        if session.state != HandshakeState::Established
+            || session.accepted_capabilities & CAP_BYE == 0
+        {
+            return;
+        }
+        Packet::Bye(ByePacket {
+            session_id: session.session_id,
+            reason: reason as u8,
+        })
+        .encode()
+    };
+    BYE_ACKED.store(false, Ordering::Relaxed);
+    for attempt in 1..=BYE_ATTEMPTS {
+        if let Err(e) = send_packet(socket, &bye) {
+            logger::warn(&format!("[BYE] Failed to send Bye: {}", e));
+        }
+        let deadline = Instant::now() + BYE_RETRY_INTERVAL;
+        while Instant::now() < deadline {
+            if BYE_ACKED.load(Ordering::Relaxed) {
+                logger::info(&format!(
+                    "[BYE] Receiver acknowledged Bye ({:?}) after {} attempt(s).",
+                    reason, attempt
+                ));
+                return;
+            }
+            thread::sleep(CONTROL_POLL_INTERVAL);
+        }
+    }
+    logger::warn(&format!(
+        "[BYE] No ByeAck after {} attempts, the receiver will time out on its own.",
+        BYE_ATTEMPTS
+    ));
+}
+
+/// 按 NACK 列表从发送历史中取出分片并重传，超出期限或已被淘汰的分片直接跳过。
+fn resend_nacked_packets(history: &Mutex<PacketHistory>, nack: &NackPacket) {
+    let mut history = history.lock().unwrap();
+    let mut pacer = PACER.lock().unwrap();
+    let mut resent = 0usize;
+    for &packet_id in &nack.packet_ids {
+        if let Some(packet_data) = history.take_for_resend(nack.stream_id, nack.frame_id, packet_id)
+        {
+            pacer.enqueue_retransmission(packet_data.to_vec());
+            resent += 1;
+        }
+    }
+    if resent < nack.packet_ids.len() {
+        logger::warn(&format!(
+            "[NACK] Frame #{} of stream {}: resent {}/{} packets, the rest expired.",
+            nack.frame_id,
+            nack.stream_id,
+            resent,
+            nack.packet_ids.len()
+        ));
+    }
+}
+
 #[no_mangle]
 pub extern "system" fn Java_com_neurocam_NativeBridge_init(mut env: JNIEnv, _class: JClass) {
     if JAVA_VM.get().is_none() {
@@ -83,103 +495,195 @@ pub extern "system" fn Java_com_neurocam_NativeBridge_init(mut env: JNIEnv, _cla
         logger::info("Performing first-time initialization of NativeBridge...");
 
         let socket_for_control = Arc::clone(&UDP_SOCKET);
-        let acked_frames_for_control = Arc::clone(&ACKED_FRAMES);
+        let history_for_control = Arc::clone(&PACKET_HISTORY);
         let control_listener = thread::spawn(move || {
             let mut buf = [0u8; CONTROL_MSG_BUFFER_SIZE];
             while !SHUTDOWN_FLAG.load(Ordering::Relaxed) {
+                send_hello_if_due(&socket_for_control);
+                poll_connection(&socket_for_control);
+                probe_path_mtu(&socket_for_control);
+                service_key_frame_requests(&socket_for_control);
                 if let Ok((len, _)) = socket_for_control.recv_from(&mut buf) {
                     if len == 0 {
                         continue;
                     }
-                    match PacketType::try_from(buf[0]) {
-                        Ok(PacketType::Ack) => {
-                            if let Some(ack) = AckPacket::from_bytes(&buf[1..len]) {
-                                acked_frames_for_control
-                                    .lock()
-                                    .unwrap()
-                                    .insert(ack.frame_id);
+                    let plaintext;
+                    let buf = match PACKET_OPENER.lock().unwrap().as_mut() {
+                        Some(opener) => match opener.open(&buf[..len]) {
+                            Ok(packet) => {
+                                plaintext = packet;
+                                plaintext.as_slice()
                             }
+                            Err(e) => {
+                                // 未通过认证的包一律丢弃，绝不当作控制消息处理
+                                let total = AUTH_FAILURES.fetch_add(1, Ordering::Relaxed) + 1;
+                                logger::warn(&format!(
+                                    "[SECURITY] Dropped unauthenticated control packet ({:?}). Total dropped: {}",
+                                    e, total
+                                ));
+                                continue;
+                            }
+                        },
+                        None => &buf[..len],
+                    };
+                    let packet = match Packet::decode(buf) {
+                        Ok(packet) => packet,
+                        Err(ProtocolError::BadVersion(version))
+                            if buf[0] == PacketType::HelloAck as u8 =>
+                        {
+                            // 版本不兼容的 HelloAck 仍然交给会话处理，由它停止推流并记录原因。
+                            // 其他版本的 HelloAck 长度可能不同，只能依靠各版本共同的开头
+                            if let Some(session_id) = handshake_session_id(&buf[1..]) {
+                                SESSION.lock().unwrap().handle_hello_ack(&HelloAckPacket {
+                                    magic: PROTOCOL_MAGIC,
+                                    version,
+                                    session_id,
+                                    status: HelloStatus::VersionMismatch as u8,
+                                    capabilities: 0,
+                                    connection_id: 0,
+                                    max_payload_size: 0,
+                                });
+                            }
+                            continue;
                         }
-                        Ok(PacketType::IFrameRequest) => {
-                            logger::info("[CONTROL] Received I-Frame Request from receiver.");
-                            call_request_key_frame_from_native();
+                        Err(e) => {
+                            logger::warn(&format!(
+                                "[PROTOCOL] Rejected control datagram: {}",
+                                e
+                            ));
+                            continue;
                         }
-                        _ => { /* Ignore Data packets or unknown types */ }
-                    }
-                }
-                thread::sleep(Duration::from_millis(10));
-            }
-            logger::info("Control listener thread shutting down.");
-        });
-
-        let socket_for_retry = Arc::clone(&UDP_SOCKET);
-        let acked_frames_for_retry = Arc::clone(&ACKED_FRAMES);
-        let unacked_iframes_for_retry = Arc::clone(&UNACKED_IFRAMES);
-        let retransmitter = thread::spawn(move || {
-            while !SHUTDOWN_FLAG.load(Ordering::Relaxed) {
-                let ids_to_process: Vec<u32> = {
-                    let mut acked_set = acked_frames_for_retry.lock().unwrap();
-                    acked_set.drain().collect()
-                };
-
-                if !ids_to_process.is_empty() {
-                    let mut unacked_cache = unacked_iframes_for_retry.lock().unwrap();
-                    for id in ids_to_process {
-                        if unacked_cache.remove(&id).is_some() {
-                            logger::info(&format!("[ACK OK] Frame #{} confirmed.", id));
+                    };
+                    SESSION.lock().unwrap().on_receiver_heard(Instant::now());
+                    match packet {
+                        Packet::Ack(ack) => {
+                            logger::info(&format!(
+                                "[ACK OK] Frame #{} of stream {} confirmed.",
+                                ack.frame_id, ack.stream_id
+                            ));
+                            KEY_FRAMES
+                                .lock()
+                                .unwrap()
+                                .on_ack(ack.stream_id, ack.frame_id);
                         }
-                    }
-                }
-
-                let mut retransmit_ids = Vec::new();
-                let mut drop_ids = Vec::new();
-                if let Ok(mut unacked_cache) = unacked_iframes_for_retry.lock() {
-                    for (frame_id, (_, sent_at, retries)) in unacked_cache.iter_mut() {
-                        if sent_at.elapsed() > RETRANSMISSION_TIMEOUT {
-                            if *retries < MAX_RETRIES {
-                                logger::warn(&format!(
-                                    "[RETRY] Frame #{} timed out (attempt {}).",
-                                    frame_id,
-                                    *retries + 1
+                        Packet::Nack(nack) => {
+                            resend_nacked_packets(&history_for_control, &nack);
+                        }
+                        Packet::HelloAck(ack) => {
+                            SESSION.lock().unwrap().handle_hello_ack(&ack);
+                        }
+                        Packet::PathChallenge(challenge) => {
+                            // 接收端在验证我们的新地址：原样回显令牌
+                            logger::info("[MIGRATION] Answering path challenge from receiver.");
+                            let _ = send_packet(
+                                &socket_for_control,
+                                &Packet::PathResponse(challenge).encode(),
+                            );
+                        }
+                        Packet::ClockPing(ping) => {
+                            let receive_ns = wall_clock_ns();
+                            let pong = ClockPongPacket {
+                                origin_ns: ping.origin_ns,
+                                receive_ns,
+                                transmit_ns: wall_clock_ns(),
+                            };
+                            let _ =
+                                send_packet(&socket_for_control, &Packet::ClockPong(pong).encode());
+                        }
+                        Packet::Heartbeat => {}
+                        Packet::Feedback(feedback) => {
+                            let target = {
+                                let mut controller = CONGESTION.lock().unwrap();
+                                let target = controller
+                                    .on_feedback(&feedback, Instant::now())
+                                    .map(|bps| (bps, controller.usage()));
+                                PACER
+                                    .lock()
+                                    .unwrap()
+                                    .set_rate(pacing_rate(controller.target_bps()));
+                                target
+                            };
+                            if let Some((bitrate_bps, usage)) = target {
+                                logger::info(&format!(
+                                    "[CONGESTION] Target bitrate -> {} kbps ({:?}; receiver sees {} kbps, loss {}/256, delay gradient {} us).",
+                                    bitrate_bps / 1000,
+                                    usage,
+                                    feedback.receive_rate_bps / 1000,
+                                    feedback.loss_fraction,
+                                    feedback.delay_gradient_us
                                 ));
-                                *sent_at = Instant::now();
-                                *retries += 1;
-                                retransmit_ids.push(*frame_id);
-                            } else {
-                                logger::error(&format!(
-                                    "[GIVE UP] Frame #{} exceeded max retries.",
-                                    frame_id
+                                call_target_bitrate_from_native(bitrate_bps);
+                            }
+                        }
+                        Packet::MtuProbeAck(ack) => {
+                            let confirmed = PMTU_PROBER
+                                .lock()
+                                .unwrap()
+                                .as_mut()
+                                .and_then(|prober| prober.on_ack(&ack).then(|| prober.confirmed()));
+                            if let Some(datagram_size) = confirmed {
+                                logger::info(&format!(
+                                    "[PMTU] Path confirmed for {} byte datagrams, fragment payload now {} bytes.",
+                                    datagram_size,
+                                    current_payload_size()
                                 ));
-                                drop_ids.push(*frame_id);
                             }
                         }
-                    }
-
-                    for id in &retransmit_ids {
-                        if let Some((packets, _, _)) = unacked_cache.get(id) {
-                            for packet_data in packets {
-                                let _ = socket_for_retry.send_to(packet_data, TARGET_ADDR);
+                        Packet::ByeAck(ack) => {
+                            if ack.session_id == SESSION.lock().unwrap().session_id {
+                                BYE_ACKED.store(true, Ordering::Relaxed);
                             }
                         }
+                        Packet::IFrameRequest { stream_id } => {
+                            logger::info(&format!(
+                                "[CONTROL] Received I-Frame Request for stream {} from receiver.",
+                                stream_id
+                            ));
+                            KEY_FRAMES
+                                .lock()
+                                .unwrap()
+                                .on_request(stream_id, Instant::now());
+                        }
+                        Packet::Encrypted(_) => {
+                            logger::warn(
+                                "[SECURITY] Received an encrypted packet but no pre-shared key is configured.",
+                            );
+                        }
+                        other => {
+                            logger::warn(&format!(
+                                "[PROTOCOL] Unexpected {:?} packet from receiver, ignored.",
+                                other.packet_type()
+                            ));
+                        }
                     }
-
-                    for id in drop_ids {
-                        unacked_cache.remove(&id);
-                    }
+                    // 收到包后立即尝试读取下一个，NACK 需要尽快得到响应
+                    continue;
                 }
-                thread::sleep(Duration::from_millis(200));
+                thread::sleep(CONTROL_POLL_INTERVAL);
             }
-            logger::info("Retransmission thread shutting down.");
+            let stats = *KEY_FRAMES.lock().unwrap().stats();
+            logger::info(&format!(
+                "Control listener thread shutting down. Key frames: {} requests ({} coalesced), {} encoder requests, {} sent, {} acked, {} escalations.",
+                stats.requests_received,
+                stats.requests_coalesced,
+                stats.encoder_requests,
+                stats.key_frames_sent,
+                stats.key_frames_acked,
+                stats.escalations
+            ));
         });
 
         let mut handles = THREAD_HANDLES.lock().unwrap();
         handles.push(control_listener);
-        handles.push(retransmitter);
-        logger::info("Background threads for control and retransmission have been started.");
+
+        let socket_for_pacer = Arc::clone(&UDP_SOCKET);
+        handles.push(thread::spawn(move || run_pacer(&socket_for_pacer)));
+        logger::info("Background control and pacer threads have been started.");
     });
     logger::info("Rust NativeBridge_init call completed.");
 }
 
+/// 分片并发送一帧视频。返回分配给它的帧号，`sendFrameMetadata` 用它把元数据关联到这一帧；帧被丢弃时返回 -1。
 #[no_mangle]
 pub extern "system" fn Java_com_neurocam_NativeBridge_sendVideoFrame(
     _env: JNIEnv,
@@ -189,60 +693,279 @@ pub extern "system" fn Java_com_neurocam_NativeBridge_sendVideoFrame(
     is_key_frame: jboolean,
 
     capture_timestamp_ns: jni::sys::jlong, // 新增时间戳参数
-) {
+    stream_id: jni::sys::jint,
+) -> jni::sys::jlong {
     let Ok(data_ptr) = _env.get_direct_buffer_address(&frame_buffer) else {
         logger::error("[Rust] Failed to get direct buffer address.");
-        return;
+        return -1;
+    };
+    let Ok(stream_id) = u8::try_from(stream_id) else {
+        logger::error(&format!("[Rust] Invalid stream id {}.", stream_id));
+        return -1;
+    };
+    let (connection_id, session_codec) = {
+        let session = SESSION.lock().unwrap();
+        if session.state == HandshakeState::Rejected {
+            // 接收端不兼容，继续发送只会被丢弃
+            return -1;
+        }
+        (session.connection_id, session.codec)
     };
     let data_slice = unsafe { std::slice::from_raw_parts(data_ptr, size as usize) };
-    let frame_id = FRAME_COUNTER.fetch_add(1, Ordering::Relaxed);
-    let chunks: Vec<&[u8]> = data_slice.chunks(MAX_PAYLOAD_SIZE).collect();
-    let total_packets = chunks.len() as u16;
-
-    let mut packets_to_cache = if is_key_frame != 0 {
-        Vec::with_capacity(chunks.len())
-    } else {
-        Vec::new()
+    if data_slice.len() > MAX_FRAME_SIZE {
+        // 接收端会拒绝超过协议上限的帧，发出去只是浪费带宽
+        logger::error(&format!(
+            "[Rust] Frame of {} bytes exceeds the {} byte protocol limit, dropped.",
+            data_slice.len(),
+            MAX_FRAME_SIZE
+        ));
+        return -1;
+    }
+    let (frame_id, codec) = {
+        let mut streams = STREAMS.lock().unwrap();
+        // 还没有发过参数集的流沿用握手中声明的编解码器
+        let stream = streams
+            .entry(stream_id)
+            .or_insert_with(|| OutgoingStream::new(session_codec));
+        let frame_id = stream.next_frame_id;
+        stream.next_frame_id = stream.next_frame_id.wrapping_add(1);
+        (frame_id, stream.codec)
     };
+    let chunks: Vec<&[u8]> = data_slice.chunks(current_payload_size()).collect();
+    let total_packets = chunks.len() as u16;
 
+    let mut data_packets = Vec::with_capacity(chunks.len());
     for (i, chunk) in chunks.iter().enumerate() {
         let header = DataHeader {
+            connection_id,
             frame_id,
             capture_timestamp_ns: capture_timestamp_ns as u64,
             packet_id: i as u16,
             total_packets,
-            is_key_frame: is_key_frame as u8,
+            is_key_frame,
+            stream_id,
         };
 
-        let mut packet_data = Vec::with_capacity(1 + DATA_HEADER_SIZE + chunk.len());
-        packet_data.push(PacketType::Data as u8);
-        packet_data.extend_from_slice(&header.to_bytes());
-        packet_data.extend_from_slice(chunk);
+        let packet_data = Packet::Data {
+            header,
+            payload: chunk,
+        }
+        .encode();
+
+        // 先记入历史再发送，发送失败的分片也可以由接收端的 NACK 补回
+        if let Ok(mut history) = PACKET_HISTORY.lock() {
+            history.insert(stream_id, frame_id, header.packet_id, packet_data.clone());
+        }
+        data_packets.push(packet_data);
+    }
+
+    let template = FecHeader {
+        connection_id,
+        frame_id,
+        capture_timestamp_ns: capture_timestamp_ns as u64,
+        total_packets,
+        is_key_frame,
+        scheme: 0,
+        group_index: 0,
+        group_count: 0,
+        parity_index: 0,
+        parity_count: 0,
+        stream_id,
+    };
+    let fec_config = *FEC_CONFIG.lock().unwrap();
+    let outgoing = match fec_config {
+        Some(config) => {
+            let parity_packets = match fec::encode_frame(&config, &template, &chunks) {
+                Ok(parity) => parity
+                    .into_iter()
+                    .map(|(header, shard)| {
+                        Packet::Fec {
+                            header,
+                            shard: &shard,
+                        }
+                        .encode()
+                    })
+                    .collect(),
+                Err(e) => {
+                    logger::error(&format!(
+                        "[FEC] Failed to encode frame #{}: {:?}",
+                        frame_id, e
+                    ));
+                    Vec::new()
+                }
+            };
+            FEC_INTERLEAVER
+                .lock()
+                .unwrap()
+                .schedule(&config, data_packets, parity_packets)
+        }
+        None => data_packets,
+    };
+
+    if is_key_frame != 0 {
+        KEY_FRAMES
+            .lock()
+            .unwrap()
+            .on_key_frame_sent(stream_id, frame_id, Instant::now());
+    }
+    // 交给发送线程按节流速率发出
+    PACER.lock().unwrap().enqueue_frame(
+        stream_id,
+        frame_id,
+        is_key_frame != 0,
+        is_key_frame != 0 || is_reference_frame(codec, data_slice),
+        outgoing,
+    );
+    frame_id as jni::sys::jlong
+}
 
-        if let Err(e) = UDP_SOCKET.send_to(&packet_data, TARGET_ADDR) {
-            logger::error(&format!("[Rust] Failed to send UDP packet. Error: {}", e));
+/// 发送一个编码后的音频帧（Opus 或不带 ADTS 头的 AAC-LC）。`capture_timestamp_ns` 是帧中第一个采样的
+/// 采集时刻，与 `sendVideoFrame` 使用同一个时钟，接收端据此对齐音画。
+/// 音频帧不分片、不做 FEC 和重传；接收端没有在握手中接受 `CAP_AUDIO` 时直接丢弃。
+#[no_mangle]
+pub extern "system" fn Java_com_neurocam_NativeBridge_sendAudioFrame(
+    env: JNIEnv,
+    _class: JClass,
+    frame_buffer: JByteBuffer,
+    size: jni::sys::jint,
+    codec: jni::sys::jint,
+    sample_rate: jni::sys::jint,
+    channels: jni::sys::jint,
+    capture_timestamp_ns: jni::sys::jlong,
+) {
+    let Ok(data_ptr) = env.get_direct_buffer_address(&frame_buffer) else {
+        logger::error("[Rust] Failed to get direct buffer address.");
+        return;
+    };
+    let connection_id = {
+        let session = SESSION.lock().unwrap();
+        if session.accepted_capabilities & CAP_AUDIO == 0 {
+            // 握手还没完成，或者接收端没有配置音频输出
             return;
         }
+        session.connection_id
+    };
+    let header = AudioHeader {
+        connection_id,
+        sequence: AUDIO_SEQUENCE.fetch_add(1, Ordering::Relaxed),
+        capture_timestamp_ns: capture_timestamp_ns as u64,
+        sample_rate: sample_rate.max(0) as u32,
+        codec: codec.clamp(0, u8::MAX as i32) as u8,
+        channels: channels.clamp(0, u8::MAX as i32) as u8,
+    };
+    let size = size.max(0) as usize;
+    if let Err(field) = check_audio_header(&header, size) {
+        logger::error(&format!(
+            "[AUDIO] Invalid audio frame ({} bytes, {:?}): bad `{}`, dropped.",
+            size, header, field
+        ));
+        return;
+    }
+    let payload = unsafe { std::slice::from_raw_parts(data_ptr, size) };
+    PACER
+        .lock()
+        .unwrap()
+        .enqueue_urgent(Packet::Audio { header, payload }.encode());
+}
 
-        if is_key_frame != 0 {
-            packets_to_cache.push(packet_data);
+/// 发送一帧视频的元数据（相机内参、曝光、姿态、IMU/GPS 样本等），按 `metadata` 模块的条目格式编码。
+/// `frame_id` 是 `sendVideoFrame` 的返回值。接收端没有在握手中接受 `CAP_METADATA` 时直接丢弃。
+#[no_mangle]
+pub extern "system" fn Java_com_neurocam_NativeBridge_sendFrameMetadata(
+    env: JNIEnv,
+    _class: JClass,
+    stream_id: jni::sys::jint,
+    frame_id: jni::sys::jlong,
+    capture_timestamp_ns: jni::sys::jlong,
+    entries: JByteArray,
+    size: jni::sys::jint,
+) {
+    let (Ok(stream_id), Ok(frame_id)) = (u8::try_from(stream_id), u32::try_from(frame_id)) else {
+        logger::error(&format!(
+            "[Rust] Invalid metadata target: stream {}, frame {}.",
+            stream_id, frame_id
+        ));
+        return;
+    };
+    let connection_id = {
+        let session = SESSION.lock().unwrap();
+        if session.accepted_capabilities & CAP_METADATA == 0 {
+            // 握手还没完成，或者接收端没有配置元数据的输出
+            return;
         }
+        session.connection_id
+    };
+    let Ok(data) = env.convert_byte_array(entries) else {
+        logger::error("[Rust] Failed to read frame metadata from Java.");
+        return;
+    };
+    let entries = &data[..(size.max(0) as usize).min(data.len())];
+    if let Err(field) = metadata::decode_entries(entries) {
+        logger::error(&format!(
+            "[METADATA] Invalid metadata for frame #{} of stream {} ({} bytes): bad `{}`, dropped.",
+            frame_id,
+            stream_id,
+            entries.len(),
+            field
+        ));
+        return;
     }
+    let header = MetadataHeader {
+        connection_id,
+        frame_id,
+        capture_timestamp_ns: capture_timestamp_ns as u64,
+        stream_id,
+    };
+    PACER
+        .lock()
+        .unwrap()
+        .enqueue_urgent(Packet::Metadata { header, entries }.encode());
+}
 
-    if is_key_frame != 0 {
-        if let Ok(mut unacked_frames) = UNACKED_IFRAMES.lock() {
-            unacked_frames.insert(frame_id, (packets_to_cache, Instant::now(), 0));
+/// 配置前向纠错。`scheme` 为负数时关闭 FEC，0 为异或，1 为 Reed-Solomon。
+#[no_mangle]
+pub extern "system" fn Java_com_neurocam_NativeBridge_setFecConfig(
+    _env: JNIEnv,
+    _class: JClass,
+    scheme: jni::sys::jint,
+    redundancy: jni::sys::jfloat,
+    interleave_depth: jni::sys::jint,
+    cross_frame_interleave: jboolean,
+) {
+    let config = if scheme < 0 {
+        None
+    } else {
+        let Ok(scheme) = FecScheme::try_from(scheme as u8) else {
+            logger::error(&format!("[FEC] Unknown FEC scheme {}.", scheme));
+            return;
+        };
+        Some(FecConfig {
+            scheme,
+            redundancy: redundancy.clamp(0.0, 1.0),
+            interleave_depth: interleave_depth.clamp(1, u8::MAX as i32) as u8,
+            cross_frame_interleave: cross_frame_interleave != 0,
+        })
+    };
+    logger::info(&format!("[FEC] Config updated: {:?}", config));
+    let previous = std::mem::replace(&mut *FEC_CONFIG.lock().unwrap(), config);
+    if previous.is_some_and(|c| c.cross_frame_interleave) {
+        // 跨帧交织暂存的校验分片属于旧配置，直接补发出去
+        for packet_data in FEC_INTERLEAVER.lock().unwrap().flush() {
+            let _ = send_packet(&UDP_SOCKET, &packet_data);
         }
     }
 }
 
 #[no_mangle]
 pub extern "system" fn Java_com_neurocam_NativeBridge_close(_env: JNIEnv, _class: JClass) {
-    logger::info("NativeBridge_close called. Signaling threads to shut down...");
+    logger::info("NativeBridge_close called. Saying goodbye to the receiver...");
+    say_goodbye(&UDP_SOCKET, ByeReason::Shutdown);
+
+    logger::info("Signaling threads to shut down...");
     SHUTDOWN_FLAG.store(true, Ordering::Relaxed);
 
     logger::info("Waiting for a graceful shutdown...");
-    thread::sleep(RETRANSMISSION_TIMEOUT + Duration::from_millis(100));
+    thread::sleep(CONTROL_POLL_INTERVAL * 10);
 
     let mut handles = THREAD_HANDLES.lock().unwrap();
     while let Some(handle) = handles.pop() {
@@ -258,20 +981,278 @@ pub extern "system" fn Java_com_neurocam_NativeBridge_close(_env: JNIEnv, _class
     // 因此，此处无需也无法手动删除。
 }
 
-use jni::objects::JByteArray;
-
 #[no_mangle]
 pub extern "system" fn Java_com_neurocam_NativeBridge_sendSpsPps(
     env: JNIEnv,
     _class: JClass,
-    buffer: jni::sys::jbyteArray,
+    buffer: JByteArray,
     size: jni::sys::jint,
 ) {
-    let size = size as usize;
-    let jarray = unsafe { JByteArray::from_raw(buffer) }; // 关键：用 from_raw
-    let spspps = env.convert_byte_array(jarray).unwrap();
-    let mut packet = Vec::with_capacity(1 + size);
-    packet.push(PacketType::SpsPps as u8);
-    packet.extend_from_slice(&spspps[..size]);
-    let _ = UDP_SOCKET.send_to(&packet, TARGET_ADDR);
+    let Ok(spspps) = env.convert_byte_array(buffer) else {
+        logger::error("[Rust] Failed to read SPS/PPS from Java.");
+        return;
+    };
+    let size = (size.max(0) as usize).min(spspps.len());
+    let packet = Packet::SpsPps(&spspps[..size]).encode();
+    let session_codec = SESSION.lock().unwrap().codec;
+    STREAMS
+        .lock()
+        .unwrap()
+        .entry(0)
+        .or_insert_with(|| OutgoingStream::new(session_codec))
+        .parameter_sets = Some(packet.clone());
+    let _ = send_packet(&UDP_SOCKET, &packet);
+}
+
+/// 发送一路流的参数集（Annex-B 格式，H.264 为 SPS/PPS，H.265 为 VPS/SPS/PPS），同时声明它的编解码器。
+/// MJPEG 没有参数集，传入空数组即可。
+#[no_mangle]
+pub extern "system" fn Java_com_neurocam_NativeBridge_sendParameterSets(
+    env: JNIEnv,
+    _class: JClass,
+    stream_id: jni::sys::jint,
+    codec: jni::sys::jint,
+    buffer: JByteArray,
+    size: jni::sys::jint,
+) {
+    let Ok(stream_id) = u8::try_from(stream_id) else {
+        logger::error(&format!("[Rust] Invalid stream id {}.", stream_id));
+        return;
+    };
+    let Ok(codec) = u8::try_from(codec)
+        .map_err(|_| ())
+        .and_then(VideoCodec::try_from)
+    else {
+        logger::error(&format!("[Rust] Unknown codec {}.", codec));
+        return;
+    };
+    let Ok(data) = env.convert_byte_array(buffer) else {
+        logger::error("[Rust] Failed to read parameter sets from Java.");
+        return;
+    };
+    let size = (size.max(0) as usize).min(data.len());
+    let packet = Packet::ParameterSets {
+        stream_id,
+        codec,
+        data: &data[..size],
+    }
+    .encode();
+    let mut streams = STREAMS.lock().unwrap();
+    let stream = streams
+        .entry(stream_id)
+        .or_insert_with(|| OutgoingStream::new(codec));
+    stream.codec = codec;
+    stream.parameter_sets = Some(packet.clone());
+    drop(streams);
+    let _ = send_packet(&UDP_SOCKET, &packet);
+}
+
+/// 配置预共享密钥，开启认证加密。传入空数组则关闭加密。
+#[no_mangle]
+pub extern "system" fn Java_com_neurocam_NativeBridge_setPreSharedKey(
+    env: JNIEnv,
+    _class: JClass,
+    key: JByteArray,
+) {
+    let Ok(key) = env.convert_byte_array(key) else {
+        logger::error("[SECURITY] Failed to read pre-shared key from Java.");
+        return;
+    };
+    if key.is_empty() {
+        *PACKET_SEALER.lock().unwrap() = None;
+        *PACKET_OPENER.lock().unwrap() = None;
+        logger::warn("[SECURITY] Pre-shared key cleared, stream is now sent in cleartext.");
+        return;
+    }
+    let Ok(key) = <[u8; KEY_SIZE]>::try_from(key.as_slice()) else {
+        logger::error(&format!(
+            "[SECURITY] Pre-shared key must be {} bytes, got {}.",
+            KEY_SIZE,
+            key.len()
+        ));
+        return;
+    };
+    *PACKET_SEALER.lock().unwrap() = Some(PacketSealer::new(&key));
+    *PACKET_OPENER.lock().unwrap() = Some(PacketOpener::new(&key));
+    logger::info("[SECURITY] Pre-shared key configured, stream is now encrypted.");
+}
+
+/// 告知 Rust 层第 0 路（主）流的编码参数，并立即发送一次携带新参数的 Hello。
+/// `codec` 取 `VideoCodec` 的取值：0 为 H.264，1 为 H.265，2 为 MJPEG。
+/// 其余的流通过 `sendParameterSets` 声明各自的编解码器。
+#[no_mangle]
+pub extern "system" fn Java_com_neurocam_NativeBridge_configureStream(
+    _env: JNIEnv,
+    _class: JClass,
+    width: jni::sys::jint,
+    height: jni::sys::jint,
+    fps: jni::sys::jint,
+    codec: jni::sys::jint,
+) {
+    let Ok(codec) = u8::try_from(codec)
+        .map_err(|_| ())
+        .and_then(VideoCodec::try_from)
+    else {
+        logger::error(&format!("[HANDSHAKE] Unknown codec {}.", codec));
+        return;
+    };
+    let capabilities = local_capabilities();
+    let hello = {
+        let mut session = SESSION.lock().unwrap();
+        session.codec = codec;
+        session.width = width.clamp(0, u16::MAX as i32) as u16;
+        session.height = height.clamp(0, u16::MAX as i32) as u16;
+        session.fps = fps.clamp(0, u8::MAX as i32) as u8;
+        logger::info(&format!(
+            "[HANDSHAKE] Stream configured: {:?} {}x{}@{}fps, session {:016x}.",
+            session.codec, session.width, session.height, session.fps, session.session_id
+        ));
+        session.hello_packet(capabilities)
+    };
+    if let Some(stream) = STREAMS.lock().unwrap().get_mut(&0) {
+        stream.codec = codec;
+    }
+    let _ = send_packet(&UDP_SOCKET, &hello);
+}
+
+/// 配置连接状态机的超时（毫秒）：Heartbeat 间隔、判定卡顿和判定断开的沉默时间。
+#[no_mangle]
+pub extern "system" fn Java_com_neurocam_NativeBridge_setConnectionTimeouts(
+    _env: JNIEnv,
+    _class: JClass,
+    heartbeat_interval_ms: jni::sys::jint,
+    stall_timeout_ms: jni::sys::jint,
+    lost_timeout_ms: jni::sys::jint,
+) {
+    if heartbeat_interval_ms <= 0 || stall_timeout_ms <= 0 || lost_timeout_ms <= stall_timeout_ms {
+        logger::error(&format!(
+            "[CONNECTION] Invalid timeouts: heartbeat {}ms, stall {}ms, lost {}ms. The lost timeout must exceed the stall timeout.",
+            heartbeat_interval_ms, stall_timeout_ms, lost_timeout_ms
+        ));
+        return;
+    }
+    let timeouts = ConnectionTimeouts {
+        heartbeat_interval: Duration::from_millis(heartbeat_interval_ms as u64),
+        stall_timeout: Duration::from_millis(stall_timeout_ms as u64),
+        lost_timeout: Duration::from_millis(lost_timeout_ms as u64),
+    };
+    logger::info(&format!("[CONNECTION] Timeouts updated: {:?}", timeouts));
+    SESSION.lock().unwrap().connection.set_timeouts(timeouts);
+}
+
+/// 配置拥塞控制的码率范围（比特/秒），目标码率回到 `start_bps`。
+/// `start_bps` 应当与编码器当前的码率一致。
+#[no_mangle]
+pub extern "system" fn Java_com_neurocam_NativeBridge_setBitrateLimits(
+    _env: JNIEnv,
+    _class: JClass,
+    min_bps: jni::sys::jint,
+    start_bps: jni::sys::jint,
+    max_bps: jni::sys::jint,
+) {
+    if min_bps <= 0 || min_bps > start_bps || start_bps > max_bps {
+        logger::error(&format!(
+            "[CONGESTION] Invalid bitrate limits: min {}, start {}, max {}. Expected 0 < min <= start <= max.",
+            min_bps, start_bps, max_bps
+        ));
+        return;
+    }
+    let limits = BitrateLimits {
+        min_bps: min_bps as u32,
+        start_bps: start_bps as u32,
+        max_bps: max_bps as u32,
+    };
+    logger::info(&format!(
+        "[CONGESTION] Bitrate limits updated: {:?}",
+        limits
+    ));
+    CONGESTION.lock().unwrap().set_limits(limits);
+    PACER
+        .lock()
+        .unwrap()
+        .set_rate(pacing_rate(limits.start_bps));
+}
+
+/// 设置在 Hello 中请求的最大分片负载（字节），实际大小由接收端在握手中决定。
+/// 超过路径 MTU 的负载会被 IP 分片，只有在确认链路支持时才应调大，或者同时开启路径 MTU 探测。
+#[no_mangle]
+pub extern "system" fn Java_com_neurocam_NativeBridge_setMaxPayloadSize(
+    _env: JNIEnv,
+    _class: JClass,
+    max_payload_size: jni::sys::jint,
+) {
+    let size = (max_payload_size.max(0) as usize).clamp(MIN_PAYLOAD_SIZE, MAX_PAYLOAD_SIZE);
+    logger::info(&format!(
+        "[MTU] Requesting fragment payloads of up to {} bytes.",
+        size
+    ));
+    let capabilities = local_capabilities();
+    let hello = {
+        let mut session = SESSION.lock().unwrap();
+        session.max_payload_size = size as u16;
+        session.payload_size = session.payload_size.min(size);
+        session.hello_packet(capabilities)
+    };
+    let _ = send_packet(&UDP_SOCKET, &hello);
+}
+
+/// 开启或关闭路径 MTU 探测。开启后套接字上的数据报都带 DF 标志，分片大小不会超过探测确认的路径大小。
+#[no_mangle]
+pub extern "system" fn Java_com_neurocam_NativeBridge_setPathMtuDiscovery(
+    _env: JNIEnv,
+    _class: JClass,
+    enabled: jboolean,
+) {
+    let enabled = enabled != 0;
+    if let Err(e) = set_path_mtu_probe_mode(&UDP_SOCKET, enabled) {
+        logger::error(&format!(
+            "[PMTU] Failed to switch path MTU discovery mode: {}",
+            e
+        ));
+        return;
+    }
+    *PMTU_PROBER.lock().unwrap() = enabled.then(|| {
+        PathMtuProber::new(MAX_PAYLOAD_SIZE + FRAGMENT_OVERHEAD + SECURE_HEADER_SIZE + TAG_SIZE)
+    });
+    logger::info(&format!(
+        "[PMTU] Path MTU discovery {}.",
+        if enabled { "enabled" } else { "disabled" }
+    ));
+    let capabilities = local_capabilities();
+    let hello = SESSION.lock().unwrap().hello_packet(capabilities);
+    let _ = send_packet(&UDP_SOCKET, &hello);
+}
+
+/// 关键帧请求的统计，依次为：收到的请求、被合并的请求、向编码器请求的次数、发出的关键帧、
+/// 应请求发出并得到接收端确认的关键帧、超时升级的次数。
+#[no_mangle]
+pub extern "system" fn Java_com_neurocam_NativeBridge_getKeyFrameStats(
+    env: JNIEnv,
+    _class: JClass,
+) -> jni::sys::jlongArray {
+    let stats = *KEY_FRAMES.lock().unwrap().stats();
+    let values = [
+        stats.requests_received,
+        stats.requests_coalesced,
+        stats.encoder_requests,
+        stats.key_frames_sent,
+        stats.key_frames_acked,
+        stats.escalations,
+    ]
+    .map(|v| v.min(i64::MAX as u64) as i64);
+    let array = match env.new_long_array(values.len() as i32) {
+        Ok(array) => array,
+        Err(e) => {
+            logger::error(&format!(
+                "[JNI] Failed to allocate key frame stats: {:?}",
+                e
+            ));
+            return std::ptr::null_mut();
+        }
+    };
+    if let Err(e) = env.set_long_array_region(&array, 0, &values) {
+        logger::error(&format!("[JNI] Failed to fill key frame stats: {:?}", e));
+        return std::ptr::null_mut();
+    }
+    array.into_raw()
 }
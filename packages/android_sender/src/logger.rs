@@ -1,7 +1,10 @@
 // --- packages/android_sender/src/logger.rs ---
 
+#[cfg(not(test))]
 use std::ffi::CString;
-use std::os::raw::{c_char, c_int};
+#[cfg(not(test))]
+use std::os::raw::c_char;
+use std::os::raw::c_int;
 
 // 定义Android日志优先级的枚举
 #[repr(i32)]
@@ -11,7 +14,8 @@ enum AndroidLogPriority {
     Error = 6,
 }
 
-// 链接 liblog.so
+// 链接 liblog.so。单元测试在开发机上运行，那里没有 liblog，改为写到标准错误
+#[cfg(not(test))]
 #[link(name = "log")]
 extern "C" {
     // 声明我们将调用的C函数
@@ -19,12 +23,18 @@ extern "C" {
 }
 
 // 这是一个私有的、不安全的辅助函数
+#[cfg(not(test))]
 unsafe fn log_write(prio: AndroidLogPriority, tag: &str, message: &str) {
     let tag = CString::new(tag).unwrap();
     let message = CString::new(message).unwrap();
     __android_log_write(prio as c_int, tag.as_ptr(), message.as_ptr());
 }
 
+#[cfg(test)]
+unsafe fn log_write(prio: AndroidLogPriority, tag: &str, message: &str) {
+    eprintln!("{}/{} {}", prio as c_int, tag, message);
+}
+
 // --- 以下是我们将暴露给其他模块的、安全的公共函数 ---
 
 const LOG_TAG: &str = "rust";
@@ -0,0 +1,348 @@
+// --- packages/android_sender/src/pacer.rs ---
+
+//! 发送队列与令牌桶节流：编码器产出的帧不再一次性全部发出，而是按目标速率均匀地送上网络，
+//! 关键帧的几十个分片不会瞬间灌满 AP 的缓冲区。
+//!
+//! 出队顺序：不分片的小数据报（音频帧、逐帧元数据）> 重传分片 > 关键帧 > 普通帧（同类按入队顺序）。
+//! 音频和元数据很小又对延迟最敏感，不参与下面的丢帧。队列中积压的数据按当前速率需要超过延迟预算才能发完时，丢弃还没开始发送的普通帧，先丢非参考帧，仍然超出时再丢参考帧；
+//! 丢了参考帧之后解码器要等下一个关键帧，由调用方通过 `take_key_frame_requests` 得知是哪几路流
+//! 并为它们请求关键帧。多路流共用同一个队列和令牌桶。
+use crate::logger;
+use std::collections::{BTreeSet, VecDeque};
+use std::time::{Duration, Instant};
+
+// 令牌桶的容量：允许的最大突发时长，以及无论速率多低都至少能连发的字节数。
+// 比容量还大的数据报（例如协商出 8800 字节的分片）在令牌桶满时照样放行，令牌余额变为负数，
+// 之后按速率补回，否则它永远攒不够令牌，发送队列就此卡死
+const MAX_BURST: Duration = Duration::from_millis(5);
+const MIN_BURST_BYTES: f64 = 3000.0;
+
+struct QueuedFrame {
+    stream_id: u8,
+    frame_id: u32,
+    is_key_frame: bool,
+    is_reference: bool,
+    packets: VecDeque<Vec<u8>>,
+    bytes: usize,
+    // 已经发出过至少一个分片：剩下的分片必须发完，否则接收端只会得到半帧
+    started: bool,
+}
+
+/// 发送队列的统计。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PacerStats {
+    pub packets_sent: u64,
+    pub frames_dropped: u64,
+    pub packets_dropped: u64,
+}
+
+pub struct Pacer {
+    urgent: VecDeque<Vec<u8>>,
+    retransmissions: VecDeque<Vec<u8>>,
+    frames: VecDeque<QueuedFrame>,
+    queued_bytes: usize,
+    rate_bps: f64,
+    latency_budget: Duration,
+    // 令牌桶中当前可用的字节数
+    tokens: f64,
+    last_refill: Option<Instant>,
+    // 丢过参考帧、需要关键帧的流
+    key_frames_needed: BTreeSet<u8>,
+    stats: PacerStats,
+}
+
+impl Pacer {
+    pub fn new(rate_bps: u32, latency_budget: Duration) -> Self {
+        Pacer {
+            urgent: VecDeque::new(),
+            retransmissions: VecDeque::new(),
+            frames: VecDeque::new(),
+            queued_bytes: 0,
+            rate_bps: rate_bps.max(1) as f64,
+            latency_budget,
+            tokens: MIN_BURST_BYTES,
+            last_refill: None,
+            key_frames_needed: BTreeSet::new(),
+            stats: PacerStats::default(),
+        }
+    }
+
+    /// 调整节流速率（比特/秒）。
+    pub fn set_rate(&mut self, rate_bps: u32) {
+        self.rate_bps = rate_bps.max(1) as f64;
+    }
+
+    /// 把一帧的全部分片（可能夹带 FEC 校验分片）放进队列，必要时丢弃积压的旧帧。
+    pub fn enqueue_frame(
+        &mut self,
+        stream_id: u8,
+        frame_id: u32,
+        is_key_frame: bool,
+        is_reference: bool,
+        packets: Vec<Vec<u8>>,
+    ) {
+        let bytes = packets.iter().map(Vec::len).sum();
+        self.queued_bytes += bytes;
+        self.frames.push_back(QueuedFrame {
+            stream_id,
+            frame_id,
+            is_key_frame,
+            is_reference,
+            packets: packets.into(),
+            bytes,
+            started: false,
+        });
+        self.enforce_latency_budget();
+    }
+
+    /// 放入一个重传分片，它会排在所有媒体帧之前发出。
+    pub fn enqueue_retransmission(&mut self, packet: Vec<u8>) {
+        self.queued_bytes += packet.len();
+        self.retransmissions.push_back(packet);
+    }
+
+    /// 放入一个不分片的小数据报（音频帧或元数据），它会排在所有视频数据之前发出。
+    pub fn enqueue_urgent(&mut self, packet: Vec<u8>) {
+        self.queued_bytes += packet.len();
+        self.urgent.push_back(packet);
+    }
+
+    /// 令牌足够时取出下一个该发送的分片。
+    pub fn poll(&mut self, now: Instant) -> Option<Vec<u8>> {
+        self.refill(now);
+        let size = self.peek_len()?;
+        if self.tokens < self.required_tokens(size) {
+            return None;
+        }
+        let packet = match self
+            .urgent
+            .pop_front()
+            .or_else(|| self.retransmissions.pop_front())
+        {
+            Some(packet) => packet,
+            None => {
+                let index = self.next_frame_index()?;
+                let frame = &mut self.frames[index];
+                frame.started = true;
+                let packet = frame.packets.pop_front()?;
+                frame.bytes -= packet.len();
+                if frame.packets.is_empty() {
+                    self.frames.remove(index);
+                }
+                packet
+            }
+        };
+        self.tokens -= packet.len() as f64;
+        self.queued_bytes -= packet.len();
+        self.stats.packets_sent += 1;
+        Some(packet)
+    }
+
+    /// 发送失败（例如套接字缓冲区已满）的分片放回队首，下次优先发送。
+    pub fn requeue(&mut self, packet: Vec<u8>) {
+        self.tokens += packet.len() as f64;
+        self.queued_bytes += packet.len();
+        self.stats.packets_sent -= 1;
+        self.retransmissions.push_front(packet);
+    }
+
+    /// 下一个分片可以发送的时刻；队列为空时返回 None。
+    pub fn next_send_time(&self, now: Instant) -> Option<Instant> {
+        let required = self.required_tokens(self.peek_len()?);
+        if self.tokens >= required {
+            return Some(now);
+        }
+        Some(now + Duration::from_secs_f64((required - self.tokens) * 8.0 / self.rate_bps))
+    }
+
+    /// 按当前速率发完队列中积压的数据需要的时间。
+    pub fn queue_delay(&self) -> Duration {
+        Duration::from_secs_f64(self.queued_bytes as f64 * 8.0 / self.rate_bps)
+    }
+
+    /// 自上次调用以来因为超出延迟预算丢弃过参考帧的流（它们之后的帧在下一个关键帧之前都无法解码）。
+    pub fn take_key_frame_requests(&mut self) -> BTreeSet<u8> {
+        std::mem::take(&mut self.key_frames_needed)
+    }
+
+    pub fn stats(&self) -> &PacerStats {
+        &self.stats
+    }
+
+    fn burst(&self) -> f64 {
+        (self.rate_bps / 8.0 * MAX_BURST.as_secs_f64()).max(MIN_BURST_BYTES)
+    }
+
+    // 发送 `size` 字节需要的令牌：超过令牌桶容量的数据报只需等到桶满
+    fn required_tokens(&self, size: usize) -> f64 {
+        (size as f64).min(self.burst())
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let burst = self.burst();
+        if let Some(last) = self.last_refill {
+            let elapsed = now.saturating_duration_since(last).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.rate_bps / 8.0).min(burst);
+        }
+        self.last_refill = Some(now);
+    }
+
+    fn peek_len(&self) -> Option<usize> {
+        if let Some(packet) = self.urgent.front().or(self.retransmissions.front()) {
+            return Some(packet.len());
+        }
+        let frame = &self.frames[self.next_frame_index()?];
+        frame.packets.front().map(Vec::len)
+    }
+
+    // 已经开始发送的帧先发完，否则关键帧优先，再按入队顺序
+    fn next_frame_index(&self) -> Option<usize> {
+        self.frames
+            .iter()
+            .position(|frame| frame.started)
+            .or_else(|| self.frames.iter().position(|frame| frame.is_key_frame))
+            .or((!self.frames.is_empty()).then_some(0))
+    }
+
+    fn enforce_latency_budget(&mut self) {
+        for drop_references in [false, true] {
+            while self.queue_delay() > self.latency_budget {
+                let Some(index) = self.frames.iter().position(|frame| {
+                    !frame.started
+                        && !frame.is_key_frame
+                        && (drop_references || !frame.is_reference)
+                }) else {
+                    break;
+                };
+                let frame = self.frames.remove(index).unwrap();
+                self.queued_bytes -= frame.bytes;
+                self.stats.frames_dropped += 1;
+                self.stats.packets_dropped += frame.packets.len() as u64;
+                if frame.is_reference {
+                    self.key_frames_needed.insert(frame.stream_id);
+                }
+                logger::warn(&format!(
+                    "[PACER] Dropped {} frame #{} of stream {} ({} bytes) to stay within the {:?} latency budget.",
+                    if frame.is_reference {
+                        "reference"
+                    } else {
+                        "non-reference"
+                    },
+                    frame.frame_id,
+                    frame.stream_id,
+                    frame.bytes,
+                    self.latency_budget
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1000 字节/秒：时间与令牌数好换算
+    const SLOW_RATE: u32 = 8000;
+
+    fn packets(tag: u8, count: usize, len: usize) -> Vec<Vec<u8>> {
+        vec![vec![tag; len]; count]
+    }
+
+    fn drain(pacer: &mut Pacer, now: Instant) -> Vec<u8> {
+        std::iter::from_fn(|| pacer.poll(now))
+            .map(|packet| packet[0])
+            .collect()
+    }
+
+    #[test]
+    fn test_priority_order() {
+        let mut pacer = Pacer::new(SLOW_RATE, Duration::from_secs(60));
+        let t0 = Instant::now();
+        pacer.enqueue_frame(0, 0, false, true, packets(1, 2, 100));
+        pacer.enqueue_frame(0, 1, true, true, packets(2, 2, 100));
+        pacer.enqueue_retransmission(vec![3; 100]);
+        pacer.enqueue_urgent(vec![4; 100]);
+        // 音频/元数据 > 重传 > 关键帧 > 普通帧
+        assert_eq!(drain(&mut pacer, t0), [4, 3, 2, 2, 1, 1]);
+        assert_eq!(pacer.next_send_time(t0), None);
+        assert_eq!(pacer.stats().packets_sent, 6);
+    }
+
+    #[test]
+    fn test_token_bucket_paces_packets() {
+        let mut pacer = Pacer::new(SLOW_RATE, Duration::from_secs(60));
+        let t0 = Instant::now();
+        pacer.enqueue_frame(0, 0, false, true, packets(1, 4, 1000));
+        // 初始的突发额度只够发 3 个分片，第 4 个要等 1 秒攒够令牌
+        assert_eq!(drain(&mut pacer, t0), [1, 1, 1]);
+        assert_eq!(pacer.next_send_time(t0), Some(t0 + Duration::from_secs(1)));
+        assert!(pacer.poll(t0 + Duration::from_millis(999)).is_none());
+        assert!(pacer.poll(t0 + Duration::from_secs(1)).is_some());
+        assert_eq!(pacer.next_send_time(t0), None);
+    }
+
+    #[test]
+    fn test_datagram_larger_than_burst_is_sent_when_bucket_is_full() {
+        let mut pacer = Pacer::new(SLOW_RATE, Duration::from_secs(60));
+        let t0 = Instant::now();
+        let size = MIN_BURST_BYTES as usize * 3;
+        pacer.enqueue_frame(0, 0, false, true, packets(1, 2, size));
+        // 令牌桶满时放行，余额变为负数
+        assert_eq!(drain(&mut pacer, t0), [1]);
+        // 下一个要等令牌补回到桶满：欠下的 6000 字节加上 3000 字节的容量，共 9 秒
+        let next = t0 + Duration::from_secs(9);
+        assert_eq!(pacer.next_send_time(t0), Some(next));
+        assert!(pacer.poll(next - Duration::from_millis(1)).is_none());
+        assert_eq!(drain(&mut pacer, next), [1]);
+    }
+
+    #[test]
+    fn test_drops_non_reference_frames_before_reference_frames() {
+        // 1000 字节/秒、1 秒的延迟预算：积压超过 1000 字节就开始丢帧
+        let mut pacer = Pacer::new(SLOW_RATE, Duration::from_secs(1));
+        let t0 = Instant::now();
+        pacer.enqueue_frame(0, 0, false, true, packets(1, 1, 400));
+        pacer.enqueue_frame(0, 1, false, false, packets(2, 1, 400));
+        pacer.enqueue_frame(0, 2, false, true, packets(3, 1, 400));
+        assert!(pacer.take_key_frame_requests().is_empty());
+        // 没有非参考帧可丢了，只能丢最早的参考帧，这路流需要关键帧
+        pacer.enqueue_frame(5, 3, false, true, packets(4, 1, 400));
+        assert_eq!(pacer.take_key_frame_requests(), BTreeSet::from([0]));
+        assert!(pacer.take_key_frame_requests().is_empty());
+        // 关键帧从不丢弃，即使超出预算
+        pacer.enqueue_frame(0, 4, true, true, packets(5, 1, 2000));
+        assert_eq!(drain(&mut pacer, t0), [5]);
+        let stats = *pacer.stats();
+        assert_eq!(stats.frames_dropped, 4);
+        assert_eq!(stats.packets_dropped, 4);
+    }
+
+    #[test]
+    fn test_started_frame_is_finished_first() {
+        let mut pacer = Pacer::new(SLOW_RATE, Duration::from_secs(1));
+        let t0 = Instant::now();
+        pacer.enqueue_frame(0, 0, false, false, packets(1, 2, 500));
+        assert_eq!(pacer.poll(t0).map(|p| p[0]), Some(1));
+        // 已经开始发送的帧不会因为超出预算被丢弃，后来的帧先被丢
+        pacer.enqueue_frame(0, 1, false, false, packets(2, 2, 500));
+        assert_eq!(pacer.stats().frames_dropped, 1);
+        // 也不会被关键帧插队，否则接收端只能得到半帧
+        pacer.enqueue_frame(0, 2, true, true, packets(3, 1, 500));
+        assert_eq!(drain(&mut pacer, t0), [1, 3]);
+    }
+
+    #[test]
+    fn test_requeue_sends_the_packet_again_first() {
+        let mut pacer = Pacer::new(SLOW_RATE, Duration::from_secs(60));
+        let t0 = Instant::now();
+        pacer.enqueue_frame(0, 0, false, true, packets(1, 3, 1000));
+        pacer.enqueue_frame(0, 1, true, true, packets(2, 1, 1000));
+        let first = pacer.poll(t0).unwrap();
+        assert_eq!(first[0], 2);
+        pacer.requeue(first);
+        // 令牌退回，这个分片排在队首
+        assert_eq!(drain(&mut pacer, t0), [2, 1, 1]);
+        assert_eq!(pacer.stats().packets_sent, 3);
+    }
+}
@@ -0,0 +1,174 @@
+// --- packages/android_sender/src/session.rs ---
+
+//! 发送端的会话握手：生成会话 ID，周期性地发送 Hello，并处理接收端的 HelloAck。
+//! 握手完成后由连接状态机跟踪接收端是否还活着，断开后自动退回握手阶段。
+use crate::logger;
+use protocol::connection::{Connection, ConnectionState, ConnectionTimeouts, Transition};
+use protocol::{
+    new_session_id, HelloAckPacket, HelloPacket, HelloStatus, Packet, VideoCodec,
+    DEFAULT_PAYLOAD_SIZE, MAX_PAYLOAD_SIZE, MIN_PAYLOAD_SIZE, PROTOCOL_MAGIC, PROTOCOL_VERSION,
+};
+use std::time::{Duration, Instant};
+
+// 握手完成前 Hello 的重发间隔
+const HELLO_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+// 握手完成后仍然周期性地发送 Hello，接收端重启后可以借此重新建立会话
+const HELLO_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeState {
+    Pending,
+    Established,
+    /// 接收端的协议版本或编解码器与本端不兼容，停止推流
+    Rejected,
+}
+
+pub struct Session {
+    pub session_id: u64,
+    pub codec: VideoCodec,
+    pub width: u16,
+    pub height: u16,
+    pub fps: u8,
+    pub state: HandshakeState,
+    /// 接收端接受的能力集（双方能力的交集）
+    pub accepted_capabilities: u32,
+    /// 接收端分配的连接 ID，写入每个数据包；握手完成前为 0。
+    /// 本端换了网络（源地址变化）之后，接收端靠它认出这仍是同一个会话。
+    pub connection_id: u32,
+    /// 在 Hello 中请求的最大分片负载
+    pub max_payload_size: u16,
+    /// 接收端在 HelloAck 中同意的分片负载上限，分片不能超过它；握手完成前为默认值
+    pub payload_size: usize,
+    /// 与接收端的连接状态
+    pub connection: Connection,
+    last_hello: Option<Instant>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session {
+            session_id: new_session_id(),
+            codec: VideoCodec::H264,
+            width: 0,
+            height: 0,
+            fps: 0,
+            state: HandshakeState::Pending,
+            accepted_capabilities: 0,
+            connection_id: 0,
+            max_payload_size: DEFAULT_PAYLOAD_SIZE as u16,
+            payload_size: DEFAULT_PAYLOAD_SIZE,
+            connection: Connection::new(ConnectionTimeouts::default()),
+            last_hello: None,
+        }
+    }
+
+    /// 构造一个完整的 Hello 数据报（含 PacketType 前缀）。
+    pub fn hello_packet(&self, capabilities: u32) -> Vec<u8> {
+        let hello = HelloPacket {
+            magic: PROTOCOL_MAGIC,
+            version: PROTOCOL_VERSION,
+            session_id: self.session_id,
+            codec: self.codec as u8,
+            width: self.width,
+            height: self.height,
+            fps: self.fps,
+            capabilities,
+            max_payload_size: self.max_payload_size,
+        };
+        Packet::Hello(hello).encode()
+    }
+
+    /// 是否到了该(重)发 Hello 的时候；返回 true 时同时记录本次发送时间。
+    pub fn poll_hello(&mut self, now: Instant) -> bool {
+        let interval = match self.state {
+            HandshakeState::Pending => HELLO_RETRY_INTERVAL,
+            HandshakeState::Established => HELLO_KEEPALIVE_INTERVAL,
+            HandshakeState::Rejected => return false,
+        };
+        if self
+            .last_hello
+            .is_some_and(|last| now.duration_since(last) < interval)
+        {
+            return false;
+        }
+        self.last_hello = Some(now);
+        if self.state == HandshakeState::Pending {
+            log_transition(self.connection.on_handshake_started(now));
+        }
+        true
+    }
+
+    /// 收到了接收端的任意一个（通过认证和解析的）消息。
+    pub fn on_receiver_heard(&mut self, now: Instant) {
+        log_transition(self.connection.on_heard(now));
+    }
+
+    /// 按沉默时间推进连接状态。接收端断开后退回握手阶段，用较快的间隔重发 Hello，
+    /// 它一回来就能重新建立会话。
+    pub fn poll_connection(&mut self, now: Instant) {
+        let transition = self.connection.poll(now);
+        log_transition(transition);
+        if transition.is_some_and(|t| t.to == ConnectionState::Lost)
+            && self.state == HandshakeState::Established
+        {
+            self.state = HandshakeState::Pending;
+            self.last_hello = None;
+        }
+    }
+
+    pub fn handle_hello_ack(&mut self, ack: &HelloAckPacket) {
+        if ack.magic != PROTOCOL_MAGIC || ack.session_id != self.session_id {
+            return;
+        }
+        let status = HelloStatus::try_from(ack.status);
+        if ack.version != PROTOCOL_VERSION || status == Ok(HelloStatus::VersionMismatch) {
+            if self.state != HandshakeState::Rejected {
+                logger::error(&format!(
+                    "[HANDSHAKE] Incompatible receiver: it speaks protocol v{}, we speak v{}. Streaming stopped.",
+                    ack.version, PROTOCOL_VERSION
+                ));
+            }
+            self.state = HandshakeState::Rejected;
+            return;
+        }
+        match status {
+            Ok(HelloStatus::Accepted) => {
+                let payload_size = (ack.max_payload_size as usize)
+                    .clamp(MIN_PAYLOAD_SIZE, MAX_PAYLOAD_SIZE)
+                    .min(self.max_payload_size as usize);
+                if self.state != HandshakeState::Established
+                    || self.connection_id != ack.connection_id
+                    || self.payload_size != payload_size
+                {
+                    logger::info(&format!(
+                        "[HANDSHAKE] Session {:016x} established, connection id {:08x}, capabilities {:#x}, payload {} bytes.",
+                        self.session_id, ack.connection_id, ack.capabilities, payload_size
+                    ));
+                }
+                self.state = HandshakeState::Established;
+                self.accepted_capabilities = ack.capabilities;
+                self.connection_id = ack.connection_id;
+                self.payload_size = payload_size;
+                log_transition(self.connection.on_established(Instant::now()));
+            }
+            _ => {
+                if self.state != HandshakeState::Rejected {
+                    logger::error(&format!(
+                        "[HANDSHAKE] Receiver rejected session {:016x} with status {}. Streaming stopped.",
+                        self.session_id, ack.status
+                    ));
+                }
+                self.state = HandshakeState::Rejected;
+            }
+        }
+    }
+}
+
+fn log_transition(transition: Option<Transition>) {
+    if let Some(transition) = transition {
+        logger::info(&format!(
+            "[CONNECTION] {:?} -> {:?}.",
+            transition.from, transition.to
+        ));
+    }
+}
@@ -0,0 +1,166 @@
+// --- packages/linux_receiver/src/health.rs ---
+
+//! 解码管线的健康状态：总线上错误与警告的分类，以及管线出错停止后重建的退避时间。
+//! 这里只有纯逻辑，读取总线和重建管线由 main.rs 完成。
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// 解码管线出错停止后第一次重建前的等待时间，之后每失败一次翻倍，最长不超过 PIPELINE_RETRY_MAX。
+// 重新加载 v4l2loopback 期间输出设备会短暂消失，设备回来之后管线自动恢复
+pub const PIPELINE_RETRY_INITIAL: Duration = Duration::from_millis(200);
+pub const PIPELINE_RETRY_MAX: Duration = Duration::from_secs(5);
+
+/// 一路流解码管线的健康状态。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineHealth {
+    Healthy,
+    /// 管线刚刚重建或重启，等待下一个关键帧送进解码器
+    Recovering,
+    /// 管线出错停止，到 `retry_at` 时重建；`attempts` 是已经失败的重建次数。期间收到的帧直接丢弃
+    Failed {
+        retry_at: Instant,
+        attempts: u32,
+    },
+}
+
+impl PipelineHealth {
+    /// 已经失败 `attempts` 次重建的管线，按退避时间安排下一次重建。
+    pub fn failed(attempts: u32, now: Instant) -> Self {
+        PipelineHealth::Failed {
+            retry_at: now + retry_delay(attempts),
+            attempts,
+        }
+    }
+
+    pub fn is_failed(&self) -> bool {
+        matches!(self, PipelineHealth::Failed { .. })
+    }
+}
+
+impl fmt::Display for PipelineHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineHealth::Healthy => write!(f, "healthy"),
+            PipelineHealth::Recovering => write!(f, "recovering"),
+            PipelineHealth::Failed { retry_at, attempts } => write!(
+                f,
+                "failed (rebuild in {}ms, {} failed attempt(s))",
+                retry_at
+                    .saturating_duration_since(Instant::now())
+                    .as_millis(),
+                attempts
+            ),
+        }
+    }
+}
+
+/// 重建失败 `attempts` 次之后，下一次重建前的等待时间。
+pub fn retry_delay(attempts: u32) -> Duration {
+    PIPELINE_RETRY_INITIAL
+        .saturating_mul(1 << attempts.min(16))
+        .min(PIPELINE_RETRY_MAX)
+}
+
+/// 当前会话中解码管线总线上的错误与警告，以及管线的重建次数。
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PipelineStats {
+    pub errors: u64,
+    pub warnings: u64,
+    pub rebuilds: u64,
+}
+
+/// 管线总线上一条错误或警告的来源类别，决定恢复方式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineIssue {
+    /// 解码器报告码流损坏或解码失败：它输出的画面不可信，要等下一个关键帧
+    Decode,
+    /// 输出设备打不开或者消失了（例如 v4l2loopback 被重新加载），或者 caps 协商失败
+    Output,
+    Other,
+}
+
+/// 总线消息中与分类有关的信息。
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IssueReport<'a> {
+    /// 消息来自解码部分（解析器或解码器）
+    pub from_decoder: bool,
+    /// 消息来自 v4l2sink
+    pub from_sink: bool,
+    /// GError 属于 GST_RESOURCE_ERROR 域（设备打不开、读写失败等）
+    pub resource_error: bool,
+    /// GError 是 GST_CORE_ERROR_NEGOTIATION
+    pub negotiation_error: bool,
+    pub debug: Option<&'a str>,
+}
+
+/// 按消息的来源元素和错误域判断问题的类别。
+pub fn classify_issue(report: &IssueReport) -> PipelineIssue {
+    if report.from_decoder {
+        return PipelineIssue::Decode;
+    }
+    // caps 协商失败通常由上游元素以 "not-negotiated" 数据流错误的形式报告
+    let negotiation = report.negotiation_error
+        || report
+            .debug
+            .is_some_and(|debug| debug.contains("not-negotiated"));
+    if report.from_sink || report.resource_error || negotiation {
+        PipelineIssue::Output
+    } else {
+        PipelineIssue::Other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_issue() {
+        let decoder = IssueReport {
+            from_decoder: true,
+            resource_error: true,
+            ..Default::default()
+        };
+        assert_eq!(classify_issue(&decoder), PipelineIssue::Decode);
+        let sink = IssueReport {
+            from_sink: true,
+            ..Default::default()
+        };
+        assert_eq!(classify_issue(&sink), PipelineIssue::Output);
+        // 设备消失时的资源错误不一定由 sink 本身报告
+        let resource = IssueReport {
+            resource_error: true,
+            ..Default::default()
+        };
+        assert_eq!(classify_issue(&resource), PipelineIssue::Output);
+        let not_negotiated = IssueReport {
+            debug: Some("streaming stopped, reason not-negotiated (-4)"),
+            ..Default::default()
+        };
+        assert_eq!(classify_issue(&not_negotiated), PipelineIssue::Output);
+        let other = IssueReport {
+            debug: Some("streaming stopped, reason error (-5)"),
+            ..Default::default()
+        };
+        assert_eq!(classify_issue(&other), PipelineIssue::Other);
+    }
+
+    #[test]
+    fn test_retry_delay_doubles_up_to_the_cap() {
+        let delays: Vec<_> = (0..7).map(|n| retry_delay(n).as_millis()).collect();
+        assert_eq!(delays, [200, 400, 800, 1600, 3200, 5000, 5000]);
+        assert_eq!(retry_delay(u32::MAX), PIPELINE_RETRY_MAX);
+
+        let now = Instant::now();
+        let health = PipelineHealth::failed(2, now);
+        assert_eq!(
+            health,
+            PipelineHealth::Failed {
+                retry_at: now + Duration::from_millis(800),
+                attempts: 2
+            }
+        );
+        assert!(health.is_failed());
+        assert!(!PipelineHealth::Recovering.is_failed());
+    }
+}
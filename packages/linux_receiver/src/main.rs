@@ -5,74 +5,1066 @@ use gstreamer as gst;
 use gstreamer::prelude::*;
 use gstreamer_app as gst_app;
 
-use protocol::{AckPacket, DataHeader, PacketType, ACK_PACKET_SIZE, DATA_HEADER_SIZE};
-use std::collections::{HashMap, VecDeque};
+use protocol::clock::{CaptureClock, ClockSync};
+use protocol::congestion::FeedbackCollector;
+use protocol::connection::{Connection, ConnectionState, ConnectionTimeouts, Transition};
+use protocol::crypto::{parse_hex_key, PacketOpener, PacketSealer};
+use protocol::jitter::{JitterBuffer, JitterProfile, ReleasedFrame};
+use protocol::metadata::{self, MetadataEntry};
+use protocol::nal::{self, NalKind};
+use protocol::reassembly::{
+    CompletedFrame, LossReason, NackPolicy, ReassemblyLimits, ReassemblyStats, ReassemblyTable,
+};
+use protocol::refchain::{ChainFrame, FrameNum, ReferenceChain};
+use protocol::sps::{self, SpsInfo};
+use protocol::{
+    handshake_session_id, new_connection_id, new_path_token, AckPacket, AudioCodec, AudioHeader,
+    ByeAckPacket, ByePacket, ByeReason, ClockPongPacket, HelloAckPacket, HelloPacket, HelloStatus,
+    MetadataHeader, MtuProbePacket, Packet, PacketType, PathPacket, ProtocolError, VideoCodec,
+    CAP_AUDIO, CAP_BYE, CAP_CLOCK_SYNC, CAP_CONGESTION_CONTROL, CAP_ENCRYPTION, CAP_FEC,
+    CAP_HEARTBEAT, CAP_METADATA, CAP_NACK, CAP_PMTU_PROBE, DEFAULT_PAYLOAD_SIZE, MAX_PAYLOAD_SIZE,
+    MIN_PAYLOAD_SIZE, PROTOCOL_MAGIC, PROTOCOL_VERSION,
+};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::fmt::Write as _;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant};
 use tokio::net::UdpSocket;
+
+mod health;
+
+use health::{IssueReport, PipelineHealth, PipelineIssue, PipelineStats, PIPELINE_RETRY_INITIAL};
 // 删除了 tokio::time::sleep
 
 const LISTEN_ADDR: &str = "0.0.0.0:8080";
 const MAX_DATAGRAM_SIZE: usize = 65_507;
-const V4L2_DEVICE: &str = "/dev/video10";
+// 第 N 路视频流输出到 /dev/video(10 + N)，需要事先用 v4l2loopback 创建这些设备
+const V4L2_FIRST_DEVICE: u32 = 10;
+// 同时接收的视频流数量上限，每路流占用一条解码管线、一个输出设备和一份重组内存预算
+const MAX_STREAMS: usize = 4;
 const LATENCY_AVG_WINDOW: usize = 60;
+// 预共享密钥（64 个十六进制字符）。设置后所有收发的数据报都经过 XChaCha20-Poly1305 认证加密
+const PSK_ENV_VAR: &str = "NEUROCAM_PSK";
+// 抖动缓冲的策略：latency（延迟优先，默认）或 smooth（流畅优先）
+const JITTER_PROFILE_ENV_VAR: &str = "NEUROCAM_JITTER_PROFILE";
+// 本端接受的最大分片负载（字节），握手时与发送端的请求取较小值。默认为协议上限
+const MAX_PAYLOAD_ENV_VAR: &str = "NEUROCAM_MAX_PAYLOAD";
+// 音频输出：auto（默认，系统默认设备）、pulse、pipewire、fake（丢弃，用于测试）、file:<路径>（写成 WAV）或 none（不接收音频）
+const AUDIO_SINK_ENV_VAR: &str = "NEUROCAM_AUDIO_SINK";
+// 音频的播放延迟与视频的实际延迟相差超过这么多时才重新调整，避免频繁的微小跳变
+const AUDIO_SYNC_TOLERANCE: Duration = Duration::from_millis(20);
+// 参考链断开后重复请求关键帧的最小间隔，关键帧还在路上时不必再要
+const KEY_FRAME_REQUEST_INTERVAL: Duration = Duration::from_millis(300);
+// 逐帧元数据的旁路输出地址（例如 127.0.0.1:8091）。设置后每送出一帧就向这里发一个 JSON 数据报；不设置时不接收元数据
+const METADATA_ADDR_ENV_VAR: &str = "NEUROCAM_METADATA_ADDR";
+// 每路流最多缓存多少帧还没送去解码的元数据，超出时丢弃最早的
+const MAX_PENDING_METADATA: usize = 64;
 // 删除了 SIGNAL_TIMEOUT
 
-struct FrameReassembler {
-    packets: Vec<Option<Vec<u8>>>,
-    received_count: u16,
-    total_packets: u16,
-    last_seen: Instant,
-    is_key_frame: bool,
+// --- NACK 选择性重传 ---
+// 扫描未完成帧的周期
+const NACK_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+const NACK_POLICY: NackPolicy = NackPolicy {
+    tail_timeout: Duration::from_millis(20),
+    retry_interval: Duration::from_millis(40),
+    deadline: Duration::from_millis(250),
+    max_nacks_per_frame: 4,
+};
+// 每路流的重组内存预算：所有未完成的帧合计最多占用的帧数与字节数，超出时淘汰最早开始的帧。
+// 正常情况下同时在重组的只有两三帧，这个上限只在遭到伪造流量或严重乱序时才会触及
+const REASSEMBLY_LIMITS: ReassemblyLimits = ReassemblyLimits {
+    max_frames: 64,
+    max_bytes: 32 * 1024 * 1024,
+};
+// 超过这么久没有收到任何新分片的未完成帧判定为丢失并释放。
+// 要比 NACK 的截止时间长，给最后一轮重传留出到达的时间
+const STALE_FRAME_TIMEOUT: Duration = Duration::from_millis(500);
+// 连接状态机的超时：发送端沉默 1.5 秒视为卡顿，5 秒视为断开
+const CONNECTION_TIMEOUTS: ConnectionTimeouts = ConnectionTimeouts {
+    heartbeat_interval: Duration::from_millis(500),
+    stall_timeout: Duration::from_millis(1500),
+    lost_timeout: Duration::from_secs(5),
+};
+// 向发送端发送 ClockPing 的间隔
+const CLOCK_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+// 每收到这么多次有效的 ClockPong 打印一次时钟偏移估计
+const CLOCK_REPORT_EVERY: u64 = 30;
+// 向发送端发送拥塞反馈（接收速率、丢包率、时延变化）的间隔
+const FEEDBACK_INTERVAL: Duration = Duration::from_millis(200);
+// 新地址迟迟没有回应路径验证时，重发 PathChallenge 的间隔
+const PATH_CHALLENGE_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 通过 Hello 握手建立的发送端会话。
+struct SessionInfo {
+    session_id: u64,
+    codec: VideoCodec,
+    width: u16,
+    height: u16,
+    fps: u8,
+    capabilities: u32,
+    // 本端在 HelloAck 中为该会话分配的连接 ID，发送端换了地址之后靠它识别
+    connection_id: u32,
+    // 协商后的分片负载上限
+    payload_size: usize,
+}
+
+/// 正在进行的路径验证：等待新地址原样回显 `token`。
+struct PendingPath {
+    addr: SocketAddr,
+    token: u64,
+    sent_at: Instant,
+}
+
+/// 一路视频流的接收状态：各自的重组、抖动缓冲、解码管线和输出设备。
+struct StreamState {
+    stream_id: u8,
+    reassembly: ReassemblyTable,
+    jitter: JitterBuffer,
+    latency_history: VecDeque<f64>,
+    // 把发送端的采集时间戳换算到管线时钟上，作为这路流每一帧的 PTS（每路流的 PTS 各自递增）
+    capture_clock: CaptureClock,
+    pipeline: gst::Pipeline,
+    appsrc: gst_app::AppSrc,
+    // 解码管线当前配置的编解码器：第 0 路由握手决定，其余的流由各自的参数集决定
+    codec: VideoCodec,
+    // 参数集缓存（H.264 的 SPS/PPS，H.265 的 VPS/SPS/PPS，4 字节起始码的 Annex-B）：
+    // 收到参数集时更新，自身不带参数集的关键帧送进管线前拼接在它前面
+    sps_pps_cache: Option<Vec<u8>>,
+    // 最近一次通过参数集消息收到的内容，只有它变化时才请求关键帧
+    last_sps_pps: Option<Vec<u8>>,
+    // 从 SPS 解析出的码流格式，决定 v4l2 输出的 caps；还没收到 SPS 时为 None
+    format: Option<SpsInfo>,
+    // 参考链断开（丢失参考帧、解码器报错或重启）时扣下后续的帧，直到下一个关键帧
+    chain: ReferenceChain,
+    last_key_frame_request: Option<Instant>,
+    key_frame_requests: u64,
+    // 解码管线的健康状态，由 monitor_pipelines 根据总线消息更新
+    health: PipelineHealth,
+    pipeline_stats: PipelineStats,
+    // 按帧号缓存的元数据，这一帧送去解码时取出并输出
+    metadata: BTreeMap<u32, PendingMetadata>,
+    // 最近送去解码的帧号，晚于它的帧到达的元数据立即输出
+    last_delivered_frame: Option<u32>,
+}
+
+/// 一帧视频的元数据，等待这一帧送去解码。
+struct PendingMetadata {
     capture_timestamp_ns: u64,
+    entries: Vec<MetadataEntry>,
+}
+
+impl StreamState {
+    /// 为 `stream_id` 建立一条输出到它自己的 v4l2 设备的解码管线，并立即启动。
+    fn open(
+        stream_id: u8,
+        codec: VideoCodec,
+        jitter_profile: JitterProfile,
+        max_payload_size: usize,
+    ) -> Result<Self> {
+        let (pipeline, appsrc) = create_video_pipeline(codec, &stream_device(stream_id))?;
+        // 输出设备暂时不可用时这路流照样打开，由 monitor_pipelines 稍后重建管线
+        let health = match start_pipeline(&pipeline) {
+            Ok(()) => PipelineHealth::Healthy,
+            Err(reason) => {
+                eprintln!(
+                    "[HEALTH] Stream {}: failed to start the pipeline on {}: {}. Retrying in {}ms.",
+                    stream_id,
+                    stream_device(stream_id),
+                    reason,
+                    PIPELINE_RETRY_INITIAL.as_millis()
+                );
+                PipelineHealth::failed(0, Instant::now())
+            }
+        };
+        let mut reassembly = ReassemblyTable::new(REASSEMBLY_LIMITS);
+        reassembly.set_max_payload_size(max_payload_size);
+        Ok(StreamState {
+            stream_id,
+            reassembly,
+            jitter: JitterBuffer::new(jitter_profile),
+            latency_history: VecDeque::with_capacity(LATENCY_AVG_WINDOW),
+            capture_clock: CaptureClock::new(),
+            pipeline,
+            appsrc,
+            codec,
+            sps_pps_cache: None,
+            last_sps_pps: None,
+            format: None,
+            chain: ReferenceChain::new(),
+            last_key_frame_request: None,
+            key_frame_requests: 0,
+            health,
+            pipeline_stats: PipelineStats::default(),
+            metadata: BTreeMap::new(),
+            last_delivered_frame: None,
+        })
+    }
+
+    fn average_latency_ms(&self) -> Option<f64> {
+        (!self.latency_history.is_empty())
+            .then(|| self.latency_history.iter().sum::<f64>() / self.latency_history.len() as f64)
+    }
+}
+
+/// 第 `stream_id` 路流的输出设备。
+fn stream_device(stream_id: u8) -> String {
+    format!("/dev/video{}", V4L2_FIRST_DEVICE + stream_id as u32)
+}
+
+/// 音频的输出方式，由 `AUDIO_SINK_ENV_VAR` 选择。
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AudioSink {
+    Auto,
+    Pulse,
+    PipeWire,
+    Fake,
+    File(String),
+}
+
+impl AudioSink {
+    /// 解析环境变量的取值；"none" 表示不接收音频，返回 Ok(None)。
+    fn from_name(name: &str) -> Result<Option<Self>> {
+        Ok(Some(match name {
+            "auto" => AudioSink::Auto,
+            "pulse" => AudioSink::Pulse,
+            "pipewire" => AudioSink::PipeWire,
+            "fake" => AudioSink::Fake,
+            "none" => return Ok(None),
+            _ => match name.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => AudioSink::File(path.to_string()),
+                _ => {
+                    return Err(anyhow!(
+                        "{} must be auto, pulse, pipewire, fake, file:<path> or none, got {:?}",
+                        AUDIO_SINK_ENV_VAR,
+                        name
+                    ))
+                }
+            },
+        }))
+    }
+
+    /// 音频管线末端的描述，解码后的 PCM 从这里输出。文件路径由用户提供，不能拼进管线描述
+    /// （引号或 `!` 会破坏甚至改写管线），由 create_audio_pipeline 通过属性设置。
+    fn description(&self) -> String {
+        match self {
+            AudioSink::Auto => "autoaudiosink".to_string(),
+            AudioSink::Pulse => "pulsesink".to_string(),
+            AudioSink::PipeWire => "pipewiresink".to_string(),
+            AudioSink::Fake => "fakesink sync=true".to_string(),
+            AudioSink::File(_) => "wavenc ! filesink name=audiofile".to_string(),
+        }
+    }
+}
+
+/// 音频管线配置的编码参数，任何一项变化都要重建管线。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AudioFormat {
+    codec: AudioCodec,
+    sample_rate: u32,
+    channels: u8,
+}
+
+struct AudioPipeline {
+    format: AudioFormat,
+    pipeline: gst::Pipeline,
+    appsrc: gst_app::AppSrc,
+}
+
+/// 音频的接收状态。音频帧不分片也不重传，到达后按采集时间戳直接送进音频管线，
+/// 播放时再推迟与视频相同的延迟，让声音和画面对齐。
+struct AudioState {
+    sink: AudioSink,
+    // 第一个音频帧到达时建立，编码参数变化时重建
+    pipeline: Option<AudioPipeline>,
+    // 建立失败的编码参数，参数不变就不再重试，免得每一帧都刷一遍错误
+    failed_format: Option<AudioFormat>,
+    // 与视频流共用 ClockSync 的参考偏移，音频和视频的 PTS 因此在同一条时间轴上
+    capture_clock: CaptureClock,
+    last_sequence: Option<u32>,
+    // 当前施加在音频上的播放延迟
+    playout_delay: Option<Duration>,
+    frames_played: u64,
+    frames_lost: u64,
+    frames_discarded: u64,
+}
+
+impl AudioState {
+    fn new(sink: AudioSink) -> Self {
+        AudioState {
+            sink,
+            pipeline: None,
+            failed_format: None,
+            capture_clock: CaptureClock::new(),
+            last_sequence: None,
+            playout_delay: None,
+            frames_played: 0,
+            frames_lost: 0,
+            frames_discarded: 0,
+        }
+    }
+
+    /// 关闭音频管线并清空时钟和序号，下一个音频帧到达时重新建立。
+    fn reset(&mut self) {
+        if let Some(audio) = self.pipeline.take() {
+            let _ = audio.pipeline.set_state(gst::State::Null);
+        }
+        *self = AudioState::new(self.sink.clone());
+    }
+
+    /// 让音频比它的采集时刻晚 `delay` 播放，扣除管线自身（主要是音频输出缓冲）的延迟。
+    fn set_playout_delay(&mut self, delay: Duration) {
+        if self
+            .playout_delay
+            .is_some_and(|current| current.abs_diff(delay) < AUDIO_SYNC_TOLERANCE)
+        {
+            return;
+        }
+        let Some(audio) = &self.pipeline else {
+            return;
+        };
+        let mut query = gst::query::Latency::new();
+        let pipeline_latency = if audio.pipeline.query(&mut query) {
+            query.result().1
+        } else {
+            gst::ClockTime::ZERO
+        };
+        let offset_ns = delay.as_nanos() as i64 - pipeline_latency.nseconds() as i64;
+        if let Some(pad) = audio.appsrc.static_pad("src") {
+            pad.set_offset(offset_ns);
+        }
+        self.playout_delay = Some(delay);
+        println!(
+            "[AUDIO] Playout delay set to {}ms to match the video (pipeline latency {}ms).",
+            delay.as_millis(),
+            pipeline_latency.mseconds()
+        );
+    }
+}
+
+/// 逐帧元数据的旁路输出：V4L2 无法携带元数据，每一帧的元数据以 JSON 数据报发往 `METADATA_ADDR_ENV_VAR`
+/// 指定的地址，`pts_ns` 与这一帧在 v4l2 设备上的 buffer 时间戳相同，下游据此把元数据和画面对应起来。
+struct MetadataOutput {
+    socket: std::net::UdpSocket,
+    addr: SocketAddr,
+}
+
+impl MetadataOutput {
+    fn new(addr: SocketAddr) -> Result<Self> {
+        let bind_addr: SocketAddr = if addr.is_ipv4() {
+            "0.0.0.0:0".parse()?
+        } else {
+            "[::]:0".parse()?
+        };
+        let socket = std::net::UdpSocket::bind(bind_addr)?;
+        // 在接收循环里直接发送，下游没有读取时宁可丢弃也不能阻塞
+        socket.set_nonblocking(true)?;
+        Ok(MetadataOutput { socket, addr })
+    }
+
+    /// 输出一帧的元数据。`pts_ns` 为 None 表示元数据晚于这一帧到达，帧已经送去解码。
+    fn publish(
+        &self,
+        stream_id: u8,
+        frame_id: u32,
+        pts_ns: Option<u64>,
+        metadata: &PendingMetadata,
+    ) {
+        let json = metadata_json(stream_id, frame_id, pts_ns, metadata);
+        if let Err(e) = self.socket.send_to(json.as_bytes(), self.addr) {
+            if e.kind() != std::io::ErrorKind::WouldBlock {
+                eprintln!("[METADATA] Failed to send metadata to {}: {}", self.addr, e);
+            }
+        }
+    }
 }
 
-impl FrameReassembler {
-    fn new(header: &DataHeader) -> Self {
-        FrameReassembler {
-            packets: vec![None; header.total_packets as usize],
-            received_count: 0,
-            total_packets: header.total_packets,
-            last_seen: Instant::now(),
-            is_key_frame: header.is_key_frame != 0,
-            capture_timestamp_ns: header.capture_timestamp_ns,
+/// 把一帧的元数据格式化为一行 JSON。条目的值都是有限的数值，可以直接写出。
+fn metadata_json(
+    stream_id: u8,
+    frame_id: u32,
+    pts_ns: Option<u64>,
+    metadata: &PendingMetadata,
+) -> String {
+    let mut json = format!(
+        r#"{{"stream_id":{},"frame_id":{},"pts_ns":{},"capture_timestamp_ns":{},"entries":["#,
+        stream_id,
+        frame_id,
+        pts_ns.map_or_else(|| "null".to_string(), |pts| pts.to_string()),
+        metadata.capture_timestamp_ns
+    );
+    for (i, entry) in metadata.entries.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
         }
+        let _ = match *entry {
+            MetadataEntry::Intrinsics {
+                fx,
+                fy,
+                cx,
+                cy,
+                skew,
+            } => write!(
+                json,
+                r#"{{"type":"intrinsics","fx":{},"fy":{},"cx":{},"cy":{},"skew":{}}}"#,
+                fx, fy, cx, cy, skew
+            ),
+            MetadataEntry::Exposure {
+                exposure_time_ns,
+                iso,
+            } => write!(
+                json,
+                r#"{{"type":"exposure","exposure_time_ns":{},"iso":{}}}"#,
+                exposure_time_ns, iso
+            ),
+            MetadataEntry::FocusDistance { diopters } => write!(
+                json,
+                r#"{{"type":"focus_distance","diopters":{}}}"#,
+                diopters
+            ),
+            MetadataEntry::Orientation { w, x, y, z } => write!(
+                json,
+                r#"{{"type":"orientation","w":{},"x":{},"y":{},"z":{}}}"#,
+                w, x, y, z
+            ),
+            MetadataEntry::Imu {
+                timestamp_ns,
+                accel,
+                gyro,
+            } => write!(
+                json,
+                r#"{{"type":"imu","timestamp_ns":{},"accel":[{},{},{}],"gyro":[{},{},{}]}}"#,
+                timestamp_ns, accel[0], accel[1], accel[2], gyro[0], gyro[1], gyro[2]
+            ),
+            MetadataEntry::Gps {
+                timestamp_ns,
+                latitude,
+                longitude,
+                altitude_m,
+                accuracy_m,
+            } => write!(
+                json,
+                r#"{{"type":"gps","timestamp_ns":{},"latitude":{},"longitude":{},"altitude_m":{},"accuracy_m":{}}}"#,
+                timestamp_ns, latitude, longitude, altitude_m, accuracy_m
+            ),
+        };
     }
+    json.push_str("]}");
+    json
+}
 
-    fn add_packet(&mut self, packet_id: u16, data: Vec<u8>) -> Option<Vec<u8>> {
-        let id = packet_id as usize;
-        if id < self.packets.len() && self.packets[id].is_none() {
-            self.packets[id] = Some(data);
-            self.received_count += 1;
+/// 接收循环中需要跨包保存的状态。
+struct ReceiverState {
+    // 按 stream_id 索引的视频流。第 0 路在启动时建立，其余的在第一次收到它们的数据时建立
+    streams: BTreeMap<u8, StreamState>,
+    // 超出数量上限或管线建立失败的流，本会话内不再尝试
+    unavailable_streams: BTreeSet<u8>,
+    jitter_profile: JitterProfile,
+    // 没有配置音频输出时为 None，此时握手中也不接受 CAP_AUDIO
+    audio: Option<AudioState>,
+    // 没有配置元数据输出时为 None，此时握手中也不接受 CAP_METADATA
+    metadata_output: Option<MetadataOutput>,
+    // 管线使用的单调时钟。管线的 base time 固定为 0，buffer 的 PTS 就是这个时钟上的绝对时刻
+    clock: gst::Clock,
+    // 与发送端的往返时钟同步，结果作为每路流 capture_clock 的参考偏移
+    clock_sync: ClockSync,
+    clock_reference: Option<i128>,
+    clock_pongs: u64,
+    // 统计每个反馈周期的网络状况（所有流合计），发送端据此调整码率
+    feedback: FeedbackCollector,
+    // 已验证的发送端地址，ACK、NACK 和关键帧请求都发往这里
+    peer_addr: Option<SocketAddr>,
+    pending_path: Option<PendingPath>,
+    // 与发送端的连接状态，状态变化驱动关键帧请求和重组器重置
+    connection: Connection,
+    session: Option<SessionInfo>,
+    // 握手时因版本不兼容被拒绝的发送端，在它发来兼容的 Hello 之前丢弃它的所有数据
+    rejected_peer: Option<SocketAddr>,
+    // 本端支持的能力，握手时与发送端的能力取交集
+    capabilities: u32,
+    // 本端接受的最大分片负载，握手时与发送端的请求取较小值
+    max_payload_size: usize,
+    // 认证加密，仅在配置了预共享密钥时存在
+    sealer: Option<PacketSealer>,
+    opener: Option<PacketOpener>,
+    auth_failures: u64,
+    // 解析或重组校验失败（截断、未知类型、字段非法或前后不一致）而被丢弃的数据报数量
+    malformed_packets: u64,
+}
+
+/// 配置了预共享密钥时加密待发送的数据报，否则原样返回。
+fn seal_outgoing(sealer: &mut Option<PacketSealer>, packet: &[u8]) -> Vec<u8> {
+    match sealer {
+        Some(sealer) => sealer.seal(packet),
+        None => packet.to_vec(),
+    }
+}
+
+/// 记录一个被拒绝的数据报。只打印第一次和此后每 100 次，避免被垃圾流量刷屏。
+fn note_rejected(state: &mut ReceiverState, remote_addr: &SocketAddr, reason: impl fmt::Display) {
+    state.malformed_packets += 1;
+    if state.malformed_packets == 1 || state.malformed_packets.is_multiple_of(100) {
+        eprintln!(
+            "[PROTOCOL] Rejected datagram from {}: {}. Total rejected: {}",
+            remote_addr, reason, state.malformed_packets
+        );
+    }
+}
+
+/// 扫描所有未完成的帧，为缺失的分片发送 NACK。
+async fn send_nacks(state: &mut ReceiverState, socket: &UdpSocket) {
+    let Some(remote_addr) = state.peer_addr else {
+        return;
+    };
+    // 握手时发送端没有声明支持 NACK，就不要发送它看不懂的包
+    if state
+        .session
+        .as_ref()
+        .is_some_and(|s| s.capabilities & CAP_NACK == 0)
+    {
+        return;
+    }
+    let now = Instant::now();
+    let nacks: Vec<_> = state
+        .streams
+        .values_mut()
+        .flat_map(|stream| stream.reassembly.poll_nacks(now, &NACK_POLICY))
+        .collect();
+    for nack in nacks {
+        let (frame_id, stream_id) = (nack.frame_id, nack.stream_id);
+        let nack_buf = seal_outgoing(&mut state.sealer, &Packet::Nack(nack).encode());
+        if let Err(e) = socket.send_to(&nack_buf, remote_addr).await {
+            eprintln!(
+                "[ERROR] Failed to send NACK for frame #{} of stream {}: {}",
+                frame_id, stream_id, e
+            );
         }
-        self.last_seen = Instant::now();
-        if self.received_count == self.total_packets {
-            let total_size = self.packets.iter().map(|p| p.as_ref().unwrap().len()).sum();
-            let mut frame_data = Vec::with_capacity(total_size);
-            for packet in self.packets.iter_mut() {
-                frame_data.extend_from_slice(packet.take().unwrap().as_slice());
+    }
+}
+
+/// 向发送端请求 `stream_id` 这一路流的关键帧。
+async fn request_iframe(state: &mut ReceiverState, socket: &UdpSocket, stream_id: u8) {
+    let Some(remote_addr) = state.peer_addr else {
+        return;
+    };
+    let request = seal_outgoing(
+        &mut state.sealer,
+        &Packet::IFrameRequest { stream_id }.encode(),
+    );
+    if let Err(e) = socket.send_to(&request, remote_addr).await {
+        eprintln!("[ERROR] Failed to send I-Frame request: {}", e);
+    }
+}
+
+/// 为参考链断开的流请求关键帧，每路流按 `KEY_FRAME_REQUEST_INTERVAL` 限速。
+async fn request_recovery_key_frames(state: &mut ReceiverState, socket: &UdpSocket) {
+    let now = Instant::now();
+    let mut due = Vec::new();
+    for stream in state.streams.values_mut() {
+        if !stream.chain.wants_key_frame()
+            || stream
+                .last_key_frame_request
+                .is_some_and(|at| now.duration_since(at) < KEY_FRAME_REQUEST_INTERVAL)
+        {
+            continue;
+        }
+        stream.last_key_frame_request = Some(now);
+        stream.key_frame_requests += 1;
+        println!(
+            "[RECOVERY] Stream {}: reference chain broken, requesting a key frame ({} frame(s) withheld so far).",
+            stream.stream_id,
+            stream.chain.stats().frames_withheld
+        );
+        due.push(stream.stream_id);
+    }
+    for stream_id in due {
+        request_iframe(state, socket, stream_id).await;
+    }
+}
+
+/// 监视每条解码管线：读取总线上的错误和警告并按来源分类处理，到时间后重建出错停止的管线。
+///
+/// 解码器报告的问题意味着它输出的画面已经不可信，与丢失参考帧一样扣下后续的帧并请求关键帧；
+/// 解码器停止工作（错误）时原地重启管线。其他元素的错误（输出设备消失、caps 协商失败等）会让整条
+/// 管线停下来，这时按退避时间重新建立管线，成功之后同样从下一个关键帧开始解码。
+fn monitor_pipelines(state: &mut ReceiverState) {
+    let now = Instant::now();
+    for stream in state.streams.values_mut() {
+        drain_pipeline_bus(stream, now);
+        if let PipelineHealth::Failed { retry_at, attempts } = stream.health {
+            if now >= retry_at {
+                rebuild_pipeline(stream, attempts, now);
             }
-            Some(frame_data)
+        }
+    }
+}
+
+/// 处理一路流的管线总线上积压的错误和警告。
+fn drain_pipeline_bus(stream: &mut StreamState, now: Instant) {
+    let Some(bus) = stream.pipeline.bus() else {
+        return;
+    };
+    let decoder = stream.pipeline.by_name("decoder");
+    let sink = stream.pipeline.by_name("sink");
+    while let Some(message) =
+        bus.pop_filtered(&[gst::MessageType::Error, gst::MessageType::Warning])
+    {
+        let (fatal, error, debug) = match message.view() {
+            gst::MessageView::Error(e) => (true, e.error(), e.debug()),
+            gst::MessageView::Warning(w) => (false, w.error(), w.debug()),
+            _ => continue,
+        };
+        let posted_by = |element: Option<&gst::Element>| {
+            message
+                .src()
+                .zip(element)
+                .is_some_and(|(src, element)| src == element || src.has_as_ancestor(element))
+        };
+        let issue = health::classify_issue(&IssueReport {
+            from_decoder: posted_by(decoder.as_ref()),
+            from_sink: posted_by(sink.as_ref()),
+            resource_error: error.kind::<gst::ResourceError>().is_some(),
+            negotiation_error: error.matches(gst::CoreError::Negotiation),
+            debug: debug.as_deref(),
+        });
+        if fatal {
+            stream.pipeline_stats.errors += 1;
         } else {
-            None
+            stream.pipeline_stats.warnings += 1;
+        }
+        eprintln!(
+            "[GStreamer] Stream {}: {} ({:?}) from {}: {} ({})",
+            stream.stream_id,
+            if fatal { "error" } else { "warning" },
+            issue,
+            message
+                .src()
+                .map_or_else(|| "pipeline".into(), |src| src.path_string()),
+            error,
+            debug.as_deref().unwrap_or("no details")
+        );
+        if stream.health.is_failed() {
+            // 已经在等待重建，同一次故障的后续消息只记录
+            continue;
+        }
+        match (fatal, issue) {
+            (false, PipelineIssue::Decode) => stream.chain.mark_broken(),
+            (false, _) => {}
+            (true, PipelineIssue::Decode) => {
+                println!(
+                    "[HEALTH] Stream {}: restarting the decoder.",
+                    stream.stream_id
+                );
+                restart_stream_pipeline(stream, now);
+                // 管线回到 Null 时总线被清空，剩下的消息都属于这次故障
+                return;
+            }
+            (true, _) => {
+                let _ = stream.pipeline.set_state(gst::State::Null);
+                schedule_pipeline_rebuild(stream, &error.to_string(), 0, now);
+                return;
+            }
         }
     }
 }
 
+/// 管线已经停止：在退避时间之后重建。`attempts` 是此前已经失败的重建次数。
+fn schedule_pipeline_rebuild(stream: &mut StreamState, reason: &str, attempts: u32, now: Instant) {
+    eprintln!(
+        "[HEALTH] Stream {}: pipeline on {} stopped ({}), rebuilding in {}ms (attempt {}).",
+        stream.stream_id,
+        stream_device(stream.stream_id),
+        reason,
+        health::retry_delay(attempts).as_millis(),
+        attempts + 1
+    );
+    stream.health = PipelineHealth::failed(attempts, now);
+}
+
+/// 原地重启一路流的管线，丢掉解码器里的所有状态，之后从下一个关键帧开始解码。
+/// 重启失败（例如输出设备恰好在这时消失）时按退避时间安排重建；已经在等待重建的管线不必重启。
+fn restart_stream_pipeline(stream: &mut StreamState, now: Instant) {
+    if stream.health.is_failed() {
+        return;
+    }
+    stream.chain.mark_broken();
+    let _ = stream.pipeline.set_state(gst::State::Null);
+    match start_pipeline(&stream.pipeline) {
+        Ok(()) => stream.health = PipelineHealth::Recovering,
+        Err(reason) => schedule_pipeline_rebuild(stream, &reason, 0, now),
+    }
+}
+
+/// 丢弃出错的管线，为这路流重新建立一条并启动；失败时按退避时间安排下一次重建。
+/// 新的解码器没有任何参考帧，成功之后扣下所有帧直到下一个关键帧（关键帧请求由
+/// request_recovery_key_frames 发出），自身不带参数集的关键帧仍然会拼接缓存的参数集。
+fn rebuild_pipeline(stream: &mut StreamState, attempts: u32, now: Instant) {
+    let _ = stream.pipeline.set_state(gst::State::Null);
+    let result = create_video_pipeline(stream.codec, &stream_device(stream.stream_id))
+        .map_err(|e| e.to_string())
+        .and_then(|(pipeline, appsrc)| {
+            set_output_caps(&pipeline, stream.format.as_ref());
+            start_pipeline(&pipeline).map(|()| (pipeline, appsrc))
+        });
+    match result {
+        Ok((pipeline, appsrc)) => {
+            stream.pipeline = pipeline;
+            stream.appsrc = appsrc;
+            stream.pipeline_stats.rebuilds += 1;
+            stream.chain.mark_broken();
+            stream.health = PipelineHealth::Recovering;
+            println!(
+                "[HEALTH] Stream {}: pipeline on {} rebuilt after {} failed attempt(s), waiting for a key frame.",
+                stream.stream_id,
+                stream_device(stream.stream_id),
+                attempts
+            );
+        }
+        Err(reason) => schedule_pipeline_rebuild(stream, &reason, attempts + 1, now),
+    }
+}
+
+/// 启动管线。失败时（例如输出设备不存在）把它停回 Null，并返回总线上报告的原因。
+fn start_pipeline(pipeline: &gst::Pipeline) -> std::result::Result<(), String> {
+    if pipeline.set_state(gst::State::Playing).is_ok() {
+        return Ok(());
+    }
+    let reason = pipeline
+        .bus()
+        .and_then(|bus| bus.pop_filtered(&[gst::MessageType::Error]))
+        .and_then(|message| match message.view() {
+            gst::MessageView::Error(e) => Some(e.error().to_string()),
+            _ => None,
+        })
+        .unwrap_or_else(|| "state change failed".to_string());
+    let _ = pipeline.set_state(gst::State::Null);
+    Err(reason)
+}
+
+/// 根据连接状态的变化执行对应的恢复动作。
+async fn on_connection_transition(
+    transition: Transition,
+    state: &mut ReceiverState,
+    socket: &UdpSocket,
+) {
+    println!(
+        "[CONNECTION] {:?} -> {:?} (peer {:?}).",
+        transition.from, transition.to, state.peer_addr
+    );
+    match transition.to {
+        ConnectionState::Streaming => {
+            // 刚建立或刚从卡顿中恢复：每路流的解码器多半都缺了参考帧，先各要一个关键帧
+            let stream_ids: Vec<u8> = state.streams.keys().copied().collect();
+            for stream_id in stream_ids {
+                request_iframe(state, socket, stream_id).await;
+            }
+        }
+        ConnectionState::Lost => {
+            // 发送端已经断开：未完成和缓冲中的帧都不会再有用了
+            log_session_stats(state);
+            for stream in state.streams.values_mut() {
+                stream.reassembly.clear();
+                stream.jitter.clear();
+            }
+            state.pending_path = None;
+        }
+        ConnectionState::Idle | ConnectionState::Handshaking | ConnectionState::Stalled => {}
+    }
+}
+
+/// 按沉默时间推进连接状态，并在会话空闲时向发送端发送 Heartbeat。
+async fn poll_connection(state: &mut ReceiverState, socket: &UdpSocket) {
+    let now = Instant::now();
+    if let Some(transition) = state.connection.poll(now) {
+        on_connection_transition(transition, state, socket).await;
+    }
+    let Some(remote_addr) = state.peer_addr else {
+        return;
+    };
+    // 只给声明了支持 Heartbeat 的发送端发
+    let supports_heartbeat = state
+        .session
+        .as_ref()
+        .is_some_and(|s| s.capabilities & CAP_HEARTBEAT != 0);
+    if supports_heartbeat && state.connection.heartbeat_due(now) {
+        let heartbeat = seal_outgoing(&mut state.sealer, &Packet::Heartbeat.encode());
+        if let Err(e) = socket.send_to(&heartbeat, remote_addr).await {
+            eprintln!("[ERROR] Failed to send heartbeat: {}", e);
+        }
+    }
+}
+
+/// 本地时钟（管线时钟）当前的时刻，单位纳秒。
+fn local_clock_ns(state: &ReceiverState) -> u64 {
+    state.clock.time().map_or(0, gst::ClockTime::nseconds)
+}
+
+/// 结束一个反馈周期，把网络状况报告给发送端。
+async fn send_feedback(state: &mut ReceiverState, socket: &UdpSocket) {
+    let Some(remote_addr) = state.peer_addr else {
+        return;
+    };
+    if state
+        .session
+        .as_ref()
+        .is_none_or(|s| s.capabilities & CAP_CONGESTION_CONTROL == 0)
+    {
+        return;
+    }
+    // 拥塞控制针对的是整条链路，所有流的统计合在一起报告
+    let mut stats = ReassemblyStats::default();
+    for stream in state.streams.values() {
+        stats += stream.reassembly.stats();
+    }
+    let Some(feedback) = state.feedback.report(Instant::now(), &stats) else {
+        return;
+    };
+    let feedback_buf = seal_outgoing(&mut state.sealer, &Packet::Feedback(feedback).encode());
+    if let Err(e) = socket.send_to(&feedback_buf, remote_addr).await {
+        eprintln!("[ERROR] Failed to send feedback: {}", e);
+    }
+}
+
+/// 向发送端发一个 ClockPing，测量两端的时钟偏移。
+async fn send_clock_ping(state: &mut ReceiverState, socket: &UdpSocket) {
+    let Some(remote_addr) = state.peer_addr else {
+        return;
+    };
+    if state
+        .session
+        .as_ref()
+        .is_none_or(|s| s.capabilities & CAP_CLOCK_SYNC == 0)
+    {
+        return;
+    }
+    let ping = state.clock_sync.ping(local_clock_ns(state));
+    let ping_buf = seal_outgoing(&mut state.sealer, &Packet::ClockPing(ping).encode());
+    if let Err(e) = socket.send_to(&ping_buf, remote_addr).await {
+        eprintln!("[ERROR] Failed to send clock ping: {}", e);
+    }
+}
+
+/// 用 ClockPong 更新时钟偏移估计，并让采集时间戳的映射改用它。
+fn handle_clock_pong(pong: &ClockPongPacket, state: &mut ReceiverState) {
+    let now_ns = local_clock_ns(state);
+    if !state.clock_sync.handle_pong(pong, now_ns) {
+        return;
+    }
+    let Some(estimate) = state.clock_sync.estimate() else {
+        return;
+    };
+    let was_synchronized = state.clock_reference.is_some();
+    state.clock_reference = Some(estimate.local_minus_remote_ns());
+    for stream in state.streams.values_mut() {
+        stream.capture_clock.set_reference(state.clock_reference);
+    }
+    if let Some(audio) = &mut state.audio {
+        audio.capture_clock.set_reference(state.clock_reference);
+    }
+    state.clock_pongs += 1;
+    if !was_synchronized || state.clock_pongs.is_multiple_of(CLOCK_REPORT_EVERY) {
+        let avg_latency = state
+            .streams
+            .values()
+            .map(|stream| match stream.average_latency_ms() {
+                Some(latency) => format!("stream {} {:.2} ms", stream.stream_id, latency),
+                None => format!("stream {} n/a", stream.stream_id),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!(
+            "[CLOCK] Sender clock offset {:+.3} ms \u{b1} {:.3} ms (RTT {:.3} ms). Average capture-to-decoder latency: {}.",
+            estimate.offset_ns as f64 / 1_000_000.0,
+            estimate.uncertainty.as_secs_f64() * 1000.0,
+            estimate.rtt.as_secs_f64() * 1000.0,
+            avg_latency
+        );
+    }
+}
+
+/// 放弃长时间收不齐的帧，并把每一帧的丢失记录到日志。
+fn sweep_stale_frames(state: &mut ReceiverState) {
+    let now = Instant::now();
+    for stream in state.streams.values_mut() {
+        for loss in stream.reassembly.sweep(now, STALE_FRAME_TIMEOUT) {
+            let reason = match loss.reason {
+                LossReason::Stale => "timed out",
+                LossReason::OverBudget => "evicted by memory budget",
+            };
+            eprintln!(
+                "[LOSS] Dropped {}frame #{} of stream {} after {}ms ({}): {}/{} packets missing.",
+                if loss.is_key_frame { "key " } else { "" },
+                loss.frame_id,
+                stream.stream_id,
+                loss.age.as_millis(),
+                reason,
+                loss.missing_packets,
+                loss.total_packets
+            );
+        }
+    }
+}
+
+/// 打印当前会话每一路流的重组与抖动缓冲统计。
+fn log_session_stats(state: &ReceiverState) {
+    for stream in state.streams.values() {
+        let stats = stream.reassembly.stats();
+        let jitter = stream.jitter.stats();
+        let format = stream
+            .format
+            .map_or_else(|| format!("{:?}", stream.codec), |f| f.to_string());
+        let chain = stream.chain.stats();
+        println!(
+            "[STATS] Stream {} ({} -> {}): frames completed: {}, frames lost: {}, packets lost: {}, duplicate packets: {}. Jitter buffer released {}, skipped {}, late {}. Reference chain breaks: {}, frames withheld: {}, key frame requests: {}. Pipeline {}: errors: {}, warnings: {}, rebuilds: {}.",
+            stream.stream_id,
+            format,
+            stream_device(stream.stream_id),
+            stats.frames_completed,
+            stats.frames_lost,
+            stats.packets_lost,
+            stats.duplicates,
+            jitter.released,
+            jitter.skipped,
+            jitter.late,
+            chain.breaks,
+            chain.frames_withheld,
+            stream.key_frame_requests,
+            stream.health,
+            stream.pipeline_stats.errors,
+            stream.pipeline_stats.warnings,
+            stream.pipeline_stats.rebuilds
+        );
+    }
+    if let Some(audio) = &state.audio {
+        println!(
+            "[STATS] Audio ({:?} -> {:?}): frames played: {}, frames lost: {}, out-of-order frames discarded: {}.",
+            audio.pipeline.as_ref().map(|p| p.format),
+            audio.sink,
+            audio.frames_played,
+            audio.frames_lost,
+            audio.frames_discarded
+        );
+    }
+}
+
+/// 确保 `stream_id` 这一路流已经打开：第一次见到它时为它建立重组、抖动缓冲和输出到独立设备的解码管线。
+/// 超出 `MAX_STREAMS` 或管线建立失败时返回 false，本会话内这路流的数据都会被丢弃。
+fn open_stream(state: &mut ReceiverState, stream_id: u8) -> bool {
+    if state.streams.contains_key(&stream_id) {
+        return true;
+    }
+    if state.unavailable_streams.contains(&stream_id) {
+        return false;
+    }
+    state.unavailable_streams.insert(stream_id);
+    if state.streams.len() >= MAX_STREAMS {
+        eprintln!(
+            "[STREAM] Ignoring stream {}: already receiving the maximum of {} streams.",
+            stream_id, MAX_STREAMS
+        );
+        return false;
+    }
+    // 还没有收到参数集的流先按握手中声明的编解码器解码
+    let (codec, payload_size) = state
+        .session
+        .as_ref()
+        .map_or((VideoCodec::H264, state.max_payload_size), |s| {
+            (s.codec, s.payload_size)
+        });
+    match StreamState::open(stream_id, codec, state.jitter_profile, payload_size) {
+        Ok(mut stream) => {
+            stream.capture_clock.set_reference(state.clock_reference);
+            println!(
+                "[STREAM] Opened stream {}: decoding {:?} to {}.",
+                stream_id,
+                codec,
+                stream_device(stream_id)
+            );
+            state.streams.insert(stream_id, stream);
+            state.unavailable_streams.remove(&stream_id);
+            true
+        }
+        Err(e) => {
+            eprintln!(
+                "[ERROR] Failed to open stream {} on {}: {}. Its packets are dropped until the next session.",
+                stream_id,
+                stream_device(stream_id),
+                e
+            );
+            false
+        }
+    }
+}
+
+/// 等到 `deadline`；没有 deadline 时永远不返回。
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
 // --- 核心修改 START ---
 
-// 我们不再需要 VideoPipeline 结构体，因为 pipeline 和 appsrc 在 main 函数中创建后会一直存在。
+// 每路流的 pipeline 和 appsrc 在 StreamState 中创建后会一直存在。
+
+/// appsrc 的 caps 与解码部分（解析器 + 解码器）的管线描述。
+fn codec_elements(codec: VideoCodec) -> (&'static str, &'static str) {
+    match codec {
+        VideoCodec::H264 => (
+            "video/x-h264,stream-format=byte-stream",
+            "h264parse ! avdec_h264",
+        ),
+        VideoCodec::H265 => (
+            "video/x-h265,stream-format=byte-stream",
+            "h265parse ! avdec_h265",
+        ),
+        VideoCodec::Mjpeg => ("image/jpeg", "jpegparse ! jpegdec"),
+    }
+}
+
+/// 把管线的解码部分换成 `codec` 对应的元素，并相应地更新 appsrc 的 caps。
+/// 管线的其余部分（appsrc、时钟设置、v4l2sink）保持不变；管线会停在 Null 状态。
+fn set_pipeline_codec(pipeline: &gst::Pipeline, codec: VideoCodec) -> Result<()> {
+    let (caps, decoder_description) = codec_elements(codec);
+    let appsrc = pipeline
+        .by_name("src")
+        .and_then(|e| e.downcast::<gst_app::AppSrc>().ok())
+        .ok_or_else(|| anyhow!("Pipeline has no appsrc"))?;
+    let queue = pipeline
+        .by_name("queue")
+        .ok_or_else(|| anyhow!("Pipeline has no queue"))?;
+    let convert = pipeline
+        .by_name("convert")
+        .ok_or_else(|| anyhow!("Pipeline has no videoconvert"))?;
 
-fn create_video_pipeline() -> Result<(gst::Pipeline, gst_app::AppSrc)> {
+    pipeline.set_state(gst::State::Null)?;
+    if let Some(old) = pipeline.by_name("decoder") {
+        pipeline.remove(&old)?;
+    }
+    let decoder = gst::parse::bin_from_description(decoder_description, true)?;
+    decoder.set_property("name", "decoder");
+    pipeline.add(&decoder)?;
+    gst::Element::link_many([&queue, decoder.upcast_ref(), &convert])?;
+    appsrc.set_caps(Some(&caps.parse::<gst::Caps>()?));
+    Ok(())
+}
+
+fn create_video_pipeline(
+    codec: VideoCodec,
+    device: &str,
+) -> Result<(gst::Pipeline, gst_app::AppSrc)> {
+    // 解码部分随编解码器变化，由 set_pipeline_codec 插在 queue 和 videoconvert 之间
     let pipeline_str = format!(
-        "appsrc name=src caps=\"video/x-h264,stream-format=byte-stream\" ! queue ! h264parse ! avdec_h264 ! videoconvert ! videoflip method=1 ! video/x-raw,format=YUY2 ! v4l2sink name=sink device={}",
-        V4L2_DEVICE
+        "appsrc name=src ! queue name=queue  videoconvert name=convert ! videoflip method=1 ! capsfilter name=outcaps caps=video/x-raw,format=YUY2 ! v4l2sink name=sink device={}",
+        device
     );
 
     let pipeline = gst::parse::launch(&pipeline_str)?
         .downcast::<gst::Pipeline>()
         .map_err(|_| anyhow!("Failed to create video pipeline"))?;
+    set_pipeline_codec(&pipeline, codec)?;
 
     let appsrc = pipeline
         .by_name("src")
@@ -81,8 +1073,13 @@ fn create_video_pipeline() -> Result<(gst::Pipeline, gst_app::AppSrc)> {
         .unwrap();
     let sink = pipeline.by_name("sink").unwrap();
 
+    // PTS 由发送端的采集时间戳换算而来（见 CaptureClock），不能让 appsrc 用到达时间覆盖它。
+    // 管线固定使用系统单调时钟且 base time 为 0，于是 PTS 原样成为 v4l2 buffer 的时间戳，
+    // 下游读到的就是该帧在本机单调时钟上的采集时刻
+    pipeline.use_clock(Some(&gst::SystemClock::obtain()));
+    pipeline.set_start_time(gst::ClockTime::NONE);
+    pipeline.set_base_time(gst::ClockTime::ZERO);
     appsrc.set_property("is-live", true);
-    appsrc.set_property("do-timestamp", true); // 让 appsrc 根据 buffer 的 PTS/DTS 来同步
     appsrc.set_format(gst::Format::Time);
     // 设置一个合理的延迟，但在这里我们主要依赖buffer时间戳
     appsrc.set_latency(
@@ -101,253 +1098,1249 @@ async fn main() -> Result<()> {
 
     let socket = Arc::new(UdpSocket::bind(LISTEN_ADDR).await?);
     println!(
-        "[OK] Listening on {}. Outputting to {}",
-        LISTEN_ADDR, V4L2_DEVICE
+        "[OK] Listening on {}. Outputting stream 0 to {} (stream N goes to /dev/video{}+N, up to {} streams).",
+        LISTEN_ADDR,
+        stream_device(0),
+        V4L2_FIRST_DEVICE,
+        MAX_STREAMS
+    );
+
+    let psk = match std::env::var(PSK_ENV_VAR) {
+        Ok(hex) => Some(
+            parse_hex_key(&hex)
+                .ok_or_else(|| anyhow!("{} must be 64 hex characters (32 bytes)", PSK_ENV_VAR))?,
+        ),
+        Err(_) => None,
+    };
+    if psk.is_some() {
+        println!("[SECURITY] Pre-shared key loaded. Unauthenticated packets will be dropped.");
+    } else {
+        println!(
+            "[SECURITY] {} not set. Stream is received in cleartext.",
+            PSK_ENV_VAR
+        );
+    }
+
+    let jitter_profile = match std::env::var(JITTER_PROFILE_ENV_VAR) {
+        Ok(name) => JitterProfile::from_name(&name).ok_or_else(|| {
+            anyhow!(
+                "{} must be \"latency\" or \"smooth\", got {:?}",
+                JITTER_PROFILE_ENV_VAR,
+                name
+            )
+        })?,
+        Err(_) => JitterProfile::LowLatency,
+    };
+    println!("[JITTER] Using {:?} jitter buffer profile.", jitter_profile);
+
+    let max_payload_size = match std::env::var(MAX_PAYLOAD_ENV_VAR) {
+        Ok(value) => value
+            .parse::<usize>()
+            .ok()
+            .filter(|size| (MIN_PAYLOAD_SIZE..=MAX_PAYLOAD_SIZE).contains(size))
+            .ok_or_else(|| {
+                anyhow!(
+                    "{} must be a number between {} and {}, got {:?}",
+                    MAX_PAYLOAD_ENV_VAR,
+                    MIN_PAYLOAD_SIZE,
+                    MAX_PAYLOAD_SIZE,
+                    value
+                )
+            })?,
+        Err(_) => MAX_PAYLOAD_SIZE,
+    };
+    println!(
+        "[MTU] Accepting fragment payloads of up to {} bytes.",
+        max_payload_size
     );
 
-    // 1. 创建唯一的、持久的 GStreamer 管线
-    let (pipeline, appsrc) = create_video_pipeline()?;
+    let audio_sink = match std::env::var(AUDIO_SINK_ENV_VAR) {
+        Ok(name) => AudioSink::from_name(&name)?,
+        Err(_) => Some(AudioSink::Auto),
+    };
+    match &audio_sink {
+        Some(sink) => println!("[AUDIO] Audio output: {:?}.", sink),
+        None => println!("[AUDIO] Audio disabled ({}=none).", AUDIO_SINK_ENV_VAR),
+    }
 
-    // 2. 立即启动管线，让它进入播放状态并永远保持
-    pipeline.set_state(gst::State::Playing)?;
-    println!("[STATE] Video pipeline is now running and waiting for data.");
+    let metadata_output = match std::env::var(METADATA_ADDR_ENV_VAR) {
+        Ok(value) => {
+            let addr: SocketAddr = value.parse().map_err(|_| {
+                anyhow!(
+                    "{} must be a socket address such as 127.0.0.1:8091",
+                    METADATA_ADDR_ENV_VAR
+                )
+            })?;
+            println!(
+                "[METADATA] Publishing per-frame metadata as JSON to {}.",
+                addr
+            );
+            Some(MetadataOutput::new(addr)?)
+        }
+        Err(_) => None,
+    };
+    let metadata_enabled = metadata_output.is_some();
 
-    // 第一次收到 I-frame 之前，我们需要主动请求一次，确保画面能尽快出来
-    let mut requested_initial_iframe = false;
+    // 1. 为第 0 路流创建持久的 GStreamer 管线并立即启动，让它进入播放状态并永远保持。
+    // 没有握手的旧版发送端只会发送 H.264 的单路流
+    let primary = StreamState::open(0, VideoCodec::H264, jitter_profile, max_payload_size)?;
+    if primary.health == PipelineHealth::Healthy {
+        println!("[STATE] Video pipeline is now running and waiting for data.");
+    }
 
     // 这些状态仍然需要
     let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
-    let mut reassemblers: HashMap<u32, FrameReassembler> = HashMap::new();
-    let mut latency_history: VecDeque<f64> = VecDeque::with_capacity(LATENCY_AVG_WINDOW);
-    let pipeline_start_time = Instant::now(); // 我们需要一个固定的时间起点来计算buffer的PTS
+    let mut state = ReceiverState {
+        streams: BTreeMap::from([(0, primary)]),
+        unavailable_streams: BTreeSet::new(),
+        jitter_profile,
+        audio: audio_sink.clone().map(AudioState::new),
+        metadata_output,
+        clock: gst::SystemClock::obtain(),
+        clock_sync: ClockSync::new(),
+        clock_reference: None,
+        clock_pongs: 0,
+        feedback: FeedbackCollector::new(),
+        peer_addr: None,
+        pending_path: None,
+        connection: Connection::new(CONNECTION_TIMEOUTS),
+        session: None,
+        rejected_peer: None,
+        capabilities: CAP_NACK
+            | CAP_FEC
+            | CAP_CLOCK_SYNC
+            | CAP_HEARTBEAT
+            | CAP_BYE
+            | CAP_CONGESTION_CONTROL
+            | CAP_PMTU_PROBE
+            | if psk.is_some() { CAP_ENCRYPTION } else { 0 }
+            | if audio_sink.is_some() { CAP_AUDIO } else { 0 }
+            | if metadata_enabled { CAP_METADATA } else { 0 },
+        max_payload_size,
+        sealer: psk.as_ref().map(PacketSealer::new),
+        opener: psk.as_ref().map(PacketOpener::new),
+        auth_failures: 0,
+        malformed_packets: 0,
+    };
+    let mut nack_timer = tokio::time::interval(NACK_CHECK_INTERVAL);
+    nack_timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let mut clock_sync_timer = tokio::time::interval(CLOCK_SYNC_INTERVAL);
+    clock_sync_timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let mut feedback_timer = tokio::time::interval(FEEDBACK_INTERVAL);
+    feedback_timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
 
-    // 3. 进入主循环，只做一件事：接收UDP包
-    let mut last_remote_ip: Option<std::net::IpAddr> = None;
-    let mut sps_pps_inject_count = 0usize;
+    // 3. 进入主循环：接收UDP包，并周期性地为缺失分片发送 NACK
     loop {
-        match socket.recv_from(&mut buf).await {
-            Ok((len, remote_addr)) => {
-                let current_ip = remote_addr.ip();
-                let is_new_source = match last_remote_ip {
-                    Some(ip) => current_ip != ip,
-                    None => true,
-                };
-                if is_new_source {
-                    println!(
-                    "[SWITCH] Source IP changed from {:?} to {}. Resetting pipeline, clearing reassemblers, and requesting I-Frame.",
-                    last_remote_ip,
-                    current_ip
-                );
-                    // 只在真正切换时赋值
-                    last_remote_ip = Some(current_ip);
-                    pipeline.set_state(gst::State::Null)?;
-                    pipeline.set_state(gst::State::Playing)?;
-                    reassemblers.clear();
-                    requested_initial_iframe = false;
-                }
+        let jitter_deadline = state
+            .streams
+            .values()
+            .filter_map(|stream| stream.jitter.next_deadline())
+            .min();
+        tokio::select! {
+            result = socket.recv_from(&mut buf) => match result {
+                Ok((len, remote_addr)) => {
+                    // 先认证再做任何处理：未通过认证的包不能触发切源、I 帧请求或进入重组
+                    let plaintext;
+                    let datagram = match state.opener.as_mut() {
+                        Some(opener) => match opener.open(&buf[..len]) {
+                            Ok(decrypted) => {
+                                plaintext = decrypted;
+                                plaintext.as_slice()
+                            }
+                            Err(e) => {
+                                state.auth_failures += 1;
+                                if state.auth_failures == 1 || state.auth_failures.is_multiple_of(100) {
+                                    eprintln!(
+                                        "[SECURITY] Dropped unauthenticated packet from {} ({:?}). Total dropped: {}",
+                                        remote_addr, e, state.auth_failures
+                                    );
+                                }
+                                continue;
+                            }
+                        },
+                        None => &buf[..len],
+                    };
+
+                    let packet = match Packet::decode(datagram) {
+                        Ok(packet) => packet,
+                        Err(ProtocolError::BadVersion(version))
+                            if datagram[0] == PacketType::Hello as u8 =>
+                        {
+                            // 版本不兼容的 Hello 也要答复，让发送端知道自己被拒绝的原因。
+                            // 其他版本的 Hello 长度可能不同，只能依靠各版本共同的开头
+                            if let Some(session_id) = handshake_session_id(&datagram[1..]) {
+                                let hello = HelloPacket {
+                                    magic: PROTOCOL_MAGIC,
+                                    version,
+                                    session_id,
+                                    codec: 0,
+                                    width: 0,
+                                    height: 0,
+                                    fps: 0,
+                                    capabilities: 0,
+                                    max_payload_size: 0,
+                                };
+                                handle_hello(&hello, &remote_addr, &mut state, &socket).await;
+                            }
+                            continue;
+                        }
+                        Err(e) => {
+                            note_rejected(&mut state, &remote_addr, e);
+                            continue;
+                        }
+                    };
 
-                // 如果这是我们收到的第一个包，立即向发送端请求一个I-frame
-                if !requested_initial_iframe {
-                    println!(
-                        "[STATE] First packet received. Requesting I-Frame from {}...",
-                        remote_addr
-                    );
-                    let request = [PacketType::IFrameRequest as u8];
-                    if let Err(e) = socket.send_to(&request, remote_addr).await {
-                        eprintln!("[ERROR] Failed to send I-Frame request: {}", e);
+                    let is_hello = matches!(packet, Packet::Hello(_));
+                    if !is_hello && state.rejected_peer == Some(remote_addr) {
+                        continue;
+                    }
+                    // 会话的切换由 Hello 中的会话纪元决定，见 handle_hello；
+                    // 同一会话换了地址则由连接 ID 识别，见 admit_packet
+                    if !admit_packet(&packet, remote_addr, &mut state, &socket).await {
+                        continue;
+                    }
+
+                    // 发送端的每个消息都刷新连接状态。Hello 由 handle_hello 处理；
+                    // 媒体数据说明发送端正在推流（没有握手的旧版发送端也是如此）
+                    let now = Instant::now();
+                    let transition = match packet {
+                        Packet::Hello(_) | Packet::Bye(_) => None,
+                        Packet::Data { .. }
+                        | Packet::Fec { .. }
+                        | Packet::SpsPps(_)
+                        | Packet::ParameterSets { .. } => {
+                            state.connection.on_established(now)
+                        }
+                        _ => state.connection.on_heard(now),
+                    };
+                    if let Some(transition) = transition {
+                        on_connection_transition(transition, &mut state, &socket).await;
                     }
-                    requested_initial_iframe = true;
+
+                    // 处理包的逻辑保持不变
+                    handle_udp_packet(packet, &remote_addr, &mut state, &socket).await;
+                }
+                Err(e) => {
+                    eprintln!("[ERROR] UDP recv_from failed: {}", e);
+                }
+            },
+            _ = nack_timer.tick() => {
+                send_nacks(&mut state, &socket).await;
+                monitor_pipelines(&mut state);
+                request_recovery_key_frames(&mut state, &socket).await;
+                sweep_stale_frames(&mut state);
+                poll_connection(&mut state, &socket).await;
+            }
+            _ = clock_sync_timer.tick() => {
+                send_clock_ping(&mut state, &socket).await;
+            }
+            _ = feedback_timer.tick() => {
+                send_feedback(&mut state, &socket).await;
+            }
+            _ = sleep_until(jitter_deadline) => {
+                for stream in state.streams.values_mut() {
+                    release_frames(stream, &state.clock, state.metadata_output.as_ref());
                 }
+            }
+        }
+    }
+}
 
-                // 处理包的逻辑保持不变
-                handle_udp_packet(
-                    len,
-                    &buf,
-                    &remote_addr,
-                    &mut reassemblers,
-                    &appsrc,
-                    &socket,
-                    &mut latency_history,
-                    pipeline_start_time,
-                    &mut sps_pps_inject_count,
-                    &pipeline,
-                )
-                .await;
+/// 决定是否接受来自 `remote_addr` 的包。
+///
+/// 会话建立后，只有已验证的地址可以发送不带连接 ID 的包。带有正确连接 ID 的
+/// 数据包和校验包即使来自新地址也照常接收，画面不会中断；同时向新地址发起路径验证，
+/// 通过之后控制流量才切换过去。
+async fn admit_packet(
+    packet: &Packet<'_>,
+    remote_addr: SocketAddr,
+    state: &mut ReceiverState,
+    socket: &UdpSocket,
+) -> bool {
+    let Some(session) = &state.session else {
+        // 尚未握手（例如旧版发送端）：跟随最近一次发来数据的地址
+        state.peer_addr = Some(remote_addr);
+        return true;
+    };
+    if state.peer_addr == Some(remote_addr) {
+        return true;
+    }
+    let connection_id = match packet {
+        // Hello 和 Bye 自带会话 ID，PathResponse 自带令牌，在各自的处理函数里校验
+        Packet::Hello(_) | Packet::Bye(_) | Packet::PathResponse(_) => return true,
+        Packet::Data { header, .. } => header.connection_id,
+        Packet::Fec { header, .. } => header.connection_id,
+        Packet::Audio { header, .. } => header.connection_id,
+        Packet::Metadata { header, .. } => header.connection_id,
+        _ => return false,
+    };
+    if connection_id != session.connection_id {
+        return false;
+    }
+    challenge_path(remote_addr, state, socket).await;
+    true
+}
+
+/// 向新地址发送路径验证挑战；同一地址的挑战按固定间隔重发，令牌保持不变。
+async fn challenge_path(addr: SocketAddr, state: &mut ReceiverState, socket: &UdpSocket) {
+    let now = Instant::now();
+    let token = match &state.pending_path {
+        Some(pending) if pending.addr == addr => {
+            if now.duration_since(pending.sent_at) < PATH_CHALLENGE_RETRY_INTERVAL {
+                return;
             }
-            Err(e) => {
-                eprintln!("[ERROR] UDP recv_from failed: {}", e);
+            pending.token
+        }
+        _ => {
+            println!(
+                "[MIGRATION] Session seen at new address {} (validated: {:?}). Validating path...",
+                addr, state.peer_addr
+            );
+            new_path_token()
+        }
+    };
+    state.pending_path = Some(PendingPath {
+        addr,
+        token,
+        sent_at: now,
+    });
+    let challenge = Packet::PathChallenge(PathPacket { token }).encode();
+    let challenge = seal_outgoing(&mut state.sealer, &challenge);
+    if let Err(e) = socket.send_to(&challenge, addr).await {
+        eprintln!("[ERROR] Failed to send path challenge to {}: {}", addr, e);
+    }
+}
+
+/// 新地址回显了正确的令牌：把会话迁移过去，管线和重组状态保持不变。
+fn complete_path_validation(
+    response: &PathPacket,
+    remote_addr: &SocketAddr,
+    state: &mut ReceiverState,
+) {
+    let Some(pending) = &state.pending_path else {
+        return;
+    };
+    if pending.addr != *remote_addr || pending.token != response.token {
+        return;
+    }
+    if let Some(session) = &state.session {
+        println!(
+            "[MIGRATION] Session {:016x} migrated from {:?} to {} without restarting the pipeline.",
+            session.session_id, state.peer_addr, remote_addr
+        );
+    }
+    state.peer_addr = Some(*remote_addr);
+    state.pending_path = None;
+}
+
+/// 处理发送端的 Hello：检查版本、按会话纪元切换会话，并回复 HelloAck。
+async fn handle_hello(
+    hello: &HelloPacket,
+    remote_addr: &SocketAddr,
+    state: &mut ReceiverState,
+    socket: &UdpSocket,
+) {
+    if hello.magic != PROTOCOL_MAGIC {
+        return;
+    }
+    let codec = VideoCodec::try_from(hello.codec);
+    let status = if hello.version != PROTOCOL_VERSION {
+        eprintln!(
+            "[HANDSHAKE] Rejected sender {}: it speaks protocol v{}, this receiver speaks v{}. Please update both ends to the same version.",
+            remote_addr, hello.version, PROTOCOL_VERSION
+        );
+        HelloStatus::VersionMismatch
+    } else if codec.is_err() {
+        eprintln!(
+            "[HANDSHAKE] Rejected sender {}: unsupported codec id {}.",
+            remote_addr, hello.codec
+        );
+        HelloStatus::UnsupportedCodec
+    } else {
+        HelloStatus::Accepted
+    };
+    let capabilities = hello.capabilities & state.capabilities;
+    let connection_id = match &state.session {
+        Some(session) if session.session_id == hello.session_id => session.connection_id,
+        _ if status == HelloStatus::Accepted => new_connection_id(),
+        _ => 0,
+    };
+    let payload_size = negotiate_payload_size(hello.max_payload_size, state.max_payload_size);
+    let ack = HelloAckPacket {
+        magic: PROTOCOL_MAGIC,
+        version: PROTOCOL_VERSION,
+        session_id: hello.session_id,
+        status: status as u8,
+        capabilities,
+        connection_id,
+        max_payload_size: payload_size as u16,
+    };
+    let ack_buf = seal_outgoing(&mut state.sealer, &Packet::HelloAck(ack).encode());
+    if let Err(e) = socket.send_to(&ack_buf, remote_addr).await {
+        eprintln!("[ERROR] Failed to send HelloAck: {}", e);
+    }
+
+    let codec = match (status, codec) {
+        (HelloStatus::Accepted, Ok(codec)) => codec,
+        _ => {
+            state.rejected_peer = Some(*remote_addr);
+            if let Some(transition) = state.connection.on_heard(Instant::now()) {
+                on_connection_transition(transition, state, socket).await;
             }
+            return;
+        }
+    };
+    if state.rejected_peer == Some(*remote_addr) {
+        state.rejected_peer = None;
+    }
+
+    let is_new_epoch = state
+        .session
+        .as_ref()
+        .is_none_or(|s| s.session_id != hello.session_id);
+    if is_new_epoch {
+        println!(
+            "[SESSION] New session epoch {:016x} from {} ({:?} {}x{}@{}fps, capabilities {:#x}, payload {} bytes). Resetting pipeline, clearing reassemblers, and requesting I-Frame.",
+            hello.session_id, remote_addr, codec, hello.width, hello.height, hello.fps, capabilities, payload_size
+        );
+        if state.session.is_some() {
+            log_session_stats(state);
+        }
+        reset_session_state(state);
+        state.peer_addr = Some(*remote_addr);
+        // 新会话从头开始：下面的 on_established 会把连接带回 Streaming 并请求关键帧
+        state.connection.reset();
+    } else {
+        if state.peer_addr != Some(*remote_addr) {
+            // 同一会话的 Hello 出现在新地址上：先验证路径，再迁移
+            challenge_path(*remote_addr, state, socket).await;
+        }
+        let session = state.session.as_ref().unwrap();
+        if (session.codec, session.width, session.height, session.fps)
+            != (codec, hello.width, hello.height, hello.fps)
+        {
+            println!(
+                "[SESSION] Session {:016x} parameters changed: {:?} {}x{}@{}fps -> {:?} {}x{}@{}fps.",
+                hello.session_id,
+                session.codec,
+                session.width,
+                session.height,
+                session.fps,
+                codec,
+                hello.width,
+                hello.height,
+                hello.fps
+            );
         }
+        if session.payload_size != payload_size {
+            println!(
+                "[MTU] Session {:016x} fragment payload changed: {} -> {} bytes.",
+                hello.session_id, session.payload_size, payload_size
+            );
+        }
+    }
+    // 握手描述的是第 0 路流，其余的流由各自的参数集声明编解码器
+    if let Some(stream) = state.streams.get_mut(&0) {
+        if codec != stream.codec {
+            switch_pipeline_codec(stream, codec);
+        }
+    }
+    state.session = Some(SessionInfo {
+        session_id: hello.session_id,
+        codec,
+        width: hello.width,
+        height: hello.height,
+        fps: hello.fps,
+        capabilities,
+        connection_id,
+        payload_size,
+    });
+    for stream in state.streams.values_mut() {
+        stream.reassembly.set_max_payload_size(payload_size);
+    }
+    if let Some(transition) = state.connection.on_established(Instant::now()) {
+        on_connection_transition(transition, state, socket).await;
+    }
+}
+
+/// 发送端换了这路流的编解码器：重建解码部分，旧编解码器的参数集缓存一并作废。
+fn switch_pipeline_codec(stream: &mut StreamState, codec: VideoCodec) {
+    println!(
+        "[PIPELINE] Stream {}: switching decoder from {:?} to {:?}.",
+        stream.stream_id, stream.codec, codec
+    );
+    match set_pipeline_codec(&stream.pipeline, codec) {
+        Ok(()) => stream.codec = codec,
+        Err(e) => eprintln!(
+            "[ERROR] Failed to switch decoder of stream {} to {:?}: {}",
+            stream.stream_id, codec, e
+        ),
+    }
+    stream.sps_pps_cache = None;
+    stream.last_sps_pps = None;
+    stream.format = None;
+    stream.chain.reset();
+    set_output_caps(&stream.pipeline, None);
+    if stream.health.is_failed() {
+        return;
+    }
+    match start_pipeline(&stream.pipeline) {
+        Ok(()) => stream.health = PipelineHealth::Recovering,
+        Err(reason) => schedule_pipeline_rebuild(stream, &reason, 0, Instant::now()),
+    }
+}
+
+/// 发送端请求的分片负载（0 表示没有指定）与本端上限取较小值，且不低于协议下限。
+fn negotiate_payload_size(requested: u16, limit: usize) -> usize {
+    let requested = match requested {
+        0 => DEFAULT_PAYLOAD_SIZE,
+        size => size as usize,
+    };
+    requested.min(limit).max(MIN_PAYLOAD_SIZE)
+}
+
+/// 丢弃上一个会话留下的一切：刷新每一路流的解码管线，清空重组、抖动缓冲和时钟估计。
+/// 已经打开的流保留它们的管线，新会话继续使用同样的输出设备。
+fn reset_session_state(state: &mut ReceiverState) {
+    let now = Instant::now();
+    for stream in state.streams.values_mut() {
+        restart_stream_pipeline(stream, now);
+        stream.reassembly.clear();
+        stream
+            .reassembly
+            .set_max_payload_size(state.max_payload_size);
+        stream.jitter.clear();
+        stream.capture_clock.reset();
+        stream.latency_history.clear();
+        stream.metadata.clear();
+        stream.last_delivered_frame = None;
+        stream.format = None;
+        stream.chain.reset();
+        stream.last_key_frame_request = None;
+        stream.key_frame_requests = 0;
+        stream.pipeline_stats = PipelineStats::default();
+    }
+    state.unavailable_streams.clear();
+    if let Some(audio) = &mut state.audio {
+        audio.reset();
+    }
+    state.clock_sync.reset();
+    state.clock_reference = None;
+    state.feedback.reset();
+    state.pending_path = None;
+}
+
+/// 处理发送端的 Bye：确认它，并在它结束的正是当前会话时立即回到空闲状态。
+/// 发送端会重发 Bye，所以会话已经结束时仍然要回复 ByeAck。
+async fn handle_bye(
+    bye: &ByePacket,
+    remote_addr: &SocketAddr,
+    state: &mut ReceiverState,
+    socket: &UdpSocket,
+) {
+    let ack = ByeAckPacket {
+        session_id: bye.session_id,
+    };
+    let ack_buf = seal_outgoing(&mut state.sealer, &Packet::ByeAck(ack).encode());
+    if let Err(e) = socket.send_to(&ack_buf, remote_addr).await {
+        eprintln!("[ERROR] Failed to send ByeAck: {}", e);
+    }
+
+    if state
+        .session
+        .as_ref()
+        .is_none_or(|s| s.session_id != bye.session_id)
+    {
+        return;
+    }
+    let reason = ByeReason::try_from(bye.reason).unwrap_or(ByeReason::Error);
+    println!(
+        "[SESSION] Sender {} closed session {:016x} ({:?}). Flushing pipeline and waiting for a new session.",
+        remote_addr, bye.session_id, reason
+    );
+    log_session_stats(state);
+    reset_session_state(state);
+    state.session = None;
+    state.peer_addr = None;
+    if let Some(transition) = state.connection.reset() {
+        on_connection_transition(transition, state, socket).await;
+    }
+}
+
+/// 处理一路流的参数集：内容变化时重启这路流的管线并向发送端请求这路流的 I 帧。
+/// 编解码器与管线当前配置的不同时先切换解码器（例如副流使用与主流不同的编解码器）。
+async fn handle_parameter_sets(
+    stream_id: u8,
+    codec: VideoCodec,
+    payload: &[u8],
+    remote_addr: &SocketAddr,
+    state: &mut ReceiverState,
+    socket: &UdpSocket,
+) {
+    if !open_stream(state, stream_id) {
+        return;
+    }
+    let Some(stream) = state.streams.get_mut(&stream_id) else {
+        return;
+    };
+    if codec != stream.codec {
+        switch_pipeline_codec(stream, codec);
+    }
+    let Some(new_sps_pps) = collect_parameter_sets(codec, payload) else {
+        eprintln!(
+            "[WARN] Stream {}: ignored a parameter set message without any {:?} parameter set NAL.",
+            stream_id, codec
+        );
+        return;
+    };
+    let changed = stream.last_sps_pps.as_ref() != Some(&new_sps_pps);
+    if changed {
+        println!(
+            "[INFO] Stream {}: {:?} parameter sets changed.",
+            stream_id, codec
+        );
+        if update_stream_format(stream, &new_sps_pps) {
+            restart_stream_pipeline(stream, Instant::now());
+        }
+        stream.last_sps_pps = Some(new_sps_pps.clone());
+        stream.sps_pps_cache = Some(new_sps_pps);
+        // 只在变化时请求I-Frame
+        let request = seal_outgoing(
+            &mut state.sealer,
+            &Packet::IFrameRequest { stream_id }.encode(),
+        );
+        if let Err(e) = socket.send_to(&request, remote_addr).await {
+            eprintln!("[ERROR] Failed to send I-Frame request: {}", e);
+        }
+    } else {
+        // 仅更新缓存，不重启pipeline
+        stream.sps_pps_cache = Some(new_sps_pps);
     }
 }
 
 async fn handle_udp_packet(
-    len: usize,
-    buf: &[u8],
+    packet: Packet<'_>,
     remote_addr: &SocketAddr,
-    reassemblers: &mut HashMap<u32, FrameReassembler>,
-    appsrc: &gst_app::AppSrc,
+    state: &mut ReceiverState,
     socket: &Arc<UdpSocket>,
-    latency_history: &mut VecDeque<f64>,
-    pipeline_start_time: Instant,
-    sps_pps_inject_count: &mut usize,
-    pipeline: &gst::Pipeline, // 新增
 ) {
-    // --- 新增：SPS/PPS缓存 ---
-    use std::sync::OnceLock;
-    static SPS_PPS_CACHE: OnceLock<std::sync::Mutex<Option<Vec<u8>>>> = OnceLock::new();
-    let sps_pps_cache = SPS_PPS_CACHE.get_or_init(|| std::sync::Mutex::new(None));
-    // --- 新增结束 ---
-
-    use std::sync::Mutex;
-    static LAST_SPS_PPS: OnceLock<Mutex<Option<Vec<u8>>>> = OnceLock::new();
-
-    if len > 0 && PacketType::try_from(buf[0]) == Ok(PacketType::SpsPps) {
-        let new_sps_pps = buf[1..len].to_vec();
-        let last_sps_pps = LAST_SPS_PPS.get_or_init(|| Mutex::new(None));
-        let mut last_guard = last_sps_pps.lock().unwrap();
-        let changed = match &*last_guard {
-            Some(old) => *old != new_sps_pps,
-            None => true,
-        };
-        if changed {
-            println!("[INFO] SPS/PPS changed, restarting pipeline!");
-            *last_guard = Some(new_sps_pps.clone());
-            *sps_pps_cache.lock().unwrap() = Some(new_sps_pps);
-            *sps_pps_inject_count = 0;
-            if let Err(e) = pipeline.set_state(gst::State::Null) {
-                eprintln!("[ERROR] Failed to set pipeline to Null: {:?}", e);
+    let now = Instant::now();
+    let (stream_id, frame_id, result) = match packet {
+        // 旧的 SpsPps 消息只用于第 0 路的 H.264，ParameterSets 自带流编号和编解码器
+        Packet::SpsPps(payload) => {
+            handle_parameter_sets(0, VideoCodec::H264, payload, remote_addr, state, socket).await;
+            return;
+        }
+        Packet::ParameterSets {
+            stream_id,
+            codec,
+            data,
+        } => {
+            handle_parameter_sets(stream_id, codec, data, remote_addr, state, socket).await;
+            return;
+        }
+        Packet::Hello(hello) => {
+            handle_hello(&hello, remote_addr, state, socket).await;
+            return;
+        }
+        Packet::Bye(bye) => {
+            handle_bye(&bye, remote_addr, state, socket).await;
+            return;
+        }
+        Packet::PathResponse(response) => {
+            complete_path_validation(&response, remote_addr, state);
+            return;
+        }
+        Packet::ClockPong(pong) => {
+            handle_clock_pong(&pong, state);
+            return;
+        }
+        Packet::Audio { header, payload } => {
+            handle_audio(&header, payload, state);
+            return;
+        }
+        Packet::Metadata { header, entries } => {
+            handle_metadata(&header, entries, state);
+            return;
+        }
+        Packet::MtuProbe(probe) => {
+            // 探测能完整到达就说明这个大小能通过整条路径，应答本身不需要填充
+            let ack = MtuProbePacket {
+                probe_id: probe.probe_id,
+                size: probe.size,
+            };
+            let ack_buf = seal_outgoing(&mut state.sealer, &Packet::MtuProbeAck(ack).encode());
+            if let Err(e) = socket.send_to(&ack_buf, remote_addr).await {
+                eprintln!("[ERROR] Failed to send MtuProbeAck: {}", e);
             }
-            if let Err(e) = pipeline.set_state(gst::State::Playing) {
-                eprintln!("[ERROR] Failed to set pipeline to Playing: {:?}", e);
+            return;
+        }
+        Packet::Data { header, payload } => {
+            let arrival_ns = local_clock_ns(state);
+            state
+                .feedback
+                .on_packet(payload.len(), header.capture_timestamp_ns, arrival_ns, now);
+            if !open_stream(state, header.stream_id) {
+                return;
             }
-            // 只在变化时请求I-Frame
-            let request = [PacketType::IFrameRequest as u8];
-            if let Err(e) = socket.send_to(&request, remote_addr).await {
-                eprintln!("[ERROR] Failed to send I-Frame request: {}", e);
+            let stream = state.streams.get_mut(&header.stream_id).unwrap();
+            (
+                header.stream_id,
+                header.frame_id,
+                stream.reassembly.insert_data(&header, payload, now),
+            )
+        }
+        Packet::Fec { header, shard } => {
+            if !open_stream(state, header.stream_id) {
+                return;
+            }
+            let stream = state.streams.get_mut(&header.stream_id).unwrap();
+            (
+                header.stream_id,
+                header.frame_id,
+                stream.reassembly.insert_parity(&header, shard, now),
+            )
+        }
+        // 其余类型（ACK、NACK 等）只会由接收端发出，收到了直接忽略
+        _ => return,
+    };
+    let frame = match result {
+        Ok(Some(frame)) => frame,
+        Ok(None) => return,
+        Err(e) => {
+            note_rejected(
+                state,
+                remote_addr,
+                format_args!(
+                    "fragment of frame #{} (stream {}): {}",
+                    frame_id, stream_id, e
+                ),
+            );
+            return;
+        }
+    };
+
+    if frame.is_key_frame {
+        let ack_buf = seal_outgoing(
+            &mut state.sealer,
+            &Packet::Ack(AckPacket {
+                frame_id,
+                stream_id,
+            })
+            .encode(),
+        );
+        let sock_clone = Arc::clone(socket);
+        let remote_addr_clone = state.peer_addr.unwrap_or(*remote_addr);
+        tokio::spawn(async move {
+            if let Err(e) = sock_clone.send_to(&ack_buf, remote_addr_clone).await {
+                eprintln!(
+                    "[ERROR] Failed to send ACK for frame #{} (stream {}): {}",
+                    frame_id, stream_id, e
+                );
             }
+        });
+    }
+
+    // 用到达时刻校准发送端采集时钟到本地时钟的映射
+    let arrival_ns = local_clock_ns(state);
+    let Some(stream) = state.streams.get_mut(&stream_id) else {
+        return;
+    };
+    if stream
+        .capture_clock
+        .observe(frame.capture_timestamp_ns, arrival_ns)
+    {
+        println!(
+            "[CLOCK] Stream {}: synchronized to sender capture clock (offset {} ns).",
+            stream_id,
+            stream.capture_clock.offset_ns().unwrap_or_default()
+        );
+    }
+
+    // 重组完成的帧先进入这路流的抖动缓冲，按帧号顺序送去解码
+    stream.jitter.push(frame, now);
+    release_frames(stream, &state.clock, state.metadata_output.as_ref());
+}
+
+/// 缓存一帧的元数据，等这一帧送去解码时再和它的 PTS 一起输出；这一帧已经送出时立即输出。
+fn handle_metadata(header: &MetadataHeader, entries: &[u8], state: &mut ReceiverState) {
+    if state.metadata_output.is_none() || !open_stream(state, header.stream_id) {
+        return;
+    }
+    // Packet 解码时已经校验过条目
+    let Ok(entries) = metadata::decode_entries(entries) else {
+        return;
+    };
+    let pending = PendingMetadata {
+        capture_timestamp_ns: header.capture_timestamp_ns,
+        entries,
+    };
+    let stream = state.streams.get_mut(&header.stream_id).unwrap();
+    if stream
+        .last_delivered_frame
+        .is_some_and(|last| header.frame_id <= last)
+    {
+        if let Some(output) = &state.metadata_output {
+            output.publish(header.stream_id, header.frame_id, None, &pending);
+        }
+        return;
+    }
+    stream.metadata.insert(header.frame_id, pending);
+    if stream.metadata.len() > MAX_PENDING_METADATA {
+        stream.metadata.pop_first();
+    }
+}
+
+/// 取出码流中的参数集 NAL，用 4 字节起始码重新拼接；没有参数集时返回 None。
+fn collect_parameter_sets(codec: VideoCodec, data: &[u8]) -> Option<Vec<u8>> {
+    let units: Vec<&[u8]> = nal::annex_b_units(data)
+        .filter(|unit| nal::nal_kind(codec, unit).is_some_and(NalKind::is_parameter_set))
+        .collect();
+    (!units.is_empty()).then(|| nal::to_annex_b(units))
+}
+
+/// 解析参数集中的 SPS，更新这路流的码流格式和 v4l2 输出的 caps。
+///
+/// 返回是否需要重启管线：第一次得知格式，或者解码输出（分辨率、色度格式、位深）变化时，v4l2sink
+/// 必须按新的 caps 重新协商；只有 profile、level 或帧率变化时解码器直接处理带内的新参数集即可。
+/// SPS 无法解析时保守地重启。
+fn update_stream_format(stream: &mut StreamState, parameter_sets: &[u8]) -> bool {
+    let format = match sps::find_sps(stream.codec, parameter_sets) {
+        Some(Ok(format)) => format,
+        Some(Err(e)) => {
+            eprintln!(
+                "[FORMAT] Stream {}: failed to parse SPS ({}), output caps left unchanged.",
+                stream.stream_id, e
+            );
+            return true;
+        }
+        None => return false,
+    };
+    if stream.format == Some(format) {
+        return false;
+    }
+    let renegotiate = stream.format.is_none_or(|old| old.output_differs(&format));
+    println!(
+        "[FORMAT] Stream {}: {}{}",
+        stream.stream_id,
+        format,
+        if renegotiate && stream.format.is_some() {
+            " (output format changed, renegotiating v4l2 output)"
         } else {
-            // 仅更新缓存，不重启pipeline
-            *sps_pps_cache.lock().unwrap() = Some(new_sps_pps);
+            ""
         }
+    );
+    stream.format = Some(format);
+    set_output_caps(&stream.pipeline, Some(&format));
+    renegotiate
+}
+
+/// 按码流格式设置 v4l2 输出的 caps。画面经过 videoflip 旋转了 90 度，宽高互换；
+/// 帧率不写进 caps，编码器写入的 VUI 帧率和解码器实际输出的不一定一致。
+fn set_output_caps(pipeline: &gst::Pipeline, format: Option<&SpsInfo>) {
+    let Some(outcaps) = pipeline.by_name("outcaps") else {
         return;
+    };
+    let mut caps = gst::Caps::builder("video/x-raw").field("format", "YUY2");
+    if let Some(format) = format {
+        caps = caps
+            .field("width", format.height as i32)
+            .field("height", format.width as i32);
     }
+    outcaps.set_property("caps", caps.build());
+}
 
-    if len > 0 && PacketType::try_from(buf[0]) == Ok(PacketType::Data) {
-        if let Some(header) = DataHeader::from_bytes(&buf[1..len]) {
-            let reassembler = reassemblers
-                .entry(header.frame_id)
-                .or_insert_with(|| FrameReassembler::new(&header));
-            let payload = buf[1 + DATA_HEADER_SIZE..len].to_vec();
+/// 按 NAL 类型整理一帧 H.264/H.265 数据，返回要送进解码管线的 Annex-B 码流。
+///
+/// 只含参数集（以及 SEI、AUD）的帧是带内发送的参数集，只更新缓存，返回 None；带着参数集的关键帧
+/// 同样更新缓存；自身没有参数集的关键帧在前面拼接缓存的参数集，解码器重启后也能立即从它开始解码。
+fn prepare_nal_frame(
+    frame: Vec<u8>,
+    frame_id: u32,
+    is_key_frame: bool,
+    stream: &mut StreamState,
+) -> Option<Vec<u8>> {
+    // MediaCodec 输出的是 Annex-B；长度前缀（AVCC，4 字节长度）的码流先转换成 Annex-B
+    let frame = if nal::is_annex_b(&frame) {
+        frame
+    } else {
+        match nal::avcc_to_annex_b(&frame, 4) {
+            Ok(converted) => converted,
+            Err(e) => {
+                eprintln!(
+                    "[WARN] Stream {}: dropped a frame that is neither Annex-B nor AVCC ({}), head={:02x?}",
+                    stream.stream_id,
+                    e,
+                    &frame[..frame.len().min(16)]
+                );
+                return None;
+            }
+        }
+    };
 
-            if let Some(complete_frame) = reassembler.add_packet(header.packet_id, payload) {
-                // 丢弃空帧
-                if complete_frame.is_empty() {
-                    eprintln!("[WARN] Dropped empty frame (size=0), skipping push to appsrc.");
-                    reassemblers.remove(&header.frame_id);
-                    return;
-                }
-                if !reassembler.is_key_frame {
-                    // println!(
-                    //     "[DEBUG] Non-key frame: len={}, head={:02x?}",
-                    //     complete_frame.len(),
-                    //     &complete_frame[..std::cmp::min(32, complete_frame.len())]
-                    // );
-                }
-                if !reassembler.is_key_frame
-                    && complete_frame.len() < 8192
-                    && (complete_frame.windows(5).any(|w| w == [0, 0, 0, 1, 0x67])
-                        || complete_frame.windows(5).any(|w| w == [0, 0, 0, 1, 0x68]))
-                {
-                    *sps_pps_cache.lock().unwrap() = Some(complete_frame.clone());
-                    reassemblers.remove(&header.frame_id);
-                    println!(
-                        "[INFO] SPS/PPS cached. len={}, head={:02x?}",
-                        complete_frame.len(),
-                        &complete_frame[..std::cmp::min(16, complete_frame.len())]
-                    );
-                    return;
-                }
+    // 第一个条带决定这一帧是否是参考帧，以及它的 frame_num
+    let mut first_slice = None;
+    let mut random_access = is_key_frame;
+    for unit in nal::annex_b_units(&frame) {
+        if let Some(NalKind::Slice {
+            random_access: slice_random_access,
+            reference,
+        }) = nal::nal_kind(stream.codec, unit)
+        {
+            first_slice.get_or_insert((unit, reference));
+            random_access |= slice_random_access;
+        }
+    }
+    let has_slice = first_slice.is_some();
+    let parameter_sets = collect_parameter_sets(stream.codec, &frame);
+    // 带内的参数集变化时，在这一帧送进管线之前更新输出格式
+    if let Some(parameter_sets) = &parameter_sets {
+        if stream.sps_pps_cache.as_ref() != Some(parameter_sets)
+            && update_stream_format(stream, parameter_sets)
+        {
+            restart_stream_pipeline(stream, Instant::now());
+        }
+    }
 
-                // I帧前拼接缓存的SPS/PPS
-                let final_frame = if reassembler.is_key_frame {
-                    if *sps_pps_inject_count < 3 {
-                        if let Some(ref sps_pps) = *sps_pps_cache.lock().unwrap() {
-                            let mut v = sps_pps.clone();
-                            v.extend_from_slice(&complete_frame);
-                            *sps_pps_inject_count += 1;
-                            v
-                        } else {
-                            complete_frame.clone()
-                        }
-                    } else {
-                        complete_frame.clone()
-                    }
-                } else {
-                    complete_frame.clone()
-                };
-                // --- 新增结束 ---
-
-                let arrival_time_ns = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_nanos() as u64;
-                let log_latency_ns =
-                    arrival_time_ns.saturating_sub(reassembler.capture_timestamp_ns);
-                let log_latency_ms = log_latency_ns as f64 / 1_000_000.0;
-
-                if latency_history.len() >= LATENCY_AVG_WINDOW {
-                    latency_history.pop_front();
-                }
-                latency_history.push_back(log_latency_ms);
-
-                let avg_latency: f64 =
-                    latency_history.iter().sum::<f64>() / latency_history.len() as f64;
-
-                // println!(
-                //     "[FRAME] #{:<5} | Size: {:>5} bytes | Latency (now): {:>6.2} ms | Latency (avg): {:>6.2} ms",
-                //     header.frame_id,
-                //     final_frame.len(),
-                //     log_latency_ms,
-                //     avg_latency,
-                // );
-
-                let mut gst_buffer = gst::Buffer::with_size(final_frame.len()).unwrap();
-                {
-                    let mut_buffer = gst_buffer.get_mut().unwrap();
-                    let running_time = Instant::now().duration_since(pipeline_start_time);
-                    mut_buffer
-                        .set_pts(gst::ClockTime::from_nseconds(running_time.as_nanos() as u64));
-                    mut_buffer.copy_from_slice(0, &final_frame).unwrap();
-                }
+    // 参考链断开时扣下这一帧，直到下一个关键帧（关键帧请求由 request_recovery_key_frames 发出）
+    let chain_frame = match first_slice {
+        Some((unit, reference)) => ChainFrame::Slice {
+            random_access,
+            reference,
+            frame_num: stream.format.and_then(|format| {
+                sps::slice_frame_num(unit, &format)
+                    .ok()
+                    .map(|value| FrameNum {
+                        value,
+                        max: 1 << format.log2_max_frame_num,
+                    })
+            }),
+        },
+        None => ChainFrame::NoSlice,
+    };
+    if !stream.chain.on_frame(frame_id, chain_frame) {
+        return None;
+    }
 
-                if let Err(e) = appsrc.push_buffer(gst_buffer) {
-                    eprintln!(
-                        "[GStreamer] Error pushing buffer: {:?}. The pipeline might be broken.",
-                        e
-                    );
-                }
+    if !has_slice {
+        if let Some(parameter_sets) = parameter_sets {
+            println!(
+                "[INFO] Stream {}: in-band parameter sets cached. len={}",
+                stream.stream_id,
+                parameter_sets.len()
+            );
+            stream.sps_pps_cache = Some(parameter_sets);
+            return None;
+        }
+        return Some(frame);
+    }
+    match (parameter_sets, &stream.sps_pps_cache) {
+        (Some(parameter_sets), _) => {
+            stream.sps_pps_cache = Some(parameter_sets);
+            Some(frame)
+        }
+        (None, Some(cached)) if random_access => {
+            let mut injected = Vec::with_capacity(cached.len() + frame.len());
+            injected.extend_from_slice(cached);
+            injected.extend_from_slice(&frame);
+            Some(injected)
+        }
+        _ => Some(frame),
+    }
+}
 
-                if reassembler.is_key_frame {
-                    let ack = AckPacket {
-                        frame_id: header.frame_id,
-                    };
-                    let mut ack_buf = [0u8; 1 + ACK_PACKET_SIZE];
-                    ack_buf[0] = PacketType::Ack as u8;
-                    ack_buf[1..].copy_from_slice(&ack.to_bytes());
-                    let sock_clone = Arc::clone(socket);
-                    let remote_addr_clone = *remote_addr;
-                    tokio::spawn(async move {
-                        if let Err(e) = sock_clone.send_to(&ack_buf, remote_addr_clone).await {
-                            eprintln!(
-                                "[ERROR] Failed to send ACK for frame #{}: {}",
-                                header.frame_id, e
-                            );
-                        }
-                    });
-                }
-                reassemblers.remove(&header.frame_id);
+/// 把一路流的抖动缓冲中已经到了释放时刻的帧按顺序送进它的解码管线。
+fn release_frames(
+    stream: &mut StreamState,
+    clock: &gst::Clock,
+    metadata_output: Option<&MetadataOutput>,
+) {
+    let now = Instant::now();
+    while let Some(ReleasedFrame { frame, skipped }) = stream.jitter.pop_ready(now) {
+        if skipped > 0 {
+            eprintln!(
+                "[JITTER] Stream {}: skipped {} frame(s) before #{} that missed their playout deadline (target delay {}ms, jitter {:.1}ms).",
+                stream.stream_id,
+                skipped,
+                frame.frame_id,
+                stream.jitter.target_delay().as_millis(),
+                stream.jitter.jitter().as_secs_f64() * 1000.0
+            );
+        }
+        deliver_frame(frame, stream, clock, metadata_output);
+    }
+}
+
+fn deliver_frame(
+    frame: CompletedFrame,
+    stream: &mut StreamState,
+    clock: &gst::Clock,
+    metadata_output: Option<&MetadataOutput>,
+) {
+    let CompletedFrame {
+        frame_id,
+        is_key_frame,
+        capture_timestamp_ns,
+        data: complete_frame,
+        ..
+    } = frame;
+
+    // 丢弃空帧
+    if complete_frame.is_empty() {
+        eprintln!("[WARN] Dropped empty frame (size=0), skipping push to appsrc.");
+        return;
+    }
+    let final_frame = if stream.codec.has_parameter_sets() {
+        match prepare_nal_frame(complete_frame, frame_id, is_key_frame, stream) {
+            Some(frame) => frame,
+            None => return,
+        }
+    } else {
+        complete_frame
+    };
+    // 管线停止期间丢弃所有帧，重建之后从下一个关键帧开始
+    if stream.health.is_failed() {
+        return;
+    }
+
+    // 只有完成了时钟同步，采集时间戳才能和本地时钟直接相减；否则两端的时钟差会混进延迟里
+    let delivered_ns = clock.time().map_or(0, gst::ClockTime::nseconds);
+    let captured_ns = stream
+        .capture_clock
+        .is_synchronized()
+        .then(|| stream.capture_clock.to_local(capture_timestamp_ns))
+        .flatten();
+    // 最近 LATENCY_AVG_WINDOW 帧的延迟，平均值用于 [CLOCK] 报告和音频的播放延迟，见 average_latency_ms
+    if let Some(captured_ns) = captured_ns {
+        let latency_ms = delivered_ns.saturating_sub(captured_ns) as f64 / 1_000_000.0;
+        let latency_history = &mut stream.latency_history;
+        if latency_history.len() >= LATENCY_AVG_WINDOW {
+            latency_history.pop_front();
+        }
+        latency_history.push_back(latency_ms);
+    }
+
+    let pts = stream
+        .capture_clock
+        .next_pts(capture_timestamp_ns)
+        .unwrap_or_else(|| clock.time().map_or(0, gst::ClockTime::nseconds));
+    let mut gst_buffer = gst::Buffer::with_size(final_frame.len()).unwrap();
+    {
+        let mut_buffer = gst_buffer.get_mut().unwrap();
+        mut_buffer.set_pts(gst::ClockTime::from_nseconds(pts));
+        mut_buffer.copy_from_slice(0, &final_frame).unwrap();
+    }
+
+    // 这一帧的元数据和它的 PTS 一起输出；更早的帧已经被跳过，它们的元数据不再需要
+    stream.last_delivered_frame = Some(frame_id);
+    while let Some(entry) = stream.metadata.first_entry() {
+        if *entry.key() > frame_id {
+            break;
+        }
+        let (id, pending) = entry.remove_entry();
+        if let Some(output) = metadata_output.filter(|_| id == frame_id) {
+            output.publish(stream.stream_id, frame_id, Some(pts), &pending);
+        }
+    }
+
+    match stream.appsrc.push_buffer(gst_buffer) {
+        Ok(_) => {
+            if stream.health == PipelineHealth::Recovering {
+                println!(
+                    "[HEALTH] Stream {}: decoding resumed at frame #{}.",
+                    stream.stream_id, frame_id
+                );
+                stream.health = PipelineHealth::Healthy;
+            }
+        }
+        Err(e) => eprintln!(
+            "[GStreamer] Stream {}: error pushing buffer: {:?}. The pipeline might be broken.",
+            stream.stream_id, e
+        ),
+    }
+}
+
+/// AAC-LC 的 AudioSpecificConfig（ISO 14496-3），作为 caps 中的 codec_data 交给解码器。
+/// 发送端发出的是不带 ADTS 头的原始帧，解码配置完全由采样率和声道数决定。
+fn aac_codec_data(sample_rate: u32, channels: u8) -> Vec<u8> {
+    const SAMPLE_RATES: [u32; 13] = [
+        96_000, 88_200, 64_000, 48_000, 44_100, 32_000, 24_000, 22_050, 16_000, 12_000, 11_025,
+        8_000, 7_350,
+    ];
+    // audioObjectType = 2 (AAC LC)
+    let (mut bits, mut len) = (2u64, 5);
+    match SAMPLE_RATES.iter().position(|&rate| rate == sample_rate) {
+        Some(index) => {
+            bits = bits << 4 | index as u64;
+            len += 4;
+        }
+        // 不在表中的采样率用转义值 0xf 加上 24 位的采样率
+        None => {
+            bits = (bits << 4 | 0xf) << 24 | sample_rate as u64;
+            len += 28;
+        }
+    }
+    // channelConfiguration，再加上 3 个为 0 的 GASpecificConfig 标志位；总长 16 或 40 位
+    bits = (bits << 4 | channels as u64) << 3;
+    len += 7;
+    bits.to_be_bytes()[8 - len / 8..].to_vec()
+}
+
+fn create_audio_pipeline(
+    format: AudioFormat,
+    sink: &AudioSink,
+) -> Result<(gst::Pipeline, gst_app::AppSrc)> {
+    let (caps, decoder) = match format.codec {
+        AudioCodec::Opus => (
+            format!(
+                "audio/x-opus,channel-mapping-family=0,rate={},channels={}",
+                format.sample_rate, format.channels
+            ),
+            "opusdec plc=true",
+        ),
+        AudioCodec::Aac => (
+            format!(
+                "audio/mpeg,mpegversion=4,stream-format=raw,rate={},channels={},codec_data=(buffer){}",
+                format.sample_rate,
+                format.channels,
+                aac_codec_data(format.sample_rate, format.channels)
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect::<String>()
+            ),
+            "aacparse ! avdec_aac",
+        ),
+    };
+    let pipeline_str = format!(
+        "appsrc name=audiosrc ! queue ! {} ! audioconvert ! audioresample ! {}",
+        decoder,
+        sink.description()
+    );
+    let pipeline = gst::parse::launch(&pipeline_str)?
+        .downcast::<gst::Pipeline>()
+        .map_err(|_| anyhow!("Failed to create audio pipeline"))?;
+    let appsrc = pipeline
+        .by_name("audiosrc")
+        .and_then(|e| e.downcast::<gst_app::AppSrc>().ok())
+        .ok_or_else(|| anyhow!("Audio pipeline has no appsrc"))?;
+    appsrc.set_caps(Some(&caps.parse::<gst::Caps>()?));
+    if let AudioSink::File(path) = sink {
+        pipeline
+            .by_name("audiofile")
+            .ok_or_else(|| anyhow!("Audio pipeline has no filesink"))?
+            .set_property("location", path.as_str());
+    }
+
+    // 与视频管线使用同一个时钟和同样的 base time，音频的 PTS 与视频的 PTS 因此可以直接比较
+    pipeline.use_clock(Some(&gst::SystemClock::obtain()));
+    pipeline.set_start_time(gst::ClockTime::NONE);
+    pipeline.set_base_time(gst::ClockTime::ZERO);
+    appsrc.set_property("is-live", true);
+    appsrc.set_format(gst::Format::Time);
+    if let Err(e) = pipeline.set_state(gst::State::Playing) {
+        let _ = pipeline.set_state(gst::State::Null);
+        return Err(e.into());
+    }
+    Ok((pipeline, appsrc))
+}
+
+/// 视频从采集到送进解码管线的实际延迟（取第 0 路流），音频推迟同样的时间播放才能与画面对齐。
+fn video_playout_delay(state: &ReceiverState) -> Option<Duration> {
+    let stream = state.streams.get(&0)?;
+    Some(match stream.average_latency_ms() {
+        Some(latency_ms) => Duration::from_secs_f64(latency_ms.max(0.0) / 1000.0),
+        // 还没有完成时钟同步：映射后的时刻已经包含了最短的传输时间，剩下的主要是抖动缓冲的等待
+        None => stream.jitter.target_delay(),
+    })
+}
+
+/// 把一个音频帧按它的采集时间戳送进音频管线，必要时（第一帧或编码参数变化）先建立管线。
+fn handle_audio(header: &AudioHeader, payload: &[u8], state: &mut ReceiverState) {
+    let playout_delay = video_playout_delay(state);
+    let arrival_ns = local_clock_ns(state);
+    let Some(audio) = &mut state.audio else {
+        return;
+    };
+    // 音频不重传也不缓冲：迟到的帧（序号不比上一帧新）直接丢弃，丢失的帧交给解码器的丢包补偿
+    if let Some(last) = audio.last_sequence {
+        let ahead = header.sequence.wrapping_sub(last);
+        if ahead == 0 || ahead > u32::MAX / 2 {
+            audio.frames_discarded += 1;
+            return;
+        }
+        audio.frames_lost += (ahead - 1) as u64;
+    }
+    audio.last_sequence = Some(header.sequence);
+
+    // 解码时已经检查过编解码器
+    let Ok(codec) = AudioCodec::try_from(header.codec) else {
+        return;
+    };
+    let format = AudioFormat {
+        codec,
+        sample_rate: header.sample_rate,
+        channels: header.channels,
+    };
+    if audio.pipeline.as_ref().map(|p| p.format) != Some(format) {
+        if audio.failed_format == Some(format) {
+            return;
+        }
+        if let Some(old) = audio.pipeline.take() {
+            let _ = old.pipeline.set_state(gst::State::Null);
+        }
+        match create_audio_pipeline(format, &audio.sink) {
+            Ok((pipeline, appsrc)) => {
+                println!(
+                    "[AUDIO] Playing {:?} {}Hz x{} to {:?}.",
+                    format.codec, format.sample_rate, format.channels, audio.sink
+                );
+                audio.pipeline = Some(AudioPipeline {
+                    format,
+                    pipeline,
+                    appsrc,
+                });
+                audio.failed_format = None;
+                audio.playout_delay = None;
+            }
+            Err(e) => {
+                eprintln!(
+                    "[ERROR] Failed to create audio pipeline for {:?} to {:?}: {}. Audio is dropped.",
+                    format, audio.sink, e
+                );
+                audio.failed_format = Some(format);
+                return;
             }
         }
     }
+
+    if audio
+        .capture_clock
+        .observe(header.capture_timestamp_ns, arrival_ns)
+    {
+        println!(
+            "[CLOCK] Audio: synchronized to sender capture clock (offset {} ns).",
+            audio.capture_clock.offset_ns().unwrap_or_default()
+        );
+    }
+    if let Some(delay) = playout_delay {
+        audio.set_playout_delay(delay);
+    }
+    let Some(pts) = audio.capture_clock.next_pts(header.capture_timestamp_ns) else {
+        return;
+    };
+    let Some(pipeline) = &audio.pipeline else {
+        return;
+    };
+    let mut gst_buffer = gst::Buffer::from_slice(payload.to_vec());
+    gst_buffer
+        .get_mut()
+        .unwrap()
+        .set_pts(gst::ClockTime::from_nseconds(pts));
+    match pipeline.appsrc.push_buffer(gst_buffer) {
+        Ok(_) => audio.frames_played += 1,
+        Err(e) => eprintln!(
+            "[GStreamer] Audio: error pushing buffer: {:?}. The pipeline might be broken.",
+            e
+        ),
+    }
 }
@@ -0,0 +1,278 @@
+// --- packages/protocol/src/clock.rs ---
+
+//! 把发送端的采集时间戳映射到接收端的时钟。
+//!
+//! 两端的时钟没有同步，采集时间戳只在发送端的时钟域里有意义。接收端把每一帧的
+//! "到达时刻 − 采集时间戳" 作为样本，估计两个时钟之间的偏移：样本变小时（更快的一次传输）
+//! 以有限的速度向下靠拢，样本变大时只缓慢地向上爬，用来跟随两端晶振的漂移而不被网络抖动带偏。
+//! 这样得到的偏移包含了最短的单程传输时间，映射后的时刻比真实的采集时刻晚这样一个近似恒定的量。
+//!
+//! 双方协商了 `CAP_CLOCK_SYNC` 时，接收端还会周期性地发送 ClockPing，由 `ClockSync`
+//! 按 NTP 的方式从往返中估计真正的时钟偏移及其不确定度；有了这个估计，`CaptureClock`
+//! 改用它作为参考，映射结果就是采集时刻本身。
+use crate::{ClockPingPacket, ClockPongPacket};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// 偏移每个样本最多向下调整这么多，一次特别快的传输不会让时间轴突然跳变
+const MAX_DOWNWARD_STEP: Duration = Duration::from_millis(2);
+// 样本高于当前偏移时，每个样本只朝它移动差值的 1/DRIFT_GAIN
+const DRIFT_GAIN: i128 = 512;
+// 样本与当前偏移相差超过这个量，认为发送端的时钟发生了跳变，直接重新同步
+const RESYNC_THRESHOLD: Duration = Duration::from_secs(1);
+// 估计时钟偏移时参考最近多少次往返（取其中往返时间最短的一次）
+const CLOCK_SYNC_WINDOW: usize = 8;
+// 记住最近发出的多少个 ClockPing，只接受对它们的应答
+const OUTSTANDING_PINGS: usize = 4;
+
+#[derive(Debug, Default, Clone)]
+pub struct CaptureClock {
+    // 平滑后的"本地时刻 − 发送端时刻"，单位纳秒
+    offset_ns: Option<i128>,
+    // 由 ClockSync 测得的"本地时刻 − 发送端时刻"，存在时优先于单程估计
+    reference_ns: Option<i128>,
+    // 上一次输出的本地时刻，保证输出严格递增
+    last_output_ns: Option<u64>,
+}
+
+impl CaptureClock {
+    pub fn new() -> Self {
+        CaptureClock::default()
+    }
+
+    /// 记录一帧的采集时间戳（发送端时钟）和它到达时的本地时刻，两者都以纳秒计。
+    /// 返回 true 表示这次发生了重新同步（第一个样本，或者发送端的时钟跳变）。
+    pub fn observe(&mut self, sender_ns: u64, local_ns: u64) -> bool {
+        let sample = local_ns as i128 - sender_ns as i128;
+        let offset = match self.offset_ns {
+            Some(offset) if (sample - offset).abs() <= RESYNC_THRESHOLD.as_nanos() as i128 => {
+                offset
+            }
+            _ => {
+                self.offset_ns = Some(sample);
+                return true;
+            }
+        };
+        self.offset_ns = Some(if sample < offset {
+            offset - (offset - sample).min(MAX_DOWNWARD_STEP.as_nanos() as i128)
+        } else {
+            offset + (sample - offset) / DRIFT_GAIN
+        });
+        false
+    }
+
+    /// 设置（或清除）往返测量得到的时钟偏移，见 `ClockEstimate::local_minus_remote_ns`。
+    pub fn set_reference(&mut self, local_minus_sender_ns: Option<i128>) {
+        self.reference_ns = local_minus_sender_ns;
+    }
+
+    /// 映射是否基于往返测量（否则只是包含传输时间的单程估计）。
+    pub fn is_synchronized(&self) -> bool {
+        self.reference_ns.is_some()
+    }
+
+    /// 把发送端的时刻换算到本地时钟；还没有任何样本时返回 None。
+    pub fn to_local(&self, sender_ns: u64) -> Option<u64> {
+        let offset = self.reference_ns.or(self.offset_ns)?;
+        Some((sender_ns as i128 + offset).max(0) as u64)
+    }
+
+    /// 与 `to_local` 相同，但保证相邻两次的结果严格递增，适合直接用作 PTS。
+    pub fn next_pts(&mut self, sender_ns: u64) -> Option<u64> {
+        let mut pts = self.to_local(sender_ns)?;
+        if let Some(last) = self.last_output_ns {
+            pts = pts.max(last + 1);
+        }
+        self.last_output_ns = Some(pts);
+        Some(pts)
+    }
+
+    /// 当前使用的时钟偏移（本地 − 发送端）。
+    pub fn offset_ns(&self) -> Option<i128> {
+        self.reference_ns.or(self.offset_ns)
+    }
+
+    pub fn reset(&mut self) {
+        *self = CaptureClock::default();
+    }
+}
+
+/// 一次往返测得的时钟关系。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ClockSample {
+    // 对端时钟 − 本地时钟
+    offset_ns: i128,
+    rtt_ns: u64,
+}
+
+/// 当前的时钟偏移估计。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockEstimate {
+    /// 对端时钟 − 本地时钟
+    pub offset_ns: i128,
+    /// 得出这个估计的那次往返的时间
+    pub rtt: Duration,
+    /// 偏移的误差上界（往返时间的一半：单程时延不对称的最坏情况）
+    pub uncertainty: Duration,
+}
+
+impl ClockEstimate {
+    /// 本地时钟 − 对端时钟，可以直接交给 `CaptureClock::set_reference`。
+    pub fn local_minus_remote_ns(&self) -> i128 {
+        -self.offset_ns
+    }
+}
+
+/// NTP 风格的时钟同步：本端周期性地发 ClockPing，对端回 ClockPong，
+/// 从最近几次往返中挑往返时间最短的一次作为偏移估计（排队越少，时延越对称）。
+#[derive(Debug, Default, Clone)]
+pub struct ClockSync {
+    samples: VecDeque<ClockSample>,
+    // 已发出、尚未收到应答的 ClockPing 的 origin
+    outstanding: VecDeque<u64>,
+}
+
+impl ClockSync {
+    pub fn new() -> Self {
+        ClockSync::default()
+    }
+
+    /// 生成一个 ClockPing，`local_ns` 是此刻的本地时钟。
+    pub fn ping(&mut self, local_ns: u64) -> ClockPingPacket {
+        if self.outstanding.len() >= OUTSTANDING_PINGS {
+            self.outstanding.pop_front();
+        }
+        self.outstanding.push_back(local_ns);
+        ClockPingPacket {
+            origin_ns: local_ns,
+        }
+    }
+
+    /// 处理一个 ClockPong，`local_ns` 是收到它时的本地时钟。
+    /// 不是对最近的 ClockPing 的应答或者时间戳自相矛盾时返回 false。
+    pub fn handle_pong(&mut self, pong: &ClockPongPacket, local_ns: u64) -> bool {
+        let Some(index) = self.outstanding.iter().position(|&o| o == pong.origin_ns) else {
+            return false;
+        };
+        self.outstanding.remove(index);
+        if local_ns < pong.origin_ns || pong.transmit_ns < pong.receive_ns {
+            return false;
+        }
+        let (t1, t2, t3, t4) = (
+            pong.origin_ns as i128,
+            pong.receive_ns as i128,
+            pong.transmit_ns as i128,
+            local_ns as i128,
+        );
+        let sample = ClockSample {
+            offset_ns: ((t2 - t1) + (t3 - t4)) / 2,
+            rtt_ns: ((t4 - t1) - (t3 - t2)).max(0) as u64,
+        };
+        // 对端的时钟跳变了（例如手机校准了系统时间）：旧的样本作废
+        if let Some(current) = self.estimate() {
+            let tolerance = RESYNC_THRESHOLD.as_nanos() as i128 + sample.rtt_ns as i128;
+            if (sample.offset_ns - current.offset_ns).abs() > tolerance {
+                self.samples.clear();
+            }
+        }
+        if self.samples.len() >= CLOCK_SYNC_WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+        true
+    }
+
+    /// 当前的偏移估计；还没有收到任何有效应答时返回 None。
+    pub fn estimate(&self) -> Option<ClockEstimate> {
+        let best = self.samples.iter().min_by_key(|sample| sample.rtt_ns)?;
+        Some(ClockEstimate {
+            offset_ns: best.offset_ns,
+            rtt: Duration::from_nanos(best.rtt_ns),
+            uncertainty: Duration::from_nanos(best.rtt_ns / 2),
+        })
+    }
+
+    pub fn reset(&mut self) {
+        *self = ClockSync::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    #[test]
+    fn test_offset_ignores_jitter_and_follows_drift() {
+        let mut clock = CaptureClock::new();
+        // 发送端时钟比本地快 5 秒，单程传输 10ms，另有 0~20ms 的抖动
+        let base = 5_000 * MS;
+        assert!(clock.observe(base, 10 * MS));
+        for i in 1..600u64 {
+            let sender = base + i * 33 * MS;
+            let jitter = (i * 7 % 21) * MS;
+            assert!(!clock.observe(sender, i * 33 * MS + 10 * MS + jitter));
+        }
+        let offset = clock.offset_ns().unwrap();
+        let expected = 10 * MS as i128 - base as i128;
+        assert!((offset - expected).abs() < 3 * MS as i128, "{}", offset);
+
+        // 发送端的时钟每帧慢 10us（约 300ppm）：偏移跟着往上走
+        let mut local = 599 * 33 * MS;
+        for i in 600..1800u64 {
+            local += 33 * MS + MS / 100;
+            clock.observe(base + i * 33 * MS, local + 10 * MS);
+        }
+        let drifted = clock.offset_ns().unwrap();
+        let expected = (local + 10 * MS) as i128 - (base + 1799 * 33 * MS) as i128;
+        assert!(drifted > offset + 5 * MS as i128, "{}", drifted);
+        assert!((drifted - expected).abs() < 8 * MS as i128, "{}", drifted);
+    }
+
+    #[test]
+    fn test_pts_is_monotonic_and_resyncs_on_jump() {
+        let mut clock = CaptureClock::new();
+        assert_eq!(clock.next_pts(0), None);
+        clock.observe(1_000 * MS, 50 * MS);
+        assert_eq!(clock.next_pts(1_000 * MS), Some(50 * MS));
+        // 更快的一次传输让偏移变小，但输出不会倒退
+        clock.observe(1_033 * MS, 80 * MS);
+        assert_eq!(clock.to_local(1_000 * MS), Some(48 * MS));
+        assert_eq!(clock.next_pts(1_000 * MS), Some(50 * MS + 1));
+        // 发送端的时钟跳了一个小时
+        assert!(clock.observe(3_600_000 * MS, 100 * MS));
+        assert_eq!(clock.to_local(3_600_000 * MS), Some(100 * MS));
+    }
+
+    #[test]
+    fn test_clock_sync_picks_fastest_round_trip() {
+        let mut sync = ClockSync::new();
+        // 对端时钟比本地快 1000ms；第一次往返上行排队 30ms，第二次对称 5ms
+        let ping = sync.ping(100 * MS);
+        let slow = ClockPongPacket {
+            origin_ns: ping.origin_ns,
+            receive_ns: 1_135 * MS,
+            transmit_ns: 1_136 * MS,
+        };
+        assert!(sync.handle_pong(&slow, 141 * MS));
+        assert!(!sync.handle_pong(&slow, 141 * MS));
+        let ping = sync.ping(200 * MS);
+        let fast = ClockPongPacket {
+            origin_ns: ping.origin_ns,
+            receive_ns: 1_205 * MS,
+            transmit_ns: 1_206 * MS,
+        };
+        assert!(sync.handle_pong(&fast, 211 * MS));
+        let estimate = sync.estimate().unwrap();
+        assert_eq!(estimate.offset_ns, 1_000 * MS as i128);
+        assert_eq!(estimate.rtt, Duration::from_millis(10));
+        assert_eq!(estimate.uncertainty, Duration::from_millis(5));
+
+        let mut capture = CaptureClock::new();
+        capture.observe(1_500 * MS, 520 * MS);
+        capture.set_reference(Some(estimate.local_minus_remote_ns()));
+        assert!(capture.is_synchronized());
+        assert_eq!(capture.to_local(1_500 * MS), Some(500 * MS));
+    }
+}
@@ -0,0 +1,334 @@
+// --- packages/protocol/src/congestion.rs ---
+
+//! 端到端的拥塞控制。
+//!
+//! 接收端用 `FeedbackCollector` 统计每个周期内的接收速率、丢包率和最小单程时延的变化，
+//! 以 Feedback 包发回发送端；发送端的 `CongestionController` 据此计算目标码率：
+//! 时延持续上升（链路上的队列在增长）时降到实际接收速率以下，丢包严重时按丢包率下调，
+//! 网络通畅时缓慢上调。目标码率交给编码器，拥塞的链路表现为画质下降而不是成片丢包。
+use crate::reassembly::ReassemblyStats;
+use crate::FeedbackPacket;
+use std::time::{Duration, Instant};
+
+// 最小单程时延每秒上升超过 30ms 视为过载
+const OVERUSE_SLOPE: f64 = 0.03;
+// 丢包率高于此值时按丢包率下调码率
+const LOSS_HIGH: f64 = 0.10;
+// 丢包率低于此值时才允许上调码率
+const LOSS_LOW: f64 = 0.02;
+// 过载时目标码率降到实际接收速率的这个比例，让队列排空
+const OVERUSE_BACKOFF: f64 = 0.85;
+// 网络通畅时每秒的上调倍数
+const INCREASE_PER_SECOND: f64 = 1.08;
+// 上调不超过实际接收速率的这么多倍，编码器产出不足时目标码率不会无限上涨
+const MAX_RATE_HEADROOM: f64 = 1.5;
+// 下调之后至少保持这么久才重新上调
+const DECREASE_HOLD: Duration = Duration::from_millis(500);
+// 目标码率相对上次通知的值变化超过这个比例才通知编码器，避免频繁调用 setParameters
+const REPORT_THRESHOLD: f64 = 0.05;
+
+/// 接收端：统计一个反馈周期内的网络状况。
+#[derive(Debug, Default, Clone)]
+pub struct FeedbackCollector {
+    // 本周期的起点；上一个周期没有收到任何数据时为 None，从下一个包开始计时
+    interval_start: Option<Instant>,
+    bytes: u64,
+    // 本周期内最小的"到达时刻 − 采集时间戳"，单位纳秒；两端时钟的偏移在做差时抵消
+    min_delay_ns: Option<i128>,
+    last_min_delay_ns: Option<i128>,
+    // 上一次报告时的重组统计，用来求本周期的增量
+    last_stats: ReassemblyStats,
+}
+
+impl FeedbackCollector {
+    pub fn new() -> Self {
+        FeedbackCollector::default()
+    }
+
+    /// 记录一个收到的数据分片（包括重传）。`bytes` 是它的负载大小，只统计编码器产出的数据，
+    /// 校验分片不计入。`local_ns` 是它到达时的本地时钟，与采集时间戳只需要各自单调。
+    pub fn on_packet(
+        &mut self,
+        bytes: usize,
+        capture_timestamp_ns: u64,
+        local_ns: u64,
+        now: Instant,
+    ) {
+        self.interval_start.get_or_insert(now);
+        self.bytes += bytes as u64;
+        let delay = local_ns as i128 - capture_timestamp_ns as i128;
+        self.min_delay_ns = Some(self.min_delay_ns.map_or(delay, |min| min.min(delay)));
+    }
+
+    /// 结束当前周期并生成报告，丢包率取自重组器的统计。周期内没有收到任何媒体数据时返回 None。
+    pub fn report(&mut self, now: Instant, stats: &ReassemblyStats) -> Option<FeedbackPacket> {
+        let start = self.interval_start?;
+        if self.bytes == 0 {
+            self.interval_start = None;
+            return None;
+        }
+        let elapsed = now
+            .saturating_duration_since(start)
+            .max(Duration::from_millis(1));
+
+        // 第一次就被 NACK 的分片算作丢包（之后可能被重传补回），没能补回的分片同样计入。
+        // 重传补回的分片也计入 received，所以分母加上没能补回的部分近似发送端的原始发送数
+        let received = stats
+            .packets_received
+            .saturating_sub(self.last_stats.packets_received);
+        let nacked = stats
+            .packets_nacked
+            .saturating_sub(self.last_stats.packets_nacked);
+        let lost = stats
+            .packets_lost
+            .saturating_sub(self.last_stats.packets_lost);
+        let loss = match received + lost {
+            0 => 0.0,
+            sent => (nacked.max(lost) as f64 / sent as f64).min(1.0),
+        };
+
+        let gradient_ns = match (self.last_min_delay_ns, self.min_delay_ns) {
+            (Some(last), Some(current)) => current - last,
+            _ => 0,
+        };
+        let feedback = FeedbackPacket {
+            receive_rate_bps: (self.bytes as f64 * 8.0 / elapsed.as_secs_f64()).min(u32::MAX as f64)
+                as u32,
+            loss_fraction: (loss * 256.0).round().min(255.0) as u8,
+            delay_gradient_us: (gradient_ns / 1_000).clamp(i32::MIN as i128, i32::MAX as i128)
+                as i32,
+            interval_ms: elapsed.as_millis().min(u16::MAX as u128) as u16,
+        };
+
+        self.interval_start = Some(now);
+        self.bytes = 0;
+        self.last_min_delay_ns = self.min_delay_ns.take();
+        self.last_stats = *stats;
+        Some(feedback)
+    }
+
+    pub fn reset(&mut self) {
+        *self = FeedbackCollector::default();
+    }
+}
+
+/// 目标码率的范围与初始值（比特/秒）。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitrateLimits {
+    pub min_bps: u32,
+    pub start_bps: u32,
+    pub max_bps: u32,
+}
+
+impl Default for BitrateLimits {
+    fn default() -> Self {
+        BitrateLimits {
+            min_bps: 300_000,
+            start_bps: 2_000_000,
+            max_bps: 8_000_000,
+        }
+    }
+}
+
+/// 最近一次反馈反映的链路状况。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BandwidthUsage {
+    Normal,
+    /// 单程时延在上升：发送速率超过了链路容量
+    Overusing,
+    /// 丢包严重
+    Lossy,
+}
+
+/// 发送端：根据接收端的反馈计算编码器的目标码率。
+#[derive(Debug, Clone)]
+pub struct CongestionController {
+    limits: BitrateLimits,
+    target_bps: f64,
+    // 最近一次通知编码器的码率
+    reported_bps: u32,
+    usage: BandwidthUsage,
+    last_feedback: Option<Instant>,
+    // 下调之后在这个时刻之前不再上调
+    hold_until: Option<Instant>,
+}
+
+impl CongestionController {
+    pub fn new(limits: BitrateLimits) -> Self {
+        let start = limits.start_bps.clamp(limits.min_bps, limits.max_bps);
+        CongestionController {
+            limits,
+            target_bps: start as f64,
+            reported_bps: start,
+            usage: BandwidthUsage::Normal,
+            last_feedback: None,
+            hold_until: None,
+        }
+    }
+
+    /// 处理一个 Feedback；目标码率变化到值得通知编码器时返回新的码率。
+    pub fn on_feedback(&mut self, feedback: &FeedbackPacket, now: Instant) -> Option<u32> {
+        let elapsed = self
+            .last_feedback
+            .map_or(Duration::ZERO, |last| now.saturating_duration_since(last));
+        self.last_feedback = Some(now);
+
+        let interval = Duration::from_millis(feedback.interval_ms.max(1) as u64);
+        let slope = feedback.delay_gradient_us as f64 / 1e6 / interval.as_secs_f64();
+        let loss = feedback.loss_fraction as f64 / 256.0;
+        let receive_rate = feedback.receive_rate_bps as f64;
+        self.usage = if slope > OVERUSE_SLOPE {
+            BandwidthUsage::Overusing
+        } else if loss > LOSS_HIGH {
+            BandwidthUsage::Lossy
+        } else {
+            BandwidthUsage::Normal
+        };
+        match self.usage {
+            BandwidthUsage::Overusing => {
+                self.target_bps = self.target_bps.min(receive_rate * OVERUSE_BACKOFF);
+                self.hold_until = Some(now + DECREASE_HOLD);
+            }
+            BandwidthUsage::Lossy => {
+                self.target_bps *= 1.0 - 0.5 * loss;
+                self.hold_until = Some(now + DECREASE_HOLD);
+            }
+            BandwidthUsage::Normal
+                if loss < LOSS_LOW && self.hold_until.is_none_or(|until| now >= until) =>
+            {
+                let increased = self.target_bps * INCREASE_PER_SECOND.powf(elapsed.as_secs_f64());
+                self.target_bps =
+                    increased.min(self.target_bps.max(receive_rate * MAX_RATE_HEADROOM));
+            }
+            BandwidthUsage::Normal => {}
+        }
+        self.target_bps = self
+            .target_bps
+            .clamp(self.limits.min_bps as f64, self.limits.max_bps as f64);
+
+        let target = self.target_bps.round() as u32;
+        let change = (target as f64 - self.reported_bps as f64).abs();
+        if change < self.reported_bps as f64 * REPORT_THRESHOLD {
+            return None;
+        }
+        self.reported_bps = target;
+        Some(target)
+    }
+
+    /// 当前的目标码率（比特/秒）。
+    pub fn target_bps(&self) -> u32 {
+        self.target_bps.round() as u32
+    }
+
+    pub fn usage(&self) -> BandwidthUsage {
+        self.usage
+    }
+
+    pub fn limits(&self) -> BitrateLimits {
+        self.limits
+    }
+
+    /// 换一组码率范围并回到初始码率。
+    pub fn set_limits(&mut self, limits: BitrateLimits) {
+        *self = CongestionController::new(limits);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    fn feedback(
+        receive_rate_bps: u32,
+        loss_fraction: u8,
+        delay_gradient_us: i32,
+    ) -> FeedbackPacket {
+        FeedbackPacket {
+            receive_rate_bps,
+            loss_fraction,
+            delay_gradient_us,
+            interval_ms: 200,
+        }
+    }
+
+    #[test]
+    fn test_collector_reports_rate_loss_and_gradient() {
+        let mut collector = FeedbackCollector::new();
+        let t0 = Instant::now();
+        let at = |ms: u64| t0 + Duration::from_millis(ms);
+        assert_eq!(collector.report(t0, &ReassemblyStats::default()), None);
+
+        // 10 个 1000 字节的包，单程时延（含时钟偏移）在 20~29ms 之间
+        for i in 0..10u64 {
+            collector.on_packet(1000, i * 20 * MS, (i * 20 + 20 + i) * MS, at(i * 20));
+        }
+        let stats = ReassemblyStats {
+            packets_received: 10,
+            packets_nacked: 1,
+            ..ReassemblyStats::default()
+        };
+        let first = collector.report(at(200), &stats).unwrap();
+        assert_eq!(first.receive_rate_bps, 400_000);
+        assert_eq!(first.loss_fraction, 26);
+        assert_eq!(first.delay_gradient_us, 0);
+        assert_eq!(first.interval_ms, 200);
+
+        // 下一个周期的最小时延涨到 25ms：队列在增长
+        for i in 10..20u64 {
+            collector.on_packet(1000, i * 20 * MS, (i * 20 + 25) * MS, at(i * 20));
+        }
+        let second = collector.report(at(400), &stats).unwrap();
+        assert_eq!(second.delay_gradient_us, 5_000);
+        assert_eq!(second.loss_fraction, 0);
+
+        // 整个周期都没有数据：不报告，也不会把空闲时间算进下一个周期的速率
+        assert_eq!(collector.report(at(600), &stats), None);
+        collector.on_packet(1000, 0, 0, at(1000));
+        let third = collector.report(at(1100), &stats).unwrap();
+        assert_eq!(third.receive_rate_bps, 80_000);
+    }
+
+    #[test]
+    fn test_controller_backs_off_and_recovers() {
+        let mut controller = CongestionController::new(BitrateLimits::default());
+        let t0 = Instant::now();
+        let at = |ms: u64| t0 + Duration::from_millis(ms);
+        assert_eq!(controller.target_bps(), 2_000_000);
+        assert_eq!(controller.on_feedback(&feedback(2_000_000, 0, 0), t0), None);
+
+        // 时延 200ms 内涨了 10ms：降到接收速率的 85%
+        assert_eq!(
+            controller.on_feedback(&feedback(1_600_000, 0, 10_000), at(200)),
+            Some(1_360_000)
+        );
+        assert_eq!(controller.usage(), BandwidthUsage::Overusing);
+        // 刚下调过，暂不上调
+        assert_eq!(
+            controller.on_feedback(&feedback(1_360_000, 0, 0), at(400)),
+            None
+        );
+        assert_eq!(controller.target_bps(), 1_360_000);
+
+        // 之后每秒上调 8%
+        let mut reported = None;
+        for i in 0..10u64 {
+            reported = controller
+                .on_feedback(&feedback(1_360_000, 0, 0), at(800 + i * 200))
+                .or(reported);
+        }
+        assert!(reported.is_some_and(|bps| bps > 1_400_000));
+        assert!(controller.target_bps() > 1_500_000 && controller.target_bps() < 1_700_000);
+
+        // 丢包 25%：按丢包率的一半下调
+        let before = controller.target_bps() as f64;
+        controller.on_feedback(&feedback(1_500_000, 64, 0), at(3_000));
+        assert_eq!(controller.usage(), BandwidthUsage::Lossy);
+        assert_eq!(controller.target_bps(), (before * 0.875).round() as u32);
+
+        // 无论如何不低于下限
+        controller.on_feedback(&feedback(10_000, 0, 50_000), at(3_200));
+        assert_eq!(controller.target_bps(), 300_000);
+    }
+}
@@ -0,0 +1,238 @@
+// --- packages/protocol/src/connection.rs ---
+
+//! 连接状态机：两端都根据"多久没有收到对端的任何消息"判断对端是否还活着。
+//!
+//! `Idle` → `Handshaking`（开始握手或第一次收到对端的消息）→ `Streaming`（会话建立）。
+//! 对端沉默超过 `stall_timeout` 进入 `Stalled`，超过 `lost_timeout` 进入 `Lost`；
+//! 再次收到对端的消息时恢复。双方在空闲时互发 Heartbeat，保证没有视频数据时也不会被误判。
+//! 状态机本身不做任何恢复动作，调用方根据返回的 `Transition` 决定请求关键帧、重置重组器等。
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// 还没有开始握手，也没有收到过对端的消息
+    Idle,
+    /// 正在握手：已经发出或收到握手消息，会话还没有建立
+    Handshaking,
+    /// 会话已建立，对端最近有消息
+    Streaming,
+    /// 对端暂时沉默，可能只是网络抖动
+    Stalled,
+    /// 对端沉默太久，认为连接已经断开
+    Lost,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionTimeouts {
+    /// 发送 Heartbeat 的间隔
+    pub heartbeat_interval: Duration,
+    /// 对端沉默多久之后进入 Stalled
+    pub stall_timeout: Duration,
+    /// 对端沉默多久之后进入 Lost
+    pub lost_timeout: Duration,
+}
+
+impl Default for ConnectionTimeouts {
+    fn default() -> Self {
+        ConnectionTimeouts {
+            heartbeat_interval: Duration::from_millis(500),
+            stall_timeout: Duration::from_millis(1500),
+            lost_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub from: ConnectionState,
+    pub to: ConnectionState,
+}
+
+#[derive(Debug, Clone)]
+pub struct Connection {
+    state: ConnectionState,
+    timeouts: ConnectionTimeouts,
+    // 最近一次收到对端消息（或开始握手）的时刻，沉默时间从这里算起
+    last_activity: Option<Instant>,
+    last_heartbeat: Option<Instant>,
+}
+
+impl Connection {
+    pub fn new(timeouts: ConnectionTimeouts) -> Self {
+        Connection {
+            state: ConnectionState::Idle,
+            timeouts,
+            last_activity: None,
+            last_heartbeat: None,
+        }
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    pub fn timeouts(&self) -> ConnectionTimeouts {
+        self.timeouts
+    }
+
+    pub fn set_timeouts(&mut self, timeouts: ConnectionTimeouts) {
+        self.timeouts = timeouts;
+    }
+
+    /// 本端开始握手（第一次发出握手消息）。
+    pub fn on_handshake_started(&mut self, now: Instant) -> Option<Transition> {
+        match self.state {
+            ConnectionState::Idle | ConnectionState::Lost => {
+                self.last_activity = Some(now);
+                self.transition(ConnectionState::Handshaking)
+            }
+            _ => None,
+        }
+    }
+
+    /// 收到了对端的任意一个消息。
+    pub fn on_heard(&mut self, now: Instant) -> Option<Transition> {
+        self.last_activity = Some(now);
+        match self.state {
+            ConnectionState::Idle | ConnectionState::Lost => {
+                self.transition(ConnectionState::Handshaking)
+            }
+            ConnectionState::Stalled => self.transition(ConnectionState::Streaming),
+            _ => None,
+        }
+    }
+
+    /// 会话建立（握手完成，或者对端开始推流）。
+    pub fn on_established(&mut self, now: Instant) -> Option<Transition> {
+        self.last_activity = Some(now);
+        self.transition(ConnectionState::Streaming)
+    }
+
+    /// 按沉默时间推进状态，应当周期性地调用。
+    pub fn poll(&mut self, now: Instant) -> Option<Transition> {
+        let silence = now.saturating_duration_since(self.last_activity?);
+        match self.state {
+            ConnectionState::Streaming if silence > self.timeouts.stall_timeout => {
+                self.transition(ConnectionState::Stalled)
+            }
+            ConnectionState::Handshaking | ConnectionState::Stalled
+                if silence > self.timeouts.lost_timeout =>
+            {
+                self.transition(ConnectionState::Lost)
+            }
+            _ => None,
+        }
+    }
+
+    /// 是否到了该发 Heartbeat 的时候；返回 true 时同时记录本次发送时间。
+    /// 只在会话建立之后（Streaming / Stalled）发送。
+    pub fn heartbeat_due(&mut self, now: Instant) -> bool {
+        if !matches!(
+            self.state,
+            ConnectionState::Streaming | ConnectionState::Stalled
+        ) {
+            return false;
+        }
+        if self
+            .last_heartbeat
+            .is_some_and(|last| now.duration_since(last) < self.timeouts.heartbeat_interval)
+        {
+            return false;
+        }
+        self.last_heartbeat = Some(now);
+        true
+    }
+
+    /// 回到 Idle（例如对端换了一个新会话）。
+    pub fn reset(&mut self) -> Option<Transition> {
+        self.last_activity = None;
+        self.last_heartbeat = None;
+        self.transition(ConnectionState::Idle)
+    }
+
+    fn transition(&mut self, to: ConnectionState) -> Option<Transition> {
+        let from = std::mem::replace(&mut self.state, to);
+        (from != to).then_some(Transition { from, to })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUTS: ConnectionTimeouts = ConnectionTimeouts {
+        heartbeat_interval: Duration::from_millis(100),
+        stall_timeout: Duration::from_millis(300),
+        lost_timeout: Duration::from_secs(1),
+    };
+
+    fn to(state: ConnectionState) -> impl Fn(Transition) -> ConnectionState {
+        move |t| {
+            assert_eq!(t.to, state);
+            t.from
+        }
+    }
+
+    #[test]
+    fn test_silence_drives_stalled_and_lost() {
+        let mut connection = Connection::new(TIMEOUTS);
+        let t0 = Instant::now();
+        let at = |ms: u64| t0 + Duration::from_millis(ms);
+        assert_eq!(connection.poll(t0), None);
+        assert_eq!(
+            connection
+                .on_handshake_started(t0)
+                .map(to(ConnectionState::Handshaking)),
+            Some(ConnectionState::Idle)
+        );
+        assert_eq!(
+            connection
+                .on_established(at(50))
+                .map(to(ConnectionState::Streaming)),
+            Some(ConnectionState::Handshaking)
+        );
+        assert_eq!(connection.poll(at(300)), None);
+        assert_eq!(
+            connection.poll(at(351)).map(to(ConnectionState::Stalled)),
+            Some(ConnectionState::Streaming)
+        );
+        // 对端又说话了：直接回到 Streaming
+        assert_eq!(
+            connection
+                .on_heard(at(400))
+                .map(to(ConnectionState::Streaming)),
+            Some(ConnectionState::Stalled)
+        );
+        assert_eq!(
+            connection.poll(at(701)).map(to(ConnectionState::Stalled)),
+            Some(ConnectionState::Streaming)
+        );
+        assert_eq!(
+            connection.poll(at(1401)).map(to(ConnectionState::Lost)),
+            Some(ConnectionState::Stalled)
+        );
+        // 断开之后要重新握手
+        assert_eq!(
+            connection
+                .on_heard(at(2000))
+                .map(to(ConnectionState::Handshaking)),
+            Some(ConnectionState::Lost)
+        );
+    }
+
+    #[test]
+    fn test_heartbeat_only_after_established() {
+        let mut connection = Connection::new(TIMEOUTS);
+        let t0 = Instant::now();
+        assert!(!connection.heartbeat_due(t0));
+        connection.on_established(t0);
+        assert!(connection.heartbeat_due(t0));
+        assert!(!connection.heartbeat_due(t0 + Duration::from_millis(99)));
+        assert!(connection.heartbeat_due(t0 + Duration::from_millis(100)));
+        assert_eq!(
+            connection.reset().map(to(ConnectionState::Idle)),
+            Some(ConnectionState::Streaming)
+        );
+        assert!(!connection.heartbeat_due(t0 + Duration::from_millis(300)));
+    }
+}
@@ -0,0 +1,285 @@
+// --- packages/protocol/src/crypto.rs ---
+
+//! 可选的 UDP 流认证加密：基于预共享密钥的 XChaCha20-Poly1305。
+//!
+//! 加密后的数据报格式为 `[Encrypted][nonce 前缀 16 字节][seq u64][密文 + 16 字节 tag]`，
+//! 其中密文解密后就是一个普通的明文数据报（第一个字节仍然是 `PacketType`）。
+//! 192 位的 nonce 由 `PacketSealer` 创建时随机生成的 128 位前缀与递增的序号拼接而成。
+//! 两个方向以及两端的每次重启共用同一个密钥，序号每次都从 0 开始，所以 nonce 不重复全靠前缀：
+//! 128 位的随机前缀要到大约 2^64 个会话时才可能撞上，而 32 位的前缀在大约 2^16 个会话时
+//! 就很可能撞上，一旦 nonce 重复就会泄露两个明文的异或，并且可以伪造 tag。
+use crate::PacketType;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use std::collections::HashMap;
+
+pub const KEY_SIZE: usize = 32;
+pub const NONCE_PREFIX_SIZE: usize = 16;
+// 加密头部的大小 (type u8:1 + nonce 前缀:16 + seq u64:8 = 25 bytes)
+pub const SECURE_HEADER_SIZE: usize = 25;
+pub const TAG_SIZE: usize = 16;
+// 重放窗口的宽度：比当前最大序号小超过该值的包一律视为重放
+pub const REPLAY_WINDOW_SIZE: u64 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CryptoError {
+    /// 数据报比加密头部加 tag 还短
+    Truncated,
+    /// 配置了密钥，但收到的是明文数据报
+    NotEncrypted,
+    /// tag 校验失败：密钥不对或数据被篡改
+    AuthenticationFailed,
+    /// 序号已经见过或落在重放窗口之外
+    Replayed,
+}
+
+/// 解析 64 个十六进制字符表示的 32 字节密钥。
+pub fn parse_hex_key(hex: &str) -> Option<[u8; KEY_SIZE]> {
+    let hex = hex.trim();
+    if hex.len() != KEY_SIZE * 2 || !hex.is_ascii() {
+        return None;
+    }
+    let mut key = [0u8; KEY_SIZE];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(key)
+}
+
+fn nonce_for(prefix: &[u8; NONCE_PREFIX_SIZE], seq: u64) -> [u8; 24] {
+    let mut nonce = [0u8; 24];
+    nonce[..NONCE_PREFIX_SIZE].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_SIZE..].copy_from_slice(&seq.to_be_bytes());
+    nonce
+}
+
+/// 负责加密发出的数据报。
+pub struct PacketSealer {
+    cipher: XChaCha20Poly1305,
+    nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+    next_seq: u64,
+}
+
+impl PacketSealer {
+    pub fn new(key: &[u8; KEY_SIZE]) -> Self {
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_SIZE];
+        getrandom::getrandom(&mut nonce_prefix).expect("Failed to obtain random nonce prefix");
+        PacketSealer {
+            cipher: XChaCha20Poly1305::new(Key::from_slice(key)),
+            nonce_prefix,
+            next_seq: 0,
+        }
+    }
+
+    pub fn seal(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let mut datagram = Vec::with_capacity(SECURE_HEADER_SIZE + plaintext.len() + TAG_SIZE);
+        datagram.push(PacketType::Encrypted as u8);
+        datagram.extend_from_slice(&self.nonce_prefix);
+        datagram.extend_from_slice(&seq.to_be_bytes());
+        let nonce = nonce_for(&self.nonce_prefix, seq);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: &datagram,
+                },
+            )
+            .expect("XChaCha20-Poly1305 encryption cannot fail for in-memory buffers");
+        datagram.extend_from_slice(&ciphertext);
+        datagram
+    }
+}
+
+/// 序号滑动窗口 (类似 IPsec 的反重放窗口)。
+#[derive(Debug, Default, Clone)]
+pub struct ReplayWindow {
+    highest: Option<u64>,
+    // 第 i 位表示序号 highest - i 是否已经收到
+    bitmap: u64,
+}
+
+impl ReplayWindow {
+    pub fn is_fresh(&self, seq: u64) -> bool {
+        match self.highest {
+            None => true,
+            Some(highest) if seq > highest => true,
+            Some(highest) => {
+                let offset = highest - seq;
+                offset < REPLAY_WINDOW_SIZE && self.bitmap & (1 << offset) == 0
+            }
+        }
+    }
+
+    /// 记录一个已经通过认证的序号。调用前应先用 `is_fresh` 检查。
+    pub fn mark(&mut self, seq: u64) {
+        match self.highest {
+            Some(highest) if seq <= highest => {
+                self.bitmap |= 1 << (highest - seq);
+            }
+            Some(highest) => {
+                let shift = seq - highest;
+                self.bitmap = if shift >= REPLAY_WINDOW_SIZE {
+                    0
+                } else {
+                    self.bitmap << shift
+                } | 1;
+                self.highest = Some(seq);
+            }
+            None => {
+                self.bitmap = 1;
+                self.highest = Some(seq);
+            }
+        }
+    }
+}
+
+/// 负责校验并解密收到的数据报。
+pub struct PacketOpener {
+    cipher: XChaCha20Poly1305,
+    // 每个 nonce 前缀各自的重放窗口。对端重启后会换一个新的前缀，但旧前缀的窗口不能丢弃：
+    // 否则交替重放两个前缀下截获的包，每次切换都会清空窗口，所有包都会被再次接受。
+    // 只有通过认证的包才会新建窗口，条目数不超过对端真实重启的次数
+    replay: HashMap<[u8; NONCE_PREFIX_SIZE], ReplayWindow>,
+}
+
+impl PacketOpener {
+    pub fn new(key: &[u8; KEY_SIZE]) -> Self {
+        PacketOpener {
+            cipher: XChaCha20Poly1305::new(Key::from_slice(key)),
+            replay: HashMap::new(),
+        }
+    }
+
+    pub fn open(&mut self, datagram: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        if datagram.first() != Some(&(PacketType::Encrypted as u8)) {
+            return Err(CryptoError::NotEncrypted);
+        }
+        if datagram.len() < SECURE_HEADER_SIZE + TAG_SIZE {
+            return Err(CryptoError::Truncated);
+        }
+        let prefix: [u8; NONCE_PREFIX_SIZE] =
+            datagram[1..1 + NONCE_PREFIX_SIZE].try_into().unwrap();
+        let seq = u64::from_be_bytes(
+            datagram[1 + NONCE_PREFIX_SIZE..SECURE_HEADER_SIZE]
+                .try_into()
+                .unwrap(),
+        );
+        if self
+            .replay
+            .get(&prefix)
+            .is_some_and(|window| !window.is_fresh(seq))
+        {
+            return Err(CryptoError::Replayed);
+        }
+        let nonce = nonce_for(&prefix, seq);
+        let plaintext = self
+            .cipher
+            .decrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &datagram[SECURE_HEADER_SIZE..],
+                    aad: &datagram[..SECURE_HEADER_SIZE],
+                },
+            )
+            .map_err(|_| CryptoError::AuthenticationFailed)?;
+        // 只有通过认证之后才更新窗口，伪造的包不能推动窗口前进
+        self.replay.entry(prefix).or_default().mark(seq);
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; KEY_SIZE] = [7u8; KEY_SIZE];
+
+    #[test]
+    fn test_seal_open_round_trip() {
+        let mut sealer = PacketSealer::new(&KEY);
+        let mut opener = PacketOpener::new(&KEY);
+        let packet = [PacketType::IFrameRequest as u8];
+        let sealed = sealer.seal(&packet);
+        assert_eq!(sealed.len(), SECURE_HEADER_SIZE + packet.len() + TAG_SIZE);
+        assert_eq!(opener.open(&sealed).unwrap(), packet);
+        // 同一个包再来一次就是重放
+        assert_eq!(opener.open(&sealed), Err(CryptoError::Replayed));
+    }
+
+    #[test]
+    fn test_rejects_tampered_and_cleartext_packets() {
+        let mut sealer = PacketSealer::new(&KEY);
+        let mut opener = PacketOpener::new(&KEY);
+        let mut sealed = sealer.seal(&[PacketType::Data as u8, 1, 2, 3]);
+        let last = sealed.len() - 1;
+        sealed[last] ^= 0xff;
+        assert_eq!(opener.open(&sealed), Err(CryptoError::AuthenticationFailed));
+        assert_eq!(
+            opener.open(&[PacketType::IFrameRequest as u8]),
+            Err(CryptoError::NotEncrypted)
+        );
+        let mut wrong_key = PacketOpener::new(&[8u8; KEY_SIZE]);
+        let sealed = sealer.seal(&[PacketType::Ack as u8, 0, 0, 0, 1]);
+        assert_eq!(
+            wrong_key.open(&sealed),
+            Err(CryptoError::AuthenticationFailed)
+        );
+    }
+
+    #[test]
+    fn test_replay_across_restarts_is_rejected() {
+        let mut before_restart = PacketSealer::new(&KEY);
+        let mut after_restart = PacketSealer::new(&KEY);
+        let mut opener = PacketOpener::new(&KEY);
+        let a = before_restart.seal(&[PacketType::Heartbeat as u8]);
+        let b = after_restart.seal(&[PacketType::Heartbeat as u8]);
+        assert!(opener.open(&a).is_ok());
+        assert!(opener.open(&b).is_ok());
+        // 切换到新的前缀不会清空旧前缀的窗口
+        assert_eq!(opener.open(&a), Err(CryptoError::Replayed));
+        assert_eq!(opener.open(&b), Err(CryptoError::Replayed));
+        // 旧前缀下继续发送的新包仍然被接受
+        assert!(opener
+            .open(&before_restart.seal(&[PacketType::Heartbeat as u8]))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_sealers_never_reuse_a_nonce() {
+        // 同一个密钥下的多个 sealer 相当于两个方向以及两端的多次重启，序号都从 0 开始
+        let mut nonces = std::collections::HashSet::new();
+        for _ in 0..1000 {
+            let mut sealer = PacketSealer::new(&KEY);
+            for _ in 0..4 {
+                let sealed = sealer.seal(&[PacketType::Heartbeat as u8]);
+                assert!(nonces.insert(sealed[1..SECURE_HEADER_SIZE].to_vec()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_replay_window_accepts_reordering() {
+        let mut window = ReplayWindow::default();
+        window.mark(10);
+        assert!(window.is_fresh(9));
+        window.mark(9);
+        assert!(!window.is_fresh(9));
+        window.mark(100);
+        // 落在窗口之外的旧序号被拒绝
+        assert!(!window.is_fresh(10));
+        assert!(window.is_fresh(99));
+    }
+
+    #[test]
+    fn test_parse_hex_key() {
+        let hex = "00".repeat(31) + "ff";
+        let key = parse_hex_key(&hex).unwrap();
+        assert_eq!(key[31], 0xff);
+        assert!(parse_hex_key("abcd").is_none());
+        assert!(parse_hex_key(&"zz".repeat(32)).is_none());
+    }
+}
@@ -0,0 +1,506 @@
+// --- packages/protocol/src/fec.rs ---
+
+//! 前向纠错 (FEC)：发送端为每一帧的数据分片生成校验分片，
+//! 接收端在丢失少量分片时无需等待一次往返重传即可直接恢复。
+//!
+//! 每帧的分片按 `packet_id % group_count` 交织到若干个组中，每组独立编码，
+//! 因此一次连续的突发丢包会分散到不同的组里，而不是集中摧毁同一组。
+use crate::{MAX_PACKETS_PER_FRAME, MAX_PAYLOAD_SIZE};
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use std::collections::VecDeque;
+
+// FEC 包头部的大小
+// (u32:4 + u32:4 + u64:8 + u16:2 + u8:1 + u8:1 + u8:1 + u8:1 + u8:1 + u8:1 + stream_id u8:1 = 25 bytes)
+pub const FEC_HEADER_SIZE: usize = 25;
+
+// 每个分片在编码前都会加上 2 字节的原始长度前缀，以便恢复长度不一的最后一个分片
+pub const SHARD_LEN_PREFIX: usize = 2;
+
+// GF(2^8) 上的 Reed-Solomon 码，数据分片与校验分片总数不能超过 256
+const MAX_RS_SHARDS: usize = 256;
+
+/// 校验分片的最大长度：最长的数据分片加上长度前缀。
+pub const MAX_SHARD_SIZE: usize = MAX_PAYLOAD_SIZE + SHARD_LEN_PREFIX;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FecScheme {
+    /// 每组一个异或校验分片，只能恢复组内一个丢失的分片，但编码开销极低
+    Xor = 0,
+    /// 每组若干个 Reed-Solomon 校验分片，可以恢复与校验分片数量相同的丢失分片
+    ReedSolomon = 1,
+}
+
+impl TryFrom<u8> for FecScheme {
+    type Error = ();
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(FecScheme::Xor),
+            1 => Ok(FecScheme::ReedSolomon),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FecConfig {
+    pub scheme: FecScheme,
+    /// 冗余比例：校验分片数 / 数据分片数，例如 0.25 表示每 4 个数据分片配 1 个校验分片
+    pub redundancy: f32,
+    /// 帧内交织深度，即每帧至少分成多少个组
+    pub interleave_depth: u8,
+    /// 为 true 时，本帧的校验分片被推迟到下一帧的数据分片之间发送，
+    /// 以一帧的恢复延迟换取对更长突发丢包的抵抗力
+    pub cross_frame_interleave: bool,
+}
+
+impl Default for FecConfig {
+    fn default() -> Self {
+        FecConfig {
+            scheme: FecScheme::Xor,
+            redundancy: 0.2,
+            interleave_depth: 1,
+            cross_frame_interleave: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FecError {
+    /// 组内丢失的分片多于可用的校验分片
+    Unrecoverable,
+    /// 分片长度不一致或长度前缀不合法
+    Malformed,
+    /// 底层 Reed-Solomon 编解码器报告的错误
+    Codec(reed_solomon_erasure::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FecHeader {
+    /// 与 `DataHeader::connection_id` 相同
+    pub connection_id: u32,
+    pub frame_id: u32,
+    pub capture_timestamp_ns: u64,
+    /// 本帧数据分片的总数，用于推算各组包含哪些 packet_id
+    pub total_packets: u16,
+    pub is_key_frame: u8,
+    pub scheme: u8,
+    pub group_index: u8,
+    pub group_count: u8,
+    pub parity_index: u8,
+    pub parity_count: u8,
+    /// 与 `DataHeader::stream_id` 相同
+    pub stream_id: u8,
+}
+
+impl FecHeader {
+    pub fn to_bytes(&self) -> [u8; FEC_HEADER_SIZE] {
+        let mut bytes = [0u8; FEC_HEADER_SIZE];
+        bytes[0..4].copy_from_slice(&self.connection_id.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.frame_id.to_be_bytes());
+        bytes[8..16].copy_from_slice(&self.capture_timestamp_ns.to_be_bytes());
+        bytes[16..18].copy_from_slice(&self.total_packets.to_be_bytes());
+        bytes[18] = self.is_key_frame;
+        bytes[19] = self.scheme;
+        bytes[20] = self.group_index;
+        bytes[21] = self.group_count;
+        bytes[22] = self.parity_index;
+        bytes[23] = self.parity_count;
+        bytes[24] = self.stream_id;
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < FEC_HEADER_SIZE {
+            return None;
+        }
+        Some(FecHeader {
+            connection_id: u32::from_be_bytes(bytes[0..4].try_into().ok()?),
+            frame_id: u32::from_be_bytes(bytes[4..8].try_into().ok()?),
+            capture_timestamp_ns: u64::from_be_bytes(bytes[8..16].try_into().ok()?),
+            total_packets: u16::from_be_bytes(bytes[16..18].try_into().ok()?),
+            is_key_frame: bytes[18],
+            scheme: bytes[19],
+            group_index: bytes[20],
+            group_count: bytes[21],
+            parity_index: bytes[22],
+            parity_count: bytes[23],
+            stream_id: bytes[24],
+        })
+    }
+}
+
+/// 组 `group_index` 中数据分片的数量，与 `group_members(..).len()` 相同。
+pub fn group_size(total_packets: u16, group_index: u8, group_count: u8) -> usize {
+    let (total, index, count) = (
+        total_packets as usize,
+        group_index as usize,
+        group_count.max(1) as usize,
+    );
+    if index >= total {
+        return 0;
+    }
+    (total - index).div_ceil(count)
+}
+
+/// 单个校验分片的合法性检查，不合法时返回出错的字段名。
+/// 除了各字段自身的范围外，还要求分组方式是发送端 `encode_frame` 可能产生的：
+/// 组数不超过数据分片数，每组的校验分片数不超过组内数据分片数，异或每组只有一个校验分片。
+pub fn check_fec_header(header: &FecHeader, shard_len: usize) -> Result<(), &'static str> {
+    let Ok(scheme) = FecScheme::try_from(header.scheme) else {
+        return Err("scheme");
+    };
+    if header.total_packets == 0 || header.total_packets as usize > MAX_PACKETS_PER_FRAME {
+        return Err("total_packets");
+    }
+    if header.group_count == 0 || header.group_count as u16 > header.total_packets {
+        return Err("group_count");
+    }
+    if header.group_index >= header.group_count {
+        return Err("group_index");
+    }
+    let members = group_size(header.total_packets, header.group_index, header.group_count);
+    let parity_limit = match scheme {
+        FecScheme::Xor => 1,
+        FecScheme::ReedSolomon => members.min(MAX_RS_SHARDS.saturating_sub(members)),
+    };
+    if header.parity_count == 0 || header.parity_count as usize > parity_limit {
+        return Err("parity_count");
+    }
+    if header.parity_index >= header.parity_count {
+        return Err("parity_index");
+    }
+    if !(SHARD_LEN_PREFIX..=MAX_SHARD_SIZE).contains(&shard_len) {
+        return Err("shard");
+    }
+    Ok(())
+}
+
+/// 组 `group_index` 中按顺序排列的 packet_id。
+pub fn group_members(total_packets: u16, group_index: u8, group_count: u8) -> Vec<u16> {
+    (group_index as u16..total_packets)
+        .step_by(group_count.max(1) as usize)
+        .collect()
+}
+
+fn group_count_for(config: &FecConfig, total_packets: u16) -> u8 {
+    let depth = config.interleave_depth.max(1) as usize;
+    let groups = match config.scheme {
+        // 异或每组只有一个校验分片，冗余比例只能通过组的数量来体现
+        FecScheme::Xor => depth.max((total_packets as f32 * config.redundancy).ceil() as usize),
+        FecScheme::ReedSolomon => depth,
+    };
+    groups.clamp(1, (total_packets as usize).clamp(1, u8::MAX as usize)) as u8
+}
+
+fn parity_count_for(config: &FecConfig, data_shards: usize) -> usize {
+    match config.scheme {
+        FecScheme::Xor => 1,
+        FecScheme::ReedSolomon => ((data_shards as f32 * config.redundancy).ceil() as usize)
+            .max(1)
+            .min(MAX_RS_SHARDS.saturating_sub(data_shards)),
+    }
+}
+
+fn to_shard(payload: &[u8], shard_size: usize) -> Vec<u8> {
+    let mut shard = vec![0u8; shard_size];
+    shard[..SHARD_LEN_PREFIX].copy_from_slice(&(payload.len() as u16).to_be_bytes());
+    shard[SHARD_LEN_PREFIX..SHARD_LEN_PREFIX + payload.len()].copy_from_slice(payload);
+    shard
+}
+
+fn from_shard(shard: &[u8]) -> Result<Vec<u8>, FecError> {
+    if shard.len() < SHARD_LEN_PREFIX {
+        return Err(FecError::Malformed);
+    }
+    let len = u16::from_be_bytes([shard[0], shard[1]]) as usize;
+    shard
+        .get(SHARD_LEN_PREFIX..SHARD_LEN_PREFIX + len)
+        .map(|p| p.to_vec())
+        .ok_or(FecError::Malformed)
+}
+
+fn xor_into(acc: &mut [u8], shard: &[u8]) {
+    for (a, b) in acc.iter_mut().zip(shard) {
+        *a ^= b;
+    }
+}
+
+/// 为一帧的全部数据分片生成校验分片。`template` 提供帧级字段
+/// （连接 ID、frame_id、时间戳、关键帧标志）。
+pub fn encode_frame(
+    config: &FecConfig,
+    template: &FecHeader,
+    chunks: &[&[u8]],
+) -> Result<Vec<(FecHeader, Vec<u8>)>, FecError> {
+    let total_packets = chunks.len() as u16;
+    if total_packets == 0 || config.redundancy <= 0.0 {
+        return Ok(Vec::new());
+    }
+    let group_count = group_count_for(config, total_packets);
+    let mut output = Vec::new();
+    for group_index in 0..group_count {
+        let members = group_members(total_packets, group_index, group_count);
+        let payloads: Vec<&[u8]> = members.iter().map(|&id| chunks[id as usize]).collect();
+        let parity_count = parity_count_for(config, payloads.len());
+        if parity_count == 0 {
+            // 组太大，超出了 Reed-Solomon 的能力范围，只能依赖 NACK
+            continue;
+        }
+        let shard_size = SHARD_LEN_PREFIX + payloads.iter().map(|p| p.len()).max().unwrap_or(0);
+        let mut shards: Vec<Vec<u8>> = payloads.iter().map(|p| to_shard(p, shard_size)).collect();
+        let parity = match config.scheme {
+            FecScheme::Xor => {
+                let mut acc = vec![0u8; shard_size];
+                for shard in &shards {
+                    xor_into(&mut acc, shard);
+                }
+                vec![acc]
+            }
+            FecScheme::ReedSolomon => {
+                let rs = ReedSolomon::new(shards.len(), parity_count).map_err(FecError::Codec)?;
+                shards.extend((0..parity_count).map(|_| vec![0u8; shard_size]));
+                rs.encode(&mut shards).map_err(FecError::Codec)?;
+                shards.split_off(payloads.len())
+            }
+        };
+        for (parity_index, shard) in parity.into_iter().enumerate() {
+            let header = FecHeader {
+                total_packets,
+                scheme: config.scheme as u8,
+                group_index,
+                group_count,
+                parity_index: parity_index as u8,
+                parity_count: parity_count as u8,
+                ..*template
+            };
+            output.push((header, shard));
+        }
+    }
+    Ok(output)
+}
+
+/// 尝试恢复一个组内丢失的数据分片。
+///
+/// `data` 按组内顺序给出已收到的数据分片（原始负载，不含长度前缀），
+/// `parity` 按 `parity_index` 给出已收到的校验分片。
+/// 成功时返回 (组内下标, 恢复出的原始负载) 列表；没有丢失时返回空列表。
+pub fn recover_group(
+    scheme: FecScheme,
+    data: &[Option<&[u8]>],
+    parity: &[Option<&[u8]>],
+) -> Result<Vec<(usize, Vec<u8>)>, FecError> {
+    let missing: Vec<usize> = (0..data.len()).filter(|&i| data[i].is_none()).collect();
+    if missing.is_empty() {
+        return Ok(Vec::new());
+    }
+    let available_parity = parity.iter().filter(|p| p.is_some()).count();
+    if missing.len() > available_parity {
+        return Err(FecError::Unrecoverable);
+    }
+    let shard_size = parity.iter().flatten().next().map(|p| p.len()).unwrap();
+    if parity.iter().flatten().any(|p| p.len() != shard_size)
+        || data
+            .iter()
+            .flatten()
+            .any(|d| d.len() + SHARD_LEN_PREFIX > shard_size)
+    {
+        return Err(FecError::Malformed);
+    }
+    match scheme {
+        // 异或只能恢复一个丢失的分片，不论收到了多少个（伪造的）校验分片
+        FecScheme::Xor if missing.len() > 1 => Err(FecError::Unrecoverable),
+        FecScheme::Xor => {
+            let mut acc = parity.iter().flatten().next().unwrap().to_vec();
+            for payload in data.iter().flatten() {
+                xor_into(&mut acc, &to_shard(payload, shard_size));
+            }
+            Ok(vec![(missing[0], from_shard(&acc)?)])
+        }
+        FecScheme::ReedSolomon => {
+            let rs = ReedSolomon::new(data.len(), parity.len()).map_err(FecError::Codec)?;
+            let mut shards: Vec<Option<Vec<u8>>> = data
+                .iter()
+                .map(|d| d.map(|p| to_shard(p, shard_size)))
+                .chain(parity.iter().map(|p| p.map(|s| s.to_vec())))
+                .collect();
+            rs.reconstruct_data(&mut shards).map_err(FecError::Codec)?;
+            missing
+                .into_iter()
+                .map(|i| Ok((i, from_shard(shards[i].as_ref().unwrap())?)))
+                .collect()
+        }
+    }
+}
+
+/// 决定数据分片与校验分片的发送顺序。
+///
+/// 校验分片被均匀地插入到数据分片之间，而不是集中在帧尾；开启跨帧交织时，
+/// 上一帧的校验分片会被插入到本帧的数据分片之间。
+#[derive(Default)]
+pub struct FecInterleaver {
+    pending_parity: VecDeque<Vec<u8>>,
+}
+
+impl FecInterleaver {
+    pub fn schedule(
+        &mut self,
+        config: &FecConfig,
+        data: Vec<Vec<u8>>,
+        parity: Vec<Vec<u8>>,
+    ) -> Vec<Vec<u8>> {
+        let mut to_spread: Vec<Vec<u8>> = self.pending_parity.drain(..).collect();
+        if config.cross_frame_interleave {
+            self.pending_parity.extend(parity);
+        } else {
+            to_spread.extend(parity);
+        }
+        let mut output = Vec::with_capacity(data.len() + to_spread.len());
+        if to_spread.is_empty() {
+            output.extend(data);
+            return output;
+        }
+        let stride = data.len().div_ceil(to_spread.len()).max(1);
+        let mut parity_iter = to_spread.into_iter();
+        for (i, packet) in data.into_iter().enumerate() {
+            output.push(packet);
+            if (i + 1) % stride == 0 {
+                output.extend(parity_iter.next());
+            }
+        }
+        output.extend(parity_iter);
+        output
+    }
+
+    /// 取出尚未发送的跨帧校验分片（例如关闭 FEC 或停止推流时）。
+    pub fn flush(&mut self) -> Vec<Vec<u8>> {
+        self.pending_parity.drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_chunks() -> Vec<Vec<u8>> {
+        (0..10u8)
+            .map(|i| (0..(100 + i as usize * 7)).map(|b| b as u8 ^ i).collect())
+            .collect()
+    }
+
+    fn template() -> FecHeader {
+        FecHeader {
+            connection_id: 1,
+            frame_id: 7,
+            capture_timestamp_ns: 123,
+            total_packets: 0,
+            is_key_frame: 1,
+            scheme: 0,
+            group_index: 0,
+            group_count: 0,
+            parity_index: 0,
+            parity_count: 0,
+            stream_id: 0,
+        }
+    }
+
+    /// 按给定的丢包集合，逐组执行恢复，返回恢复出的完整分片列表。
+    fn recover_all(
+        scheme: FecScheme,
+        chunks: &[Vec<u8>],
+        parity: &[(FecHeader, Vec<u8>)],
+        lost: &[u16],
+    ) -> Result<Vec<Vec<u8>>, FecError> {
+        let mut result: Vec<Option<Vec<u8>>> = chunks
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (!lost.contains(&(i as u16))).then(|| c.clone()))
+            .collect();
+        let group_count = parity[0].0.group_count;
+        for group_index in 0..group_count {
+            let members = group_members(chunks.len() as u16, group_index, group_count);
+            let data: Vec<Option<&[u8]>> = members
+                .iter()
+                .map(|&id| result[id as usize].as_deref())
+                .collect();
+            let group_parity: Vec<&(FecHeader, Vec<u8>)> = parity
+                .iter()
+                .filter(|(h, _)| h.group_index == group_index)
+                .collect();
+            let parity_slots: Vec<Option<&[u8]>> = group_parity
+                .iter()
+                .map(|(_, p)| Some(p.as_slice()))
+                .collect();
+            for (i, payload) in recover_group(scheme, &data, &parity_slots)? {
+                result[members[i] as usize] = Some(payload);
+            }
+        }
+        Ok(result.into_iter().map(|p| p.unwrap()).collect())
+    }
+
+    #[test]
+    fn test_fec_header_serialization() {
+        let header = FecHeader {
+            total_packets: 10,
+            group_index: 1,
+            group_count: 2,
+            parity_index: 1,
+            parity_count: 3,
+            stream_id: 1,
+            ..template()
+        };
+        assert_eq!(FecHeader::from_bytes(&header.to_bytes()).unwrap(), header);
+    }
+
+    #[test]
+    fn test_xor_recovers_one_loss_per_group() {
+        let chunks = sample_chunks();
+        let refs: Vec<&[u8]> = chunks.iter().map(|c| c.as_slice()).collect();
+        let config = FecConfig {
+            scheme: FecScheme::Xor,
+            redundancy: 0.2,
+            interleave_depth: 2,
+            cross_frame_interleave: false,
+        };
+        let parity = encode_frame(&config, &template(), &refs).unwrap();
+        assert_eq!(parity.len(), 2);
+        // 连续丢失的两个分片落在不同的交织组中，都能恢复
+        let recovered = recover_all(FecScheme::Xor, &chunks, &parity, &[4, 5]).unwrap();
+        assert_eq!(recovered, chunks);
+        // 同一组内丢失两个分片则无法恢复
+        assert_eq!(
+            recover_all(FecScheme::Xor, &chunks, &parity, &[4, 6]),
+            Err(FecError::Unrecoverable)
+        );
+    }
+
+    #[test]
+    fn test_reed_solomon_recovers_multiple_losses() {
+        let chunks = sample_chunks();
+        let refs: Vec<&[u8]> = chunks.iter().map(|c| c.as_slice()).collect();
+        let config = FecConfig {
+            scheme: FecScheme::ReedSolomon,
+            redundancy: 0.25,
+            interleave_depth: 1,
+            cross_frame_interleave: false,
+        };
+        let parity = encode_frame(&config, &template(), &refs).unwrap();
+        assert_eq!(parity.len(), 3);
+        let recovered = recover_all(FecScheme::ReedSolomon, &chunks, &parity, &[0, 5, 9]).unwrap();
+        assert_eq!(recovered, chunks);
+    }
+
+    #[test]
+    fn test_interleaver_spreads_parity() {
+        let config = FecConfig {
+            cross_frame_interleave: true,
+            ..FecConfig::default()
+        };
+        let mut interleaver = FecInterleaver::default();
+        let first = interleaver.schedule(&config, vec![vec![0], vec![1]], vec![vec![10]]);
+        assert_eq!(first, vec![vec![0], vec![1]]);
+        let second = interleaver.schedule(&config, vec![vec![2], vec![3]], vec![vec![11]]);
+        assert_eq!(second, vec![vec![2], vec![3], vec![10]]);
+        assert_eq!(interleaver.flush(), vec![vec![11]]);
+    }
+}
@@ -0,0 +1,294 @@
+// --- packages/protocol/src/jitter.rs ---
+
+//! 接收端的抖动缓冲：位于帧重组与解码器之间，按 `frame_id` 顺序释放帧。
+//!
+//! 每一帧的播放时刻 = 采集时间戳 + 时钟偏移 + 目标延迟。时钟偏移取最近一段时间内最小的
+//! "到达时刻 − 采集时间戳"（也就是最快的一次传输），目标延迟随测得的到达抖动自适应。
+//! 前面的帧到了后面帧的播放时刻还没等到，就直接跳过它，后面的帧不会被无限期地卡住。
+use crate::reassembly::CompletedFrame;
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+// 估计时钟偏移时参考的最近帧数
+const TRANSIT_WINDOW: usize = 128;
+// 缓冲的帧数上限：超出时不再等待，直接释放最早的帧（发送端的帧号跳变时不至于堆积）
+const MAX_BUFFERED_FRAMES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JitterProfile {
+    /// 延迟优先：按顺序到达的帧立即释放，只有出现空洞时才等待
+    LowLatency,
+    /// 流畅优先：每一帧都按采集节奏排定播放时刻，突发到达的帧会被重新摊开
+    Smooth,
+}
+
+impl JitterProfile {
+    /// 解析配置中的名字（`latency` / `smooth`）。
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "latency" | "low-latency" => Some(JitterProfile::LowLatency),
+            "smooth" | "smoothness" => Some(JitterProfile::Smooth),
+            _ => None,
+        }
+    }
+
+    fn min_delay(self) -> Duration {
+        match self {
+            JitterProfile::LowLatency => Duration::from_millis(10),
+            JitterProfile::Smooth => Duration::from_millis(40),
+        }
+    }
+
+    fn max_delay(self) -> Duration {
+        match self {
+            JitterProfile::LowLatency => Duration::from_millis(100),
+            JitterProfile::Smooth => Duration::from_millis(300),
+        }
+    }
+
+    // 目标延迟是抖动估计值的多少倍
+    fn jitter_multiplier(self) -> f64 {
+        match self {
+            JitterProfile::LowLatency => 2.0,
+            JitterProfile::Smooth => 4.0,
+        }
+    }
+}
+
+/// 从抖动缓冲中释放的一帧。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReleasedFrame {
+    pub frame: CompletedFrame,
+    /// 为了释放这一帧而跳过的（没有等到的）帧数
+    pub skipped: u32,
+}
+
+/// 当前会话的抖动缓冲统计，`clear` 时归零。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JitterStats {
+    pub released: u64,
+    /// 错过播放时刻而被跳过的帧
+    pub skipped: u64,
+    /// 在后面的帧已经释放之后才到达、只能丢弃的帧
+    pub late: u64,
+}
+
+pub struct JitterBuffer {
+    profile: JitterProfile,
+    frames: BTreeMap<u32, CompletedFrame>,
+    // 下一个应当释放的帧号；还没有释放过任何帧时为 None
+    next_frame_id: Option<u32>,
+    // 本地时间的零点（第一帧到达的时刻），传输时间都相对它计算
+    epoch: Option<Instant>,
+    // 最近若干帧的"到达时刻 − 采集时间戳"，单位纳秒
+    transits: VecDeque<i128>,
+    last_transit: Option<i128>,
+    // RFC 3550 风格的到达抖动估计，单位纳秒
+    jitter_ns: f64,
+    target_delay: Duration,
+    stats: JitterStats,
+}
+
+impl JitterBuffer {
+    pub fn new(profile: JitterProfile) -> Self {
+        JitterBuffer {
+            profile,
+            frames: BTreeMap::new(),
+            next_frame_id: None,
+            epoch: None,
+            transits: VecDeque::with_capacity(TRANSIT_WINDOW),
+            last_transit: None,
+            jitter_ns: 0.0,
+            target_delay: profile.min_delay(),
+            stats: JitterStats::default(),
+        }
+    }
+
+    /// 放入一帧重组完成的帧。在它之后的帧已经释放过了（来得太晚）则丢弃并返回 false。
+    pub fn push(&mut self, frame: CompletedFrame, now: Instant) -> bool {
+        if self.next_frame_id.is_some_and(|next| frame.frame_id < next)
+            || self.frames.contains_key(&frame.frame_id)
+        {
+            self.stats.late += 1;
+            return false;
+        }
+        let epoch = *self.epoch.get_or_insert(now);
+        let arrival_ns = now.saturating_duration_since(epoch).as_nanos() as i128;
+        self.observe_transit(arrival_ns - frame.capture_timestamp_ns as i128);
+        self.frames.insert(frame.frame_id, frame);
+        true
+    }
+
+    /// 取出下一帧可以送去解码的帧；队首的帧还没到释放时刻时返回 None。
+    pub fn pop_ready(&mut self, now: Instant) -> Option<ReleasedFrame> {
+        let release_at = self.next_deadline()?;
+        if now < release_at && self.frames.len() <= MAX_BUFFERED_FRAMES {
+            return None;
+        }
+        let (frame_id, frame) = self.frames.pop_first()?;
+        let skipped = self.next_frame_id.map_or(0, |next| frame_id - next);
+        self.next_frame_id = Some(frame_id.saturating_add(1));
+        self.stats.released += 1;
+        self.stats.skipped += skipped as u64;
+        Some(ReleasedFrame { frame, skipped })
+    }
+
+    /// 队首的帧可以释放的时刻，缓冲为空时返回 None。
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let (&frame_id, frame) = self.frames.first_key_value()?;
+        let epoch = self.epoch?;
+        let in_order = self.next_frame_id.is_none_or(|next| frame_id == next);
+        if in_order && self.profile == JitterProfile::LowLatency {
+            return Some(epoch);
+        }
+        let offset = self.transits.iter().min().copied().unwrap_or(0);
+        let playout_ns =
+            frame.capture_timestamp_ns as i128 + offset + self.target_delay.as_nanos() as i128;
+        Some(epoch + Duration::from_nanos(playout_ns.max(0) as u64))
+    }
+
+    pub fn set_profile(&mut self, profile: JitterProfile) {
+        self.profile = profile;
+        self.update_target_delay();
+    }
+
+    pub fn profile(&self) -> JitterProfile {
+        self.profile
+    }
+
+    /// 当前的目标延迟（相对最快一次传输额外等待的时间）。
+    pub fn target_delay(&self) -> Duration {
+        self.target_delay
+    }
+
+    /// 当前的到达抖动估计。
+    pub fn jitter(&self) -> Duration {
+        Duration::from_nanos(self.jitter_ns as u64)
+    }
+
+    pub fn stats(&self) -> &JitterStats {
+        &self.stats
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// 丢弃缓冲中的所有帧并重置估计与统计（例如新会话开始时）。
+    pub fn clear(&mut self) {
+        *self = JitterBuffer::new(self.profile);
+    }
+
+    fn observe_transit(&mut self, transit: i128) {
+        if let Some(last) = self.last_transit {
+            let d = (transit - last).abs() as f64;
+            self.jitter_ns += (d - self.jitter_ns) / 16.0;
+        }
+        self.last_transit = Some(transit);
+        if self.transits.len() >= TRANSIT_WINDOW {
+            self.transits.pop_front();
+        }
+        self.transits.push_back(transit);
+        self.update_target_delay();
+    }
+
+    fn update_target_delay(&mut self) {
+        let wanted =
+            Duration::from_nanos((self.jitter_ns * self.profile.jitter_multiplier()) as u64);
+        self.target_delay = wanted.clamp(self.profile.min_delay(), self.profile.max_delay());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    fn frame(frame_id: u32, capture_ms: u64) -> CompletedFrame {
+        CompletedFrame {
+            frame_id,
+            is_key_frame: false,
+            capture_timestamp_ns: capture_ms * MS,
+            data: vec![frame_id as u8],
+        }
+    }
+
+    fn released_ids(buffer: &mut JitterBuffer, now: Instant) -> Vec<(u32, u32)> {
+        std::iter::from_fn(|| buffer.pop_ready(now))
+            .map(|released| (released.frame.frame_id, released.skipped))
+            .collect()
+    }
+
+    #[test]
+    fn test_low_latency_reorders_and_skips_missing_frames() {
+        let mut buffer = JitterBuffer::new(JitterProfile::LowLatency);
+        let t0 = Instant::now();
+        let at = |ms: u64| t0 + Duration::from_millis(ms);
+        assert!(buffer.push(frame(1, 0), t0));
+        assert_eq!(released_ids(&mut buffer, t0), [(1, 0)]);
+
+        // 帧 3 先于帧 2 到达：等帧 2 而不是立即把 3 送去解码
+        assert!(buffer.push(frame(3, 66), at(33)));
+        assert!(released_ids(&mut buffer, at(33)).is_empty());
+        assert!(buffer.push(frame(2, 33), at(35)));
+        assert_eq!(released_ids(&mut buffer, at(35)), [(2, 0), (3, 0)]);
+
+        // 帧 4 一直没来：帧 5 到了播放时刻就跳过它
+        assert!(buffer.push(frame(5, 132), at(100)));
+        assert!(released_ids(&mut buffer, at(100)).is_empty());
+        assert_eq!(released_ids(&mut buffer, at(400)), [(5, 1)]);
+        assert!(!buffer.push(frame(4, 99), at(401)));
+        assert_eq!(
+            *buffer.stats(),
+            JitterStats {
+                released: 4,
+                skipped: 1,
+                late: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_smooth_profile_spreads_bursts() {
+        let mut buffer = JitterBuffer::new(JitterProfile::Smooth);
+        let t0 = Instant::now();
+        for (frame_id, capture_ms) in [(1, 0), (2, 33), (3, 66)] {
+            assert!(buffer.push(frame(frame_id, capture_ms), t0));
+        }
+        assert_eq!(buffer.target_delay(), Duration::from_millis(40));
+        // 三帧同时到达，按采集间隔依次释放
+        assert_eq!(released_ids(&mut buffer, t0), [(1, 0)]);
+        assert_eq!(buffer.next_deadline(), Some(t0 + Duration::from_millis(7)));
+        assert_eq!(
+            released_ids(&mut buffer, t0 + Duration::from_millis(7)),
+            [(2, 0)]
+        );
+        assert_eq!(
+            released_ids(&mut buffer, t0 + Duration::from_millis(40)),
+            [(3, 0)]
+        );
+    }
+
+    #[test]
+    fn test_target_delay_follows_jitter() {
+        let mut buffer = JitterBuffer::new(JitterProfile::LowLatency);
+        let t0 = Instant::now();
+        // 传输时间在 0 与 30ms 之间来回跳
+        for i in 0..64u32 {
+            let capture_ms = i as u64 * 33;
+            let arrival_ms = capture_ms + if i % 2 == 0 { 0 } else { 30 };
+            buffer.push(frame(i, capture_ms), t0 + Duration::from_millis(arrival_ms));
+        }
+        assert!(buffer.jitter() > Duration::from_millis(20));
+        assert!(buffer.target_delay() > Duration::from_millis(40));
+        assert!(buffer.target_delay() <= Duration::from_millis(100));
+
+        buffer.set_profile(JitterProfile::Smooth);
+        assert!(buffer.target_delay() > Duration::from_millis(100));
+    }
+}
@@ -3,6 +3,22 @@
 //! 定义了 NeuroCam 项目中用于网络传输的UDP分片与重组协议。
 use std::mem::size_of;
 
+pub mod clock;
+pub mod congestion;
+pub mod connection;
+pub mod crypto;
+pub mod fec;
+pub mod jitter;
+pub mod metadata;
+pub mod nal;
+mod packet;
+pub mod pmtu;
+pub mod reassembly;
+pub mod refchain;
+pub mod sps;
+
+pub use packet::{Packet, ProtocolError};
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PacketType {
@@ -10,29 +26,87 @@ pub enum PacketType {
     Ack = 1,
     IFrameRequest = 2,
     SpsPps = 3, // 新增
+    Nack = 4,
+    Fec = 5,
+    Encrypted = 6,
+    Hello = 7,
+    HelloAck = 8,
+    PathChallenge = 9,
+    PathResponse = 10,
+    ClockPing = 11,
+    ClockPong = 12,
+    Heartbeat = 13,
+    Bye = 14,
+    ByeAck = 15,
+    Feedback = 16,
+    MtuProbe = 17,
+    MtuProbeAck = 18,
+    ParameterSets = 19,
+    Audio = 20,
+    Metadata = 21,
 }
-// ... (TryFrom 实现无变化)
 impl TryFrom<u8> for PacketType {
-    type Error = ();
+    type Error = ProtocolError;
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
             0 => Ok(PacketType::Data),
             1 => Ok(PacketType::Ack),
             2 => Ok(PacketType::IFrameRequest),
             3 => Ok(PacketType::SpsPps), // 必须加上
-            _ => Err(()),
+            4 => Ok(PacketType::Nack),
+            5 => Ok(PacketType::Fec),
+            6 => Ok(PacketType::Encrypted),
+            7 => Ok(PacketType::Hello),
+            8 => Ok(PacketType::HelloAck),
+            9 => Ok(PacketType::PathChallenge),
+            10 => Ok(PacketType::PathResponse),
+            11 => Ok(PacketType::ClockPing),
+            12 => Ok(PacketType::ClockPong),
+            13 => Ok(PacketType::Heartbeat),
+            14 => Ok(PacketType::Bye),
+            15 => Ok(PacketType::ByeAck),
+            16 => Ok(PacketType::Feedback),
+            17 => Ok(PacketType::MtuProbe),
+            18 => Ok(PacketType::MtuProbeAck),
+            19 => Ok(PacketType::ParameterSets),
+            20 => Ok(PacketType::Audio),
+            21 => Ok(PacketType::Metadata),
+            _ => Err(ProtocolError::UnknownType(value)),
         }
     }
 }
 
 // --- 数据包 (Data) 相关 ---
-// 数据包头部的大小 (u32:4 + u64:8 + u16:2 + u16:2 + u8:1 = 17 bytes)
-pub const DATA_HEADER_SIZE: usize = 17;
+// 数据包头部的大小
+// (connection_id u32:4 + frame_id u32:4 + u64:8 + u16:2 + u16:2 + u8:1 + stream_id u8:1 = 22 bytes)
+pub const DATA_HEADER_SIZE: usize = 22;
 
-pub const MAX_PAYLOAD_SIZE: usize = 1400;
+/// 分片负载的协议上限，对应 9000 字节 MTU 的巨型帧局域网。
+/// 每个会话实际使用的负载大小在握手时协商，见 `HelloPacket::max_payload_size`。
+pub const MAX_PAYLOAD_SIZE: usize = 8800;
+/// 分片负载的协议下限，再小的话包头的开销就太大了
+pub const MIN_PAYLOAD_SIZE: usize = 512;
+/// 没有协商时使用的分片负载，适合常见的 1500 字节 MTU
+pub const DEFAULT_PAYLOAD_SIZE: usize = 1400;
+/// 单帧允许的最大字节数。接收端按 `total_packets` 预先分配槽位，
+/// 因此这个上限同时限制了一个伪造的头部能让接收端分配多少内存。
+pub const MAX_FRAME_SIZE: usize = 4 * 1024 * 1024;
+pub const MAX_PACKETS_PER_FRAME: usize = MAX_FRAME_SIZE.div_ceil(MIN_PAYLOAD_SIZE);
+/// 一个分片数据报（类型字节 + 包头 + 负载）在负载之外的最大开销，
+/// 取数据包与 FEC 校验包（含分片长度前缀）中较大的一个，不含加密的开销。
+pub const FRAGMENT_OVERHEAD: usize = 1 + fec::FEC_HEADER_SIZE + fec::SHARD_LEN_PREFIX;
+
+/// 未加密的数据报最大为 `packet_size` 字节时，分片负载最多能有多大（不小于协议下限）。
+pub fn payload_size_for_packet(packet_size: usize) -> usize {
+    packet_size
+        .saturating_sub(FRAGMENT_OVERHEAD)
+        .clamp(MIN_PAYLOAD_SIZE, MAX_PAYLOAD_SIZE)
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct DataHeader {
+    /// 接收端在 HelloAck 中分配的连接 ID；握手完成前为 0
+    pub connection_id: u32,
     pub frame_id: u32,
 
     /// 捕获时的原始时间戳（纳秒）
@@ -41,16 +115,20 @@ pub struct DataHeader {
     pub packet_id: u16,
     pub total_packets: u16,
     pub is_key_frame: u8,
+    /// 同一会话中的第几路视频流（例如广角与长焦、全分辨率与预览）。帧号在每路流内独立递增
+    pub stream_id: u8,
 }
 
 impl DataHeader {
     pub fn to_bytes(&self) -> [u8; DATA_HEADER_SIZE] {
         let mut bytes = [0u8; DATA_HEADER_SIZE];
-        bytes[0..4].copy_from_slice(&self.frame_id.to_be_bytes());
-        bytes[4..12].copy_from_slice(&self.capture_timestamp_ns.to_be_bytes());
-        bytes[12..14].copy_from_slice(&self.packet_id.to_be_bytes());
-        bytes[14..16].copy_from_slice(&self.total_packets.to_be_bytes());
-        bytes[16] = self.is_key_frame;
+        bytes[0..4].copy_from_slice(&self.connection_id.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.frame_id.to_be_bytes());
+        bytes[8..16].copy_from_slice(&self.capture_timestamp_ns.to_be_bytes());
+        bytes[16..18].copy_from_slice(&self.packet_id.to_be_bytes());
+        bytes[18..20].copy_from_slice(&self.total_packets.to_be_bytes());
+        bytes[20] = self.is_key_frame;
+        bytes[21] = self.stream_id;
         bytes
     }
 
@@ -58,30 +136,52 @@ impl DataHeader {
         if bytes.len() < DATA_HEADER_SIZE {
             return None;
         }
-        let frame_id = u32::from_be_bytes(bytes[0..4].try_into().ok()?);
-        let capture_timestamp_ns = u64::from_be_bytes(bytes[4..12].try_into().ok()?);
-        let packet_id = u16::from_be_bytes(bytes[12..14].try_into().ok()?);
-        let total_packets = u16::from_be_bytes(bytes[14..16].try_into().ok()?);
-        let is_key_frame = bytes[16];
+        let connection_id = u32::from_be_bytes(bytes[0..4].try_into().ok()?);
+        let frame_id = u32::from_be_bytes(bytes[4..8].try_into().ok()?);
+        let capture_timestamp_ns = u64::from_be_bytes(bytes[8..16].try_into().ok()?);
+        let packet_id = u16::from_be_bytes(bytes[16..18].try_into().ok()?);
+        let total_packets = u16::from_be_bytes(bytes[18..20].try_into().ok()?);
+        let is_key_frame = bytes[20];
+        let stream_id = bytes[21];
         Some(DataHeader {
+            connection_id,
             frame_id,
             capture_timestamp_ns,
             packet_id,
             total_packets,
             is_key_frame,
+            stream_id,
         })
     }
 }
 
-// ... (AckPacket 和 tests 无变化)
-pub const ACK_PACKET_SIZE: usize = size_of::<u32>();
+/// 单个数据分片的合法性检查，不合法时返回出错的字段名。解码和重组共用这套规则。
+pub fn check_data_header(header: &DataHeader, payload_len: usize) -> Result<(), &'static str> {
+    if header.total_packets == 0 || header.total_packets as usize > MAX_PACKETS_PER_FRAME {
+        return Err("total_packets");
+    }
+    if header.packet_id >= header.total_packets {
+        return Err("packet_id");
+    }
+    if payload_len > MAX_PAYLOAD_SIZE {
+        return Err("payload");
+    }
+    Ok(())
+}
+
+// ACK 包的大小 (frame_id u32:4 + stream_id u8:1 = 5 bytes)
+pub const ACK_PACKET_SIZE: usize = size_of::<u32>() + size_of::<u8>();
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct AckPacket {
     pub frame_id: u32,
+    pub stream_id: u8,
 }
 impl AckPacket {
     pub fn to_bytes(&self) -> [u8; ACK_PACKET_SIZE] {
-        self.frame_id.to_be_bytes()
+        let mut bytes = [0u8; ACK_PACKET_SIZE];
+        bytes[0..4].copy_from_slice(&self.frame_id.to_be_bytes());
+        bytes[4] = self.stream_id;
+        bytes
     }
     pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
         if bytes.len() < ACK_PACKET_SIZE {
@@ -89,24 +189,680 @@ impl AckPacket {
         }
         Some(AckPacket {
             frame_id: u32::from_be_bytes(bytes[0..4].try_into().ok()?),
+            stream_id: bytes[4],
+        })
+    }
+}
+
+// --- NACK 相关 ---
+// NACK 包头部的大小 (frame_id u32:4 + count u16:2 + stream_id u8:1 = 7 bytes)，其后是 count 个 u16 packet_id
+pub const NACK_HEADER_SIZE: usize = 7;
+
+/// 单个 NACK 包最多携带的缺失 packet_id 数量，保证 NACK 本身在任何协商结果下都不会超过一个 MTU。
+pub const MAX_NACK_IDS: usize = (MIN_PAYLOAD_SIZE - NACK_HEADER_SIZE) / size_of::<u16>();
+
+/// 接收端发现某一帧缺少分片时发送的选择性重传请求。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NackPacket {
+    pub frame_id: u32,
+    pub stream_id: u8,
+    /// 缺失的分片序号，发送端只重传这些分片
+    pub packet_ids: Vec<u16>,
+}
+
+impl NackPacket {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let count = self.packet_ids.len().min(MAX_NACK_IDS);
+        let mut bytes = Vec::with_capacity(NACK_HEADER_SIZE + count * size_of::<u16>());
+        bytes.extend_from_slice(&self.frame_id.to_be_bytes());
+        bytes.extend_from_slice(&(count as u16).to_be_bytes());
+        bytes.push(self.stream_id);
+        for id in &self.packet_ids[..count] {
+            bytes.extend_from_slice(&id.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < NACK_HEADER_SIZE {
+            return None;
+        }
+        let frame_id = u32::from_be_bytes(bytes[0..4].try_into().ok()?);
+        let count = u16::from_be_bytes(bytes[4..6].try_into().ok()?) as usize;
+        let stream_id = bytes[6];
+        let ids = bytes.get(NACK_HEADER_SIZE..NACK_HEADER_SIZE + count * size_of::<u16>())?;
+        let packet_ids = ids
+            .chunks_exact(size_of::<u16>())
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect();
+        Some(NackPacket {
+            frame_id,
+            stream_id,
+            packet_ids,
         })
     }
 }
+
+// --- 会话握手 (Hello / HelloAck) ---
+/// 所有握手包开头的魔数 "NCAM"，用来快速排除不相干的 UDP 流量。
+pub const PROTOCOL_MAGIC: u32 = 0x4E43_414D;
+/// 线协议版本号。任何不兼容的线格式变化都必须递增它。
+///
+/// - 2：数据包与校验包的头部加入 `connection_id`
+/// - 3：Hello 与 HelloAck 末尾加入 `max_payload_size`
+/// - 4：数据包与校验包的头部加入 `stream_id`，IFrameRequest 携带 `stream_id`
+pub const PROTOCOL_VERSION: u8 = 4;
+
+// 能力位掩码：双方在握手时交换各自支持的特性，最终生效的是两者的交集
+pub const CAP_NACK: u32 = 1 << 0;
+pub const CAP_FEC: u32 = 1 << 1;
+pub const CAP_ENCRYPTION: u32 = 1 << 2;
+pub const CAP_CLOCK_SYNC: u32 = 1 << 3;
+pub const CAP_HEARTBEAT: u32 = 1 << 4;
+pub const CAP_BYE: u32 = 1 << 5;
+pub const CAP_CONGESTION_CONTROL: u32 = 1 << 6;
+pub const CAP_PMTU_PROBE: u32 = 1 << 7;
+pub const CAP_AUDIO: u32 = 1 << 8;
+pub const CAP_METADATA: u32 = 1 << 9;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoCodec {
+    H264 = 0,
+    /// H.265/HEVC，参数集比 H.264 多一个 VPS
+    H265 = 1,
+    /// Motion JPEG：每帧独立编码，没有参数集，也没有帧间参考
+    Mjpeg = 2,
+}
+
+impl VideoCodec {
+    /// 解码前是否需要先拿到参数集（H.264 的 SPS/PPS，H.265 的 VPS/SPS/PPS）。
+    pub fn has_parameter_sets(self) -> bool {
+        !matches!(self, VideoCodec::Mjpeg)
+    }
+}
+
+impl TryFrom<u8> for VideoCodec {
+    type Error = ();
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(VideoCodec::H264),
+            1 => Ok(VideoCodec::H265),
+            2 => Ok(VideoCodec::Mjpeg),
+            _ => Err(()),
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioCodec {
+    Opus = 0,
+    /// AAC-LC 的原始帧（MediaCodec 的输出，不带 ADTS 头），解码配置由采样率和声道数推出
+    Aac = 1,
+}
+
+impl TryFrom<u8> for AudioCodec {
+    type Error = ();
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(AudioCodec::Opus),
+            1 => Ok(AudioCodec::Aac),
+            _ => Err(()),
+        }
+    }
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    getrandom::getrandom(&mut bytes).expect("Failed to obtain random bytes");
+    bytes
+}
+
+/// 为一次推流生成随机的会话 ID（也作为会话纪元使用）。
+pub fn new_session_id() -> u64 {
+    u64::from_be_bytes(random_bytes())
+}
+
+/// 接收端为一个会话分配的连接 ID。0 保留给"尚未握手"，因此不会被分配。
+pub fn new_connection_id() -> u32 {
+    loop {
+        let id = u32::from_be_bytes(random_bytes());
+        if id != 0 {
+            return id;
+        }
+    }
+}
+
+/// 路径验证挑战中使用的一次性随机令牌。
+pub fn new_path_token() -> u64 {
+    u64::from_be_bytes(random_bytes())
+}
+
+// 握手包共有前缀的大小 (magic u32:4 + version u8:1 = 5 bytes)。各版本的 Hello/HelloAck
+// 都以它开头，紧接着是 session_id u64
+pub const HANDSHAKE_PREFIX_SIZE: usize = 5;
+
+/// 读出任意版本的 Hello/HelloAck 中的 session_id。版本不兼容时其余字段的布局可能不同，
+/// 拒绝对方只能依靠这部分共同的开头。
+pub fn handshake_session_id(bytes: &[u8]) -> Option<u64> {
+    let id = bytes.get(HANDSHAKE_PREFIX_SIZE..HANDSHAKE_PREFIX_SIZE + 8)?;
+    Some(u64::from_be_bytes(id.try_into().ok()?))
+}
+
+// Hello 包的大小 (magic u32:4 + version u8:1 + session_id u64:8 + codec u8:1
+// + width u16:2 + height u16:2 + fps u8:1 + capabilities u32:4 + max_payload_size u16:2 = 25 bytes)
+pub const HELLO_PACKET_SIZE: usize = 25;
+
+/// 发送端在开始推流前（以及之后周期性地）发出的会话声明。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HelloPacket {
+    pub magic: u32,
+    pub version: u8,
+    /// 每次发送端启动时随机生成；接收端看到新的 session_id 就认为是新的会话纪元
+    pub session_id: u64,
+    pub codec: u8,
+    pub width: u16,
+    pub height: u16,
+    pub fps: u8,
+    pub capabilities: u32,
+    /// 发送端希望使用的最大分片负载（字节）
+    pub max_payload_size: u16,
+}
+
+impl HelloPacket {
+    pub fn to_bytes(&self) -> [u8; HELLO_PACKET_SIZE] {
+        let mut bytes = [0u8; HELLO_PACKET_SIZE];
+        bytes[0..4].copy_from_slice(&self.magic.to_be_bytes());
+        bytes[4] = self.version;
+        bytes[5..13].copy_from_slice(&self.session_id.to_be_bytes());
+        bytes[13] = self.codec;
+        bytes[14..16].copy_from_slice(&self.width.to_be_bytes());
+        bytes[16..18].copy_from_slice(&self.height.to_be_bytes());
+        bytes[18] = self.fps;
+        bytes[19..23].copy_from_slice(&self.capabilities.to_be_bytes());
+        bytes[23..25].copy_from_slice(&self.max_payload_size.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HELLO_PACKET_SIZE {
+            return None;
+        }
+        Some(HelloPacket {
+            magic: u32::from_be_bytes(bytes[0..4].try_into().ok()?),
+            version: bytes[4],
+            session_id: u64::from_be_bytes(bytes[5..13].try_into().ok()?),
+            codec: bytes[13],
+            width: u16::from_be_bytes(bytes[14..16].try_into().ok()?),
+            height: u16::from_be_bytes(bytes[16..18].try_into().ok()?),
+            fps: bytes[18],
+            capabilities: u32::from_be_bytes(bytes[19..23].try_into().ok()?),
+            max_payload_size: u16::from_be_bytes(bytes[23..25].try_into().ok()?),
+        })
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HelloStatus {
+    Accepted = 0,
+    VersionMismatch = 1,
+    UnsupportedCodec = 2,
+}
+
+impl TryFrom<u8> for HelloStatus {
+    type Error = ();
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(HelloStatus::Accepted),
+            1 => Ok(HelloStatus::VersionMismatch),
+            2 => Ok(HelloStatus::UnsupportedCodec),
+            _ => Err(()),
+        }
+    }
+}
+
+// HelloAck 包的大小 (magic u32:4 + version u8:1 + session_id u64:8 + status u8:1
+// + capabilities u32:4 + connection_id u32:4 + max_payload_size u16:2 = 24 bytes)
+pub const HELLO_ACK_PACKET_SIZE: usize = 24;
+
+/// 接收端对 Hello 的答复。`version` 是接收端自己的协议版本，
+/// `capabilities` 是双方能力的交集，`connection_id` 是接收端为该会话分配的连接 ID，
+/// 发送端此后在每个数据包里都带上它。`max_payload_size` 是协商后的分片负载上限，
+/// 发送端的分片不能超过它。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HelloAckPacket {
+    pub magic: u32,
+    pub version: u8,
+    pub session_id: u64,
+    pub status: u8,
+    pub capabilities: u32,
+    pub connection_id: u32,
+    pub max_payload_size: u16,
+}
+
+impl HelloAckPacket {
+    pub fn to_bytes(&self) -> [u8; HELLO_ACK_PACKET_SIZE] {
+        let mut bytes = [0u8; HELLO_ACK_PACKET_SIZE];
+        bytes[0..4].copy_from_slice(&self.magic.to_be_bytes());
+        bytes[4] = self.version;
+        bytes[5..13].copy_from_slice(&self.session_id.to_be_bytes());
+        bytes[13] = self.status;
+        bytes[14..18].copy_from_slice(&self.capabilities.to_be_bytes());
+        bytes[18..22].copy_from_slice(&self.connection_id.to_be_bytes());
+        bytes[22..24].copy_from_slice(&self.max_payload_size.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HELLO_ACK_PACKET_SIZE {
+            return None;
+        }
+        Some(HelloAckPacket {
+            magic: u32::from_be_bytes(bytes[0..4].try_into().ok()?),
+            version: bytes[4],
+            session_id: u64::from_be_bytes(bytes[5..13].try_into().ok()?),
+            status: bytes[13],
+            capabilities: u32::from_be_bytes(bytes[14..18].try_into().ok()?),
+            connection_id: u32::from_be_bytes(bytes[18..22].try_into().ok()?),
+            max_payload_size: u16::from_be_bytes(bytes[22..24].try_into().ok()?),
+        })
+    }
+}
+
+// --- 路径验证 (PathChallenge / PathResponse) ---
+pub const PATH_PACKET_SIZE: usize = size_of::<u64>();
+
+/// 接收端看到某个连接 ID 出现在新的源地址上时，向新地址发送 PathChallenge；
+/// 发送端原样回一个 PathResponse。只有收到正确的令牌后，接收端才把控制流量
+/// (ACK/NACK/关键帧请求) 切换到新地址，防止伪造源地址把流量引走。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathPacket {
+    pub token: u64,
+}
+
+impl PathPacket {
+    pub fn to_bytes(&self) -> [u8; PATH_PACKET_SIZE] {
+        self.token.to_be_bytes()
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(PathPacket {
+            token: u64::from_be_bytes(bytes.get(0..PATH_PACKET_SIZE)?.try_into().ok()?),
+        })
+    }
+}
+
+// --- 时钟同步 (ClockPing / ClockPong) ---
+pub const CLOCK_PING_PACKET_SIZE: usize = size_of::<u64>();
+// 时钟同步应答的大小 (origin u64:8 + receive u64:8 + transmit u64:8 = 24 bytes)
+pub const CLOCK_PONG_PACKET_SIZE: usize = 24;
+
+/// 接收端发出的时钟同步请求，`origin_ns` 是接收端发出时自己时钟上的时刻。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockPingPacket {
+    pub origin_ns: u64,
+}
+
+impl ClockPingPacket {
+    pub fn to_bytes(&self) -> [u8; CLOCK_PING_PACKET_SIZE] {
+        self.origin_ns.to_be_bytes()
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(ClockPingPacket {
+            origin_ns: u64::from_be_bytes(bytes.get(0..CLOCK_PING_PACKET_SIZE)?.try_into().ok()?),
+        })
+    }
+}
+
+/// 发送端对 ClockPing 的应答 (类似 NTP)：原样带回 `origin_ns`，
+/// 并附上自己时钟（也就是采集时间戳所在的时钟）上收到请求和发出应答的时刻。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockPongPacket {
+    pub origin_ns: u64,
+    pub receive_ns: u64,
+    pub transmit_ns: u64,
+}
+
+impl ClockPongPacket {
+    pub fn to_bytes(&self) -> [u8; CLOCK_PONG_PACKET_SIZE] {
+        let mut buf = [0u8; CLOCK_PONG_PACKET_SIZE];
+        buf[0..8].copy_from_slice(&self.origin_ns.to_be_bytes());
+        buf[8..16].copy_from_slice(&self.receive_ns.to_be_bytes());
+        buf[16..24].copy_from_slice(&self.transmit_ns.to_be_bytes());
+        buf
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < CLOCK_PONG_PACKET_SIZE {
+            return None;
+        }
+        Some(ClockPongPacket {
+            origin_ns: u64::from_be_bytes(bytes[0..8].try_into().ok()?),
+            receive_ns: u64::from_be_bytes(bytes[8..16].try_into().ok()?),
+            transmit_ns: u64::from_be_bytes(bytes[16..24].try_into().ok()?),
+        })
+    }
+}
+
+// --- 会话结束 (Bye / ByeAck) ---
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByeReason {
+    /// 应用正常关闭
+    Shutdown = 0,
+    /// 发送端即将用新的会话重新开始（例如切换了摄像头）
+    Restart = 1,
+    /// 发送端遇到无法恢复的错误
+    Error = 2,
+}
+
+impl TryFrom<u8> for ByeReason {
+    type Error = ();
+    fn try_from(value: u8) -> Result<Self, ()> {
+        match value {
+            0 => Ok(ByeReason::Shutdown),
+            1 => Ok(ByeReason::Restart),
+            2 => Ok(ByeReason::Error),
+            _ => Err(()),
+        }
+    }
+}
+
+// Bye 包的大小 (session_id u64:8 + reason u8:1 = 9 bytes)
+pub const BYE_PACKET_SIZE: usize = 9;
+pub const BYE_ACK_PACKET_SIZE: usize = size_of::<u64>();
+
+/// 发送端关闭时发出，接收端据此立即结束会话，而不必等到连接超时。
+/// 发送端会重发 Bye，直到收到 ByeAck 或者次数用完。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByePacket {
+    pub session_id: u64,
+    /// `ByeReason`，未知取值按 `Error` 处理
+    pub reason: u8,
+}
+
+impl ByePacket {
+    pub fn to_bytes(&self) -> [u8; BYE_PACKET_SIZE] {
+        let mut buf = [0u8; BYE_PACKET_SIZE];
+        buf[0..8].copy_from_slice(&self.session_id.to_be_bytes());
+        buf[8] = self.reason;
+        buf
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < BYE_PACKET_SIZE {
+            return None;
+        }
+        Some(ByePacket {
+            session_id: u64::from_be_bytes(bytes[0..8].try_into().ok()?),
+            reason: bytes[8],
+        })
+    }
+}
+
+/// 接收端对 Bye 的确认，带回被结束的会话 ID。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByeAckPacket {
+    pub session_id: u64,
+}
+
+impl ByeAckPacket {
+    pub fn to_bytes(&self) -> [u8; BYE_ACK_PACKET_SIZE] {
+        self.session_id.to_be_bytes()
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(ByeAckPacket {
+            session_id: u64::from_be_bytes(bytes.get(0..BYE_ACK_PACKET_SIZE)?.try_into().ok()?),
+        })
+    }
+}
+
+// --- 拥塞反馈 (Feedback) ---
+// Feedback 包的大小 (receive_rate_bps u32:4 + loss_fraction u8:1 + delay_gradient_us i32:4 + interval_ms u16:2 = 11 bytes)
+pub const FEEDBACK_PACKET_SIZE: usize = 11;
+
+/// 接收端周期性发给发送端的网络状况报告，发送端的拥塞控制据此调整目标码率。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeedbackPacket {
+    /// 本周期内收到的视频数据速率（比特/秒，只计数据分片的负载）
+    pub receive_rate_bps: u32,
+    /// 本周期内的丢包率，以 1/256 为单位（与 RTCP 相同）
+    pub loss_fraction: u8,
+    /// 最小单程时延相对上一周期的变化（微秒），持续为正说明链路上的队列在增长
+    pub delay_gradient_us: i32,
+    /// 本报告覆盖的时长（毫秒）
+    pub interval_ms: u16,
+}
+
+impl FeedbackPacket {
+    pub fn to_bytes(&self) -> [u8; FEEDBACK_PACKET_SIZE] {
+        let mut buf = [0u8; FEEDBACK_PACKET_SIZE];
+        buf[0..4].copy_from_slice(&self.receive_rate_bps.to_be_bytes());
+        buf[4] = self.loss_fraction;
+        buf[5..9].copy_from_slice(&self.delay_gradient_us.to_be_bytes());
+        buf[9..11].copy_from_slice(&self.interval_ms.to_be_bytes());
+        buf
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < FEEDBACK_PACKET_SIZE {
+            return None;
+        }
+        Some(FeedbackPacket {
+            receive_rate_bps: u32::from_be_bytes(bytes[0..4].try_into().ok()?),
+            loss_fraction: bytes[4],
+            delay_gradient_us: i32::from_be_bytes(bytes[5..9].try_into().ok()?),
+            interval_ms: u16::from_be_bytes(bytes[9..11].try_into().ok()?),
+        })
+    }
+}
+
+// --- 路径 MTU 探测 (MtuProbe / MtuProbeAck) ---
+// MtuProbe 包头的大小 (probe_id u32:4 + size u16:2 = 6 bytes)，MtuProbe 其后用零填充到 size
+pub const MTU_PROBE_HEADER_SIZE: usize = 6;
+
+/// 发送端用设置了 DF 的 MtuProbe 试探路径能否通过 `size` 字节的数据报，
+/// 接收端收到后回一个不带填充的 MtuProbeAck。`size` 是编码后（加密前）整个数据报的字节数。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MtuProbePacket {
+    pub probe_id: u32,
+    pub size: u16,
+}
+
+impl MtuProbePacket {
+    pub fn to_bytes(&self) -> [u8; MTU_PROBE_HEADER_SIZE] {
+        let mut buf = [0u8; MTU_PROBE_HEADER_SIZE];
+        buf[0..4].copy_from_slice(&self.probe_id.to_be_bytes());
+        buf[4..6].copy_from_slice(&self.size.to_be_bytes());
+        buf
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < MTU_PROBE_HEADER_SIZE {
+            return None;
+        }
+        Some(MtuProbePacket {
+            probe_id: u32::from_be_bytes(bytes[0..4].try_into().ok()?),
+            size: u16::from_be_bytes(bytes[4..6].try_into().ok()?),
+        })
+    }
+}
+
+// --- 音频 (Audio) ---
+// 音频包头的大小
+// (connection_id u32:4 + sequence u32:4 + capture_timestamp_ns u64:8 + sample_rate u32:4 + codec u8:1 + channels u8:1 = 22 bytes)
+pub const AUDIO_HEADER_SIZE: usize = 22;
+/// 单个音频帧的最大字节数。音频帧不分片，一个数据报只装一帧，
+/// 这个上限与默认的分片负载相同，保证在常见的 1500 字节 MTU 上不需要 IP 分片（Opus 单帧最大 1275 字节）。
+pub const MAX_AUDIO_FRAME_SIZE: usize = DEFAULT_PAYLOAD_SIZE;
+/// 支持的声道数上限（单声道或立体声）
+pub const MAX_AUDIO_CHANNELS: u8 = 2;
+
+/// 一个编码后的音频帧的描述。编码参数随每一帧发送，接收端发现变化时重建音频管线。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioHeader {
+    /// 接收端在 HelloAck 中分配的连接 ID；握手完成前为 0
+    pub connection_id: u32,
+    /// 音频帧的序号，逐帧递增，接收端据此丢弃乱序和重复的帧
+    pub sequence: u32,
+    /// 帧中第一个采样的采集时间戳（纳秒），与视频帧使用同一个时钟
+    pub capture_timestamp_ns: u64,
+    pub sample_rate: u32,
+    pub codec: u8,
+    pub channels: u8,
+}
+
+impl AudioHeader {
+    pub fn to_bytes(&self) -> [u8; AUDIO_HEADER_SIZE] {
+        let mut bytes = [0u8; AUDIO_HEADER_SIZE];
+        bytes[0..4].copy_from_slice(&self.connection_id.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.sequence.to_be_bytes());
+        bytes[8..16].copy_from_slice(&self.capture_timestamp_ns.to_be_bytes());
+        bytes[16..20].copy_from_slice(&self.sample_rate.to_be_bytes());
+        bytes[20] = self.codec;
+        bytes[21] = self.channels;
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < AUDIO_HEADER_SIZE {
+            return None;
+        }
+        Some(AudioHeader {
+            connection_id: u32::from_be_bytes(bytes[0..4].try_into().ok()?),
+            sequence: u32::from_be_bytes(bytes[4..8].try_into().ok()?),
+            capture_timestamp_ns: u64::from_be_bytes(bytes[8..16].try_into().ok()?),
+            sample_rate: u32::from_be_bytes(bytes[16..20].try_into().ok()?),
+            codec: bytes[20],
+            channels: bytes[21],
+        })
+    }
+}
+
+/// 音频帧的合法性检查，不合法时返回出错的字段名。
+pub fn check_audio_header(header: &AudioHeader, payload_len: usize) -> Result<(), &'static str> {
+    if AudioCodec::try_from(header.codec).is_err() {
+        return Err("codec");
+    }
+    if !(8_000..=96_000).contains(&header.sample_rate) {
+        return Err("sample_rate");
+    }
+    if header.channels == 0 || header.channels > MAX_AUDIO_CHANNELS {
+        return Err("channels");
+    }
+    if payload_len == 0 || payload_len > MAX_AUDIO_FRAME_SIZE {
+        return Err("payload");
+    }
+    Ok(())
+}
+
+// --- 逐帧元数据 (Metadata) ---
+// 元数据包头的大小 (connection_id u32:4 + frame_id u32:4 + capture_timestamp_ns u64:8 + stream_id u8:1 = 17 bytes)，
+// 其后是 `metadata` 模块定义的条目
+pub const METADATA_HEADER_SIZE: usize = 17;
+
+/// 一组元数据所属的视频帧。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetadataHeader {
+    /// 接收端在 HelloAck 中分配的连接 ID；握手完成前为 0
+    pub connection_id: u32,
+    pub frame_id: u32,
+    /// 所属视频帧的采集时间戳（纳秒）
+    pub capture_timestamp_ns: u64,
+    pub stream_id: u8,
+}
+
+impl MetadataHeader {
+    pub fn to_bytes(&self) -> [u8; METADATA_HEADER_SIZE] {
+        let mut bytes = [0u8; METADATA_HEADER_SIZE];
+        bytes[0..4].copy_from_slice(&self.connection_id.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.frame_id.to_be_bytes());
+        bytes[8..16].copy_from_slice(&self.capture_timestamp_ns.to_be_bytes());
+        bytes[16] = self.stream_id;
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < METADATA_HEADER_SIZE {
+            return None;
+        }
+        Some(MetadataHeader {
+            connection_id: u32::from_be_bytes(bytes[0..4].try_into().ok()?),
+            frame_id: u32::from_be_bytes(bytes[4..8].try_into().ok()?),
+            capture_timestamp_ns: u64::from_be_bytes(bytes[8..16].try_into().ok()?),
+            stream_id: bytes[16],
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     #[test]
     fn test_data_header_serialization() {
         let header = DataHeader {
+            connection_id: 0x0102_0304,
             frame_id: 12345,
             capture_timestamp_ns: 9876543210,
             packet_id: 1,
             total_packets: 10,
             is_key_frame: 1,
+            stream_id: 2,
         };
         let bytes = header.to_bytes();
         let reconstructed = DataHeader::from_bytes(&bytes).unwrap();
         assert_eq!(header, reconstructed);
     }
+
+    #[test]
+    fn test_nack_packet_serialization() {
+        let nack = NackPacket {
+            frame_id: 42,
+            stream_id: 1,
+            packet_ids: vec![0, 3, 17],
+        };
+        let bytes = nack.to_bytes();
+        assert_eq!(bytes.len(), NACK_HEADER_SIZE + 6);
+        assert_eq!(NackPacket::from_bytes(&bytes).unwrap(), nack);
+        // 声明的数量超过实际长度时视为截断
+        assert!(NackPacket::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn test_hello_serialization() {
+        let hello = HelloPacket {
+            magic: PROTOCOL_MAGIC,
+            version: PROTOCOL_VERSION,
+            session_id: 0xDEAD_BEEF_0000_0001,
+            codec: VideoCodec::H264 as u8,
+            width: 1280,
+            height: 720,
+            fps: 30,
+            capabilities: CAP_NACK | CAP_FEC,
+            max_payload_size: DEFAULT_PAYLOAD_SIZE as u16,
+        };
+        assert_eq!(HelloPacket::from_bytes(&hello.to_bytes()).unwrap(), hello);
+
+        let ack = HelloAckPacket {
+            magic: PROTOCOL_MAGIC,
+            version: PROTOCOL_VERSION,
+            session_id: hello.session_id,
+            status: HelloStatus::VersionMismatch as u8,
+            capabilities: CAP_NACK,
+            connection_id: new_connection_id(),
+            max_payload_size: 1200,
+        };
+        assert_eq!(HelloAckPacket::from_bytes(&ack.to_bytes()).unwrap(), ack);
+        assert_ne!(ack.connection_id, 0);
+
+        let path = PathPacket { token: u64::MAX };
+        assert_eq!(PathPacket::from_bytes(&path.to_bytes()).unwrap(), path);
+        assert!(PathPacket::from_bytes(&[0u8; PATH_PACKET_SIZE - 1]).is_none());
+
+        let pong = ClockPongPacket {
+            origin_ns: 1,
+            receive_ns: u64::MAX,
+            transmit_ns: 3,
+        };
+        assert_eq!(ClockPongPacket::from_bytes(&pong.to_bytes()).unwrap(), pong);
+        assert!(ClockPongPacket::from_bytes(&[0u8; CLOCK_PONG_PACKET_SIZE - 1]).is_none());
+    }
     // ...
 }
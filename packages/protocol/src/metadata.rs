@@ -0,0 +1,305 @@
+// --- packages/protocol/src/metadata.rs ---
+
+//! 逐帧的元数据：相机内参、曝光、对焦、设备姿态，以及两帧之间采集到的 IMU 和 GPS 样本。
+//!
+//! 元数据不塞进视频码流，而是用单独的 Metadata 包发送，按 (stream_id, frame_id) 关联到视频帧。
+//! 包体是一串 TLV 条目（tag u8 + 长度 u16 + 值），数值一律大端序，浮点数必须是有限值。
+//! 解码时跳过不认识的 tag，以后增加新的条目类型不需要升级协议版本。
+use crate::DEFAULT_PAYLOAD_SIZE;
+
+/// 一个 Metadata 包中条目部分的最大字节数。元数据不分片，与音频帧一样保证不需要 IP 分片
+pub const MAX_METADATA_SIZE: usize = DEFAULT_PAYLOAD_SIZE;
+// 每个条目的 tag (u8) 与值的长度 (u16)
+const ENTRY_HEADER_SIZE: usize = 3;
+
+const TAG_INTRINSICS: u8 = 1;
+const TAG_EXPOSURE: u8 = 2;
+const TAG_FOCUS_DISTANCE: u8 = 3;
+const TAG_ORIENTATION: u8 = 4;
+const TAG_IMU: u8 = 5;
+const TAG_GPS: u8 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetadataEntry {
+    /// 相机内参（像素）：焦距、主点与倾斜系数，与 Android 的 LENS_INTRINSIC_CALIBRATION 含义相同
+    Intrinsics {
+        fx: f32,
+        fy: f32,
+        cx: f32,
+        cy: f32,
+        skew: f32,
+    },
+    Exposure {
+        exposure_time_ns: u64,
+        iso: u32,
+    },
+    /// 对焦距离（屈光度，0 表示无穷远），与 Android 的 LENS_FOCUS_DISTANCE 相同
+    FocusDistance {
+        diopters: f32,
+    },
+    /// 设备姿态的单位四元数，来自旋转矢量传感器
+    Orientation {
+        w: f32,
+        x: f32,
+        y: f32,
+        z: f32,
+    },
+    /// 一个 IMU 样本：加速度 (m/s²) 和角速度 (rad/s)。时间戳与视频帧的采集时间戳使用同一个时钟
+    Imu {
+        timestamp_ns: u64,
+        accel: [f32; 3],
+        gyro: [f32; 3],
+    },
+    /// 一次 GPS 定位：经纬度（度）、海拔与水平精度（米）
+    Gps {
+        timestamp_ns: u64,
+        latitude: f64,
+        longitude: f64,
+        altitude_m: f32,
+        accuracy_m: f32,
+    },
+}
+
+impl MetadataEntry {
+    fn tag(&self) -> u8 {
+        match self {
+            MetadataEntry::Intrinsics { .. } => TAG_INTRINSICS,
+            MetadataEntry::Exposure { .. } => TAG_EXPOSURE,
+            MetadataEntry::FocusDistance { .. } => TAG_FOCUS_DISTANCE,
+            MetadataEntry::Orientation { .. } => TAG_ORIENTATION,
+            MetadataEntry::Imu { .. } => TAG_IMU,
+            MetadataEntry::Gps { .. } => TAG_GPS,
+        }
+    }
+
+    fn write_value(&self, out: &mut Vec<u8>) {
+        let floats = |values: &[f32], out: &mut Vec<u8>| {
+            for value in values {
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+        };
+        match *self {
+            MetadataEntry::Intrinsics {
+                fx,
+                fy,
+                cx,
+                cy,
+                skew,
+            } => floats(&[fx, fy, cx, cy, skew], out),
+            MetadataEntry::Exposure {
+                exposure_time_ns,
+                iso,
+            } => {
+                out.extend_from_slice(&exposure_time_ns.to_be_bytes());
+                out.extend_from_slice(&iso.to_be_bytes());
+            }
+            MetadataEntry::FocusDistance { diopters } => floats(&[diopters], out),
+            MetadataEntry::Orientation { w, x, y, z } => floats(&[w, x, y, z], out),
+            MetadataEntry::Imu {
+                timestamp_ns,
+                accel,
+                gyro,
+            } => {
+                out.extend_from_slice(&timestamp_ns.to_be_bytes());
+                floats(&accel, out);
+                floats(&gyro, out);
+            }
+            MetadataEntry::Gps {
+                timestamp_ns,
+                latitude,
+                longitude,
+                altitude_m,
+                accuracy_m,
+            } => {
+                out.extend_from_slice(&timestamp_ns.to_be_bytes());
+                out.extend_from_slice(&latitude.to_be_bytes());
+                out.extend_from_slice(&longitude.to_be_bytes());
+                floats(&[altitude_m, accuracy_m], out);
+            }
+        }
+    }
+
+    // 解析一个条目的值；不认识的 tag 返回 Ok(None)
+    fn read(tag: u8, value: &[u8]) -> Result<Option<Self>, &'static str> {
+        let expected_len = match tag {
+            TAG_INTRINSICS => 20,
+            TAG_EXPOSURE => 12,
+            TAG_FOCUS_DISTANCE => 4,
+            TAG_ORIENTATION => 16,
+            TAG_IMU => 32,
+            TAG_GPS => 32,
+            _ => return Ok(None),
+        };
+        if value.len() != expected_len {
+            return Err("length");
+        }
+        let mut reader = Reader { bytes: value };
+        let entry = match tag {
+            TAG_INTRINSICS => MetadataEntry::Intrinsics {
+                fx: reader.f32()?,
+                fy: reader.f32()?,
+                cx: reader.f32()?,
+                cy: reader.f32()?,
+                skew: reader.f32()?,
+            },
+            TAG_EXPOSURE => MetadataEntry::Exposure {
+                exposure_time_ns: reader.u64(),
+                iso: reader.u32(),
+            },
+            TAG_FOCUS_DISTANCE => MetadataEntry::FocusDistance {
+                diopters: reader.f32()?,
+            },
+            TAG_ORIENTATION => MetadataEntry::Orientation {
+                w: reader.f32()?,
+                x: reader.f32()?,
+                y: reader.f32()?,
+                z: reader.f32()?,
+            },
+            TAG_IMU => MetadataEntry::Imu {
+                timestamp_ns: reader.u64(),
+                accel: [reader.f32()?, reader.f32()?, reader.f32()?],
+                gyro: [reader.f32()?, reader.f32()?, reader.f32()?],
+            },
+            _ => MetadataEntry::Gps {
+                timestamp_ns: reader.u64(),
+                latitude: reader.f64()?,
+                longitude: reader.f64()?,
+                altitude_m: reader.f32()?,
+                accuracy_m: reader.f32()?,
+            },
+        };
+        Ok(Some(entry))
+    }
+}
+
+// 按顺序读出定长字段。调用方已经检查过总长度
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let (head, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        head.try_into().unwrap()
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_be_bytes(self.take())
+    }
+
+    fn u64(&mut self) -> u64 {
+        u64::from_be_bytes(self.take())
+    }
+
+    fn f32(&mut self) -> Result<f32, &'static str> {
+        let value = f32::from_be_bytes(self.take());
+        value.is_finite().then_some(value).ok_or("value")
+    }
+
+    fn f64(&mut self) -> Result<f64, &'static str> {
+        let value = f64::from_be_bytes(self.take());
+        value.is_finite().then_some(value).ok_or("value")
+    }
+}
+
+/// 把条目编码为 Metadata 包的条目部分。
+pub fn encode_entries(entries: &[MetadataEntry]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for entry in entries {
+        let start = out.len();
+        out.push(entry.tag());
+        out.extend_from_slice(&[0, 0]);
+        entry.write_value(&mut out);
+        let len = (out.len() - start - ENTRY_HEADER_SIZE) as u16;
+        out[start + 1..start + ENTRY_HEADER_SIZE].copy_from_slice(&len.to_be_bytes());
+    }
+    out
+}
+
+/// 解析 Metadata 包的条目部分，跳过不认识的条目。格式错误时返回出错的字段名。
+pub fn decode_entries(bytes: &[u8]) -> Result<Vec<MetadataEntry>, &'static str> {
+    if bytes.len() > MAX_METADATA_SIZE {
+        return Err("entries");
+    }
+    let mut entries = Vec::new();
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        if rest.len() < ENTRY_HEADER_SIZE {
+            return Err("entries");
+        }
+        let len = u16::from_be_bytes([rest[1], rest[2]]) as usize;
+        let value = rest
+            .get(ENTRY_HEADER_SIZE..ENTRY_HEADER_SIZE + len)
+            .ok_or("entries")?;
+        entries.extend(MetadataEntry::read(rest[0], value)?);
+        rest = &rest[ENTRY_HEADER_SIZE + len..];
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_round_trip() {
+        let entries = [
+            MetadataEntry::Intrinsics {
+                fx: 1450.5,
+                fy: 1451.0,
+                cx: 959.5,
+                cy: 539.5,
+                skew: 0.0,
+            },
+            MetadataEntry::Exposure {
+                exposure_time_ns: 8_333_333,
+                iso: 400,
+            },
+            MetadataEntry::FocusDistance { diopters: 2.5 },
+            MetadataEntry::Orientation {
+                w: 1.0,
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            MetadataEntry::Imu {
+                timestamp_ns: 1_000,
+                accel: [0.1, 9.8, -0.2],
+                gyro: [0.01, -0.02, 0.03],
+            },
+            MetadataEntry::Gps {
+                timestamp_ns: 2_000,
+                latitude: 31.2304,
+                longitude: 121.4737,
+                altitude_m: 4.0,
+                accuracy_m: 3.5,
+            },
+        ];
+        let bytes = encode_entries(&entries);
+        assert_eq!(decode_entries(&bytes).unwrap(), entries);
+        assert_eq!(decode_entries(&[]).unwrap(), []);
+    }
+
+    #[test]
+    fn test_unknown_entries_are_skipped() {
+        let focus = MetadataEntry::FocusDistance { diopters: 0.0 };
+        let mut bytes = vec![200, 0, 3, 1, 2, 3];
+        bytes.extend(encode_entries(&[focus]));
+        assert_eq!(decode_entries(&bytes).unwrap(), [focus]);
+    }
+
+    #[test]
+    fn test_malformed_entries_are_rejected() {
+        let bytes = encode_entries(&[MetadataEntry::Exposure {
+            exposure_time_ns: 1,
+            iso: 100,
+        }]);
+        assert_eq!(decode_entries(&bytes[..bytes.len() - 1]), Err("entries"));
+        assert_eq!(decode_entries(&bytes[..2]), Err("entries"));
+        // 已知的 tag 长度不对
+        assert_eq!(decode_entries(&[TAG_FOCUS_DISTANCE, 0, 0]), Err("length"));
+        let nan = encode_entries(&[MetadataEntry::FocusDistance { diopters: f32::NAN }]);
+        assert_eq!(decode_entries(&nan), Err("value"));
+    }
+}
@@ -0,0 +1,281 @@
+// --- packages/protocol/src/nal.rs ---
+
+//! H.264 / H.265 码流的 NAL 单元解析。
+//!
+//! 支持两种封装：Annex-B（每个 NAL 前面是 3 或 4 字节的起始码，MediaCodec 的输出就是这种）和
+//! AVCC/HVCC（每个 NAL 前面是大端的长度字段，MP4 等容器使用）。NAL 的负载里插入了防竞争字节
+//! （`00 00 03`），解析语法元素（例如 SPS）之前要先用 [`unescape_rbsp`] 去掉。
+use crate::VideoCodec;
+
+/// NAL 单元的类别。只区分发送端和接收端关心的几类，其余的归入 `Other`。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NalKind {
+    /// 编码条带。`random_access` 表示可以从这一帧开始解码（H.264 的 IDR，H.265 的 IRAP），
+    /// `reference` 表示这一帧可能被后续帧参考
+    Slice {
+        random_access: bool,
+        reference: bool,
+    },
+    /// H.265 的视频参数集
+    Vps,
+    Sps,
+    Pps,
+    Sei,
+    AccessUnitDelimiter,
+    Other,
+}
+
+impl NalKind {
+    /// 是否是参数集（VPS/SPS/PPS）。
+    pub fn is_parameter_set(self) -> bool {
+        matches!(self, NalKind::Vps | NalKind::Sps | NalKind::Pps)
+    }
+}
+
+/// NAL 头中的类型字段：H.264 是第一个字节的低 5 位，H.265 是第一个字节的第 1..7 位。
+/// MJPEG 没有 NAL，NAL 头不完整时也返回 None。
+pub fn nal_type(codec: VideoCodec, nal: &[u8]) -> Option<u8> {
+    match codec {
+        VideoCodec::H264 => nal.first().map(|header| header & 0x1f),
+        // H.265 的 NAL 头有两个字节
+        VideoCodec::H265 if nal.len() >= 2 => Some((nal[0] >> 1) & 0x3f),
+        _ => None,
+    }
+}
+
+/// 按 NAL 头判断一个 NAL 单元（不含起始码或长度前缀）的类别。
+pub fn nal_kind(codec: VideoCodec, nal: &[u8]) -> Option<NalKind> {
+    let nal_type = nal_type(codec, nal)?;
+    let kind = match codec {
+        VideoCodec::H264 => match nal_type {
+            // 非 IDR 条带与数据分区 A；nal_ref_idc 为 0 的图像不会被参考
+            1 | 2 => NalKind::Slice {
+                random_access: false,
+                reference: nal[0] & 0x60 != 0,
+            },
+            5 => NalKind::Slice {
+                random_access: true,
+                reference: true,
+            },
+            6 => NalKind::Sei,
+            7 => NalKind::Sps,
+            8 => NalKind::Pps,
+            9 => NalKind::AccessUnitDelimiter,
+            _ => NalKind::Other,
+        },
+        _ => match nal_type {
+            // 非 IRAP 条带。其中的偶数类型（TRAIL_N、TSA_N、STSA_N、RADL_N、RASL_N）是子层非参考图像；
+            // 10..=15 是保留的 VCL 类型，不会出现在合规的码流里，归入 Other
+            0..=9 => NalKind::Slice {
+                random_access: false,
+                reference: nal_type % 2 == 1,
+            },
+            // BLA、IDR、CRA 以及保留的 IRAP 类型
+            16..=23 => NalKind::Slice {
+                random_access: true,
+                reference: true,
+            },
+            32 => NalKind::Vps,
+            33 => NalKind::Sps,
+            34 => NalKind::Pps,
+            35 => NalKind::AccessUnitDelimiter,
+            39 | 40 => NalKind::Sei,
+            _ => NalKind::Other,
+        },
+    };
+    Some(kind)
+}
+
+/// 依次取出 Annex-B 码流中的 NAL 单元，见 [`annex_b_units`]。
+pub struct AnnexBUnits<'a> {
+    rest: &'a [u8],
+}
+
+/// 按起始码（`00 00 01` 或 `00 00 00 01`）切分 Annex-B 码流，依次返回不含起始码的 NAL 单元。
+/// 第一个起始码之前的数据被忽略；NAL 末尾的填充零字节（包括 4 字节起始码的第一个字节）被去掉。
+pub fn annex_b_units(data: &[u8]) -> AnnexBUnits<'_> {
+    let rest = match find_start_code(data) {
+        Some(start) => &data[start + 3..],
+        None => &[],
+    };
+    AnnexBUnits { rest }
+}
+
+impl<'a> Iterator for AnnexBUnits<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        while !self.rest.is_empty() {
+            let (unit, rest) = match find_start_code(self.rest) {
+                Some(end) => (&self.rest[..end], &self.rest[end + 3..]),
+                None => (self.rest, &[][..]),
+            };
+            self.rest = rest;
+            let len = unit.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+            // 两个起始码之间没有数据时跳过
+            if len > 0 {
+                return Some(&unit[..len]);
+            }
+        }
+        None
+    }
+}
+
+// 下一个 `00 00 01` 的位置
+fn find_start_code(data: &[u8]) -> Option<usize> {
+    data.windows(3).position(|w| w == [0, 0, 1])
+}
+
+/// 数据是否以 Annex-B 起始码开头。
+pub fn is_annex_b(data: &[u8]) -> bool {
+    data.starts_with(&[0, 0, 1]) || data.starts_with(&[0, 0, 0, 1])
+}
+
+/// 按长度前缀（`length_size` 为 1、2 或 4 字节，大端）切分 AVCC/HVCC 码流。
+/// 长度字段不合法或数据被截断时返回出错的字段名。
+pub fn avcc_units(data: &[u8], length_size: usize) -> Result<Vec<&[u8]>, &'static str> {
+    if !matches!(length_size, 1 | 2 | 4) {
+        return Err("length_size");
+    }
+    let mut units = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let len_bytes = rest.get(..length_size).ok_or("length")?;
+        let len = len_bytes
+            .iter()
+            .fold(0usize, |len, &b| (len << 8) | b as usize);
+        let unit = rest.get(length_size..length_size + len).ok_or("length")?;
+        if unit.is_empty() {
+            return Err("length");
+        }
+        units.push(unit);
+        rest = &rest[length_size + len..];
+    }
+    Ok(units)
+}
+
+/// 把 AVCC/HVCC 码流转换为 4 字节起始码的 Annex-B 码流。
+pub fn avcc_to_annex_b(data: &[u8], length_size: usize) -> Result<Vec<u8>, &'static str> {
+    let units = avcc_units(data, length_size)?;
+    Ok(to_annex_b(units))
+}
+
+/// 用 4 字节起始码把 NAL 单元拼接为 Annex-B 码流。
+pub fn to_annex_b<'a>(units: impl IntoIterator<Item = &'a [u8]>) -> Vec<u8> {
+    let mut out = Vec::new();
+    for unit in units {
+        out.extend_from_slice(&[0, 0, 0, 1]);
+        out.extend_from_slice(unit);
+    }
+    out
+}
+
+/// 去掉 NAL 单元中的防竞争字节：`00 00 03` 中的 `03` 是编码器为了避免出现起始码而插入的。
+pub fn unescape_rbsp(nal: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &b in nal {
+        if zeros >= 2 && b == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        out.push(b);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_annex_b_units_handle_both_start_codes() {
+        let data = [
+            0xff, // 第一个起始码之前的数据被忽略
+            0, 0, 0, 1, 0x67, 0x42, 0x00, // SPS，4 字节起始码
+            0, 0, 1, 0x68, 0xce, // PPS，3 字节起始码
+            0, 0, 0, 1, 0x65, 0x88, 0x00, 0x00, // IDR 条带，末尾的零字节被去掉
+        ];
+        let units: Vec<_> = annex_b_units(&data).collect();
+        assert_eq!(
+            units,
+            [&[0x67, 0x42][..], &[0x68, 0xce][..], &[0x65, 0x88][..]]
+        );
+        assert_eq!(annex_b_units(&[1, 2, 3]).count(), 0);
+        assert!(is_annex_b(&data[1..]));
+        assert!(!is_annex_b(&data));
+    }
+
+    #[test]
+    fn test_nal_kinds() {
+        let kinds: Vec<_> = [0x67u8, 0x68, 0x06, 0x09, 0x65, 0x41, 0x01]
+            .iter()
+            .map(|header| nal_kind(VideoCodec::H264, &[*header]).unwrap())
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                NalKind::Sps,
+                NalKind::Pps,
+                NalKind::Sei,
+                NalKind::AccessUnitDelimiter,
+                NalKind::Slice {
+                    random_access: true,
+                    reference: true
+                },
+                NalKind::Slice {
+                    random_access: false,
+                    reference: true
+                },
+                NalKind::Slice {
+                    random_access: false,
+                    reference: false
+                },
+            ]
+        );
+        // H.265：VPS、IDR_W_RADL、TRAIL_N
+        assert_eq!(
+            nal_kind(VideoCodec::H265, &[0x40, 0x01]),
+            Some(NalKind::Vps)
+        );
+        assert_eq!(
+            nal_kind(VideoCodec::H265, &[0x26, 0x01]),
+            Some(NalKind::Slice {
+                random_access: true,
+                reference: true
+            })
+        );
+        assert_eq!(
+            nal_kind(VideoCodec::H265, &[0x00, 0x01]),
+            Some(NalKind::Slice {
+                random_access: false,
+                reference: false
+            })
+        );
+        assert_eq!(nal_kind(VideoCodec::H265, &[0x40]), None);
+        assert_eq!(nal_kind(VideoCodec::Mjpeg, &[0xff, 0xd8]), None);
+    }
+
+    #[test]
+    fn test_avcc_units() {
+        let data = [0, 0, 0, 2, 0x67, 0x42, 0, 0, 0, 1, 0x68];
+        let units = avcc_units(&data, 4).unwrap();
+        assert_eq!(units, [&[0x67, 0x42][..], &[0x68][..]]);
+        assert_eq!(
+            avcc_to_annex_b(&data, 4).unwrap(),
+            [0, 0, 0, 1, 0x67, 0x42, 0, 0, 0, 1, 0x68]
+        );
+        assert_eq!(avcc_units(&data[..5], 4), Err("length"));
+        assert_eq!(avcc_units(&data, 3), Err("length_size"));
+    }
+
+    #[test]
+    fn test_unescape_rbsp() {
+        assert_eq!(
+            unescape_rbsp(&[0x67, 0, 0, 3, 1, 0, 0, 3, 0, 0, 3]),
+            [0x67, 0, 0, 1, 0, 0, 0, 0]
+        );
+        assert_eq!(unescape_rbsp(&[0, 3, 0, 0, 2]), [0, 3, 0, 0, 2]);
+    }
+}
@@ -0,0 +1,658 @@
+// --- packages/protocol/src/packet.rs ---
+
+//! 统一的线协议消息：`Packet` 覆盖所有数据报类型，`decode` 一次完成类型识别、
+//! 长度检查和字段校验，失败时通过 `ProtocolError` 说明原因。
+use crate::fec::{check_fec_header, FecHeader, FEC_HEADER_SIZE};
+use crate::metadata::decode_entries;
+use crate::{
+    check_audio_header, check_data_header, AckPacket, AudioHeader, ByeAckPacket, ByePacket,
+    ClockPingPacket, ClockPongPacket, DataHeader, FeedbackPacket, HelloAckPacket, HelloPacket,
+    MetadataHeader, MtuProbePacket, NackPacket, PacketType, PathPacket, VideoCodec,
+    ACK_PACKET_SIZE, AUDIO_HEADER_SIZE, BYE_ACK_PACKET_SIZE, BYE_PACKET_SIZE,
+    CLOCK_PING_PACKET_SIZE, CLOCK_PONG_PACKET_SIZE, DATA_HEADER_SIZE, FEEDBACK_PACKET_SIZE,
+    HANDSHAKE_PREFIX_SIZE, HELLO_ACK_PACKET_SIZE, HELLO_PACKET_SIZE, MAX_NACK_IDS,
+    METADATA_HEADER_SIZE, MTU_PROBE_HEADER_SIZE, NACK_HEADER_SIZE, PATH_PACKET_SIZE,
+    PROTOCOL_MAGIC, PROTOCOL_VERSION,
+};
+use std::fmt;
+use std::mem::size_of;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// 数据报比该类型要求的最小长度短
+    Truncated { needed: usize, actual: usize },
+    /// 第一个字节不是已知的 `PacketType`
+    UnknownType(u8),
+    /// 握手包的协议版本与本端不同
+    BadVersion(u8),
+    /// 长度足够，但某个字段的取值不合法
+    InvalidField(&'static str),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Truncated { needed, actual } => {
+                write!(
+                    f,
+                    "truncated datagram: need {} bytes, got {}",
+                    needed, actual
+                )
+            }
+            ProtocolError::UnknownType(value) => write!(f, "unknown packet type {}", value),
+            ProtocolError::BadVersion(version) => write!(
+                f,
+                "protocol version {} is not supported (expected {})",
+                version, PROTOCOL_VERSION
+            ),
+            ProtocolError::InvalidField(field) => write!(f, "invalid field `{}`", field),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// 一个完整的数据报（含 `PacketType` 前缀）。负载以借用的形式指向原始缓冲区。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet<'a> {
+    Data {
+        header: DataHeader,
+        payload: &'a [u8],
+    },
+    Ack(AckPacket),
+    /// 请求指定视频流的下一帧编码为关键帧
+    IFrameRequest {
+        stream_id: u8,
+    },
+    /// 第 0 路流的 H.264 SPS/PPS，已被 `ParameterSets` 取代
+    SpsPps(&'a [u8]),
+    Nack(NackPacket),
+    Fec {
+        header: FecHeader,
+        shard: &'a [u8],
+    },
+    /// 加密信封（类型字节之后的全部内容），需要先交给 `crypto::PacketOpener` 解开
+    Encrypted(&'a [u8]),
+    Hello(HelloPacket),
+    HelloAck(HelloAckPacket),
+    PathChallenge(PathPacket),
+    PathResponse(PathPacket),
+    ClockPing(ClockPingPacket),
+    ClockPong(ClockPongPacket),
+    /// 空闲时的保活消息，没有负载
+    Heartbeat,
+    Bye(ByePacket),
+    ByeAck(ByeAckPacket),
+    Feedback(FeedbackPacket),
+    /// 编码时用零填充到 `size` 字节
+    MtuProbe(MtuProbePacket),
+    MtuProbeAck(MtuProbePacket),
+    /// 任意编解码器的参数集（Annex-B 格式，H.265 为 VPS/SPS/PPS），取代只适用于 H.264 的 `SpsPps`。
+    /// 同时声明了这路流的编解码器：没有参数集的编解码器（MJPEG）发送 `data` 为空的消息
+    ParameterSets {
+        stream_id: u8,
+        codec: VideoCodec,
+        data: &'a [u8],
+    },
+    /// 一个完整的编码音频帧，不分片、不重传
+    Audio {
+        header: AudioHeader,
+        payload: &'a [u8],
+    },
+    /// 一帧视频的元数据，`entries` 用 `metadata::decode_entries` 解析（解码时已经检查过格式）
+    Metadata {
+        header: MetadataHeader,
+        entries: &'a [u8],
+    },
+}
+
+fn require(body: &[u8], needed: usize) -> Result<(), ProtocolError> {
+    if body.len() < needed {
+        return Err(ProtocolError::Truncated {
+            needed: 1 + needed,
+            actual: 1 + body.len(),
+        });
+    }
+    Ok(())
+}
+
+/// 握手包共有的前缀 (magic u32 + version u8)。各版本都必须保持这个前缀不变，
+/// 这样即使版本不兼容，双方也能认出对方并给出明确的拒绝理由。
+/// 因此要在检查包长之前先检查它：不同版本的握手包长度可能不同。
+fn check_magic_and_version(body: &[u8]) -> Result<(), ProtocolError> {
+    require(body, HANDSHAKE_PREFIX_SIZE)?;
+    let magic = u32::from_be_bytes(body[0..4].try_into().unwrap());
+    let version = body[4];
+    if magic != PROTOCOL_MAGIC {
+        return Err(ProtocolError::InvalidField("magic"));
+    }
+    if version != PROTOCOL_VERSION {
+        return Err(ProtocolError::BadVersion(version));
+    }
+    Ok(())
+}
+
+impl<'a> Packet<'a> {
+    pub fn packet_type(&self) -> PacketType {
+        match self {
+            Packet::Data { .. } => PacketType::Data,
+            Packet::Ack(_) => PacketType::Ack,
+            Packet::IFrameRequest { .. } => PacketType::IFrameRequest,
+            Packet::SpsPps(_) => PacketType::SpsPps,
+            Packet::Nack(_) => PacketType::Nack,
+            Packet::Fec { .. } => PacketType::Fec,
+            Packet::Encrypted(_) => PacketType::Encrypted,
+            Packet::Hello(_) => PacketType::Hello,
+            Packet::HelloAck(_) => PacketType::HelloAck,
+            Packet::PathChallenge(_) => PacketType::PathChallenge,
+            Packet::PathResponse(_) => PacketType::PathResponse,
+            Packet::ClockPing(_) => PacketType::ClockPing,
+            Packet::ClockPong(_) => PacketType::ClockPong,
+            Packet::Heartbeat => PacketType::Heartbeat,
+            Packet::Bye(_) => PacketType::Bye,
+            Packet::ByeAck(_) => PacketType::ByeAck,
+            Packet::Feedback(_) => PacketType::Feedback,
+            Packet::MtuProbe(_) => PacketType::MtuProbe,
+            Packet::MtuProbeAck(_) => PacketType::MtuProbeAck,
+            Packet::ParameterSets { .. } => PacketType::ParameterSets,
+            Packet::Audio { .. } => PacketType::Audio,
+            Packet::Metadata { .. } => PacketType::Metadata,
+        }
+    }
+
+    pub fn decode(datagram: &'a [u8]) -> Result<Self, ProtocolError> {
+        let (&first, body) = datagram.split_first().ok_or(ProtocolError::Truncated {
+            needed: 1,
+            actual: 0,
+        })?;
+        match PacketType::try_from(first)? {
+            PacketType::Data => {
+                require(body, DATA_HEADER_SIZE)?;
+                let header =
+                    DataHeader::from_bytes(body).ok_or(ProtocolError::InvalidField("header"))?;
+                let payload = &body[DATA_HEADER_SIZE..];
+                check_data_header(&header, payload.len()).map_err(ProtocolError::InvalidField)?;
+                Ok(Packet::Data { header, payload })
+            }
+            PacketType::Ack => {
+                require(body, ACK_PACKET_SIZE)?;
+                AckPacket::from_bytes(body)
+                    .map(Packet::Ack)
+                    .ok_or(ProtocolError::InvalidField("frame_id"))
+            }
+            PacketType::IFrameRequest => {
+                require(body, 1)?;
+                Ok(Packet::IFrameRequest { stream_id: body[0] })
+            }
+            PacketType::SpsPps => Ok(Packet::SpsPps(body)),
+            PacketType::Nack => {
+                require(body, NACK_HEADER_SIZE)?;
+                let count = u16::from_be_bytes([body[4], body[5]]) as usize;
+                if count > MAX_NACK_IDS {
+                    return Err(ProtocolError::InvalidField("count"));
+                }
+                require(body, NACK_HEADER_SIZE + count * size_of::<u16>())?;
+                NackPacket::from_bytes(body)
+                    .map(Packet::Nack)
+                    .ok_or(ProtocolError::InvalidField("packet_ids"))
+            }
+            PacketType::Fec => {
+                require(body, FEC_HEADER_SIZE)?;
+                let header =
+                    FecHeader::from_bytes(body).ok_or(ProtocolError::InvalidField("header"))?;
+                let shard = &body[FEC_HEADER_SIZE..];
+                check_fec_header(&header, shard.len()).map_err(ProtocolError::InvalidField)?;
+                Ok(Packet::Fec { header, shard })
+            }
+            PacketType::Encrypted => Ok(Packet::Encrypted(body)),
+            PacketType::Hello => {
+                check_magic_and_version(body)?;
+                require(body, HELLO_PACKET_SIZE)?;
+                HelloPacket::from_bytes(body)
+                    .map(Packet::Hello)
+                    .ok_or(ProtocolError::InvalidField("header"))
+            }
+            PacketType::HelloAck => {
+                check_magic_and_version(body)?;
+                require(body, HELLO_ACK_PACKET_SIZE)?;
+                HelloAckPacket::from_bytes(body)
+                    .map(Packet::HelloAck)
+                    .ok_or(ProtocolError::InvalidField("header"))
+            }
+            PacketType::PathChallenge | PacketType::PathResponse => {
+                require(body, PATH_PACKET_SIZE)?;
+                let path =
+                    PathPacket::from_bytes(body).ok_or(ProtocolError::InvalidField("token"))?;
+                Ok(if first == PacketType::PathChallenge as u8 {
+                    Packet::PathChallenge(path)
+                } else {
+                    Packet::PathResponse(path)
+                })
+            }
+            PacketType::ClockPing => {
+                require(body, CLOCK_PING_PACKET_SIZE)?;
+                ClockPingPacket::from_bytes(body)
+                    .map(Packet::ClockPing)
+                    .ok_or(ProtocolError::InvalidField("origin_ns"))
+            }
+            PacketType::ClockPong => {
+                require(body, CLOCK_PONG_PACKET_SIZE)?;
+                ClockPongPacket::from_bytes(body)
+                    .map(Packet::ClockPong)
+                    .ok_or(ProtocolError::InvalidField("timestamps"))
+            }
+            PacketType::Heartbeat => Ok(Packet::Heartbeat),
+            PacketType::Bye => {
+                require(body, BYE_PACKET_SIZE)?;
+                ByePacket::from_bytes(body)
+                    .map(Packet::Bye)
+                    .ok_or(ProtocolError::InvalidField("session_id"))
+            }
+            PacketType::ByeAck => {
+                require(body, BYE_ACK_PACKET_SIZE)?;
+                ByeAckPacket::from_bytes(body)
+                    .map(Packet::ByeAck)
+                    .ok_or(ProtocolError::InvalidField("session_id"))
+            }
+            PacketType::Feedback => {
+                require(body, FEEDBACK_PACKET_SIZE)?;
+                FeedbackPacket::from_bytes(body)
+                    .map(Packet::Feedback)
+                    .ok_or(ProtocolError::InvalidField("feedback"))
+            }
+            PacketType::MtuProbe | PacketType::MtuProbeAck => {
+                require(body, MTU_PROBE_HEADER_SIZE)?;
+                let probe = MtuProbePacket::from_bytes(body)
+                    .ok_or(ProtocolError::InvalidField("probe_id"))?;
+                if first == PacketType::MtuProbeAck as u8 {
+                    return Ok(Packet::MtuProbeAck(probe));
+                }
+                // 声明的大小必须与实际收到的一致，否则这个探测什么也证明不了
+                if probe.size as usize != datagram.len() {
+                    return Err(ProtocolError::InvalidField("size"));
+                }
+                Ok(Packet::MtuProbe(probe))
+            }
+            PacketType::ParameterSets => {
+                require(body, 2)?;
+                let codec = VideoCodec::try_from(body[1])
+                    .map_err(|_| ProtocolError::InvalidField("codec"))?;
+                Ok(Packet::ParameterSets {
+                    stream_id: body[0],
+                    codec,
+                    data: &body[2..],
+                })
+            }
+            PacketType::Audio => {
+                require(body, AUDIO_HEADER_SIZE)?;
+                let header =
+                    AudioHeader::from_bytes(body).ok_or(ProtocolError::InvalidField("header"))?;
+                let payload = &body[AUDIO_HEADER_SIZE..];
+                check_audio_header(&header, payload.len()).map_err(ProtocolError::InvalidField)?;
+                Ok(Packet::Audio { header, payload })
+            }
+            PacketType::Metadata => {
+                require(body, METADATA_HEADER_SIZE)?;
+                let header = MetadataHeader::from_bytes(body)
+                    .ok_or(ProtocolError::InvalidField("header"))?;
+                let entries = &body[METADATA_HEADER_SIZE..];
+                decode_entries(entries).map_err(ProtocolError::InvalidField)?;
+                Ok(Packet::Metadata { header, entries })
+            }
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![self.packet_type() as u8];
+        match self {
+            Packet::Data { header, payload } => {
+                bytes.reserve(DATA_HEADER_SIZE + payload.len());
+                bytes.extend_from_slice(&header.to_bytes());
+                bytes.extend_from_slice(payload);
+            }
+            Packet::Ack(ack) => bytes.extend_from_slice(&ack.to_bytes()),
+            Packet::IFrameRequest { stream_id } => bytes.push(*stream_id),
+            Packet::Heartbeat => {}
+            Packet::SpsPps(payload) | Packet::Encrypted(payload) => {
+                bytes.extend_from_slice(payload)
+            }
+            Packet::Nack(nack) => bytes.extend_from_slice(&nack.to_bytes()),
+            Packet::Fec { header, shard } => {
+                bytes.reserve(FEC_HEADER_SIZE + shard.len());
+                bytes.extend_from_slice(&header.to_bytes());
+                bytes.extend_from_slice(shard);
+            }
+            Packet::Hello(hello) => bytes.extend_from_slice(&hello.to_bytes()),
+            Packet::HelloAck(ack) => bytes.extend_from_slice(&ack.to_bytes()),
+            Packet::PathChallenge(path) | Packet::PathResponse(path) => {
+                bytes.extend_from_slice(&path.to_bytes())
+            }
+            Packet::ClockPing(ping) => bytes.extend_from_slice(&ping.to_bytes()),
+            Packet::ClockPong(pong) => bytes.extend_from_slice(&pong.to_bytes()),
+            Packet::Bye(bye) => bytes.extend_from_slice(&bye.to_bytes()),
+            Packet::ByeAck(ack) => bytes.extend_from_slice(&ack.to_bytes()),
+            Packet::Feedback(feedback) => bytes.extend_from_slice(&feedback.to_bytes()),
+            Packet::MtuProbe(probe) => {
+                bytes.extend_from_slice(&probe.to_bytes());
+                bytes.resize(bytes.len().max(probe.size as usize), 0);
+            }
+            Packet::MtuProbeAck(probe) => bytes.extend_from_slice(&probe.to_bytes()),
+            Packet::ParameterSets {
+                stream_id,
+                codec,
+                data,
+            } => {
+                bytes.push(*stream_id);
+                bytes.push(*codec as u8);
+                bytes.extend_from_slice(data);
+            }
+            Packet::Audio { header, payload } => {
+                bytes.reserve(AUDIO_HEADER_SIZE + payload.len());
+                bytes.extend_from_slice(&header.to_bytes());
+                bytes.extend_from_slice(payload);
+            }
+            Packet::Metadata { header, entries } => {
+                bytes.reserve(METADATA_HEADER_SIZE + entries.len());
+                bytes.extend_from_slice(&header.to_bytes());
+                bytes.extend_from_slice(entries);
+            }
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fec::FecScheme;
+    use crate::metadata::{encode_entries, MetadataEntry};
+    use crate::{handshake_session_id, AudioCodec, ByeReason, VideoCodec, CAP_NACK};
+
+    #[test]
+    fn test_round_trip_every_packet_type() {
+        let data_header = DataHeader {
+            connection_id: 9,
+            frame_id: 1,
+            capture_timestamp_ns: 2,
+            packet_id: 3,
+            total_packets: 4,
+            is_key_frame: 1,
+            stream_id: 1,
+        };
+        let fec_header = FecHeader {
+            connection_id: 9,
+            frame_id: 1,
+            capture_timestamp_ns: 2,
+            total_packets: 4,
+            is_key_frame: 1,
+            scheme: FecScheme::Xor as u8,
+            group_index: 0,
+            group_count: 1,
+            parity_index: 0,
+            parity_count: 1,
+            stream_id: 1,
+        };
+        let audio_header = AudioHeader {
+            connection_id: 9,
+            sequence: 21,
+            capture_timestamp_ns: 2,
+            sample_rate: 48_000,
+            codec: AudioCodec::Opus as u8,
+            channels: 2,
+        };
+        let hello = HelloPacket {
+            magic: PROTOCOL_MAGIC,
+            version: PROTOCOL_VERSION,
+            session_id: 5,
+            codec: VideoCodec::H264 as u8,
+            width: 640,
+            height: 480,
+            fps: 30,
+            capabilities: CAP_NACK,
+            max_payload_size: 1400,
+        };
+        let hello_ack = HelloAckPacket {
+            magic: PROTOCOL_MAGIC,
+            version: PROTOCOL_VERSION,
+            session_id: 5,
+            status: 0,
+            capabilities: CAP_NACK,
+            connection_id: 9,
+            max_payload_size: 1200,
+        };
+        let metadata_entries = encode_entries(&[
+            MetadataEntry::Exposure {
+                exposure_time_ns: 10_000_000,
+                iso: 200,
+            },
+            MetadataEntry::Orientation {
+                w: 0.5,
+                x: 0.5,
+                y: -0.5,
+                z: 0.5,
+            },
+        ]);
+        let metadata_header = MetadataHeader {
+            connection_id: 9,
+            frame_id: 1,
+            capture_timestamp_ns: 2,
+            stream_id: 1,
+        };
+        let packets = [
+            Packet::Data {
+                header: data_header,
+                payload: &[1, 2, 3],
+            },
+            Packet::Ack(AckPacket {
+                frame_id: 7,
+                stream_id: 1,
+            }),
+            Packet::IFrameRequest { stream_id: 2 },
+            Packet::SpsPps(&[0, 0, 0, 1, 0x67]),
+            Packet::Nack(NackPacket {
+                frame_id: 7,
+                stream_id: 1,
+                packet_ids: vec![1, 2],
+            }),
+            Packet::Fec {
+                header: fec_header,
+                shard: &[4, 5],
+            },
+            Packet::Encrypted(&[6; 8]),
+            Packet::Hello(hello),
+            Packet::HelloAck(hello_ack),
+            Packet::PathChallenge(PathPacket { token: 11 }),
+            Packet::PathResponse(PathPacket { token: 12 }),
+            Packet::ClockPing(ClockPingPacket { origin_ns: 13 }),
+            Packet::ClockPong(ClockPongPacket {
+                origin_ns: 13,
+                receive_ns: 14,
+                transmit_ns: 15,
+            }),
+            Packet::Heartbeat,
+            Packet::Bye(ByePacket {
+                session_id: 5,
+                reason: ByeReason::Shutdown as u8,
+            }),
+            Packet::ByeAck(ByeAckPacket { session_id: 5 }),
+            Packet::MtuProbe(MtuProbePacket {
+                probe_id: 3,
+                size: 1200,
+            }),
+            Packet::MtuProbeAck(MtuProbePacket {
+                probe_id: 3,
+                size: 1200,
+            }),
+            Packet::ParameterSets {
+                stream_id: 1,
+                codec: VideoCodec::H265,
+                data: &[0, 0, 0, 1, 0x40, 0x01, 0, 0, 0, 1, 0x42, 0x01],
+            },
+            Packet::Feedback(FeedbackPacket {
+                receive_rate_bps: 1_800_000,
+                loss_fraction: 12,
+                delay_gradient_us: -2_500,
+                interval_ms: 200,
+            }),
+            Packet::Audio {
+                header: audio_header,
+                payload: &[0xfc, 0xff, 0xfe],
+            },
+            Packet::Metadata {
+                header: metadata_header,
+                entries: &metadata_entries,
+            },
+        ];
+        for packet in &packets {
+            assert_eq!(&Packet::decode(&packet.encode()).unwrap(), packet);
+        }
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(
+            Packet::decode(&[]),
+            Err(ProtocolError::Truncated {
+                needed: 1,
+                actual: 0
+            })
+        );
+        assert_eq!(Packet::decode(&[200]), Err(ProtocolError::UnknownType(200)));
+        assert_eq!(
+            Packet::decode(&[PacketType::Ack as u8, 0]),
+            Err(ProtocolError::Truncated {
+                needed: 1 + ACK_PACKET_SIZE,
+                actual: 2
+            })
+        );
+
+        let mut data = Packet::Data {
+            header: DataHeader {
+                connection_id: 0,
+                frame_id: 1,
+                capture_timestamp_ns: 0,
+                packet_id: 0,
+                total_packets: 1,
+                is_key_frame: 0,
+                stream_id: 0,
+            },
+            payload: &[],
+        }
+        .encode();
+        // packet_id (第 17..19 字节) 改成超出 total_packets
+        data[18] = 5;
+        assert_eq!(
+            Packet::decode(&data),
+            Err(ProtocolError::InvalidField("packet_id"))
+        );
+        assert_eq!(
+            Packet::decode(&[PacketType::IFrameRequest as u8]),
+            Err(ProtocolError::Truncated {
+                needed: 2,
+                actual: 1
+            })
+        );
+        assert_eq!(
+            Packet::decode(&[PacketType::ParameterSets as u8, 0, 9, 0, 0, 0, 1]),
+            Err(ProtocolError::InvalidField("codec"))
+        );
+
+        let audio_header = AudioHeader {
+            connection_id: 0,
+            sequence: 0,
+            capture_timestamp_ns: 0,
+            sample_rate: 44_100,
+            codec: AudioCodec::Aac as u8,
+            channels: 1,
+        };
+        let mut audio = Packet::Audio {
+            header: audio_header,
+            payload: &[0x21],
+        }
+        .encode();
+        // channels 是包头的最后一个字节
+        audio[AUDIO_HEADER_SIZE] = 3;
+        assert_eq!(
+            Packet::decode(&audio),
+            Err(ProtocolError::InvalidField("channels"))
+        );
+        assert_eq!(
+            Packet::decode(
+                &Packet::Audio {
+                    header: audio_header,
+                    payload: &[],
+                }
+                .encode()
+            ),
+            Err(ProtocolError::InvalidField("payload"))
+        );
+
+        let mut hello = Packet::Hello(HelloPacket {
+            magic: PROTOCOL_MAGIC,
+            version: PROTOCOL_VERSION,
+            session_id: 1,
+            codec: 0,
+            width: 0,
+            height: 0,
+            fps: 0,
+            capabilities: 0,
+            max_payload_size: 0,
+        })
+        .encode();
+        hello[5] = PROTOCOL_VERSION + 1;
+        assert_eq!(
+            Packet::decode(&hello),
+            Err(ProtocolError::BadVersion(PROTOCOL_VERSION + 1))
+        );
+        hello[1] ^= 0xff;
+        assert_eq!(
+            Packet::decode(&hello),
+            Err(ProtocolError::InvalidField("magic"))
+        );
+    }
+
+    #[test]
+    fn test_handshake_from_other_version_with_other_length_is_bad_version() {
+        let hello = Packet::Hello(HelloPacket {
+            magic: PROTOCOL_MAGIC,
+            version: PROTOCOL_VERSION,
+            session_id: 0x1122_3344_5566_7788,
+            codec: 0,
+            width: 1920,
+            height: 1080,
+            fps: 30,
+            capabilities: 0,
+            max_payload_size: 1400,
+        })
+        .encode();
+        // 没有 max_payload_size 的旧版 Hello 比当前版本短两个字节
+        let mut old = hello[..hello.len() - 2].to_vec();
+        old[5] = 2;
+        assert_eq!(Packet::decode(&old), Err(ProtocolError::BadVersion(2)));
+        assert_eq!(handshake_session_id(&old[1..]), Some(0x1122_3344_5566_7788));
+
+        let mut old_ack = Packet::HelloAck(HelloAckPacket {
+            magic: PROTOCOL_MAGIC,
+            version: PROTOCOL_VERSION,
+            session_id: 1,
+            status: 0,
+            capabilities: 0,
+            connection_id: 1,
+            max_payload_size: 1400,
+        })
+        .encode();
+        old_ack.truncate(old_ack.len() - 2);
+        old_ack[5] = 2;
+        assert_eq!(Packet::decode(&old_ack), Err(ProtocolError::BadVersion(2)));
+
+        // 连共同前缀都不完整的才算截断；版本正确但长度不够的同样是截断
+        assert!(matches!(
+            Packet::decode(&hello[..HANDSHAKE_PREFIX_SIZE]),
+            Err(ProtocolError::Truncated { .. })
+        ));
+        assert!(matches!(
+            Packet::decode(&hello[..hello.len() - 2]),
+            Err(ProtocolError::Truncated { .. })
+        ));
+    }
+}
@@ -0,0 +1,195 @@
+// --- packages/protocol/src/pmtu.rs ---
+
+//! 路径 MTU 探测：发送端在设置了 DF（不允许分片）的套接字上发出逐渐变大的 MtuProbe，
+//! 接收端对收到的每个探测回一个 MtuProbeAck。被应答的大小说明整条路径都能通过，
+//! 没有应答（多次超时）或本地直接报 EMSGSIZE 的大小则作为上界，在两者之间二分查找。
+//!
+//! 这里的大小都是 UDP 数据报（编码、加密之后）的字节数。查找结束后每隔 `REPROBE_INTERVAL`
+//! 重新向上试探一次，路径换了（例如从 5GHz 切回 2.4GHz 的另一台 AP）也能用上更大的分片。
+use crate::MtuProbePacket;
+use std::time::{Duration, Instant};
+
+/// 不经探测就假定路径能通过的数据报大小（IPv6 最小 MTU 1280 减去 IP/UDP 包头后仍有余量）
+pub const BASE_DATAGRAM_SIZE: usize = 1200;
+// 一个探测多久没有应答就算丢失
+const PROBE_TIMEOUT: Duration = Duration::from_millis(300);
+// 同一个大小连续丢失这么多次才认为路径通不过（单次丢失可能只是普通丢包）
+const MAX_PROBE_ATTEMPTS: u8 = 3;
+// 上下界相差小于这个值时结束查找
+const SEARCH_GRANULARITY: usize = 16;
+// 查找结束后多久重新向上试探
+const REPROBE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+struct InFlightProbe {
+    probe: MtuProbePacket,
+    sent_at: Instant,
+    attempts: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct PathMtuProber {
+    // 已确认能通过的最大数据报
+    confirmed: usize,
+    // 查找的上界（含），大于它的大小已知通不过或超出了本端的需要
+    ceiling: usize,
+    max_size: usize,
+    in_flight: Option<InFlightProbe>,
+    next_probe_id: u32,
+    search_done_at: Option<Instant>,
+}
+
+impl PathMtuProber {
+    /// `max_size` 是本端会用到的最大数据报，探测不会超过它。
+    pub fn new(max_size: usize) -> Self {
+        let max_size = max_size.clamp(BASE_DATAGRAM_SIZE, u16::MAX as usize);
+        PathMtuProber {
+            confirmed: BASE_DATAGRAM_SIZE,
+            ceiling: max_size,
+            max_size,
+            in_flight: None,
+            next_probe_id: 0,
+            search_done_at: None,
+        }
+    }
+
+    /// 已确认整条路径能通过的最大数据报大小。
+    pub fn confirmed(&self) -> usize {
+        self.confirmed
+    }
+
+    /// 是否还在查找中（否则在等待下一次重新试探）。
+    pub fn is_searching(&self) -> bool {
+        self.search_done_at.is_none()
+    }
+
+    /// 推进探测，返回此刻应当发出的探测（包括超时重发）。应当周期性地调用。
+    pub fn poll(&mut self, now: Instant) -> Option<MtuProbePacket> {
+        if let Some(in_flight) = self.in_flight {
+            if now.saturating_duration_since(in_flight.sent_at) < PROBE_TIMEOUT {
+                return None;
+            }
+            self.in_flight = None;
+            if in_flight.attempts < MAX_PROBE_ATTEMPTS {
+                return Some(self.send(in_flight.probe.size as usize, in_flight.attempts + 1, now));
+            }
+            self.ceiling = in_flight.probe.size as usize - 1;
+        }
+        if let Some(done_at) = self.search_done_at {
+            if now.saturating_duration_since(done_at) < REPROBE_INTERVAL {
+                return None;
+            }
+            self.search_done_at = None;
+            self.ceiling = self.max_size;
+        }
+        if self.ceiling < self.confirmed + SEARCH_GRANULARITY {
+            self.search_done_at = Some(now);
+            return None;
+        }
+        let size = self.confirmed + (self.ceiling - self.confirmed).div_ceil(2);
+        Some(self.send(size, 1, now))
+    }
+
+    /// 处理一个 MtuProbeAck；确认的大小因此变大时返回 true。
+    pub fn on_ack(&mut self, ack: &MtuProbePacket) -> bool {
+        let Some(in_flight) = self.in_flight else {
+            return false;
+        };
+        if in_flight.probe.probe_id != ack.probe_id {
+            return false;
+        }
+        self.in_flight = None;
+        let size = in_flight.probe.size as usize;
+        if size <= self.confirmed {
+            return false;
+        }
+        self.confirmed = size;
+        true
+    }
+
+    /// 本地发送 `size` 字节的数据报时报告过大（EMSGSIZE）：不用等超时，直接降低上界。
+    pub fn on_too_big(&mut self, size: usize) {
+        if self
+            .in_flight
+            .is_some_and(|in_flight| in_flight.probe.size as usize >= size)
+        {
+            self.in_flight = None;
+        }
+        self.ceiling = self.ceiling.min(size.saturating_sub(1));
+        // 本地接口的 MTU 变小了，已确认的大小也不再可信
+        self.confirmed = self.confirmed.min(self.ceiling).max(BASE_DATAGRAM_SIZE);
+    }
+
+    fn send(&mut self, size: usize, attempts: u8, now: Instant) -> MtuProbePacket {
+        let probe = MtuProbePacket {
+            probe_id: self.next_probe_id,
+            size: size as u16,
+        };
+        self.next_probe_id = self.next_probe_id.wrapping_add(1);
+        self.in_flight = Some(InFlightProbe {
+            probe,
+            sent_at: now,
+            attempts,
+        });
+        probe
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 模拟一条只能通过 `path_limit` 字节数据报的路径，返回查找结束时确认的大小和经过的时间
+    fn run_search(prober: &mut PathMtuProber, path_limit: usize, t0: Instant) -> (usize, Instant) {
+        let mut now = t0;
+        loop {
+            if let Some(probe) = prober.poll(now) {
+                if probe.size as usize <= path_limit {
+                    prober.on_ack(&probe);
+                }
+            }
+            if !prober.is_searching() {
+                return (prober.confirmed(), now);
+            }
+            now += Duration::from_millis(50);
+            assert!(now < t0 + Duration::from_secs(60), "search did not finish");
+        }
+    }
+
+    #[test]
+    fn test_binary_search_converges_below_path_limit() {
+        let mut prober = PathMtuProber::new(9000);
+        let t0 = Instant::now();
+        // 1500 字节 MTU 的以太网：IPv4 + UDP 包头之后剩 1472 字节
+        let (confirmed, done) = run_search(&mut prober, 1472, t0);
+        assert!(confirmed <= 1472);
+        assert!(confirmed + SEARCH_GRANULARITY > 1472, "{}", confirmed);
+
+        // 一分钟后重新试探，这次路径支持巨型帧
+        assert_eq!(prober.poll(done + Duration::from_secs(1)), None);
+        let (confirmed, _) = run_search(&mut prober, 8972, done + REPROBE_INTERVAL);
+        assert!(confirmed <= 8972);
+        assert!(confirmed + SEARCH_GRANULARITY > 8972, "{}", confirmed);
+    }
+
+    #[test]
+    fn test_lost_probe_is_retried_and_too_big_lowers_ceiling() {
+        let mut prober = PathMtuProber::new(2000);
+        let t0 = Instant::now();
+        let first = prober.poll(t0).unwrap();
+        assert_eq!(first.size, 1600);
+        assert_eq!(prober.poll(t0 + Duration::from_millis(100)), None);
+        // 超时之后以同样的大小重发，旧探测迟到的应答不再算数
+        let retry = prober.poll(t0 + PROBE_TIMEOUT).unwrap();
+        assert_eq!(retry.size, first.size);
+        assert_ne!(retry.probe_id, first.probe_id);
+        assert!(!prober.on_ack(&first));
+
+        // 本地接口直接拒绝了这个大小：上界立刻降到它之下
+        prober.on_too_big(1500);
+        let next = prober.poll(t0 + PROBE_TIMEOUT).unwrap();
+        assert_eq!(next.size, 1350);
+        assert!(prober.on_ack(&next));
+        assert_eq!(prober.confirmed(), 1350);
+    }
+}
@@ -0,0 +1,755 @@
+// --- packages/protocol/src/reassembly.rs ---
+
+//! 接收端的帧重组：把同一帧的数据分片（以及 FEC 校验分片）拼回完整的帧。
+//!
+//! 所有输入都来自网络，不可信：每个分片先按 `check_data_header` / `check_fec_header`
+//! 校验，同一帧的分片还必须在总分片数、关键帧标志、时间戳和所属的流上保持一致。
+//! 帧号只在一路流内唯一，多路流的接收端为每路流各用一个 `ReassemblyTable`。
+//! 所有未完成帧占用的内存受 `ReassemblyLimits` 约束，超出时淘汰最早开始的帧；
+//! 长时间没有新分片到达的帧由 `sweep` 清理。每一帧被放弃时都会产生一个 `LossEvent`。
+use crate::fec::{
+    check_fec_header, group_members, recover_group, FecHeader, FecScheme, SHARD_LEN_PREFIX,
+};
+use crate::{check_data_header, DataHeader, NackPacket, MAX_NACK_IDS, MAX_PAYLOAD_SIZE};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+
+// 记住最近完成（或已放弃）的帧，迟到的重传分片和跨帧交织的校验分片不会再为它们新建重组器
+const RECENTLY_FINISHED_CAPACITY: usize = 256;
+// 每个分片槽位本身的开销：槽位在帧开始时就按 total_packets 全部分配
+const SLOT_SIZE: usize = size_of::<Option<Vec<u8>>>();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReassemblyError {
+    /// 分片头部本身不合法（字段名）
+    Invalid(&'static str),
+    /// 分片与同一帧先前的分片在某个帧级字段上不一致（字段名）
+    Inconsistent(&'static str),
+    /// 即使淘汰了其他所有未完成的帧，内存预算仍然放不下这个分片
+    OverBudget,
+}
+
+impl fmt::Display for ReassemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReassemblyError::Invalid(field) => write!(f, "invalid field `{}`", field),
+            ReassemblyError::Inconsistent(field) => {
+                write!(f, "`{}` disagrees with earlier packets of the frame", field)
+            }
+            ReassemblyError::OverBudget => write!(f, "reassembly memory budget exhausted"),
+        }
+    }
+}
+
+impl std::error::Error for ReassemblyError {}
+
+/// 决定何时为未完成的帧发送 NACK。
+#[derive(Debug, Clone, Copy)]
+pub struct NackPolicy {
+    /// 帧尾分片在最后一个分片到达后多久仍未出现，才认为是丢失（而不是还在路上）
+    pub tail_timeout: Duration,
+    /// 同一帧两次 NACK 之间的最小间隔，应略大于链路 RTT
+    pub retry_interval: Duration,
+    /// 帧从第一个分片到达起超过该期限就不再请求重传，重传回来也已经来不及显示
+    pub deadline: Duration,
+    pub max_nacks_per_frame: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReassemblyLimits {
+    /// 同时处于重组中的帧数上限
+    pub max_frames: usize,
+    /// 所有未完成帧占用的字节数上限（分片数据加槽位开销）
+    pub max_bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LossReason {
+    /// 超过空闲期限仍未收齐
+    Stale,
+    /// 为了给其他帧腾出内存预算而被淘汰
+    OverBudget,
+}
+
+/// 一帧未能重组完成而被放弃。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LossEvent {
+    pub frame_id: u32,
+    pub is_key_frame: bool,
+    pub total_packets: u16,
+    /// 放弃时仍然缺失的数据分片数
+    pub missing_packets: u16,
+    /// 从第一个分片到达到被放弃经过的时间
+    pub age: Duration,
+    pub reason: LossReason,
+}
+
+/// 当前会话的重组统计，`clear` 时归零。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReassemblyStats {
+    pub frames_completed: u64,
+    pub frames_lost: u64,
+    /// 被放弃的帧中缺失的数据分片总数
+    pub packets_lost: u64,
+    /// 重复到达的数据分片（包括帧已经完成之后才到的重传）
+    pub duplicates: u64,
+    /// 收到的（不重复的）数据分片，包括重传补回的
+    pub packets_received: u64,
+    /// 在 NACK 中报告过缺失的数据分片，每个分片只计一次
+    pub packets_nacked: u64,
+}
+
+impl std::ops::AddAssign<&ReassemblyStats> for ReassemblyStats {
+    fn add_assign(&mut self, other: &ReassemblyStats) {
+        self.frames_completed += other.frames_completed;
+        self.frames_lost += other.frames_lost;
+        self.packets_lost += other.packets_lost;
+        self.duplicates += other.duplicates;
+        self.packets_received += other.packets_received;
+        self.packets_nacked += other.packets_nacked;
+    }
+}
+
+/// 重组完成的一帧。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompletedFrame {
+    pub frame_id: u32,
+    pub is_key_frame: bool,
+    pub capture_timestamp_ns: u64,
+    pub data: Vec<u8>,
+}
+
+struct FrameReassembler {
+    packets: Vec<Option<Vec<u8>>>,
+    received_count: u16,
+    total_packets: u16,
+    first_seen: Instant,
+    last_seen: Instant,
+    is_key_frame: bool,
+    capture_timestamp_ns: u64,
+    stream_id: u8,
+    // 已收到的最大 packet_id，小于它的空位可以确定是丢包
+    highest_packet_id: u16,
+    last_nack: Option<Instant>,
+    nack_count: u8,
+    // 每个分片是否已经在某个 NACK 中报告过
+    nacked: Vec<bool>,
+    // FEC：按组索引保存已收到的校验分片，槽位按 parity_index 排列
+    fec_scheme: Option<FecScheme>,
+    fec_group_count: u8,
+    parity: HashMap<u8, Vec<Option<Vec<u8>>>>,
+    // 本帧当前占用的字节数，计入全局预算
+    bytes: usize,
+}
+
+impl FrameReassembler {
+    fn new(
+        total_packets: u16,
+        is_key_frame: bool,
+        capture_timestamp_ns: u64,
+        stream_id: u8,
+        now: Instant,
+    ) -> Self {
+        FrameReassembler {
+            packets: vec![None; total_packets as usize],
+            received_count: 0,
+            total_packets,
+            first_seen: now,
+            last_seen: now,
+            is_key_frame,
+            capture_timestamp_ns,
+            stream_id,
+            highest_packet_id: 0,
+            last_nack: None,
+            nack_count: 0,
+            nacked: vec![false; total_packets as usize],
+            fec_scheme: None,
+            fec_group_count: 0,
+            parity: HashMap::new(),
+            bytes: total_packets as usize * SLOT_SIZE,
+        }
+    }
+
+    fn check_consistent(
+        &self,
+        total_packets: u16,
+        is_key_frame: bool,
+        capture_timestamp_ns: u64,
+        stream_id: u8,
+    ) -> Result<(), ReassemblyError> {
+        if total_packets != self.total_packets {
+            return Err(ReassemblyError::Inconsistent("total_packets"));
+        }
+        if is_key_frame != self.is_key_frame {
+            return Err(ReassemblyError::Inconsistent("is_key_frame"));
+        }
+        if capture_timestamp_ns != self.capture_timestamp_ns {
+            return Err(ReassemblyError::Inconsistent("capture_timestamp_ns"));
+        }
+        if stream_id != self.stream_id {
+            return Err(ReassemblyError::Inconsistent("stream_id"));
+        }
+        Ok(())
+    }
+
+    /// 校验分片的分组方式必须与本帧已收到的校验分片一致，返回新建该组槽位所需的字节数。
+    fn check_parity_layout(&self, header: &FecHeader) -> Result<usize, ReassemblyError> {
+        if let Some(scheme) = self.fec_scheme {
+            if header.scheme != scheme as u8 {
+                return Err(ReassemblyError::Inconsistent("scheme"));
+            }
+            if header.group_count != self.fec_group_count {
+                return Err(ReassemblyError::Inconsistent("group_count"));
+            }
+        }
+        match self.parity.get(&header.group_index) {
+            Some(slots) if slots.len() != header.parity_count as usize => {
+                Err(ReassemblyError::Inconsistent("parity_count"))
+            }
+            Some(_) => Ok(0),
+            None => Ok(header.parity_count as usize * SLOT_SIZE),
+        }
+    }
+
+    /// 加入一个数据分片，返回它是否是新的（而不是重复到达的）。
+    fn add_packet(&mut self, packet_id: u16, data: &[u8], now: Instant) -> bool {
+        let id = packet_id as usize;
+        self.last_seen = now;
+        if self.packets[id].is_some() {
+            return false;
+        }
+        self.bytes += data.len();
+        self.packets[id] = Some(data.to_vec());
+        self.received_count += 1;
+        self.highest_packet_id = self.highest_packet_id.max(packet_id);
+        self.try_fec_recovery();
+        true
+    }
+
+    fn loss_event(&self, frame_id: u32, now: Instant, reason: LossReason) -> LossEvent {
+        LossEvent {
+            frame_id,
+            is_key_frame: self.is_key_frame,
+            total_packets: self.total_packets,
+            missing_packets: self.total_packets - self.received_count,
+            age: now.saturating_duration_since(self.first_seen),
+            reason,
+        }
+    }
+
+    fn add_parity(&mut self, header: &FecHeader, shard: &[u8], now: Instant) {
+        // 调用前已经通过 check_fec_header 与 check_parity_layout
+        let Ok(scheme) = FecScheme::try_from(header.scheme) else {
+            return;
+        };
+        self.fec_scheme = Some(scheme);
+        self.fec_group_count = header.group_count;
+        if !self.parity.contains_key(&header.group_index) {
+            self.bytes += header.parity_count as usize * SLOT_SIZE;
+        }
+        let slots = self
+            .parity
+            .entry(header.group_index)
+            .or_insert_with(|| vec![None; header.parity_count as usize]);
+        let slot = &mut slots[header.parity_index as usize];
+        if slot.is_none() {
+            self.bytes += shard.len();
+            *slot = Some(shard.to_vec());
+        }
+        self.last_seen = now;
+        self.try_fec_recovery();
+    }
+
+    /// 对每个收到了校验分片的组尝试恢复丢失的数据分片。
+    fn try_fec_recovery(&mut self) {
+        let Some(scheme) = self.fec_scheme else {
+            return;
+        };
+        if self.received_count == self.total_packets {
+            return;
+        }
+        let mut recovered = Vec::new();
+        for (&group_index, parity) in &self.parity {
+            let members = group_members(self.total_packets, group_index, self.fec_group_count);
+            let data: Vec<Option<&[u8]>> = members
+                .iter()
+                .map(|&id| self.packets[id as usize].as_deref())
+                .collect();
+            if data.iter().all(Option::is_some) {
+                continue;
+            }
+            let parity: Vec<Option<&[u8]>> = parity.iter().map(|p| p.as_deref()).collect();
+            if let Ok(payloads) = recover_group(scheme, &data, &parity) {
+                recovered.extend(payloads.into_iter().map(|(i, p)| (members[i], p)));
+            }
+        }
+        for (packet_id, payload) in recovered {
+            let slot = &mut self.packets[packet_id as usize];
+            if slot.is_none() {
+                self.bytes += payload.len();
+                *slot = Some(payload);
+                self.received_count += 1;
+            }
+        }
+    }
+
+    /// 所有分片都已到齐（或被 FEC 恢复）时取出拼接好的整帧。
+    fn take_if_complete(&mut self) -> Option<Vec<u8>> {
+        if self.received_count == self.total_packets {
+            let total_size = self.packets.iter().map(|p| p.as_ref().unwrap().len()).sum();
+            let mut frame_data = Vec::with_capacity(total_size);
+            for packet in self.packets.iter_mut() {
+                frame_data.extend_from_slice(packet.take().unwrap().as_slice());
+            }
+            Some(frame_data)
+        } else {
+            None
+        }
+    }
+
+    /// 计算应当 NACK 的分片。`newer_frame_seen` 表示已经有更新的帧开始到达，
+    /// 此时本帧尾部缺失的分片也可以确定是丢了。
+    fn missing_packets(
+        &self,
+        now: Instant,
+        newer_frame_seen: bool,
+        policy: &NackPolicy,
+    ) -> Vec<u16> {
+        let tail_lost =
+            newer_frame_seen || now.duration_since(self.last_seen) > policy.tail_timeout;
+        let scan_end = if tail_lost {
+            self.packets.len()
+        } else {
+            self.highest_packet_id as usize
+        };
+        self.packets[..scan_end]
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.is_none())
+            .map(|(id, _)| id as u16)
+            .take(MAX_NACK_IDS)
+            .collect()
+    }
+
+    /// 判断当前是否应该为本帧发送 NACK，并在需要时记录本次发送。
+    fn poll_nack(
+        &mut self,
+        now: Instant,
+        newer_frame_seen: bool,
+        policy: &NackPolicy,
+    ) -> Option<Vec<u16>> {
+        if now.duration_since(self.first_seen) > policy.deadline
+            || self.nack_count >= policy.max_nacks_per_frame
+        {
+            return None;
+        }
+        if let Some(last) = self.last_nack {
+            if now.duration_since(last) < policy.retry_interval {
+                return None;
+            }
+        }
+        let missing = self.missing_packets(now, newer_frame_seen, policy);
+        if missing.is_empty() {
+            return None;
+        }
+        self.last_nack = Some(now);
+        self.nack_count += 1;
+        Some(missing)
+    }
+
+    /// 记录这些分片已经被 NACK 过，返回其中第一次被报告的个数。
+    fn mark_nacked(&mut self, packet_ids: &[u16]) -> u64 {
+        packet_ids
+            .iter()
+            .filter(|&&id| !std::mem::replace(&mut self.nacked[id as usize], true))
+            .count() as u64
+    }
+}
+
+/// 所有处于重组中的帧，以及它们共同的内存预算。
+pub struct ReassemblyTable {
+    frames: HashMap<u32, FrameReassembler>,
+    recently_finished: VecDeque<u32>,
+    limits: ReassemblyLimits,
+    // 本会话协商的分片负载上限，超过它的分片直接拒绝
+    max_payload_size: usize,
+    buffered_bytes: usize,
+    stats: ReassemblyStats,
+    // 因内存预算被淘汰、尚未通过 sweep 交给调用方的丢帧事件
+    pending_losses: Vec<LossEvent>,
+}
+
+impl ReassemblyTable {
+    pub fn new(limits: ReassemblyLimits) -> Self {
+        ReassemblyTable {
+            frames: HashMap::new(),
+            recently_finished: VecDeque::with_capacity(RECENTLY_FINISHED_CAPACITY),
+            limits,
+            max_payload_size: MAX_PAYLOAD_SIZE,
+            buffered_bytes: 0,
+            stats: ReassemblyStats::default(),
+            pending_losses: Vec::new(),
+        }
+    }
+
+    /// 加入一个数据分片；帧因此完整时返回整帧。
+    pub fn insert_data(
+        &mut self,
+        header: &DataHeader,
+        payload: &[u8],
+        now: Instant,
+    ) -> Result<Option<CompletedFrame>, ReassemblyError> {
+        check_data_header(header, payload.len()).map_err(ReassemblyError::Invalid)?;
+        if payload.len() > self.max_payload_size {
+            return Err(ReassemblyError::Invalid("payload"));
+        }
+        if self.recently_finished.contains(&header.frame_id) {
+            self.stats.duplicates += 1;
+            return Ok(None);
+        }
+        let is_key_frame = header.is_key_frame != 0;
+        let needed = match self.frames.get(&header.frame_id) {
+            Some(frame) => {
+                frame.check_consistent(
+                    header.total_packets,
+                    is_key_frame,
+                    header.capture_timestamp_ns,
+                    header.stream_id,
+                )?;
+                payload.len()
+            }
+            None => header.total_packets as usize * SLOT_SIZE + payload.len(),
+        };
+        self.make_room(header.frame_id, needed, now)?;
+        let before = self.frames.get(&header.frame_id).map_or(0, |f| f.bytes);
+        let frame = self.frames.entry(header.frame_id).or_insert_with(|| {
+            FrameReassembler::new(
+                header.total_packets,
+                is_key_frame,
+                header.capture_timestamp_ns,
+                header.stream_id,
+                now,
+            )
+        });
+        if frame.add_packet(header.packet_id, payload, now) {
+            self.stats.packets_received += 1;
+        } else {
+            self.stats.duplicates += 1;
+        }
+        Ok(self.finish(header.frame_id, before, now))
+    }
+
+    /// 加入一个校验分片；帧因此被恢复完整时返回整帧。
+    pub fn insert_parity(
+        &mut self,
+        header: &FecHeader,
+        shard: &[u8],
+        now: Instant,
+    ) -> Result<Option<CompletedFrame>, ReassemblyError> {
+        check_fec_header(header, shard.len()).map_err(ReassemblyError::Invalid)?;
+        if shard.len() > self.max_payload_size + SHARD_LEN_PREFIX {
+            return Err(ReassemblyError::Invalid("shard"));
+        }
+        if self.recently_finished.contains(&header.frame_id) {
+            return Ok(None);
+        }
+        let is_key_frame = header.is_key_frame != 0;
+        let needed = match self.frames.get(&header.frame_id) {
+            Some(frame) => {
+                frame.check_consistent(
+                    header.total_packets,
+                    is_key_frame,
+                    header.capture_timestamp_ns,
+                    header.stream_id,
+                )?;
+                frame.check_parity_layout(header)? + shard.len()
+            }
+            // 帧的第一个到达的包也可能是校验分片，它同样携带了帧级信息
+            None => {
+                (header.total_packets as usize + header.parity_count as usize) * SLOT_SIZE
+                    + shard.len()
+            }
+        };
+        self.make_room(header.frame_id, needed, now)?;
+        let before = self.frames.get(&header.frame_id).map_or(0, |f| f.bytes);
+        let frame = self.frames.entry(header.frame_id).or_insert_with(|| {
+            FrameReassembler::new(
+                header.total_packets,
+                is_key_frame,
+                header.capture_timestamp_ns,
+                header.stream_id,
+                now,
+            )
+        });
+        frame.add_parity(header, shard, now);
+        Ok(self.finish(header.frame_id, before, now))
+    }
+
+    /// 扫描所有未完成的帧，返回需要发送的 NACK。
+    pub fn poll_nacks(&mut self, now: Instant, policy: &NackPolicy) -> Vec<NackPacket> {
+        let newest_frame_id = self.frames.keys().copied().max();
+        let mut nacks = Vec::new();
+        for (&frame_id, frame) in self.frames.iter_mut() {
+            let newer_frame_seen = newest_frame_id.is_some_and(|newest| newest > frame_id);
+            if let Some(packet_ids) = frame.poll_nack(now, newer_frame_seen, policy) {
+                self.stats.packets_nacked += frame.mark_nacked(&packet_ids);
+                nacks.push(NackPacket {
+                    frame_id,
+                    stream_id: frame.stream_id,
+                    packet_ids,
+                });
+            }
+        }
+        nacks
+    }
+
+    /// 放弃超过 `idle_timeout` 没有收到任何新分片的帧，并返回自上次调用以来所有被放弃的帧
+    /// （包括因内存预算被淘汰的）。应当周期性地调用，否则丢了分片的帧会一直占着内存。
+    pub fn sweep(&mut self, now: Instant, idle_timeout: Duration) -> Vec<LossEvent> {
+        let stale: Vec<u32> = self
+            .frames
+            .iter()
+            .filter(|(_, frame)| now.saturating_duration_since(frame.last_seen) > idle_timeout)
+            .map(|(&id, _)| id)
+            .collect();
+        for frame_id in stale {
+            self.abandon(frame_id, now, LossReason::Stale);
+        }
+        std::mem::take(&mut self.pending_losses)
+    }
+
+    /// 丢弃所有未完成的帧并清零统计（例如新会话开始时）。
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.recently_finished.clear();
+        self.buffered_bytes = 0;
+        self.stats = ReassemblyStats::default();
+        self.pending_losses.clear();
+    }
+
+    /// 设置会话协商的分片负载上限（不超过协议上限 `MAX_PAYLOAD_SIZE`），`clear` 不会重置它。
+    pub fn set_max_payload_size(&mut self, max_payload_size: usize) {
+        self.max_payload_size = max_payload_size.min(MAX_PAYLOAD_SIZE);
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// 所有未完成帧当前占用的字节数。
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes
+    }
+
+    pub fn stats(&self) -> &ReassemblyStats {
+        &self.stats
+    }
+
+    /// 为 `frame_id` 腾出 `needed` 字节（新帧还需要一个帧数名额），必要时按开始时间淘汰最早的其他帧。
+    fn make_room(
+        &mut self,
+        frame_id: u32,
+        needed: usize,
+        now: Instant,
+    ) -> Result<(), ReassemblyError> {
+        let is_new = !self.frames.contains_key(&frame_id);
+        if needed > self.limits.max_bytes || (is_new && self.limits.max_frames == 0) {
+            return Err(ReassemblyError::OverBudget);
+        }
+        while self.buffered_bytes + needed > self.limits.max_bytes
+            || (is_new && self.frames.len() >= self.limits.max_frames)
+        {
+            let oldest = self
+                .frames
+                .iter()
+                .filter(|(&id, _)| id != frame_id)
+                .min_by_key(|(_, frame)| frame.first_seen)
+                .map(|(&id, _)| id);
+            let Some(oldest) = oldest else {
+                return Err(ReassemblyError::OverBudget);
+            };
+            self.abandon(oldest, now, LossReason::OverBudget);
+        }
+        Ok(())
+    }
+
+    /// 放弃一个未完成的帧：释放它的内存并记录丢帧事件。
+    fn abandon(&mut self, frame_id: u32, now: Instant, reason: LossReason) {
+        let Some(frame) = self.frames.remove(&frame_id) else {
+            return;
+        };
+        self.buffered_bytes -= frame.bytes;
+        let event = frame.loss_event(frame_id, now, reason);
+        self.stats.frames_lost += 1;
+        self.stats.packets_lost += event.missing_packets as u64;
+        self.pending_losses.push(event);
+        self.remember_finished(frame_id);
+    }
+
+    fn remember_finished(&mut self, frame_id: u32) {
+        if self.recently_finished.len() >= RECENTLY_FINISHED_CAPACITY {
+            self.recently_finished.pop_front();
+        }
+        self.recently_finished.push_back(frame_id);
+    }
+
+    /// 把本次插入新增的字节计入预算；帧已经完整时把它移出表并返回。
+    fn finish(
+        &mut self,
+        frame_id: u32,
+        bytes_before: usize,
+        now: Instant,
+    ) -> Option<CompletedFrame> {
+        let frame = self.frames.get_mut(&frame_id)?;
+        self.buffered_bytes += frame.bytes - bytes_before;
+        let Some(data) = frame.take_if_complete() else {
+            // FEC 恢复出的分片不在 make_room 预留的字节之内，超出预算时同样要淘汰
+            if self.make_room(frame_id, 0, now).is_err() {
+                self.abandon(frame_id, now, LossReason::OverBudget);
+            }
+            return None;
+        };
+        let frame = self.frames.remove(&frame_id).unwrap();
+        self.buffered_bytes -= frame.bytes;
+        self.stats.frames_completed += 1;
+        self.remember_finished(frame_id);
+        Some(CompletedFrame {
+            frame_id,
+            is_key_frame: frame.is_key_frame,
+            capture_timestamp_ns: frame.capture_timestamp_ns,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: ReassemblyLimits = ReassemblyLimits {
+        max_frames: 2,
+        max_bytes: 64 * 1024,
+    };
+
+    fn header(frame_id: u32, packet_id: u16, total_packets: u16) -> DataHeader {
+        DataHeader {
+            connection_id: 1,
+            frame_id,
+            capture_timestamp_ns: 100,
+            packet_id,
+            total_packets,
+            is_key_frame: 0,
+            stream_id: 0,
+        }
+    }
+
+    #[test]
+    fn test_rejects_invalid_and_inconsistent_fragments() {
+        let mut table = ReassemblyTable::new(LIMITS);
+        let now = Instant::now();
+        assert_eq!(
+            table.insert_data(&header(1, 0, 0), &[], now),
+            Err(ReassemblyError::Invalid("total_packets"))
+        );
+        assert_eq!(
+            table.insert_data(&header(1, 2, 2), &[], now),
+            Err(ReassemblyError::Invalid("packet_id"))
+        );
+        assert_eq!(table.insert_data(&header(1, 0, 2), &[1], now), Ok(None));
+        assert_eq!(
+            table.insert_data(&header(1, 1, 3), &[2], now),
+            Err(ReassemblyError::Inconsistent("total_packets"))
+        );
+        let mut key_frame = header(1, 1, 2);
+        key_frame.is_key_frame = 1;
+        assert_eq!(
+            table.insert_data(&key_frame, &[2], now),
+            Err(ReassemblyError::Inconsistent("is_key_frame"))
+        );
+        let mut other_stream = header(1, 1, 2);
+        other_stream.stream_id = 1;
+        assert_eq!(
+            table.insert_data(&other_stream, &[2], now),
+            Err(ReassemblyError::Inconsistent("stream_id"))
+        );
+        let frame = table
+            .insert_data(&header(1, 1, 2), &[2], now)
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.data, vec![1, 2]);
+        assert!(table.is_empty());
+        assert_eq!(table.buffered_bytes(), 0);
+
+        // 超过协商的负载上限
+        table.set_max_payload_size(4);
+        assert_eq!(
+            table.insert_data(&header(2, 0, 1), &[0; 5], now),
+            Err(ReassemblyError::Invalid("payload"))
+        );
+        assert!(table.insert_data(&header(2, 0, 1), &[0; 4], now).is_ok());
+    }
+
+    #[test]
+    fn test_evicts_oldest_frame_when_over_budget() {
+        let mut table = ReassemblyTable::new(LIMITS);
+        let start = Instant::now();
+        for frame_id in 0..3 {
+            let now = start + Duration::from_millis(frame_id as u64);
+            assert_eq!(
+                table.insert_data(&header(frame_id, 0, 2), &[0], now),
+                Ok(None)
+            );
+        }
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.stats().frames_lost, 1);
+        // 最早的帧 0 已被淘汰；帧 2 收齐后移出，表里只剩帧 1
+        assert_eq!(
+            table
+                .insert_data(&header(2, 1, 2), &[1], start)
+                .unwrap()
+                .unwrap()
+                .frame_id,
+            2
+        );
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn test_sweep_evicts_stale_frames_and_counts_losses() {
+        let mut table = ReassemblyTable::new(LIMITS);
+        let start = Instant::now();
+        let timeout = Duration::from_millis(500);
+        assert_eq!(table.insert_data(&header(1, 0, 3), &[0], start), Ok(None));
+        assert_eq!(table.insert_data(&header(1, 0, 3), &[0], start), Ok(None));
+        assert!(table.sweep(start + timeout, timeout).is_empty());
+
+        let losses = table.sweep(start + timeout * 2, timeout);
+        assert_eq!(losses.len(), 1);
+        assert_eq!(losses[0].frame_id, 1);
+        assert_eq!(losses[0].missing_packets, 2);
+        assert_eq!(losses[0].reason, LossReason::Stale);
+        assert!(table.is_empty());
+        assert_eq!(table.buffered_bytes(), 0);
+
+        // 被放弃的帧的迟到分片不会再新建重组器
+        assert_eq!(table.insert_data(&header(1, 1, 3), &[1], start), Ok(None));
+        assert!(table.is_empty());
+        assert_eq!(
+            *table.stats(),
+            ReassemblyStats {
+                frames_completed: 0,
+                frames_lost: 1,
+                packets_lost: 2,
+                duplicates: 2,
+                packets_received: 1,
+                packets_nacked: 0,
+            }
+        );
+    }
+}
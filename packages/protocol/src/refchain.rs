@@ -0,0 +1,206 @@
+// --- packages/protocol/src/refchain.rs ---
+
+//! 接收端的参考链完整性跟踪：位于抖动缓冲与解码器之间，判断每一帧能否正确解码。
+//!
+//! 丢失一个参考帧之后，后面依赖它的帧解码出来都是花屏，一直持续到下一个关键帧。与其把这些帧
+//! 送进解码器，不如扣下它们、保持最后一帧正确的画面，同时请求发送端尽快发送关键帧（类似 RTCP PLI）。
+//!
+//! H.264 用条带头的 frame_num 判断：它在每个参考帧之后加一，于是丢失的只是非参考帧时链条仍然
+//! 完整（发送端在拥塞时会主动丢弃非参考帧）。没有 frame_num 的码流（H.265，或者还没解析出 SPS）
+//! 只能按帧号是否连续判断，中间少了任何一帧都视为断链。
+
+/// H.264 条带头中的 frame_num 及其取值范围（MaxFrameNum = 2^log2_max_frame_num）。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameNum {
+    pub value: u32,
+    pub max: u32,
+}
+
+/// 一帧的内容对参考链的意义。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainFrame {
+    /// 不含条带的帧，例如单独发送的参数集。不影响参考链
+    NoSlice,
+    Slice {
+        /// 可以从这一帧开始解码（IDR/IRAP）
+        random_access: bool,
+        /// 这一帧可能被后续帧参考
+        reference: bool,
+        frame_num: Option<FrameNum>,
+    },
+}
+
+/// 当前会话的参考链统计，`reset` 时归零。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChainStats {
+    /// 参考链断开的次数（包括解码器报错与重启）
+    pub breaks: u64,
+    /// 断链期间没有送去解码的帧
+    pub frames_withheld: u64,
+}
+
+pub struct ReferenceChain {
+    // 为真时扣下所有帧，直到下一个可随机访问的帧。会话开始时还没有收到关键帧，同样是断开的
+    broken: bool,
+    last_frame_id: Option<u32>,
+    // 上一帧之后帧号不连续，还没有确认丢失的是不是参考帧
+    pending_gap: bool,
+    // 最近一个参考帧的 frame_num（H.264 的 PrevRefFrameNum）
+    prev_ref_frame_num: Option<u32>,
+    stats: ChainStats,
+}
+
+impl Default for ReferenceChain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReferenceChain {
+    pub fn new() -> Self {
+        ReferenceChain {
+            broken: true,
+            last_frame_id: None,
+            pending_gap: false,
+            prev_ref_frame_num: None,
+            stats: ChainStats::default(),
+        }
+    }
+
+    /// 按释放顺序登记一帧，返回它能否送去解码。返回 false 时参考链已经断开，调用方应当扣下这一帧
+    /// 并请求关键帧。
+    pub fn on_frame(&mut self, frame_id: u32, frame: ChainFrame) -> bool {
+        if self
+            .last_frame_id
+            .is_some_and(|last| frame_id.wrapping_sub(last) != 1)
+        {
+            self.pending_gap = true;
+        }
+        self.last_frame_id = Some(frame_id);
+
+        let ChainFrame::Slice {
+            random_access,
+            reference,
+            frame_num,
+        } = frame
+        else {
+            return true;
+        };
+        if random_access {
+            self.broken = false;
+            self.pending_gap = false;
+            self.prev_ref_frame_num = frame_num.map(|n| n.value);
+            return true;
+        }
+        if !self.broken {
+            let intact = match (frame_num, self.prev_ref_frame_num) {
+                // 紧跟在参考帧之后的帧 frame_num 加一；场编码的第二场与前一场相同
+                (Some(n), Some(prev)) => {
+                    n.value == prev || n.value == prev.wrapping_add(1) % n.max.max(1)
+                }
+                _ => !self.pending_gap,
+            };
+            self.pending_gap = false;
+            if !intact {
+                self.mark_broken();
+            }
+        }
+        if self.broken {
+            self.stats.frames_withheld += 1;
+            return false;
+        }
+        if reference {
+            if let Some(n) = frame_num {
+                self.prev_ref_frame_num = Some(n.value);
+            }
+        }
+        true
+    }
+
+    /// 参考链已经损坏（例如解码器报错），在下一个可随机访问的帧之前扣下所有帧。
+    pub fn mark_broken(&mut self) {
+        if !self.broken {
+            self.broken = true;
+            self.stats.breaks += 1;
+        }
+    }
+
+    /// 是否正在等待关键帧。
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    /// 是否应当请求关键帧：链条断开，并且这路流已经有帧在传输（还没收到任何帧的流不需要）。
+    pub fn wants_key_frame(&self) -> bool {
+        self.broken && self.last_frame_id.is_some()
+    }
+
+    pub fn stats(&self) -> &ChainStats {
+        &self.stats
+    }
+
+    /// 开始新的会话：清空状态与统计，等待第一个关键帧。
+    pub fn reset(&mut self) {
+        *self = ReferenceChain::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slice(random_access: bool, reference: bool, frame_num: Option<u32>) -> ChainFrame {
+        ChainFrame::Slice {
+            random_access,
+            reference,
+            frame_num: frame_num.map(|value| FrameNum { value, max: 16 }),
+        }
+    }
+
+    #[test]
+    fn test_waits_for_first_key_frame() {
+        let mut chain = ReferenceChain::new();
+        assert!(!chain.on_frame(0, slice(false, true, Some(3))));
+        assert!(chain.is_broken());
+        // 参数集不受影响
+        assert!(chain.on_frame(1, ChainFrame::NoSlice));
+        assert!(chain.on_frame(2, slice(true, true, Some(0))));
+        assert!(chain.on_frame(3, slice(false, true, Some(1))));
+        assert_eq!(chain.stats().frames_withheld, 1);
+        assert_eq!(chain.stats().breaks, 0);
+    }
+
+    #[test]
+    fn test_lost_non_reference_frame_keeps_chain() {
+        let mut chain = ReferenceChain::new();
+        assert!(chain.on_frame(0, slice(true, true, Some(0))));
+        assert!(chain.on_frame(1, slice(false, true, Some(1))));
+        // 第 2 帧（非参考帧，frame_num 2）丢失，第 3 帧的 frame_num 仍然是 2
+        assert!(chain.on_frame(3, slice(false, true, Some(2))));
+        assert!(!chain.is_broken());
+        // 第 4 帧（参考帧，frame_num 3）丢失，第 5 帧的 frame_num 是 4
+        assert!(!chain.on_frame(5, slice(false, true, Some(4))));
+        assert!(!chain.on_frame(6, slice(false, true, Some(5))));
+        assert_eq!(chain.stats().breaks, 1);
+        assert!(chain.on_frame(7, slice(true, true, Some(0))));
+        // frame_num 在 MaxFrameNum 处回绕
+        chain.prev_ref_frame_num = Some(15);
+        assert!(chain.on_frame(8, slice(false, true, Some(0))));
+    }
+
+    #[test]
+    fn test_gap_without_frame_num_breaks_chain() {
+        let mut chain = ReferenceChain::new();
+        assert!(chain.on_frame(0, slice(true, true, None)));
+        assert!(chain.on_frame(1, slice(false, true, None)));
+        assert!(chain.on_frame(2, ChainFrame::NoSlice));
+        assert!(!chain.on_frame(4, slice(false, true, None)));
+        assert!(chain.on_frame(5, slice(true, true, None)));
+        chain.mark_broken();
+        assert!(!chain.on_frame(6, slice(false, false, None)));
+        assert_eq!(chain.stats().breaks, 2);
+        chain.reset();
+        assert_eq!(chain.stats().breaks, 0);
+        assert!(chain.is_broken());
+    }
+}
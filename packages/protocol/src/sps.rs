@@ -0,0 +1,662 @@
+// --- packages/protocol/src/sps.rs ---
+
+//! 解析 H.264 / H.265 的序列参数集（SPS），得到码流的分辨率、profile、level、色度格式、位深和帧率。
+//!
+//! 接收端据此在数据到达解码器之前就知道输出格式，判断参数集变化之后是否需要重新协商 v4l2 输出。
+//! 只解析到 VUI 的时序信息为止，后面的字段（HRD、码流限制等）不需要。
+use crate::nal::{self, NalKind};
+use crate::VideoCodec;
+use std::fmt;
+
+/// 从 SPS 解析出的码流格式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpsInfo {
+    pub codec: VideoCodec,
+    pub profile_idc: u8,
+    pub level_idc: u8,
+    /// 去掉裁剪区域之后的显示宽高（像素）
+    pub width: u32,
+    pub height: u32,
+    /// 0 为单色，1 为 4:2:0，2 为 4:2:2，3 为 4:4:4
+    pub chroma_format_idc: u8,
+    pub bit_depth: u8,
+    /// VUI 中的帧率（分子, 分母）；编码器没有写入时序信息时为 None
+    pub frame_rate: Option<(u32, u32)>,
+    /// H.264 条带头中 frame_num 的位数（H.265 没有 frame_num，为 0）
+    pub log2_max_frame_num: u8,
+    /// 4:4:4 的三个色彩平面是否分开编码；为真时条带头中多一个 colour_plane_id
+    pub separate_colour_plane: bool,
+}
+
+impl SpsInfo {
+    /// 与 `other` 的解码输出（分辨率、色度格式、位深）是否不同。只有 profile、level 或帧率变化时
+    /// 解码器输出的画面格式不变，下游不需要重新协商。
+    pub fn output_differs(&self, other: &SpsInfo) -> bool {
+        (
+            self.width,
+            self.height,
+            self.chroma_format_idc,
+            self.bit_depth,
+        ) != (
+            other.width,
+            other.height,
+            other.chroma_format_idc,
+            other.bit_depth,
+        )
+    }
+
+    fn profile_name(&self) -> Option<&'static str> {
+        match (self.codec, self.profile_idc) {
+            (VideoCodec::H264, 66) => Some("Baseline"),
+            (VideoCodec::H264, 77) => Some("Main"),
+            (VideoCodec::H264, 88) => Some("Extended"),
+            (VideoCodec::H264, 100) => Some("High"),
+            (VideoCodec::H264, 110) => Some("High 10"),
+            (VideoCodec::H264, 122) => Some("High 4:2:2"),
+            (VideoCodec::H264, 244) => Some("High 4:4:4"),
+            (VideoCodec::H265, 1) => Some("Main"),
+            (VideoCodec::H265, 2) => Some("Main 10"),
+            (VideoCodec::H265, 3) => Some("Main Still Picture"),
+            (VideoCodec::H265, 4) => Some("Range Extensions"),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SpsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.profile_name() {
+            Some(name) => write!(f, "{:?} {}", self.codec, name)?,
+            None => write!(f, "{:?} profile {}", self.codec, self.profile_idc)?,
+        }
+        // H.264 的 level_idc 是 level 的 10 倍，H.265 是 30 倍
+        let level_scale = if self.codec == VideoCodec::H265 {
+            30
+        } else {
+            10
+        };
+        write!(
+            f,
+            "@L{}.{} {}x{}",
+            self.level_idc / level_scale,
+            self.level_idc % level_scale / (level_scale / 10),
+            self.width,
+            self.height
+        )?;
+        let chroma = match self.chroma_format_idc {
+            0 => "4:0:0",
+            1 => "4:2:0",
+            2 => "4:2:2",
+            _ => "4:4:4",
+        };
+        write!(f, " {} {}-bit", chroma, self.bit_depth)?;
+        if let Some((num, den)) = self.frame_rate {
+            write!(f, " {:.2}fps", num as f64 / den as f64)?;
+        }
+        Ok(())
+    }
+}
+
+/// 解析一个 SPS NAL 单元（含 NAL 头，不含起始码）。格式错误或数据被截断时返回出错的字段名。
+pub fn parse_sps(codec: VideoCodec, nal: &[u8]) -> Result<SpsInfo, &'static str> {
+    if nal::nal_kind(codec, nal) != Some(NalKind::Sps) {
+        return Err("nal_type");
+    }
+    let rbsp = nal::unescape_rbsp(nal);
+    match codec {
+        VideoCodec::H264 => parse_h264_sps(BitReader::new(&rbsp[1..])),
+        _ => parse_h265_sps(BitReader::new(&rbsp[2..])),
+    }
+}
+
+/// 在一段 Annex-B 码流（例如缓存的参数集）中找到第一个 SPS 并解析。没有 SPS 时返回 None。
+pub fn find_sps(codec: VideoCodec, data: &[u8]) -> Option<Result<SpsInfo, &'static str>> {
+    nal::annex_b_units(data)
+        .find(|unit| nal::nal_kind(codec, unit) == Some(NalKind::Sps))
+        .map(|sps| parse_sps(codec, sps))
+}
+
+/// 读出 H.264 条带 NAL 单元（含 NAL 头）的 frame_num。frame_num 在每个参考帧之后加一，
+/// 接收端据此判断丢失的帧是不是参考帧。`sps` 必须是这个码流当前的 SPS。
+pub fn slice_frame_num(nal: &[u8], sps: &SpsInfo) -> Result<u32, &'static str> {
+    if sps.codec != VideoCodec::H264
+        || !matches!(nal::nal_kind(sps.codec, nal), Some(NalKind::Slice { .. }))
+    {
+        return Err("nal_type");
+    }
+    // 条带头在 frame_num 之前只有几个指数哥伦布编码的字段，只需要去掉开头部分的防竞争字节
+    let rbsp = nal::unescape_rbsp(&nal[1..nal.len().min(32)]);
+    let mut r = BitReader::new(&rbsp);
+    r.ue()?; // first_mb_in_slice
+    r.ue()?; // slice_type
+    r.ue()?; // pic_parameter_set_id
+    if sps.separate_colour_plane {
+        r.skip(2)?; // colour_plane_id
+    }
+    r.bits(sps.log2_max_frame_num as u32)
+}
+
+// 按位读取 RBSP，支持指数哥伦布编码。数据不够时返回 Err("sps")
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader { data, pos: 0 }
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32, &'static str> {
+        let mut value = 0u32;
+        for _ in 0..n {
+            let byte = self.data.get(self.pos / 8).ok_or("sps")?;
+            let bit = (byte >> (7 - self.pos % 8)) & 1;
+            value = (value << 1) | bit as u32;
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+
+    fn flag(&mut self) -> Result<bool, &'static str> {
+        Ok(self.bits(1)? == 1)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), &'static str> {
+        if self.pos + n > self.data.len() * 8 {
+            return Err("sps");
+        }
+        self.pos += n;
+        Ok(())
+    }
+
+    // 无符号指数哥伦布编码 ue(v)
+    fn ue(&mut self) -> Result<u32, &'static str> {
+        let mut leading_zeros = 0;
+        while !self.flag()? {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return Err("sps");
+            }
+        }
+        Ok((1u32 << leading_zeros) - 1 + self.bits(leading_zeros)?)
+    }
+
+    // 有符号指数哥伦布编码 se(v)
+    fn se(&mut self) -> Result<i32, &'static str> {
+        let value = self.ue()?;
+        let magnitude = value.div_ceil(2) as i32;
+        Ok(if value % 2 == 1 {
+            magnitude
+        } else {
+            -magnitude
+        })
+    }
+}
+
+fn parse_h264_sps(mut r: BitReader) -> Result<SpsInfo, &'static str> {
+    let profile_idc = r.bits(8)? as u8;
+    r.skip(8)?; // constraint_set 标志
+    let level_idc = r.bits(8)? as u8;
+    r.ue()?; // seq_parameter_set_id
+
+    let mut chroma_format_idc = 1;
+    let mut separate_colour_plane = false;
+    let mut bit_depth = 8;
+    if matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format_idc = r.ue()?;
+        if chroma_format_idc > 3 {
+            return Err("chroma_format_idc");
+        }
+        if chroma_format_idc == 3 {
+            separate_colour_plane = r.flag()?;
+        }
+        bit_depth = read_bit_depth(&mut r)?;
+        r.ue()?; // bit_depth_chroma_minus8
+        r.skip(1)?; // qpprime_y_zero_transform_bypass_flag
+        if r.flag()? {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..lists {
+                if r.flag()? {
+                    skip_scaling_list(&mut r, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    let log2_max_frame_num_minus4 = r.ue()?;
+    if log2_max_frame_num_minus4 > 12 {
+        return Err("log2_max_frame_num");
+    }
+    let log2_max_frame_num = log2_max_frame_num_minus4 + 4;
+    match r.ue()? {
+        0 => {
+            r.ue()?; // log2_max_pic_order_cnt_lsb_minus4
+        }
+        1 => {
+            r.skip(1)?; // delta_pic_order_always_zero_flag
+            r.se()?; // offset_for_non_ref_pic
+            r.se()?; // offset_for_top_to_bottom_field
+            for _ in 0..r.ue()? {
+                r.se()?; // offset_for_ref_frame
+            }
+        }
+        _ => {}
+    }
+    r.ue()?; // max_num_ref_frames
+    r.skip(1)?; // gaps_in_frame_num_value_allowed_flag
+    let width_in_mbs = r.ue()? + 1;
+    let height_in_map_units = r.ue()? + 1;
+    let frame_mbs_only = r.flag()?;
+    if !frame_mbs_only {
+        r.skip(1)?; // mb_adaptive_frame_field_flag
+    }
+    r.skip(1)?; // direct_8x8_inference_flag
+    let mut crop = [0u32; 4];
+    if r.flag()? {
+        for value in &mut crop {
+            *value = r.ue()?;
+        }
+    }
+
+    // 裁剪的单位取决于色度采样（ChromaArrayType 为 0 时以亮度像素为单位）和是否是场编码
+    let field_factor = if frame_mbs_only { 1 } else { 2 };
+    let (crop_unit_x, crop_unit_y) = match (separate_colour_plane, chroma_format_idc) {
+        (true, _) | (false, 0) => (1, field_factor),
+        (false, 1) => (2, 2 * field_factor),
+        (false, 2) => (2, field_factor),
+        _ => (1, field_factor),
+    };
+    let coded_width = width_in_mbs.checked_mul(16).ok_or("pic_size")?;
+    let coded_height = height_in_map_units
+        .checked_mul(16 * field_factor)
+        .ok_or("pic_size")?;
+    let width = cropped_size(coded_width, crop_unit_x, crop[0], crop[1]).ok_or("frame_crop")?;
+    let height = cropped_size(coded_height, crop_unit_y, crop[2], crop[3]).ok_or("frame_crop")?;
+
+    let frame_rate = if r.flag()? {
+        parse_h264_vui_timing(&mut r)?
+    } else {
+        None
+    };
+    Ok(SpsInfo {
+        codec: VideoCodec::H264,
+        profile_idc,
+        level_idc,
+        width,
+        height,
+        chroma_format_idc: chroma_format_idc as u8,
+        bit_depth: bit_depth as u8,
+        frame_rate,
+        log2_max_frame_num: log2_max_frame_num as u8,
+        separate_colour_plane,
+    })
+}
+
+// bit_depth_luma_minus8：两种编码的位深都不超过 16
+fn read_bit_depth(r: &mut BitReader) -> Result<u32, &'static str> {
+    let bit_depth_minus8 = r.ue()?;
+    if bit_depth_minus8 > 8 {
+        return Err("bit_depth");
+    }
+    Ok(8 + bit_depth_minus8)
+}
+
+// 编码尺寸减去两侧的裁剪（以 `unit` 个像素为单位），裁剪掉全部画面时返回 None
+fn cropped_size(coded: u32, unit: u32, first: u32, second: u32) -> Option<u32> {
+    let crop = unit as u64 * (first as u64 + second as u64);
+    (coded as u64)
+        .checked_sub(crop)
+        .filter(|&size| size > 0)
+        .map(|size| size as u32)
+}
+
+// H.264 的 scaling_list()：只需要跳过
+fn skip_scaling_list(r: &mut BitReader, size: usize) -> Result<(), &'static str> {
+    let mut last_scale = 8;
+    let mut next_scale = 8;
+    for _ in 0..size {
+        if next_scale != 0 {
+            next_scale = (last_scale + r.se()? + 256) % 256;
+        }
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+    Ok(())
+}
+
+// 跳过 VUI 中时序信息之前的字段，返回帧率。H.264 的一帧是两个 tick（每场一个）
+fn parse_h264_vui_timing(r: &mut BitReader) -> Result<Option<(u32, u32)>, &'static str> {
+    skip_vui_common(r)?;
+    if !r.flag()? {
+        return Ok(None);
+    }
+    let num_units_in_tick = r.bits(32)?;
+    let time_scale = r.bits(32)?;
+    Ok(frame_rate(time_scale, num_units_in_tick.checked_mul(2)))
+}
+
+// H.264 与 H.265 的 VUI 开头相同：宽高比、过扫描、视频信号类型和色度采样位置
+fn skip_vui_common(r: &mut BitReader) -> Result<(), &'static str> {
+    if r.flag()? {
+        // aspect_ratio_idc 为 255（Extended_SAR）时后面跟着 sar_width 和 sar_height
+        if r.bits(8)? == 255 {
+            r.skip(32)?;
+        }
+    }
+    if r.flag()? {
+        r.skip(1)?; // overscan_appropriate_flag
+    }
+    if r.flag()? {
+        r.skip(4)?; // video_format 与 video_full_range_flag
+        if r.flag()? {
+            r.skip(24)?; // colour_primaries、transfer_characteristics、matrix_coefficients
+        }
+    }
+    if r.flag()? {
+        r.ue()?; // chroma_sample_loc_type_top_field
+        r.ue()?; // chroma_sample_loc_type_bottom_field
+    }
+    Ok(())
+}
+
+fn frame_rate(time_scale: u32, ticks_per_frame: Option<u32>) -> Option<(u32, u32)> {
+    ticks_per_frame
+        .filter(|&ticks| ticks > 0 && time_scale > 0)
+        .map(|ticks| (time_scale, ticks))
+}
+
+fn parse_h265_sps(mut r: BitReader) -> Result<SpsInfo, &'static str> {
+    r.skip(4)?; // sps_video_parameter_set_id
+    let max_sub_layers_minus1 = r.bits(3)?;
+    r.skip(1)?; // sps_temporal_id_nesting_flag
+    let (profile_idc, level_idc) = parse_h265_profile_tier_level(&mut r, max_sub_layers_minus1)?;
+    r.ue()?; // sps_seq_parameter_set_id
+    let chroma_format_idc = r.ue()?;
+    if chroma_format_idc > 3 {
+        return Err("chroma_format_idc");
+    }
+    let separate_colour_plane = chroma_format_idc == 3 && r.flag()?;
+    let coded_width = r.ue()?;
+    let coded_height = r.ue()?;
+    let mut window = [0u32; 4];
+    if r.flag()? {
+        for value in &mut window {
+            *value = r.ue()?;
+        }
+    }
+    let bit_depth = read_bit_depth(&mut r)?;
+    r.ue()?; // bit_depth_chroma_minus8
+
+    // 一致性窗口以色度采样为单位
+    let (sub_width, sub_height) = match (separate_colour_plane, chroma_format_idc) {
+        (false, 1) => (2, 2),
+        (false, 2) => (2, 1),
+        _ => (1, 1),
+    };
+    let width =
+        cropped_size(coded_width, sub_width, window[0], window[1]).ok_or("conformance_window")?;
+    let height =
+        cropped_size(coded_height, sub_height, window[2], window[3]).ok_or("conformance_window")?;
+
+    let log2_max_poc_lsb_minus4 = r.ue()?;
+    if log2_max_poc_lsb_minus4 > 12 {
+        return Err("log2_max_pic_order_cnt_lsb");
+    }
+    let log2_max_poc_lsb = log2_max_poc_lsb_minus4 + 4;
+    let ordering_info_for_all_layers = r.flag()?;
+    let first_layer = if ordering_info_for_all_layers {
+        0
+    } else {
+        max_sub_layers_minus1
+    };
+    for _ in first_layer..=max_sub_layers_minus1 {
+        r.ue()?; // sps_max_dec_pic_buffering_minus1
+        r.ue()?; // sps_max_num_reorder_pics
+        r.ue()?; // sps_max_latency_increase_plus1
+    }
+    for _ in 0..6 {
+        // 编码块与变换块的尺寸、变换层级深度
+        r.ue()?;
+    }
+    if r.flag()? && r.flag()? {
+        skip_h265_scaling_list_data(&mut r)?;
+    }
+    r.skip(2)?; // amp_enabled_flag、sample_adaptive_offset_enabled_flag
+    if r.flag()? {
+        r.skip(8)?; // pcm_sample_bit_depth_luma_minus1、pcm_sample_bit_depth_chroma_minus1
+        r.ue()?;
+        r.ue()?;
+        r.skip(1)?; // pcm_loop_filter_disabled_flag
+    }
+    let num_short_term_ref_pic_sets = r.ue()?;
+    if num_short_term_ref_pic_sets > 64 {
+        return Err("num_short_term_ref_pic_sets");
+    }
+    let mut num_delta_pics = Vec::with_capacity(num_short_term_ref_pic_sets as usize);
+    for idx in 0..num_short_term_ref_pic_sets as usize {
+        let count = skip_h265_st_ref_pic_set(&mut r, idx, &num_delta_pics)?;
+        num_delta_pics.push(count);
+    }
+    if r.flag()? {
+        let num_long_term_ref_pics = r.ue()?;
+        if num_long_term_ref_pics > 32 {
+            return Err("num_long_term_ref_pics_sps");
+        }
+        for _ in 0..num_long_term_ref_pics {
+            r.skip(log2_max_poc_lsb as usize + 1)?; // lt_ref_pic_poc_lsb_sps 与 used_by_curr_pic_lt_sps_flag
+        }
+    }
+    r.skip(2)?; // sps_temporal_mvp_enabled_flag、strong_intra_smoothing_enabled_flag
+
+    let frame_rate = if r.flag()? {
+        parse_h265_vui_timing(&mut r)?
+    } else {
+        None
+    };
+    Ok(SpsInfo {
+        codec: VideoCodec::H265,
+        profile_idc,
+        level_idc,
+        width,
+        height,
+        chroma_format_idc: chroma_format_idc as u8,
+        bit_depth: bit_depth as u8,
+        frame_rate,
+        log2_max_frame_num: 0,
+        separate_colour_plane,
+    })
+}
+
+// profile_tier_level()：返回 general_profile_idc 和 general_level_idc，子层的信息只需要跳过
+fn parse_h265_profile_tier_level(
+    r: &mut BitReader,
+    max_sub_layers_minus1: u32,
+) -> Result<(u8, u8), &'static str> {
+    r.skip(3)?; // general_profile_space、general_tier_flag
+    let profile_idc = r.bits(5)? as u8;
+    r.skip(32 + 48)?; // 兼容性标志与约束标志
+    let level_idc = r.bits(8)? as u8;
+    let mut sub_layers = Vec::with_capacity(max_sub_layers_minus1 as usize);
+    for _ in 0..max_sub_layers_minus1 {
+        sub_layers.push((r.flag()?, r.flag()?));
+    }
+    if max_sub_layers_minus1 > 0 {
+        r.skip(2 * (8 - max_sub_layers_minus1 as usize))?; // reserved_zero_2bits
+    }
+    for (profile_present, level_present) in sub_layers {
+        if profile_present {
+            r.skip(88)?;
+        }
+        if level_present {
+            r.skip(8)?;
+        }
+    }
+    Ok((profile_idc, level_idc))
+}
+
+fn skip_h265_scaling_list_data(r: &mut BitReader) -> Result<(), &'static str> {
+    for size_id in 0..4 {
+        let step = if size_id == 3 { 3 } else { 1 };
+        for _ in (0..6).step_by(step) {
+            if !r.flag()? {
+                r.ue()?; // scaling_list_pred_matrix_id_delta
+                continue;
+            }
+            let coef_num = 64.min(1 << (4 + (size_id << 1)));
+            if size_id > 1 {
+                r.se()?; // scaling_list_dc_coef_minus8
+            }
+            for _ in 0..coef_num {
+                r.se()?; // scaling_list_delta_coef
+            }
+        }
+    }
+    Ok(())
+}
+
+// st_ref_pic_set(idx)：跳过一个短期参考图像集，返回它包含的参考图像数（NumDeltaPics）。
+// SPS 中的参考图像集只能从前一个集合预测
+fn skip_h265_st_ref_pic_set(
+    r: &mut BitReader,
+    idx: usize,
+    num_delta_pics: &[u32],
+) -> Result<u32, &'static str> {
+    if idx > 0 && r.flag()? {
+        r.skip(1)?; // delta_rps_sign
+        r.ue()?; // abs_delta_rps_minus1
+        let mut count = 0;
+        for _ in 0..=num_delta_pics[idx - 1] {
+            let used_by_curr_pic = r.flag()?;
+            if used_by_curr_pic || r.flag()? {
+                count += 1;
+            }
+        }
+        return Ok(count);
+    }
+    let num_negative_pics = r.ue()?;
+    let num_positive_pics = r.ue()?;
+    let count = num_negative_pics
+        .checked_add(num_positive_pics)
+        .filter(|&count| count <= 32)
+        .ok_or("num_delta_pics")?;
+    for _ in 0..count {
+        r.ue()?; // delta_poc_minus1
+        r.skip(1)?; // used_by_curr_pic_flag
+    }
+    Ok(count)
+}
+
+// H.265 的 VUI 在时序信息之前还有几个字段；一帧就是一个 tick
+fn parse_h265_vui_timing(r: &mut BitReader) -> Result<Option<(u32, u32)>, &'static str> {
+    skip_vui_common(r)?;
+    r.skip(3)?; // neutral_chroma_indication_flag、field_seq_flag、frame_field_info_present_flag
+    if r.flag()? {
+        for _ in 0..4 {
+            r.ue()?; // 默认显示窗口
+        }
+    }
+    if !r.flag()? {
+        return Ok(None);
+    }
+    let num_units_in_tick = r.bits(32)?;
+    let time_scale = r.bits(32)?;
+    Ok(frame_rate(time_scale, Some(num_units_in_tick)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1920x1080 High@4.0：编码高度 1088，裁剪掉底部 8 行；VUI 中带有 25fps 的时序信息，含防竞争字节
+    const H264_SPS_1080P: [u8; 28] = [
+        0x67, 0x64, 0x00, 0x28, 0xac, 0xca, 0x50, 0x1e, 0x00, 0x89, 0xf9, 0x70, 0x16, 0xa0, 0x20,
+        0x20, 0x28, 0x00, 0x00, 0x03, 0x00, 0x08, 0x00, 0x00, 0x03, 0x01, 0x94, 0x20,
+    ];
+
+    #[test]
+    fn test_h264_sps_with_cropping_and_timing() {
+        let info = parse_sps(VideoCodec::H264, &H264_SPS_1080P).unwrap();
+        assert_eq!(
+            info,
+            SpsInfo {
+                codec: VideoCodec::H264,
+                profile_idc: 100,
+                level_idc: 40,
+                width: 1920,
+                height: 1080,
+                chroma_format_idc: 1,
+                bit_depth: 8,
+                frame_rate: Some((50, 2)),
+                log2_max_frame_num: 4,
+                separate_colour_plane: false,
+            }
+        );
+        assert_eq!(
+            info.to_string(),
+            "H264 High@L4.0 1920x1080 4:2:0 8-bit 25.00fps"
+        );
+    }
+
+    #[test]
+    fn test_h264_baseline_sps_without_vui() {
+        // Baseline 640x480，没有裁剪和 VUI
+        let sps = [0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80, 0xf6, 0x40];
+        let info = parse_sps(VideoCodec::H264, &sps).unwrap();
+        assert_eq!((info.width, info.height), (640, 480));
+        assert_eq!(info.frame_rate, None);
+        assert_eq!(parse_sps(VideoCodec::H264, &sps[..5]), Err("sps"));
+        assert_eq!(parse_sps(VideoCodec::H264, &[0x68, 0xce]), Err("nal_type"));
+    }
+
+    #[test]
+    fn test_h265_sps() {
+        // 1920x1080 Main@L4.1：一致性窗口裁剪掉底部 8 行，第二个短期参考图像集由第一个预测，
+        // VUI 中带有 30fps 的时序信息
+        let sps = [
+            0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00,
+            0x00, 0x03, 0x00, 0x7b, 0xa0, 0x03, 0xc0, 0x80, 0x11, 0x07, 0xcb, 0x96, 0x57, 0x92,
+            0x44, 0x89, 0xaf, 0xeb, 0x80, 0x40, 0x00, 0x00, 0x03, 0x00, 0x40, 0x00, 0x00, 0x07,
+            0x82,
+        ];
+        let info = parse_sps(VideoCodec::H265, &sps).unwrap();
+        assert_eq!(info.profile_idc, 1);
+        assert_eq!((info.width, info.height), (1920, 1080));
+        assert_eq!(info.frame_rate, Some((30, 1)));
+        assert_eq!(
+            info.to_string(),
+            "H265 Main@L4.1 1920x1080 4:2:0 8-bit 30.00fps"
+        );
+    }
+
+    #[test]
+    fn test_slice_frame_num() {
+        let info = parse_sps(VideoCodec::H264, &H264_SPS_1080P).unwrap();
+        // 非 IDR 条带：first_mb_in_slice = 0，slice_type = 5 (P)，pps_id = 0，frame_num = 9（4 位）
+        let slice = [0x41, 0x9b, 0x30];
+        assert_eq!(slice_frame_num(&slice, &info), Ok(9));
+        assert_eq!(slice_frame_num(&[0x41], &info), Err("sps"));
+        assert_eq!(slice_frame_num(&[0x67, 0x64], &info), Err("nal_type"));
+    }
+
+    #[test]
+    fn test_output_differs() {
+        let info = parse_sps(VideoCodec::H264, &H264_SPS_1080P).unwrap();
+        let faster = SpsInfo {
+            level_idc: 42,
+            frame_rate: Some((60, 1)),
+            ..info
+        };
+        assert!(!info.output_differs(&faster));
+        let rotated = SpsInfo {
+            width: 1080,
+            height: 1920,
+            ..info
+        };
+        assert!(info.output_differs(&rotated));
+    }
+}
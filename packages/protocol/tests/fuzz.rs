@@ -0,0 +1,389 @@
+// --- packages/protocol/tests/fuzz.rs ---
+
+//! 针对解码器、重组器与码流解析的随机化测试，普通的 `cargo test` 即可运行，不需要 nightly 或 libFuzzer。
+//!
+//! 迭代次数和随机种子可以通过环境变量调整，便于长时间跑或复现失败：
+//! `NEUROCAM_FUZZ_ITERATIONS=1000000 NEUROCAM_FUZZ_SEED=42 cargo test -p protocol --test fuzz`
+use protocol::crypto::{PacketOpener, PacketSealer, KEY_SIZE};
+use protocol::fec::{self, recover_group, FecConfig, FecHeader, FecScheme};
+use protocol::reassembly::{NackPolicy, ReassemblyLimits, ReassemblyTable};
+use protocol::{nal, sps};
+use protocol::{
+    DataHeader, Packet, PacketType, VideoCodec, DEFAULT_PAYLOAD_SIZE, MAX_PAYLOAD_SIZE,
+};
+use std::time::{Duration, Instant};
+
+const DEFAULT_ITERATIONS: usize = 10_000;
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// 不依赖外部 crate 的 xorshift64* 伪随机数发生器，同一个种子总是产生同一个序列。
+struct Rng(u64);
+
+impl Rng {
+    fn from_env(salt: u64) -> Self {
+        let seed: u64 = env_or("NEUROCAM_FUZZ_SEED", 0x4E43_414D);
+        Rng((seed ^ salt).max(1))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n.max(1) as u64) as usize
+    }
+
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next_u64() as u8).collect()
+    }
+
+    /// 偏向边界值的 u16，比均匀分布更容易撞到 0、1、上限附近的取值。
+    fn interesting_u16(&mut self) -> u16 {
+        match self.below(6) {
+            0 => 0,
+            1 => 1,
+            2 => u16::MAX,
+            3 => self.below(8) as u16,
+            4 => 2990 + self.below(20) as u16,
+            _ => self.next_u64() as u16,
+        }
+    }
+
+    fn interesting_u8(&mut self) -> u8 {
+        match self.below(4) {
+            0 => 0,
+            1 => 1,
+            2 => u8::MAX,
+            _ => self.next_u64() as u8,
+        }
+    }
+}
+
+fn iterations() -> usize {
+    env_or("NEUROCAM_FUZZ_ITERATIONS", DEFAULT_ITERATIONS)
+}
+
+/// 一个合法的数据报：随机选择类型，再用随机字段填充。
+fn valid_datagram(rng: &mut Rng) -> Vec<u8> {
+    let mut datagram = vec![rng.below(PacketType::Metadata as usize + 1) as u8];
+    let body_len = match rng.below(3) {
+        0 => rng.below(32),
+        1 => rng.below(64) + 16,
+        _ => rng.below(MAX_PAYLOAD_SIZE + 64),
+    };
+    datagram.extend(rng.bytes(body_len));
+    datagram
+}
+
+/// 对数据报做一次随机变异：翻转、截断、追加或改写类型字节。
+fn mutate(rng: &mut Rng, datagram: &mut Vec<u8>) {
+    match rng.below(4) {
+        0 if !datagram.is_empty() => {
+            let i = rng.below(datagram.len());
+            datagram[i] ^= 1 << rng.below(8);
+        }
+        1 => datagram.truncate(rng.below(datagram.len() + 1)),
+        2 => {
+            let extra = rng.below(16);
+            datagram.extend(rng.bytes(extra));
+        }
+        _ if !datagram.is_empty() => datagram[0] = rng.interesting_u8(),
+        _ => {}
+    }
+}
+
+#[test]
+fn fuzz_packet_decode_never_panics_and_round_trips() {
+    let mut rng = Rng::from_env(1);
+    for _ in 0..iterations() {
+        let mut datagram = valid_datagram(&mut rng);
+        for _ in 0..rng.below(4) {
+            mutate(&mut rng, &mut datagram);
+        }
+        if let Ok(packet) = Packet::decode(&datagram) {
+            // 解码成功的包重新编码后必须解码出同样的内容
+            let encoded = packet.encode();
+            assert_eq!(
+                Packet::decode(&encoded).as_ref(),
+                Ok(&packet),
+                "datagram {:02x?}",
+                datagram
+            );
+        }
+    }
+}
+
+#[test]
+fn fuzz_packet_opener_rejects_garbage() {
+    let mut rng = Rng::from_env(2);
+    let key = [3u8; KEY_SIZE];
+    let mut sealer = PacketSealer::new(&key);
+    let mut opener = PacketOpener::new(&key);
+    for _ in 0..iterations() {
+        let plaintext = valid_datagram(&mut rng);
+        let mut sealed = sealer.seal(&plaintext);
+        if rng.chance(50) {
+            mutate(&mut rng, &mut sealed);
+            // 任何改动过的密文都不能通过认证（改动可能恰好为空操作，此时允许成功）
+            if let Ok(opened) = opener.open(&sealed) {
+                assert_eq!(opened, plaintext);
+            }
+        } else {
+            assert_eq!(opener.open(&sealed).unwrap(), plaintext);
+        }
+    }
+}
+
+#[test]
+fn fuzz_recover_group_never_panics() {
+    let mut rng = Rng::from_env(3);
+    for _ in 0..iterations() / 10 {
+        let scheme = if rng.chance(50) {
+            FecScheme::Xor
+        } else {
+            FecScheme::ReedSolomon
+        };
+        let shard_len = rng.below(64);
+        let data: Vec<Vec<u8>> = (0..rng.below(12))
+            .map(|_| {
+                let len = rng.below(shard_len + 4);
+                rng.bytes(len)
+            })
+            .collect();
+        let parity: Vec<Vec<u8>> = (0..rng.below(6))
+            .map(|_| {
+                let len = if rng.chance(80) {
+                    shard_len
+                } else {
+                    rng.below(80)
+                };
+                rng.bytes(len)
+            })
+            .collect();
+        let data: Vec<Option<&[u8]>> = data
+            .iter()
+            .map(|d| (!rng.chance(30)).then_some(d.as_slice()))
+            .collect();
+        let parity: Vec<Option<&[u8]>> = parity
+            .iter()
+            .map(|p| (!rng.chance(30)).then_some(p.as_slice()))
+            .collect();
+        let _ = recover_group(scheme, &data, &parity);
+    }
+}
+
+fn random_data_header(rng: &mut Rng) -> DataHeader {
+    DataHeader {
+        connection_id: rng.next_u64() as u32,
+        frame_id: u32::MAX - rng.below(16) as u32,
+        capture_timestamp_ns: rng.below(3) as u64,
+        packet_id: rng.interesting_u16(),
+        total_packets: rng.interesting_u16(),
+        is_key_frame: rng.below(2) as u8,
+        stream_id: rng.below(2) as u8,
+    }
+}
+
+fn random_fec_header(rng: &mut Rng) -> FecHeader {
+    FecHeader {
+        connection_id: rng.next_u64() as u32,
+        frame_id: u32::MAX - rng.below(16) as u32,
+        capture_timestamp_ns: rng.below(3) as u64,
+        total_packets: rng.interesting_u16(),
+        is_key_frame: rng.below(2) as u8,
+        scheme: rng.below(3) as u8,
+        group_index: rng.interesting_u8(),
+        group_count: rng.interesting_u8(),
+        parity_index: rng.interesting_u8(),
+        parity_count: rng.interesting_u8(),
+        stream_id: rng.below(2) as u8,
+    }
+}
+
+/// 一帧真实的数据，以及发送端为它生成的数据分片和校验分片。
+struct GenuineFrame {
+    bytes: Vec<u8>,
+    data: Vec<(DataHeader, Vec<u8>)>,
+    parity: Vec<(FecHeader, Vec<u8>)>,
+}
+
+fn genuine_frame(rng: &mut Rng, frame_id: u32, config: &FecConfig) -> GenuineFrame {
+    let len = rng.below(DEFAULT_PAYLOAD_SIZE * 12) + 1;
+    let frame = rng.bytes(len);
+    let chunks: Vec<&[u8]> = frame.chunks(DEFAULT_PAYLOAD_SIZE).collect();
+    let total_packets = chunks.len() as u16;
+    let data = chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let header = DataHeader {
+                connection_id: 7,
+                frame_id,
+                capture_timestamp_ns: frame_id as u64,
+                packet_id: i as u16,
+                total_packets,
+                is_key_frame: 1,
+                stream_id: 0,
+            };
+            (header, chunk.to_vec())
+        })
+        .collect();
+    let template = FecHeader {
+        connection_id: 7,
+        frame_id,
+        capture_timestamp_ns: frame_id as u64,
+        total_packets,
+        is_key_frame: 1,
+        scheme: 0,
+        group_index: 0,
+        group_count: 0,
+        parity_index: 0,
+        parity_count: 0,
+        stream_id: 0,
+    };
+    let parity = fec::encode_frame(config, &template, &chunks).unwrap();
+    GenuineFrame {
+        bytes: frame,
+        data,
+        parity,
+    }
+}
+
+#[test]
+fn fuzz_reassembly_respects_budget_and_never_corrupts_frames() {
+    let mut rng = Rng::from_env(4);
+    let limits = ReassemblyLimits {
+        max_frames: 8,
+        max_bytes: 256 * 1024,
+    };
+    let policy = NackPolicy {
+        tail_timeout: Duration::from_millis(20),
+        retry_interval: Duration::from_millis(40),
+        deadline: Duration::from_millis(250),
+        max_nacks_per_frame: 4,
+    };
+    let mut table = ReassemblyTable::new(limits);
+    let start = Instant::now();
+    let rounds = iterations() / 100;
+    let mut completed_frames = 0;
+    for round in 0..rounds {
+        // 真实的帧 id 与垃圾分片的 id 区间不重叠，这样可以断言真实帧内容不被破坏
+        let frame_id = round as u32;
+        let config = FecConfig {
+            scheme: if rng.chance(50) {
+                FecScheme::Xor
+            } else {
+                FecScheme::ReedSolomon
+            },
+            redundancy: 0.5,
+            interleave_depth: 1 + rng.below(3) as u8,
+            cross_frame_interleave: false,
+        };
+        let GenuineFrame {
+            bytes: frame,
+            data,
+            parity,
+        } = genuine_frame(&mut rng, frame_id, &config);
+        let mut events: Vec<(bool, usize)> = (0..data.len())
+            .map(|i| (true, i))
+            .chain((0..parity.len()).map(|i| (false, i)))
+            .collect();
+        // 打乱顺序
+        for i in (1..events.len()).rev() {
+            events.swap(i, rng.below(i + 1));
+        }
+        let mut completed = None;
+        for (step, &(is_data, index)) in events.iter().enumerate() {
+            let now = start + Duration::from_millis((round * 100 + step) as u64);
+            // 在真实分片之间穿插伪造的分片
+            for _ in 0..rng.below(4) {
+                let payload_len = rng.below(MAX_PAYLOAD_SIZE + 8);
+                let payload = rng.bytes(payload_len);
+                let garbage = if rng.chance(50) {
+                    table.insert_data(&random_data_header(&mut rng), &payload, now)
+                } else {
+                    table.insert_parity(&random_fec_header(&mut rng), &payload, now)
+                };
+                if let Ok(Some(frame)) = garbage {
+                    assert!(frame.frame_id >= u32::MAX - 16);
+                }
+                assert!(table.len() <= limits.max_frames);
+                assert!(table.buffered_bytes() <= limits.max_bytes);
+            }
+            let result = if is_data {
+                let (header, payload) = &data[index];
+                table.insert_data(header, payload, now)
+            } else {
+                let (header, shard) = &parity[index];
+                table.insert_parity(header, shard, now)
+            };
+            if let Ok(Some(frame)) = result {
+                assert_eq!(frame.frame_id, frame_id);
+                completed = Some(frame);
+            }
+            assert!(table.len() <= limits.max_frames);
+            assert!(table.buffered_bytes() <= limits.max_bytes);
+            let _ = table.poll_nacks(now, &policy);
+        }
+        // 真实帧可能因预算被淘汰，但只要完成了，内容就必须与原始数据一致
+        if let Some(completed) = completed {
+            assert_eq!(completed.data, frame, "round {}", round);
+            completed_frames += 1;
+        }
+        if table.is_empty() {
+            assert_eq!(table.buffered_bytes(), 0);
+        }
+    }
+    // 垃圾分片最多只能挤掉少数真实帧，否则说明淘汰策略出了问题
+    assert!(
+        completed_frames * 2 > rounds,
+        "{}/{}",
+        completed_frames,
+        rounds
+    );
+    table.clear();
+    assert_eq!(table.buffered_bytes(), 0);
+}
+
+#[test]
+fn fuzz_nal_and_sps_parsers_never_panic() {
+    let mut rng = Rng::from_env(5);
+    for _ in 0..iterations() {
+        let codec = [VideoCodec::H264, VideoCodec::H265][rng.below(2)];
+        let len = rng.below(64);
+        let mut data = rng.bytes(len);
+        // 插入一些起始码和零字节，让切分和防竞争字节的处理走到更多分支
+        for _ in 0..rng.below(4) {
+            let at = rng.below(data.len() + 1);
+            let last = rng.below(4) as u8;
+            data.splice(at..at, [0, 0, last]);
+        }
+        for unit in nal::annex_b_units(&data) {
+            assert!(!unit.is_empty());
+            let _ = nal::nal_kind(codec, unit);
+        }
+        let _ = nal::avcc_units(&data, [1, 2, 4][rng.below(3)]);
+
+        let mut sps = match codec {
+            VideoCodec::H264 => vec![0x67],
+            _ => vec![0x42, 0x01],
+        };
+        sps.extend(&data);
+        if let Ok(info) = sps::parse_sps(codec, &sps) {
+            assert!(info.width > 0 && info.height > 0);
+        }
+    }
+}